@@ -0,0 +1,26 @@
+//! EDR Event Generator
+//!
+//! Creates endpoint events (processes, file operations, and network connections) and logs a ground
+//! truth record of each one, to verify the detection and classification of an EDR product.
+//!
+//! The `edr_generator` binary reads scenarios from csv, JSON, or YAML files. The same engine can
+//! be driven from Rust code, e.g. from integration tests, with a
+//! [`ScenarioBuilder`](modules::builder::ScenarioBuilder):
+//!
+//! ```no_run
+//! use edr_generator::modules::builder::ScenarioBuilder;
+//! use edr_generator::modules::formatter::LogFormat;
+//!
+//! let report = ScenarioBuilder::new()
+//!     .output("events.csv")
+//!     .format(LogFormat::Cef)
+//!     .process("cmd", &["/c", "whoami"])
+//!     .file_new("C:\\temp\\dropper.txt")
+//!     .file_delete("C:\\temp\\dropper.txt")
+//!     .connect_self("hello world")
+//!     .run()
+//!     .unwrap();
+//! assert_eq!(report.errors, 0);
+//! ```
+
+pub mod modules;
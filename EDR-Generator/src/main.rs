@@ -1,15 +1,67 @@
-use clap::{Arg, App};
-use crate::modules::logger::Logger;
-use crate::modules::commander::TaskCommander;
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use edr_generator::modules::logger::{self, Logger, OutputOptions};
+use edr_generator::modules::commander::TaskCommander;
+use edr_generator::modules::formatter::LogFormat;
+use edr_generator::modules::template::Template;
+use edr_generator::modules::sink::{Sink, RegistrySink, StreamSink, NatsSink};
+use edr_generator::modules::synthetic::Weights;
+use edr_generator::modules::rate::RateLimiter;
+use edr_generator::modules::common::GenerationError;
+use edr_generator::modules::server::{self, Server};
+use edr_generator::modules::policy::Policy;
+use edr_generator::modules::alias::Aliases;
+use edr_generator::modules::validate;
+use edr_generator::modules::report::ElasticsearchReporter;
+use edr_generator::modules::pack::{self, Bundle, PackStore};
+use edr_generator::modules::signature::{SigningKey, TrustedKey};
+use edr_generator::modules::workdir::WorkDir;
+use edr_generator::modules::lock::RunLock;
+use edr_generator::modules::stix;
+use edr_generator::modules::vectr;
+use edr_generator::modules::verify;
+use edr_generator::modules::events;
+use edr_generator::modules::dashboard;
+use edr_generator::modules::boot;
+use edr_generator::modules::pcap;
+use edr_generator::modules::ingest::{self, IngestConfig, IngestProbe};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+/// Exit code of a run which completed without errors
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code of a run which encountered errors, or a dry run which found problems
+const EXIT_ERRORS: i32 = 1;
+/// Exit code when the run could not be set up or the input contained no instructions
+const EXIT_SETUP_FAILURE: i32 = 2;
+/// Exit code of a run which completed without errors but did not generate every expected event
+const EXIT_UNVERIFIED: i32 = 3;
+/// Exit code of an interrupted run, matching the shell convention for SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
 
-mod modules;
+/// Options of a run from an input file which `serve` does not support, by argument name and flag
+const SERVE_UNSUPPORTED: [(&str, &str); 15] = [("TUI", "--tui"), ("Verify", "--verify"), ("Ingest Lag", "--ingest-lag"),
+    ("Stream", "--stream"), ("NATS", "--nats"), ("Registry Key", "--registry-key"), ("Rate", "--rate"), ("Workers", "--workers"),
+    ("Summary", "--summary"), ("Elasticsearch", "--elasticsearch"), ("STIX", "--stix"), ("VECTR", "--vectr"),
+    ("Sample Interval", "--sample-interval"), ("Random", "--random"), ("Campaign", "--campaign")];
 
-fn main(){
+
+fn main() {
+    // destructors do not run on exit, so every resource is released by run before exiting
+    std::process::exit(run());
+}
+
+/// Parses the command line and executes the requested run
+///
+/// # Returns
+///
+/// Exit code of the process
+fn run() -> i32 {
     let matches = App::new("EDR Event Generator")
         .version("1.0")
         .author("Christopher Makarem")
         .about("Creates EDR events to verify detection and classification")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("Deliminator")
             .short("d")
             .long("deliminator")
@@ -22,58 +74,875 @@ fn main(){
             .value_name("FILE")
             .help("Sets the output file location to log events (default value: 'log.csv')")
             .takes_value(true))
+        .arg(Arg::with_name("Format")
+            .short("f")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Sets the output format for logged events: csv, cef, or leef (default value: 'csv')")
+            .takes_value(true))
+        .arg(Arg::with_name("Template")
+            .long("template")
+            .value_name("FILE")
+            .help("Formats logged events with the template in FILE instead of a built in format")
+            .conflicts_with("Format")
+            .takes_value(true))
+        .arg(Arg::with_name("Registry Key")
+            .long("registry-key")
+            .value_name("KEY")
+            .help("Also writes each event under a per-run subkey of the registry key, e.g. HKCU\\Software\\EDRGenerator (Windows only)")
+            .takes_value(true))
+        .arg(Arg::with_name("Stream")
+            .long("stream")
+            .value_name("PATH")
+            .help("Also streams each event to a Unix domain socket, FIFO, or named pipe (\\\\.\\pipe\\<name>)")
+            .takes_value(true))
+        .arg(Arg::with_name("NATS")
+            .long("nats")
+            .value_name("ADDRESS")
+            .help("Also publishes each event to the NATS server at host[:port] (default port: 4222)")
+            .takes_value(true))
+        .arg(Arg::with_name("NATS Subject")
+            .long("nats-subject")
+            .value_name("SUBJECT")
+            .help("Sets the NATS subject events are published to (default value: 'edr.events')")
+            .takes_value(true))
+        .arg(Arg::with_name("Random")
+            .long("random")
+            .value_name("COUNT")
+            .help("Generates COUNT random events instead of reading an input file")
+            .takes_value(true))
+        .arg(Arg::with_name("Seed")
+            .long("seed")
+            .value_name("NUMBER")
+            .help("Sets the seed used by --random and --campaign to reproduce a previous run (default value: random)")
+            .takes_value(true))
+        .arg(Arg::with_name("Campaign")
+            .long("campaign")
+            .value_name("LIBRARY")
+            .help("Runs scenarios selected by weight from the YAML or JSON campaign LIBRARY until its duration has elapsed, instead of reading an input file")
+            .conflicts_with("Random")
+            .takes_value(true))
+        .arg(Arg::with_name("Manifest")
+            .long("manifest")
+            .value_name("FILE")
+            .help("Writes the JSON manifest of the scenarios run by --campaign, and when they ran, to FILE (default value: 'campaign_manifest.json')")
+            .requires("Campaign")
+            .takes_value(true))
+        .arg(Arg::with_name("Weights")
+            .long("weights")
+            .value_name("WEIGHTS")
+            .help("Sets the relative weights of the event categories used by --random (default value: 'process=1,file=1,network=1')")
+            .takes_value(true))
+        .arg(Arg::with_name("Rate")
+            .long("rate")
+            .value_name("EVENTS")
+            .help("Limits execution to EVENTS instructions per second (default value: unlimited)")
+            .takes_value(true))
+        .arg(Arg::with_name("Burst")
+            .long("burst")
+            .value_name("COUNT")
+            .help("Sets the number of instructions --rate allows back to back after an idle period (default value: '1')")
+            .requires("Rate")
+            .takes_value(true))
+        .arg(Arg::with_name("Jitter")
+            .long("jitter")
+            .value_name("PERCENT")
+            .help("Randomly varies each --rate wait by up to PERCENT (default value: '0')")
+            .requires("Rate")
+            .takes_value(true))
+        .arg(Arg::with_name("Workers")
+            .long("workers")
+            .value_name("COUNT")
+            .help("Executes process, file, and network instructions on COUNT threads concurrently (default value: '1')")
+            .takes_value(true))
+        .arg(Arg::with_name("Sample Interval")
+            .long("sample-interval")
+            .value_name("MSEC")
+            .help("Logs the CPU, memory, and disk usage of child processes every MSEC milliseconds")
+            .takes_value(true))
+        .arg(Arg::with_name("Instruction Timeout")
+            .long("instruction-timeout")
+            .value_name("MSEC")
+            .help("Abandons any instruction which does not complete within MSEC milliseconds, logging a timeout error and continuing with the next instruction")
+            .takes_value(true))
+        .arg(Arg::with_name("Flush Interval")
+            .long("flush-interval")
+            .value_name("MSEC")
+            .help("Flushes records written to the output file at least every MSEC milliseconds")
+            .takes_value(true))
+        .arg(Arg::with_name("Max Log Size")
+            .long("max-log-size")
+            .value_name("SIZE")
+            .help("Rotates the output file once it reaches SIZE bytes (e.g. 500K, 100M, 2G), keeping earlier records in numbered files (log.1.csv, log.2.csv, ...)")
+            .takes_value(true))
+        .arg(Arg::with_name("Compress Rotated")
+            .long("compress-rotated")
+            .help("Compresses rotated output files with gzip")
+            .requires("Max Log Size"))
+        .arg(Arg::with_name("Elasticsearch")
+            .long("elasticsearch")
+            .value_name("URL")
+            .help("Indexes the run summary in an Elasticsearch or OpenSearch index, e.g. https://elastic@es.lab.local:9200/edr-runs (credentials: the EDR_ELASTICSEARCH_PASSWORD or EDR_ELASTICSEARCH_API_KEY environment variable)")
+            .takes_value(true))
+        .arg(Arg::with_name("Dry Run")
+            .long("dry-run")
+            .help("Checks every instruction of the input file and reports problems without executing anything")
+            .conflicts_with_all(&["Random", "Campaign"]))
+        .arg(Arg::with_name("Summary")
+            .long("summary")
+            .value_name("FILE")
+            .help("Writes a JSON summary of the run, with counts per instruction and error kind, to FILE")
+            .takes_value(true))
+        .arg(Arg::with_name("STIX")
+            .long("stix")
+            .value_name("FILE")
+            .help("Writes the artifacts and behaviors of the run as a STIX 2.1 bundle to FILE")
+            .takes_value(true))
+        .arg(Arg::with_name("VECTR")
+            .long("vectr")
+            .value_name("FILE")
+            .help("Writes the test cases of the run as a CSV file matching the VECTR import template to FILE")
+            .takes_value(true))
+        .arg(Arg::with_name("Verify")
+            .long("verify")
+            .value_name("FILE")
+            .help("Checks the events of the run against the expected events listed in FILE and reports which were not generated")
+            .takes_value(true))
+        .arg(Arg::with_name("Ingest Lag")
+            .long("ingest-lag")
+            .value_name("FILE")
+            .help("Searches the SIEM described by the YAML or JSON FILE for each event until it appears, and reports the ingestion latency per event type")
+            .takes_value(true))
+        .arg(Arg::with_name("Require Signed")
+            .long("require-signed")
+            .value_name("PUBLIC_KEY")
+            .help("Refuses to run scenario files which are not signed with the minisign public key, given as a key file or base64 key")
+            .takes_value(true))
+        .arg(Arg::with_name("Track Descendants")
+            .long("track-descendants")
+            .help("Logs processes started by child processes and stops them at the end of the run"))
+        .arg(Arg::with_name("Workdir")
+            .long("workdir")
+            .value_name("DIRECTORY")
+            .help("Runs in a new directory created inside DIRECTORY, relative paths of the scenario resolve inside it and it is removed at the end of the run")
+            .takes_value(true))
+        .arg(Arg::with_name("Cleanup")
+            .long("cleanup")
+            .help("Removes the run keys, startup folder files, crontab entries, services, and scheduled tasks created by persistence instructions at the end of the run"))
+        .arg(Arg::with_name("Write Ahead")
+            .long("write-ahead")
+            .help("Logs an Intent record, synced to disk, before every instruction which changes or removes host state and a Completed record after it, so a crashed run still shows what may have been changed"))
+        .arg(Arg::with_name("Aliases")
+            .long("aliases")
+            .value_name("FILE")
+            .help("Resolves alternative instruction names defined in a YAML or JSON file (e.g. T1059: process) to built-in instructions")
+            .takes_value(true))
+        .arg(Arg::with_name("Allow Concurrent")
+            .long("allow-concurrent")
+            .help("Runs even if another instance is executing on this host, interleaving the events of both runs"))
+        .arg(Arg::with_name("TUI")
+            .long("tui")
+            .help("Shows a live dashboard of the run (counters by instruction, recent instructions, errors, and the instruction rate) instead of printing errors, q interrupts the run"))
+        .arg(Arg::with_name("Boot")
+            .long("boot")
+            .help("Marks the run as a boot-time run: the activity of every event ends with (boot) and the uptime of the host is recorded"))
         .arg(Arg::with_name("INPUT")
             .value_name("FILE")
-            .help("Sets the input file to use for event creation")
-            .required(true)
+            .help("Sets the input file to use for event creation, - reads instructions from stdin as they arrive")
+            .required_unless_one(&["Random", "Campaign"])
             .index(1))
+        .subcommand(SubCommand::with_name("serve")
+            .about("Executes instructions received over TCP connections and streams the resulting records back")
+            .arg(Arg::with_name("Listen")
+                .long("listen")
+                .value_name("ADDRESS")
+                .help("Sets the address to listen on (default value: '127.0.0.1:7878')")
+                .takes_value(true))
+            .arg(Arg::with_name("Policy")
+                .long("policy")
+                .value_name("FILE")
+                .help("Restricts the instructions each controller may trigger to those allowed by its role in a YAML or JSON policy file")
+                .takes_value(true))
+            .arg(Arg::with_name("Token")
+                .long("token")
+                .value_name("SECRET")
+                .help("Requires controllers to send token,SECRET as their first line (default value: the EDR_SERVE_TOKEN environment variable), required unless listening on a loopback address")
+                .takes_value(true))
+            .arg(Arg::with_name("Idle Timeout")
+                .long("idle-timeout")
+                .value_name("SECONDS")
+                .help("Ends the run of a controller which sends nothing for SECONDS seconds (default value: '300')")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("pack")
+            .about("Installs, lists, updates, and runs signed bundles of scenario files")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .arg(Arg::with_name("Directory")
+                .long("dir")
+                .value_name("DIRECTORY")
+                .help("Sets the directory packs are installed to (default value: 'packs')")
+                .takes_value(true))
+            .arg(Arg::with_name("Public Key")
+                .long("public-key")
+                .value_name("PUBLIC_KEY")
+                .help("Sets the minisign public key file or base64 encoded key bundles must be signed with (default value: the EDR_PACK_PUBLIC_KEY environment variable)")
+                .takes_value(true))
+            .subcommand(SubCommand::with_name("install")
+                .about("Verifies and installs a bundle, replacing any installed version of the pack")
+                .arg(Arg::with_name("SOURCE")
+                    .help("Sets the path or http or https URL of the bundle")
+                    .required(true)
+                    .index(1)))
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists the installed packs"))
+            .subcommand(SubCommand::with_name("update")
+                .about("Reinstalls packs from the path or URL they were installed from")
+                .arg(Arg::with_name("NAME")
+                    .help("Sets the pack to update, every installed pack is updated when not given")
+                    .index(1)))
+            .subcommand(SubCommand::with_name("run")
+                .about("Runs a scenario of an installed pack with the options given before 'pack'")
+                .arg(Arg::with_name("NAME")
+                    .help("Sets the pack to run")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("SCENARIO")
+                    .help("Sets the scenario to run (default value: the first scenario of the pack)")
+                    .index(2)))
+            .subcommand(SubCommand::with_name("sign")
+                .about("Signs a bundle file in place")
+                .arg(Arg::with_name("FILE")
+                    .help("Sets the bundle file to sign")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("Secret Key")
+                    .long("secret-key")
+                    .value_name("SECRET_KEY")
+                    .help("Sets the minisign secret key file to sign with, its password is prompted for when it is encrypted")
+                    .required(true)
+                    .takes_value(true))))
+        .subcommand(SubCommand::with_name("boot")
+            .about("Installs or removes a scenario run at system boot, before users log on")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("install")
+                .about("Runs the scenario at every boot with the output options given before 'boot', replacing any installed boot-time scenario")
+                .arg(Arg::with_name("SCENARIO")
+                    .help("Sets the scenario file to run")
+                    .required(true)
+                    .index(1)))
+            .subcommand(SubCommand::with_name("remove")
+                .about("Removes the installed boot-time scenario")))
+        .subcommand(SubCommand::with_name("import-pcap")
+            .about("Converts the TCP and UDP flows of a packet capture into a scenario replaying the same traffic shape")
+            .arg(Arg::with_name("PCAP")
+                .help("Sets the packet capture file (pcap format) to read")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("SCENARIO")
+                .help("Sets the scenario file to write (default value: the capture file name with a .csv extension)")
+                .index(2)))
         .get_matches();
     let delim = matches.value_of("Deliminator").unwrap_or(",");
     let out_file = matches.value_of("Output File").unwrap_or("log.csv");
-    let input_file = matches.value_of("INPUT").unwrap_or("windows_input.csv");
-    let logger = Logger::new(&String::from(out_file));
+    let mut input_file = matches.value_of("INPUT").unwrap_or("windows_input.csv").to_string();
+    if let Some(pack) = matches.subcommand_matches("pack") {
+        match manage_packs(pack) {
+            Ok(Some(scenario)) => input_file = scenario,
+            Ok(None) => return EXIT_SUCCESS,
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(boot) = matches.subcommand_matches("boot") {
+        return match manage_boot(&matches, boot) {
+            Ok(_) => EXIT_SUCCESS,
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(import) = matches.subcommand_matches("import-pcap") {
+        return match import_pcap(import) {
+            Ok(message) => {
+                println!("{}", message);
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    let format = match matches.value_of("Template") {
+        Some(path) => Template::load(&path.to_string()).map(LogFormat::Template),
+        None => LogFormat::from_name(matches.value_of("Format").unwrap_or("csv"))
+    };
+    let format = match format {
+        Ok(inner) => inner,
+        Err(e) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+    };
+    let aliases = match matches.value_of("Aliases").map(|path| Aliases::load(&path.to_string())) {
+        Some(Ok(inner)) => inner,
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => Aliases::default()
+    };
+    if matches.is_present("Dry Run") {
+        return match validate::validate_scenario(&input_file, delim.as_bytes()[0], &aliases) {
+            Ok(findings) => {
+                let mut problems = 0;
+                for finding in &findings {
+                    match &finding.result {
+                        Ok(_) => {
+                            println!("{}: ok", finding.location);
+                            for artifact in &finding.artifacts {
+                                println!("    {}", artifact);
+                            }
+                        }
+                        Err(e) => {
+                            problems += 1;
+                            println!("{}: {}", finding.location, e);
+                        }
+                    }
+                }
+                println!("Dry run complete. {} Instructions Found. {} problem(s) found.", findings.len(), problems);
+                if problems > 0 { EXIT_ERRORS } else { EXIT_SUCCESS }
+            }
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    let trusted_key = match matches.value_of("Require Signed").map(TrustedKey::load) {
+        Some(Ok(inner)) => Some(inner),
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let instruction_timeout = match matches.value_of("Instruction Timeout").map(|timeout| (timeout, timeout.parse::<u64>())) {
+        Some((_, Ok(inner))) if inner > 0 => Some(Duration::from_millis(inner)),
+        Some((timeout, _)) => {
+            eprintln!("Encountered an unexpected error when setting up: {} is not a valid instruction timeout", timeout);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let flush_interval = match matches.value_of("Flush Interval").map(|interval| (interval, interval.parse::<u64>())) {
+        Some((_, Ok(inner))) => Some(Duration::from_millis(inner)),
+        Some((interval, _)) => {
+            eprintln!("Encountered an unexpected error when setting up: {} is not a valid flush interval", interval);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let max_size = match matches.value_of("Max Log Size").map(logger::parse_size) {
+        Some(Ok(inner)) => Some(inner),
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let output_options = OutputOptions { flush_interval, max_size, compress: matches.is_present("Compress Rotated") };
+    // held until the run ends, dry runs and pack management do not execute anything
+    let _lock = if matches.is_present("Allow Concurrent") {
+        None
+    } else {
+        match RunLock::acquire() {
+            Ok(inner) => Some(inner),
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    };
+    if let Some(serve) = matches.subcommand_matches("serve") {
+        if trusted_key.is_some() {
+            eprintln!("Encountered an unexpected error when setting up: --require-signed can not be used with serve, instructions received over TCP are not signed");
+            return EXIT_SETUP_FAILURE
+        }
+        if matches.is_present("Boot") {
+            eprintln!("Encountered an unexpected error when setting up: --boot can not be used with serve, install a boot-time scenario with 'boot install' instead");
+            return EXIT_SETUP_FAILURE
+        }
+        if let Some((_, flag)) = SERVE_UNSUPPORTED.iter().find(|(name, _)| matches.is_present(name)) {
+            eprintln!("Encountered an unexpected error when setting up: {} can not be used with serve", flag);
+            return EXIT_SETUP_FAILURE
+        }
+        let address = serve.value_of("Listen").unwrap_or(server::DEFAULT_LISTEN_ADDRESS);
+        let mut server = match Server::bind(address, out_file, format, delim.as_bytes()[0]) {
+            Ok(inner) => inner,
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        };
+        if matches.is_present("Track Descendants") {
+            server.set_track_descendants();
+        }
+        if let Some(base) = matches.value_of("Workdir") {
+            server.set_workdir(base);
+        }
+        if matches.is_present("Cleanup") {
+            server.set_cleanup();
+        }
+        if matches.is_present("Write Ahead") {
+            server.set_write_ahead();
+        }
+        if let Some(timeout) = instruction_timeout {
+            server.set_instruction_timeout(timeout);
+        }
+        server.set_output_options(output_options);
+        server.set_aliases(aliases);
+        match serve.value_of("Idle Timeout").map(|timeout| (timeout, timeout.parse::<u64>())) {
+            Some((_, Ok(inner))) if inner > 0 => server.set_idle_timeout(Duration::from_secs(inner)),
+            Some((timeout, _)) => {
+                eprintln!("Encountered an unexpected error when setting up: {} is not a valid idle timeout", timeout);
+                return EXIT_SETUP_FAILURE
+            }
+            None => {}
+        }
+        if let Some(path) = serve.value_of("Policy") {
+            match Policy::load(&path.to_string()) {
+                Ok(policy) => server.set_policy(policy),
+                Err(e) => {
+                    eprintln!("Encountered an unexpected error when setting up: {}", e);
+                    return EXIT_SETUP_FAILURE
+                }
+            }
+        }
+        match serve.value_of("Token").map(String::from).or_else(|| std::env::var(server::TOKEN_VARIABLE).ok()) {
+            Some(token) if !token.is_empty() => server.set_token(token),
+            _ if server.is_exposed() => {
+                eprintln!("Encountered an unexpected error when setting up: {} accepts controllers from other hosts, set a --token (or {}) controllers must send", address, server::TOKEN_VARIABLE);
+                return EXIT_SETUP_FAILURE
+            }
+            _ => {}
+        }
+        if let Err(e) = events::console(server.subscribe()) {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        println!("Listening for instructions on {}", address);
+        server.run();
+        return EXIT_SUCCESS
+    }
+    let expectations = match matches.value_of("Verify").map(|path| verify::load(&path.to_string())) {
+        Some(Ok(inner)) => Some(inner),
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let ingest_config = match matches.value_of("Ingest Lag").map(|path| IngestConfig::load(&path.to_string())) {
+        Some(Ok(inner)) => Some(inner),
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let reporter = match matches.value_of("Elasticsearch").map(ElasticsearchReporter::new) {
+        Some(Ok(inner)) => Some(inner),
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let mut logger = Logger::new(&String::from(out_file), format);
+    logger.set_output_options(output_options);
+    if matches.is_present("Boot") {
+        logger.set_boot(boot::uptime());
+    }
+    if let Some(key) = matches.value_of("Registry Key") {
+        match RegistrySink::new(&key.to_string()) {
+            Ok(inner) => logger.add_sink(Sink::Registry(inner)),
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(path) = matches.value_of("Stream") {
+        match StreamSink::new(&path.to_string()) {
+            Ok(inner) => logger.add_sink(Sink::Stream(inner)),
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(address) = matches.value_of("NATS") {
+        let subject = matches.value_of("NATS Subject").unwrap_or("edr.events");
+        match NatsSink::new(&address.to_string(), &subject.to_string()) {
+            Ok(inner) => logger.add_sink(Sink::Nats(inner)),
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
 
-    let mut commander = match TaskCommander::new(&input_file.to_string(), delim.as_bytes()[0], logger) {
+    let seed = match matches.value_of("Seed") {
+        Some(seed) => match seed.parse::<u64>() {
+            Ok(inner) => inner,
+            Err(_) => {
+                eprintln!("Encountered an unexpected error when setting up: {} is not a valid seed", seed);
+                return EXIT_SETUP_FAILURE
+            }
+        },
+        None => rand::random::<u64>()
+    };
+    let commander = match matches.value_of("Random") {
+        Some(count) => {
+            let count = match count.parse::<u64>() {
+                Ok(inner) => inner,
+                Err(_) => {
+                    eprintln!("Encountered an unexpected error when setting up: {} is not a valid event count", count);
+                    return EXIT_SETUP_FAILURE
+                }
+            };
+            match Weights::parse(matches.value_of("Weights").unwrap_or("")) {
+                Ok(weights) => TaskCommander::new_random(count, &weights, seed, logger),
+                Err(e) => Err(e)
+            }
+        }
+        None if matches.is_present("Campaign") && trusted_key.is_some() => Err(GenerationError::new("signature".to_string(), "--require-signed can not be used with --campaign, the scenarios of a campaign library are not verified".to_string())),
+        None if matches.is_present("Campaign") => TaskCommander::new_campaign(&matches.value_of("Campaign").unwrap_or("").to_string(), delim.as_bytes()[0], seed, logger),
+        None if input_file == "-" && trusted_key.is_some() => Err(GenerationError::new("signature".to_string(), "--require-signed can not be used with stdin, streamed instructions are not signed".to_string())),
+        None if input_file == "-" => Ok(TaskCommander::from_stream("stdin".to_string(), Box::new(std::io::stdin()), delim.as_bytes()[0], logger)),
+        None => match &trusted_key {
+            Some(key) => TaskCommander::new_signed(&input_file, delim.as_bytes()[0], key, logger),
+            None => TaskCommander::new(&input_file, delim.as_bytes()[0], logger)
+        }
+    };
+    let mut commander = match commander {
+        Ok(inner) => inner,
+        Err(e) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+    };
+    if matches.is_present("STIX") || matches.is_present("VECTR") || expectations.is_some() {
+        commander.set_record_events();
+    }
+    if matches.is_present("Cleanup") {
+        commander.set_cleanup();
+    }
+    if matches.is_present("Write Ahead") {
+        commander.set_write_ahead();
+    }
+    commander.set_aliases(aliases);
+    if let Some(timeout) = instruction_timeout {
+        commander.set_instruction_timeout(timeout);
+    }
+    if let Some(base) = matches.value_of("Workdir") {
+        match WorkDir::create(base) {
+            Ok(workdir) => {
+                println!("Working directory: {}", workdir.path().display());
+                commander.set_workdir(workdir);
+            }
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(rate) = matches.value_of("Rate") {
+        let limiter = match (rate.parse::<f64>(), matches.value_of("Burst").unwrap_or("1").parse::<u32>(), matches.value_of("Jitter").unwrap_or("0").parse::<f64>()) {
+            (Ok(rate), Ok(burst), Ok(jitter)) => RateLimiter::new(rate, burst, jitter),
+            _ => Err(GenerationError::new("input_format".to_string(), "--rate, --burst, and --jitter must be numbers".to_string()))
+        };
+        match limiter {
+            Ok(inner) => commander.set_rate_limit(inner),
+            Err(e) => {
+                eprintln!("Encountered an unexpected error when setting up: {}", e);
+                return EXIT_SETUP_FAILURE
+            }
+        }
+    }
+    if let Some(workers) = matches.value_of("Workers") {
+        let result = match workers.parse::<usize>() {
+            Ok(inner) if inner > 1 => commander.set_workers(inner),
+            Ok(_) => Ok(()),
+            Err(_) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid number of workers", workers)))
+        };
+        if let Err(e) = result {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+    }
+    if matches.is_present("Track Descendants") {
+        commander.set_track_descendants();
+    }
+    if let Some(interval) = matches.value_of("Sample Interval") {
+        let result = match interval.parse::<u64>() {
+            Ok(inner) => commander.set_sample_interval(Duration::from_millis(inner)),
+            Err(_) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid sample interval", interval)))
+        };
+        if let Err(e) = result {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+    }
+    let probe = match ingest_config.map(|config| IngestProbe::start(config, commander.interrupt_flag())) {
+        Some(Ok(inner)) => {
+            commander.set_ingest_probe(Some(inner.sender()));
+            Some(inner)
+        }
+        Some(Err(e)) => {
+            eprintln!("Encountered an unexpected error when setting up: {}", e);
+            return EXIT_SETUP_FAILURE
+        }
+        None => None
+    };
+    let interrupted = commander.interrupt_flag();
+    let handler = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting without cleaning up");
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        eprintln!("Interrupted, stopping child processes and writing the run summary (press Ctrl+C again to exit immediately)");
+    });
+    if let Err(e) = handler {
+        eprintln!("Encountered an unexpected error when setting up: {}", e);
+        return EXIT_SETUP_FAILURE
+    }
+    let console = if matches.is_present("TUI") {
+        dashboard::dashboard(commander.subscribe(), commander.interrupt_flag())
+    } else {
+        events::console(commander.subscribe())
+    };
+    let console = match console {
         Ok(inner) => inner,
         Err(e) => {
             eprintln!("Encountered an unexpected error when setting up: {}", e);
-            return
+            return EXIT_SETUP_FAILURE
         }
     };
     let mut commands_processed = 0;
     while commander.read_next() {
         commands_processed = commands_processed + 1;
     }
-    if commands_processed <= 0 {
-        eprintln!("Input File was empty or was of bad format. No Commands Processed")
+    let mut summary = commander.log_summary(commands_processed);
+    // an abandoned instruction may still hold the event bus, closing it lets the console finish
+    commander.events().close();
+    let _ = console.join();
+    let events = commander.take_events();
+    if let Some(expectations) = &expectations {
+        let verification = verify::verify(expectations, &events);
+        println!("Verification: {}", verification.message());
+        summary.verification = Some(verification);
+    }
+    if let Some(probe) = probe {
+        commander.set_ingest_probe(None);
+        println!("Waiting for the events of the run to be ingested by the SIEM");
+        let lags = probe.finish();
+        println!("Ingest lag: {}", ingest::message(&lags));
+        summary.ingest_lag = Some(lags);
+    }
+    if let Some(reporter) = reporter {
+        if let Err(e) = reporter.push(&summary) {
+            eprintln!("Unable to push the run summary: {}", e);
+        }
+    }
+    if let Some(path) = matches.value_of("Summary") {
+        if let Err(e) = summary.write(&path.to_string()) {
+            eprintln!("Unable to write the run summary: {}", e);
+        }
+    }
+    if let Some(path) = matches.value_of("STIX") {
+        if let Err(e) = stix::write_bundle(&path.to_string(), &summary, &events) {
+            eprintln!("Unable to write the STIX bundle: {}", e);
+        }
+    }
+    if let Some(path) = matches.value_of("VECTR") {
+        if let Err(e) = vectr::write_test_cases(&path.to_string(), &summary, &events) {
+            eprintln!("Unable to write the VECTR test cases: {}", e);
+        }
+    }
+    if let Some(manifest) = commander.campaign_manifest() {
+        let path = matches.value_of("Manifest").unwrap_or("campaign_manifest.json");
+        if let Err(e) = manifest.write(&path.to_string()) {
+            eprintln!("Unable to write the campaign manifest: {}", e);
+        }
+    }
+    let errors = commander.get_num_errors();
+    if summary.interrupted {
+        EXIT_INTERRUPTED
+    } else if commands_processed == 0 {
+        EXIT_SETUP_FAILURE
+    } else if errors > 0 {
+        EXIT_ERRORS
+    } else if summary.verification.as_ref().map_or(false, |verification| !verification.passed()) {
+        EXIT_UNVERIFIED
     } else {
-        println!("Done. {} Instructions Found. Encountered {} error(s).", commands_processed, commander.get_num_errors())
+        EXIT_SUCCESS
     }
 }
 
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Executes a `pack` subcommand
+///
+/// # Parameters
+///
+/// - `pack`: arguments of the `pack` subcommand
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Path of the scenario to run for `pack run`, nothing for the other subcommands
+/// - `Err`: The subcommand failed
+fn manage_packs(pack: &ArgMatches) -> Result<Option<String>, GenerationError> {
+    let store = PackStore::new(pack.value_of("Directory").unwrap_or(pack::DEFAULT_PACK_DIRECTORY));
+    let key = match pack.value_of("Public Key") {
+        Some(key) => Some(key.to_string()),
+        None => std::env::var(pack::KEY_VARIABLE).ok()
+    };
+    let require_key = || match &key {
+        Some(key) if !key.is_empty() => TrustedKey::load(key),
+        _ => Err(GenerationError::new("input_format".to_string(), format!("A public key is required, set --public-key or the {} environment variable", pack::KEY_VARIABLE)))
+    };
+    match pack.subcommand() {
+        ("install", Some(args)) => {
+            let installed = store.install(args.value_of("SOURCE").unwrap_or(""), &require_key()?)?;
+            println!("Installed {} {} ({} scenario(s))", installed.name, installed.version, installed.scenarios.len());
+        }
+        ("list", _) => {
+            for installed in store.list()? {
+                println!("{} {}: {}", installed.name, installed.version, installed.scenarios.join(", "));
+                if let Some(description) = installed.description {
+                    println!("    {}", description);
+                }
+            }
+        }
+        ("update", Some(args)) => {
+            let key = require_key()?;
+            let names: Vec<String> = match args.value_of("NAME") {
+                Some(name) => vec![name.to_string()],
+                None => store.list()?.into_iter().map(|installed| installed.name).collect()
+            };
+            let mut failed = 0;
+            for name in names {
+                match store.update(&name, &key) {
+                    Ok((previous, updated)) if previous == updated.version => println!("{} {} is up to date", name, previous),
+                    Ok((previous, updated)) => println!("Updated {} from {} to {}", name, previous, updated.version),
+                    // a pack which can not be updated keeps its installed version
+                    Err(e) => {
+                        failed += 1;
+                        eprintln!("Unable to update {}: {}", name, e);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(GenerationError::new("io".to_string(), format!("{} pack(s) could not be updated", failed)));
+            }
+        }
+        ("run", Some(args)) => return Ok(Some(store.scenario_path(args.value_of("NAME").unwrap_or(""), args.value_of("SCENARIO"))?)),
+        ("sign", Some(args)) => {
+            let path = args.value_of("FILE").unwrap_or("");
+            let mut bundle = Bundle::load(path)?;
+            bundle.sign(&SigningKey::load(args.value_of("Secret Key").unwrap_or(""))?)?;
+            bundle.write(path)?;
+            println!("Signed {} {}", bundle.name, bundle.version);
+        }
+        _ => {}
+    }
+    Ok(None)
+}
 
-    #[test]
-    fn test_good_inputs() {
-        let logger = Logger::new(&String::from("test.csv"));
-        let mut commander =  TaskCommander::new(&"tests/good_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
-        let mut commands_processed = 0;
-        while commander.read_next() {
-            commands_processed = commands_processed + 1;
-        }
-        assert_eq!(0, commander.get_num_errors())
-    }
-    #[test]
-    fn test_bad_inputs() {
-        let logger = Logger::new(&String::from("test.csv"));
-        let mut commander =  TaskCommander::new(&"tests/bad_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
-        let mut commands_processed = 0;
-        while commander.read_next() {
-            commands_processed = commands_processed + 1;
-        }
-        assert_eq!(9, commander.get_num_errors())
+/// Executes an `import-pcap` subcommand, writing the scenario next to the capture unless a path is
+/// given. The payload files of large flows are written to a directory named after the scenario.
+///
+/// # Parameters
+///
+/// - `import`: arguments of the `import-pcap` subcommand
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A description of the scenario written
+/// - `Err`: The capture could not be read, or the scenario could not be written
+fn import_pcap(import: &ArgMatches) -> Result<String, GenerationError> {
+    let capture = import.value_of("PCAP").unwrap_or("");
+    let scenario = match import.value_of("SCENARIO") {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => Path::new(capture).with_extension("csv")
+    };
+    let flows = pcap::load(capture)?;
+    let stem = scenario.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let payloads = std::env::current_dir()?.join(&scenario).with_file_name(format!("{}_payloads", stem));
+    let records = pcap::scenario(&flows, &payloads)?;
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_path(&scenario).map_err(|e| GenerationError::new("io".to_string(), e.to_string()))?;
+    for record in &records {
+        writer.write_record(record).map_err(|e| GenerationError::new("io".to_string(), e.to_string()))?;
+    }
+    writer.flush()?;
+    Ok(format!("Wrote {} instruction(s) replaying {} flow(s) to {}", records.len(), flows.len(), scenario.display()))
+}
+
+/// Executes a `boot` subcommand. The boot-time run writes to the output file, in the format or
+/// template, and with the summary, cleanup, write-ahead, and alias options given before `boot`, with
+/// relative paths resolved against the current directory.
+///
+/// # Parameters
+///
+/// - `matches`: arguments of the whole command line
+/// - `boot`: arguments of the `boot` subcommand
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The boot-time scenario was installed or removed
+/// - `Err`: The subcommand failed
+fn manage_boot(matches: &ArgMatches, boot: &ArgMatches) -> Result<(), GenerationError> {
+    match boot.subcommand() {
+        ("install", Some(args)) => {
+            let directory = std::env::current_dir()?;
+            let absolute = |path: &str| directory.join(path).to_string_lossy().to_string();
+            let scenario = args.value_of("SCENARIO").unwrap_or("");
+            if !Path::new(scenario).is_file() {
+                return Err(GenerationError::new("io".to_string(), format!("{} is not a scenario file", scenario)));
+            }
+            let mut arguments = vec!["--outfile".to_string(), absolute(matches.value_of("Output File").unwrap_or("log.csv"))];
+            if let Some(format) = matches.value_of("Format") {
+                arguments.extend(vec!["--format".to_string(), format.to_string()]);
+            }
+            if let Some(path) = matches.value_of("Template") {
+                arguments.extend(vec!["--template".to_string(), absolute(path)]);
+            }
+            if let Some(path) = matches.value_of("Summary") {
+                arguments.extend(vec!["--summary".to_string(), absolute(path)]);
+            }
+            if matches.is_present("Cleanup") {
+                arguments.push("--cleanup".to_string());
+            }
+            if matches.is_present("Write Ahead") {
+                arguments.push("--write-ahead".to_string());
+            }
+            if let Some(path) = matches.value_of("Aliases") {
+                arguments.extend(vec!["--aliases".to_string(), absolute(path)]);
+            }
+            arguments.push(absolute(scenario));
+            println!("Installed the boot-time scenario as {}", boot::install(&arguments)?);
+        }
+        ("remove", _) => println!("Removed the boot-time scenario {}", boot::remove()?),
+        _ => {}
     }
+    Ok(())
 }
@@ -0,0 +1,273 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use crate::modules::tamper;
+use rand::Rng;
+
+/// Longest account name accepted, the limit of `net user` on Windows
+pub const MAX_NAME_LENGTH: usize = 20;
+
+/// Characters which may not appear in a group name on Windows
+const INVALID_GROUP_CHARACTERS: &str = "\"/\\[]:;|=,+*?<>";
+
+/// Determines if local accounts can be managed on this platform, with `net.exe` on Windows,
+/// `useradd`, `userdel`, and `usermod` on Linux, or `sysadminctl` and `dseditgroup` on macOS
+///
+/// # Returns
+///
+/// True if `create_user`, `delete_user`, and `add_to_group` instructions can be executed on this
+/// platform
+pub fn supported() -> bool {
+    cfg!(any(windows, target_os = "linux", target_os = "macos"))
+}
+
+/// Determines if a name can be used as the name of a local account on every supported platform:
+/// letters, digits, `-`, `_`, and `.`, not starting with `-` or `.`, and at most `MAX_NAME_LENGTH`
+/// characters
+///
+/// # Parameters
+///
+/// - `name`: name of the account
+///
+/// # Returns
+///
+/// True if the name is valid
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LENGTH && !name.starts_with(&['-', '.'][..])
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Determines if a name can be used as the name of a local group. Spaces are allowed, as in
+/// `Remote Desktop Users`.
+///
+/// # Parameters
+///
+/// - `name`: name of the group
+///
+/// # Returns
+///
+/// True if the name is valid
+pub fn valid_group(name: &str) -> bool {
+    !name.trim().is_empty() && !name.starts_with('-') && !name.chars().any(|c| c.is_control() || INVALID_GROUP_CHARACTERS.contains(c))
+}
+
+/// Determines if the generator runs with the privileges needed to manage local accounts, as an
+/// elevated Administrator on Windows or as root elsewhere
+///
+/// # Returns
+///
+/// True if the generator is elevated
+#[cfg(windows)]
+pub fn elevated() -> bool {
+    use std::mem;
+    use std::ptr;
+    use winapi::shared::minwindef::{DWORD, LPVOID};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winnt::{TokenElevation, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY};
+
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let mut elevation: TOKEN_ELEVATION = mem::zeroed();
+        let mut size: DWORD = 0;
+        let queried = GetTokenInformation(token, TokenElevation, &mut elevation as *mut TOKEN_ELEVATION as LPVOID,
+                                          mem::size_of::<TOKEN_ELEVATION>() as DWORD, &mut size);
+        CloseHandle(token);
+        queried != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+#[cfg(unix)]
+pub fn elevated() -> bool {
+    extern "C" {
+        fn geteuid() -> u32;
+    }
+    unsafe { geteuid() == 0 }
+}
+
+#[cfg(not(any(windows, unix)))]
+pub fn elevated() -> bool {
+    false
+}
+
+/// Creates a local account which can not be logged in to interactively on Unix, and which has a
+/// random password on Windows and macOS
+///
+/// # Parameters
+///
+/// - `name`: name of the account
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the tool which created the account, as a `User Account Created` event
+/// - `Err`: The generator is not elevated, the platform is not supported, or the tool failed
+///   (e.g. the account already exists)
+pub fn create_user(name: &str) -> Result<Log, GenerationError> {
+    let password = random_password();
+    let arguments = if cfg!(windows) {
+        vec!["user", name, password.as_str(), "/add"]
+    } else if cfg!(target_os = "macos") {
+        vec!["-addUser", name, "-password", password.as_str()]
+    } else {
+        vec!["-M", name]
+    };
+    let mut log = run_account_tool(account_tool("create"), &arguments, Some(&password))?;
+    log.activity = format!("User Account Created ({})", name);
+    Ok(log)
+}
+
+/// Deletes a local account
+///
+/// # Parameters
+///
+/// - `name`: name of the account
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the tool which deleted the account, as a `User Account Deleted` event
+/// - `Err`: The generator is not elevated, the platform is not supported, or the tool failed
+///   (e.g. the account does not exist)
+pub fn delete_user(name: &str) -> Result<Log, GenerationError> {
+    let arguments = if cfg!(windows) {
+        vec!["user", name, "/delete"]
+    } else if cfg!(target_os = "macos") {
+        vec!["-deleteUser", name]
+    } else {
+        vec![name]
+    };
+    let mut log = run_account_tool(account_tool("delete"), &arguments, None)?;
+    log.activity = format!("User Account Deleted ({})", name);
+    Ok(log)
+}
+
+/// Adds a local account to a local group
+///
+/// # Parameters
+///
+/// - `name`: name of the account
+/// - `group`: name of the group (e.g. `Administrators`, `sudo`)
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the tool which changed the group, as a `Group Membership Added` event
+/// - `Err`: The generator is not elevated, the platform is not supported, or the tool failed
+///   (e.g. the account or the group does not exist)
+pub fn add_to_group(name: &str, group: &str) -> Result<Log, GenerationError> {
+    let arguments = if cfg!(windows) {
+        vec!["localgroup", group, name, "/add"]
+    } else if cfg!(target_os = "macos") {
+        vec!["-o", "edit", "-a", name, "-t", "user", group]
+    } else {
+        vec!["-a", "-G", group, name]
+    };
+    let mut log = run_account_tool(account_tool("group"), &arguments, None)?;
+    log.activity = format!("Group Membership Added ({} to {})", name, group);
+    Ok(log)
+}
+
+/// Selects the account management tool of the platform for an action
+///
+/// # Parameters
+///
+/// - `action`: `create`, `delete`, or `group`
+///
+/// # Returns
+///
+/// The program managing accounts
+fn account_tool(action: &str) -> &'static str {
+    if cfg!(windows) {
+        "net.exe"
+    } else if cfg!(target_os = "macos") {
+        if action == "group" { "dseditgroup" } else { "sysadminctl" }
+    } else {
+        match action {
+            "create" => "useradd",
+            "delete" => "userdel",
+            _ => "usermod"
+        }
+    }
+}
+
+/// Runs an account management tool after checking the platform and the privileges of the
+/// generator, logging it as the process which managed the account. The password of a new account
+/// is masked in the logged command line.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the tool, with its output and exit code
+/// - `Err`: The generator is not elevated, the platform is not supported, or the tool failed
+fn run_account_tool(program: &str, arguments: &[&str], password: Option<&str>) -> Result<Log, GenerationError> {
+    if !supported() {
+        return Err(GenerationError::new("unsupported_platform".to_string(), "Local accounts can only be managed on Windows, Linux, and macOS".to_string()));
+    }
+    if !elevated() {
+        let required = if cfg!(windows) { "an elevated Administrator" } else { "root" };
+        return Err(GenerationError::new("user_permissions".to_string(), format!("Managing local accounts with {} requires {}", program, required)));
+    }
+    let (mut log, code) = tamper::run_tool(program, arguments)?;
+    if let Some(password) = password {
+        log.proc_cmd = log.proc_cmd.replace(password, "********");
+    }
+    log.exit_code = code.map(|code| code.to_string()).unwrap_or_default();
+    if code != Some(0) {
+        return Err(GenerationError::new("io".to_string(), format!("{} failed: {}", log.proc_cmd, log.stdout)));
+    }
+    Ok(log)
+}
+
+/// Generates a password meeting the default complexity requirements of Windows
+fn random_password() -> String {
+    let letters: String = rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(12).map(char::from).collect();
+    format!("Edr-{}7a", letters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_validated() {
+        assert!(valid_name("edr_test"));
+        assert!(valid_name("svc-backup.01"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("-edr"));
+        assert!(!valid_name(".edr"));
+        assert!(!valid_name("edr test"));
+        assert!(!valid_name("edr;whoami"));
+        assert!(!valid_name("edr_test_account_name_too_long"));
+        assert!(valid_group("Administrators"));
+        assert!(valid_group("Remote Desktop Users"));
+        assert!(!valid_group(" "));
+        assert!(!valid_group("-sudo"));
+        assert!(!valid_group("wheel,sudo"));
+        assert!(!valid_group("admins\n"));
+    }
+
+    #[test]
+    fn passwords_generated() {
+        let password = random_password();
+        assert_eq!(password.len(), 18);
+        assert!(password.starts_with("Edr-"));
+        assert_ne!(password, random_password());
+    }
+
+    #[test]
+    fn privileges_required() {
+        if elevated() || !supported() {
+            return;
+        }
+        assert_eq!(create_user("edr_test").err().map(|e| e.kind), Some("user_permissions".to_string()));
+        assert_eq!(add_to_group("edr_test", "wheel").err().map(|e| e.kind), Some("user_permissions".to_string()));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::modules::common::GenerationError;
+use crate::modules::policy;
+use csv::StringRecord;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Names handled by the scenario script itself, which can not be used as aliases
+const KEYWORDS: [&str; 3] = ["repeat", "end", "include"];
+
+/// Structure defining alternative names for instructions, so scenarios can be written in the
+/// vocabulary of an existing tool or team. Every alias resolves to a built-in instruction.
+///
+/// ```yaml
+/// T1059: process
+/// T1048: exfil
+/// create-proc: process
+/// ```
+///
+/// # Parameters
+///
+/// - `names`: built-in instruction of each alias
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    names: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    /// Reads an alias file. Files with a `.json` extension are read as JSON, all others as YAML.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the alias file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Aliases instance
+    /// - `Err`: The file could not be read, is not a valid alias file, or defines an invalid alias
+    pub fn load(path: &String) -> Result<Aliases, GenerationError> {
+        let contents = fs::read_to_string(path)?;
+        let parsed = if path.to_lowercase().ends_with(".json") {
+            serde_json::from_str::<BTreeMap<String, String>>(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_str::<BTreeMap<String, String>>(&contents).map_err(|e| e.to_string())
+        };
+        match parsed {
+            Ok(names) => Aliases::new(names).map_err(|e| GenerationError::new(e.kind, format!("{}: {}", path, e.message))),
+            Err(e) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid alias file: {}", path, e)))
+        }
+    }
+
+    /// Creates aliases from a map of alias names to instructions. An alias may not contain
+    /// whitespace, shadow a built-in instruction or a scripting keyword (`repeat`, `end`,
+    /// `include`), or resolve to another alias.
+    ///
+    /// # Parameters
+    ///
+    /// - `names`: built-in instruction of each alias
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Aliases instance
+    /// - `Err`: An alias is not valid, or names an unknown instruction
+    pub fn new(names: BTreeMap<String, String>) -> Result<Aliases, GenerationError> {
+        for (alias, instruction) in names.iter() {
+            if alias.is_empty() || alias.chars().any(char::is_whitespace) {
+                return Err(GenerationError::new("input_format".to_string(), format!("Alias {:?} must be a single word", alias)));
+            }
+            if policy::is_instruction(alias) || alias == "pause" || KEYWORDS.contains(&alias.as_str()) {
+                return Err(GenerationError::new("input_format".to_string(), format!("Alias {} would replace a built-in instruction", alias)));
+            }
+            if !policy::is_instruction(instruction) && instruction != "pause" {
+                return Err(GenerationError::new("input_format".to_string(), format!("Alias {} names {} which is not an instruction", alias, instruction)));
+            }
+        }
+        Ok(Aliases { names })
+    }
+
+    /// Replaces the instruction of a record with the built-in instruction it is an alias of.
+    /// Records of built-in instructions, and of unknown names, are returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction and its parameters
+    ///
+    /// # Returns
+    ///
+    /// The record naming a built-in instruction
+    pub fn resolve(&self, record: StringRecord) -> StringRecord {
+        match record.get(0).and_then(|name| self.names.get(name)) {
+            Some(instruction) => {
+                let mut resolved = StringRecord::from(vec![instruction.as_str()]);
+                resolved.extend(record.iter().skip(1));
+                resolved.set_position(record.position().cloned());
+                resolved
+            }
+            None => record
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    fn names(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(alias, instruction)| (alias.to_string(), instruction.to_string())).collect()
+    }
+
+    #[test]
+    fn aliases_resolved() {
+        let aliases = Aliases::new(names(&[("T1048", "exfil"), ("create-proc", "process")])).unwrap();
+        let resolved = aliases.resolve(StringRecord::from(vec!["create-proc", "sh", "-c exit"]));
+        assert_eq!(resolved, StringRecord::from(vec!["process", "sh", "-c exit"]));
+        let unchanged = aliases.resolve(StringRecord::from(vec!["new_file", "a.txt"]));
+        assert_eq!(unchanged, StringRecord::from(vec!["new_file", "a.txt"]));
+        assert_eq!(aliases.resolve(StringRecord::from(vec!["T1105"])), StringRecord::from(vec!["T1105"]));
+    }
+
+    #[test]
+    fn aliases_validated() {
+        assert!(Aliases::new(names(&[("sleep", "pause")])).is_ok());
+        assert!(Aliases::new(names(&[("process", "new_file")])).is_err());
+        assert!(Aliases::new(names(&[("repeat", "process")])).is_err());
+        assert!(Aliases::new(names(&[("create proc", "process")])).is_err());
+        assert!(Aliases::new(names(&[("", "process")])).is_err());
+        assert!(Aliases::new(names(&[("T1105", "download")])).is_err());
+        assert!(Aliases::new(names(&[("spawn", "create-proc"), ("create-proc", "process")])).is_err());
+    }
+
+    #[test]
+    fn aliases_loaded() {
+        let path = TempPath::new("aliases.yaml");
+        fs::write(&path, "T1048: exfil\ncreate-proc: process\n").unwrap();
+        let aliases = Aliases::load(&path.as_string()).unwrap();
+        assert_eq!(aliases.names.len(), 2);
+        fs::write(&path, "T1048: [exfil]\n").unwrap();
+        let error = Aliases::load(&path.as_string()).err().unwrap();
+        assert!(error.message.contains("is not a valid alias file"));
+        fs::write(&path, "T1105: download\n").unwrap();
+        assert!(Aliases::load(&path.as_string()).is_err());
+    }
+}
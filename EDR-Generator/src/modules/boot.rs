@@ -0,0 +1,161 @@
+use crate::modules::common::GenerationError;
+use crate::modules::persistence::run_tool;
+use crate::modules::process::windows_argument;
+use std::env;
+use std::fs;
+use std::path::Path;
+use sysinfo::SystemExt;
+
+/// Name of the scheduled task which runs the boot-time scenario on Windows
+pub const BOOT_TASK: &str = "EdrGeneratorBoot";
+
+/// Name of the systemd unit which runs the boot-time scenario on Linux
+pub const BOOT_UNIT: &str = "edr-generator-boot.service";
+
+/// Directory the systemd unit of the boot-time scenario is installed to
+const UNIT_DIRECTORY: &str = "/etc/systemd/system";
+
+/// Registers the generator to run a scenario at system boot, before users can log on: as a
+/// scheduled task started by `SYSTEM` at startup on Windows, or as a systemd unit ordered before
+/// user sessions are allowed on Linux. The run is started with `--boot` so its events are marked
+/// as boot-time events. A previously installed boot-time scenario is replaced.
+///
+/// # Parameters
+///
+/// - `arguments`: arguments of the boot-time run, ending with the scenario file, with every path
+///   already absolute since the run does not start in the current directory
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Name of the scheduled task or path of the unit file
+/// - `Err`: The run could not be registered (no permissions) or boot-time runs are not supported
+///   on this platform
+pub fn install(arguments: &[String]) -> Result<String, GenerationError> {
+    let mut command = vec![env::current_exe()?.to_string_lossy().to_string(), "--boot".to_string()];
+    command.extend(arguments.iter().cloned());
+    if cfg!(windows) {
+        run_tool("schtasks.exe", &["/Create", "/TN", BOOT_TASK, "/TR", &command_line(&command), "/SC", "ONSTART", "/RU", "SYSTEM", "/RL", "HIGHEST", "/F"])?;
+        return Ok(format!("\\{}", BOOT_TASK));
+    }
+    if !cfg!(target_os = "linux") {
+        return Err(unsupported());
+    }
+    let path = Path::new(UNIT_DIRECTORY).join(BOOT_UNIT);
+    fs::write(&path, boot_unit(&exec_start(&command), &env::current_dir()?.to_string_lossy()))?;
+    run_tool("systemctl", &["daemon-reload"])?;
+    run_tool("systemctl", &["enable", BOOT_UNIT])?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Removes the boot-time scenario registered by `install`
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Name of the scheduled task or path of the unit file removed
+/// - `Err`: No boot-time scenario is installed, it could not be removed (no permissions), or
+///   boot-time runs are not supported on this platform
+pub fn remove() -> Result<String, GenerationError> {
+    if cfg!(windows) {
+        run_tool("schtasks.exe", &["/Delete", "/TN", BOOT_TASK, "/F"])?;
+        return Ok(format!("\\{}", BOOT_TASK));
+    }
+    if !cfg!(target_os = "linux") {
+        return Err(unsupported());
+    }
+    let path = Path::new(UNIT_DIRECTORY).join(BOOT_UNIT);
+    if !path.exists() {
+        return Err(GenerationError::new("io".to_string(), format!("No boot-time scenario is installed ({} does not exist)", path.display())));
+    }
+    run_tool("systemctl", &["disable", BOOT_UNIT])?;
+    fs::remove_file(&path)?;
+    run_tool("systemctl", &["daemon-reload"])?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Determines how long the host has been running
+///
+/// # Returns
+///
+/// Number of seconds since the host booted
+pub fn uptime() -> u64 {
+    sysinfo::System::new().get_uptime()
+}
+
+/// Builds the systemd unit running the boot-time scenario. The unit is started once per boot,
+/// after local file systems are mounted and before `systemd-user-sessions.service` allows users to
+/// log on. It is a `simple` service, so logons are not held back until the run completes, however
+/// long the scenario takes.
+///
+/// # Parameters
+///
+/// - `command`: command line of the boot-time run, escaped with `exec_start`
+/// - `directory`: working directory of the run
+///
+/// # Returns
+///
+/// Contents of the unit file
+fn boot_unit(command: &str, directory: &str) -> String {
+    format!("[Unit]\nDescription=EDR Event Generator boot-time scenario\nAfter=local-fs.target\nBefore=systemd-user-sessions.service\n\n\
+             [Service]\nType=simple\nWorkingDirectory={}\nExecStart={}\n\n[Install]\nWantedBy=multi-user.target\n", directory, command)
+}
+
+/// Joins the command line of a systemd `ExecStart=` setting. Arguments which are empty or contain
+/// whitespace, quotes, backslashes, or `;` are quoted with their quotes and backslashes escaped,
+/// and `%` and `$` are doubled so systemd does not expand them as specifiers or variables.
+fn exec_start(arguments: &[String]) -> String {
+    let quoted: Vec<String> = arguments.iter().map(|argument| {
+        let argument = argument.replace('%', "%%").replace('$', "$$");
+        if argument.is_empty() || argument.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';')) {
+            format!("\"{}\"", argument.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            argument
+        }
+    }).collect();
+    quoted.join(" ")
+}
+
+/// Joins the command line of the scheduled task, quoting each argument so Windows splits it back
+/// into the same arguments
+fn command_line(arguments: &[String]) -> String {
+    let quoted: Vec<String> = arguments.iter().map(|argument| windows_argument(argument)).collect();
+    quoted.join(" ")
+}
+
+/// Error returned on platforms without a supported boot-time mechanism
+fn unsupported() -> GenerationError {
+    GenerationError::new("unsupported_platform".to_string(), "Boot-time scenarios can only be installed on Windows and Linux".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_unit_written() {
+        let arguments = ["/opt/edr/edr_generator", "--boot", "-o", "/var/log/edr boot.csv", "/opt/edr/boot.csv"].map(String::from);
+        assert_eq!(command_line(&arguments), "/opt/edr/edr_generator --boot -o \"/var/log/edr boot.csv\" /opt/edr/boot.csv");
+        let command = exec_start(&arguments);
+        assert_eq!(command, "/opt/edr/edr_generator --boot -o \"/var/log/edr boot.csv\" /opt/edr/boot.csv");
+        let unit = boot_unit(&command, "/opt/edr");
+        assert!(unit.contains("Before=systemd-user-sessions.service\n"));
+        assert!(unit.contains("\nType=simple\n"));
+        assert!(unit.contains("\nWorkingDirectory=/opt/edr\nExecStart=/opt/edr/edr_generator --boot"));
+        assert!(unit.ends_with("WantedBy=multi-user.target\n"));
+    }
+
+    #[test]
+    fn task_command_quoted() {
+        let arguments = [r"C:\EDR Tools\edr_generator.exe", "--boot", "-o", r"C:\logs dir\", "--summary", r#"say "hi""#, "", r"C:\edr\boot.csv"].map(String::from);
+        assert_eq!(command_line(&arguments), r#""C:\EDR Tools\edr_generator.exe" --boot -o "C:\logs dir\\" --summary "say \"hi\"" "" C:\edr\boot.csv"#);
+    }
+
+    #[test]
+    fn exec_start_escaped() {
+        let arguments = ["/opt/edr/edr_generator", "--summary", "/opt/100%/$HOME.json", r#"say "hi" C:\x"#, "", ";"].map(String::from);
+        assert_eq!(exec_start(&arguments), r#"/opt/edr/edr_generator --summary /opt/100%%/$$HOME.json "say \"hi\" C:\\x" "" ";""#);
+    }
+}
@@ -0,0 +1,598 @@
+use crate::modules::commander::TaskCommander;
+use crate::modules::common::GenerationError;
+use crate::modules::formatter::LogFormat;
+use crate::modules::logger::Logger;
+use crate::modules::scenario::join_arguments;
+use csv::StringRecord;
+use std::time::Duration;
+
+/// Structure defining a scenario assembled in code rather than read from an input file, for driving
+/// generation from Rust integration tests. Instructions are recorded in the order the methods are
+/// called and accept the same values, including `${...}` variables, as the csv input format.
+///
+/// ```no_run
+/// use edr_generator::modules::builder::ScenarioBuilder;
+///
+/// let report = ScenarioBuilder::new()
+///     .output("events.csv")
+///     .process("sh", &["-c", "exit"])
+///     .file_new("${TMP}/edr_builder.txt")
+///     .file_delete("${TMP}/edr_builder.txt")
+///     .run()
+///     .unwrap();
+/// assert_eq!(report.instructions, 3);
+/// ```
+///
+/// # Parameters
+///
+/// - `instructions`: instruction rows in the order they are executed
+/// - `output`: path of the file the event log is written to
+/// - `format`: format of the event log
+/// - `workers`: number of threads executing instructions, 1 executes them sequentially
+/// - `track_descendants`: whether processes started by child processes are tracked
+/// - `cleanup`: whether persistence artifacts are removed at the end of the run
+/// - `instruction_timeout`: optional number of milliseconds any instruction may take
+#[derive(Debug, Clone)]
+pub struct ScenarioBuilder {
+    instructions: Vec<StringRecord>,
+    output: String,
+    format: LogFormat,
+    workers: usize,
+    track_descendants: bool,
+    cleanup: bool,
+    instruction_timeout: Option<u64>,
+}
+
+/// Structure defining the outcome of a scenario run by a ScenarioBuilder
+///
+/// # Parameters
+///
+/// - `instructions`: number of instructions processed
+/// - `errors`: number of instructions which resulted in an error
+/// - `skipped`: number of instructions which were not executed (e.g. not supported on this platform)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunReport {
+    pub instructions: usize,
+    pub errors: usize,
+    pub skipped: usize,
+}
+
+impl ScenarioBuilder {
+    /// Instantiates an empty scenario logging csv records to `log.csv`
+    ///
+    /// # Returns
+    ///
+    /// ScenarioBuilder instance
+    pub fn new() -> ScenarioBuilder {
+        ScenarioBuilder {
+            instructions: Vec::new(),
+            output: "log.csv".to_string(),
+            format: LogFormat::Csv,
+            workers: 1,
+            track_descendants: false,
+            cleanup: false,
+            instruction_timeout: None,
+        }
+    }
+
+    /// Sets the path of the event log
+    pub fn output(mut self, path: &str) -> ScenarioBuilder {
+        self.output = path.to_string();
+        self
+    }
+
+    /// Sets the format of the event log
+    pub fn format(mut self, format: LogFormat) -> ScenarioBuilder {
+        self.format = format;
+        self
+    }
+
+    /// Sets the number of threads executing instructions, see `--workers`
+    pub fn workers(mut self, workers: usize) -> ScenarioBuilder {
+        self.workers = workers;
+        self
+    }
+
+    /// Sets whether processes started by child processes are tracked, see `--track-descendants`
+    pub fn track_descendants(mut self, enabled: bool) -> ScenarioBuilder {
+        self.track_descendants = enabled;
+        self
+    }
+
+    /// Sets whether persistence artifacts are removed at the end of the run, see `--cleanup`
+    pub fn cleanup(mut self, enabled: bool) -> ScenarioBuilder {
+        self.cleanup = enabled;
+        self
+    }
+
+    /// Sets the number of milliseconds any instruction may take, see `--instruction-timeout`
+    pub fn instruction_timeout(mut self, milliseconds: u64) -> ScenarioBuilder {
+        self.instruction_timeout = Some(milliseconds);
+        self
+    }
+
+    /// Adds a `process` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the executable to start
+    /// - `arguments`: arguments passed to the executable, each is quoted so it reaches the process
+    ///   unchanged even when it contains whitespace
+    pub fn process(self, path: &str, arguments: &[&str]) -> ScenarioBuilder {
+        let arguments = join_arguments(arguments.iter().copied());
+        self.instruction(&["process", path, &arguments])
+    }
+
+    /// Adds a `process_in_session` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `session`: ID of the session, name of the user logged on to it, or `other`
+    /// - `path`: path of the executable to start
+    /// - `arguments`: arguments passed to the executable, quoted as for `process`
+    pub fn process_in_session(self, session: &str, path: &str, arguments: &[&str]) -> ScenarioBuilder {
+        let arguments = join_arguments(arguments.iter().copied());
+        self.instruction(&["process_in_session", session, path, &arguments])
+    }
+
+    /// Adds a `new_file` instruction
+    pub fn file_new(self, path: &str) -> ScenarioBuilder {
+        self.instruction(&["new_file", path])
+    }
+
+    /// Adds a `mod_file` instruction
+    pub fn file_mod(self, path: &str) -> ScenarioBuilder {
+        self.instruction(&["mod_file", path])
+    }
+
+    /// Adds a `delete_file` instruction
+    pub fn file_delete(self, path: &str) -> ScenarioBuilder {
+        self.instruction(&["delete_file", path])
+    }
+
+    /// Adds a `connect` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `host`: destination IP address or hostname
+    /// - `port`: destination port
+    /// - `message`: data sent once connected
+    pub fn connect(self, host: &str, port: u16, message: &str) -> ScenarioBuilder {
+        let port = port.to_string();
+        self.instruction(&["connect", host, &port, message])
+    }
+
+    /// Adds a `fan_out` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `hosts`: destination IP addresses, hostnames, or IPv4 blocks, separated by spaces
+    /// - `ports`: destination ports or ranges of ports, separated by spaces
+    /// - `message`: data sent once connected
+    /// - `concurrency`: optional largest number of connections open at the same time
+    pub fn fan_out(self, hosts: &str, ports: &str, message: &str, concurrency: Option<usize>) -> ScenarioBuilder {
+        match concurrency {
+            Some(concurrency) => self.instruction(&["fan_out", hosts, ports, message, &format!("concurrency={}", concurrency)]),
+            None => self.instruction(&["fan_out", hosts, ports, message])
+        }
+    }
+
+    /// Adds a `connect_self` instruction
+    pub fn connect_self(self, message: &str) -> ScenarioBuilder {
+        self.instruction(&["connect_self", message])
+    }
+
+    /// Adds a `beacon` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `host`: destination IP address or hostname
+    /// - `port`: destination port
+    /// - `interval`: time between two beacons in milliseconds
+    /// - `count`: number of beacons
+    /// - `size`: optional payload size of each beacon in bytes, 64 when not given
+    /// - `jitter`: optional percentage (0-100) by which each interval randomly varies
+    pub fn beacon(self, host: &str, port: u16, interval: u64, count: u64, size: Option<usize>, jitter: Option<f64>) -> ScenarioBuilder {
+        let mut fields = vec!["beacon".to_string(), host.to_string(), port.to_string(), interval.to_string(), count.to_string()];
+        if size.is_some() || jitter.is_some() {
+            fields.push(size.map(|size| size.to_string()).unwrap_or_default());
+        }
+        if let Some(jitter) = jitter {
+            fields.push(jitter.to_string());
+        }
+        self.instruction(&fields.iter().map(|field| field.as_str()).collect::<Vec<&str>>())
+    }
+
+    /// Adds a `listen` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `interface`: local interface to listen on, `0.0.0.0` for every interface
+    /// - `port`: port to listen on, 0 for an OS assigned port
+    /// - `timeout`: optional time to wait for a connection in milliseconds
+    pub fn listen(self, interface: &str, port: u16, timeout: Option<u64>) -> ScenarioBuilder {
+        let port = port.to_string();
+        match timeout {
+            Some(timeout) => self.instruction(&["listen", interface, &port, &timeout.to_string()]),
+            None => self.instruction(&["listen", interface, &port])
+        }
+    }
+
+    /// Adds an `exfil` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the file sent
+    /// - `host`: destination IP address or hostname
+    /// - `port`: destination port
+    /// - `protocol`: `tcp` to send the contents as is, `http` to send them in a POST request
+    pub fn exfil(self, path: &str, host: &str, port: u16, protocol: &str) -> ScenarioBuilder {
+        let port = port.to_string();
+        self.instruction(&["exfil", path, host, &port, protocol])
+    }
+
+    /// Adds an `encrypt_burst` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `directory`: directory to create the decoy files in, must not exist yet
+    /// - `count`: number of decoy files
+    /// - `extension`: optional extension appended to the encrypted files, `locked` when not given
+    pub fn encrypt_burst(self, directory: &str, count: u64, extension: Option<&str>) -> ScenarioBuilder {
+        let count = count.to_string();
+        match extension {
+            Some(extension) => self.instruction(&["encrypt_burst", directory, &count, extension]),
+            None => self.instruction(&["encrypt_burst", directory, &count])
+        }
+    }
+
+    /// Adds an `ads_write` instruction (Windows only)
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the file hosting the stream, created when it does not exist
+    /// - `stream`: name of the alternate data stream
+    /// - `payload`: optional data written to the stream, a fixed text when not given
+    pub fn ads_write(self, path: &str, stream: &str, payload: Option<&str>) -> ScenarioBuilder {
+        match payload {
+            Some(payload) => self.instruction(&["ads_write", path, stream, payload]),
+            None => self.instruction(&["ads_write", path, stream])
+        }
+    }
+
+    /// Adds a `symlink` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `target`: path the link points to, which does not need to exist
+    /// - `link`: path of the new link
+    pub fn symlink(self, target: &str, link: &str) -> ScenarioBuilder {
+        self.instruction(&["symlink", target, link])
+    }
+
+    /// Adds a `hardlink` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `target`: path of the existing file
+    /// - `link`: path of the new link
+    pub fn hardlink(self, target: &str, link: &str) -> ScenarioBuilder {
+        self.instruction(&["hardlink", target, link])
+    }
+
+    /// Adds a `chmod_file` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the existing file
+    /// - `permissions`: octal mode bits on Unix (e.g. `0400`), or attributes on Windows (e.g. `readonly+hidden`)
+    pub fn chmod_file(self, path: &str, permissions: &str) -> ScenarioBuilder {
+        self.instruction(&["chmod_file", path, permissions])
+    }
+
+    /// Adds a `persist_runkey` instruction (Windows only)
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the value added to the `Run` registry key
+    /// - `command`: command started at logon
+    pub fn persist_runkey(self, name: &str, command: &str) -> ScenarioBuilder {
+        self.instruction(&["persist_runkey", name, command])
+    }
+
+    /// Adds a `persist_startup` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the startup folder file, without its extension
+    /// - `command`: command started at logon
+    pub fn persist_startup(self, name: &str, command: &str) -> ScenarioBuilder {
+        self.instruction(&["persist_startup", name, command])
+    }
+
+    /// Adds a `persist_cron` instruction (not supported on Windows)
+    ///
+    /// # Parameters
+    ///
+    /// - `schedule`: five field cron schedule, or a nickname such as `@reboot`
+    /// - `command`: command started on the schedule
+    pub fn persist_cron(self, schedule: &str, command: &str) -> ScenarioBuilder {
+        self.instruction(&["persist_cron", schedule, command])
+    }
+
+    /// Adds a `persist_service` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the service
+    /// - `command`: command the service starts
+    pub fn persist_service(self, name: &str, command: &str) -> ScenarioBuilder {
+        self.instruction(&["persist_service", name, command])
+    }
+
+    /// Adds a `schedule_task` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the task
+    /// - `trigger`: `onstart`, `minute`, `hourly`, `daily`, or `weekly`
+    /// - `command`: command the task starts
+    pub fn schedule_task(self, name: &str, trigger: &str, command: &str) -> ScenarioBuilder {
+        self.instruction(&["schedule_task", name, trigger, command])
+    }
+
+    /// Adds a `tamper_service` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `service`: name of the service of the security agent
+    pub fn tamper_service(self, service: &str) -> ScenarioBuilder {
+        self.instruction(&["tamper_service", service])
+    }
+
+    /// Adds a `tamper_files` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `directory`: install directory of the security agent
+    pub fn tamper_files(self, directory: &str) -> ScenarioBuilder {
+        self.instruction(&["tamper_files", directory])
+    }
+
+    /// Adds a `tamper_rename` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the binary of the security agent
+    pub fn tamper_rename(self, path: &str) -> ScenarioBuilder {
+        self.instruction(&["tamper_rename", path])
+    }
+
+    /// Adds a `create_user` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the local account
+    pub fn create_user(self, name: &str) -> ScenarioBuilder {
+        self.instruction(&["create_user", name])
+    }
+
+    /// Adds a `delete_user` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the local account
+    pub fn delete_user(self, name: &str) -> ScenarioBuilder {
+        self.instruction(&["delete_user", name])
+    }
+
+    /// Adds an `add_to_group` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the local account
+    /// - `group`: name of the local group
+    pub fn add_to_group(self, name: &str, group: &str) -> ScenarioBuilder {
+        self.instruction(&["add_to_group", name, group])
+    }
+
+    /// Adds a `wait_process` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `target`: alias (given with `alias=<name>`) or PID of the process to wait for
+    /// - `timeout`: number of milliseconds to wait before giving up, or `None` to wait until the
+    ///   process exits
+    pub fn wait_process(self, target: &str, timeout: Option<u64>) -> ScenarioBuilder {
+        match timeout {
+            Some(milliseconds) => self.instruction(&["wait_process", target, &milliseconds.to_string()]),
+            None => self.instruction(&["wait_process", target])
+        }
+    }
+
+    /// Adds a `pause` instruction
+    pub fn pause(self, milliseconds: u64) -> ScenarioBuilder {
+        let milliseconds = milliseconds.to_string();
+        self.instruction(&["pause", &milliseconds])
+    }
+
+    /// Adds an instruction given as the fields of a csv row, for instructions and options (such as
+    /// `run_for`, `stdin`, `capture`, `alias`, `latency`, `netns`, `timeout`, or `@field=value`
+    /// overrides) without a dedicated method
+    ///
+    /// # Parameters
+    ///
+    /// - `fields`: instruction name followed by its values
+    pub fn instruction(mut self, fields: &[&str]) -> ScenarioBuilder {
+        self.instructions.push(StringRecord::from(fields.to_vec()));
+        self
+    }
+
+    /// Executes every instruction, logging the results to the configured output
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Number of instructions processed and errors encountered
+    /// - `Err`: The worker threads could not be started
+    pub fn run(self) -> Result<RunReport, GenerationError> {
+        let logger = Logger::new(&self.output, self.format);
+        let mut commander = TaskCommander::from_records(self.instructions, "scenario builder".to_string(), logger);
+        if self.track_descendants {
+            commander.set_track_descendants();
+        }
+        if self.cleanup {
+            commander.set_cleanup();
+        }
+        if let Some(timeout) = self.instruction_timeout {
+            commander.set_instruction_timeout(Duration::from_millis(timeout));
+        }
+        if self.workers > 1 {
+            commander.set_workers(self.workers)?;
+        }
+        let mut instructions: usize = 0;
+        while commander.read_next() {
+            instructions += 1;
+        }
+        let summary = commander.log_summary(instructions);
+        Ok(RunReport { instructions, errors: commander.get_num_errors(), skipped: summary.skipped })
+    }
+}
+
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        ScenarioBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+    use std::fs;
+
+    #[test]
+    fn builder_rows() {
+        let builder = ScenarioBuilder::new()
+            .process("cmd", &["/c", "exit"])
+            .connect("127.0.0.1", 8080, "hello")
+            .fan_out("10.0.0.0/24", "22 445", "hello", Some(128))
+            .listen("0.0.0.0", 4444, Some(500))
+            .beacon("10.0.0.5", 443, 60000, 100, None, Some(20.0))
+            .exfil("secrets.txt", "10.0.0.5", 8080, "http")
+            .process_in_session("other", "sh", &["-c", "echo \"hello world\""])
+            .encrypt_burst("ransom", 20, Some("crypt"))
+            .ads_write("report.txt", "payload.exe", None)
+            .hardlink("report.txt", "report_link.txt")
+            .chmod_file("report.txt", "0400")
+            .persist_cron("@reboot", "/tmp/payload")
+            .schedule_task("EdrUpdate", "daily", "/tmp/payload")
+            .tamper_service("WinDefend")
+            .tamper_rename("/opt/agent/bin/agent")
+            .add_to_group("edr_test", "Administrators")
+            .wait_process("slow", Some(5000))
+            .pause(10);
+        assert_eq!(builder.instructions, vec![
+            StringRecord::from(vec!["process", "cmd", "/c exit"]),
+            StringRecord::from(vec!["connect", "127.0.0.1", "8080", "hello"]),
+            StringRecord::from(vec!["fan_out", "10.0.0.0/24", "22 445", "hello", "concurrency=128"]),
+            StringRecord::from(vec!["listen", "0.0.0.0", "4444", "500"]),
+            StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100", "", "20"]),
+            StringRecord::from(vec!["exfil", "secrets.txt", "10.0.0.5", "8080", "http"]),
+            StringRecord::from(vec!["process_in_session", "other", "sh", "-c 'echo \"hello world\"'"]),
+            StringRecord::from(vec!["encrypt_burst", "ransom", "20", "crypt"]),
+            StringRecord::from(vec!["ads_write", "report.txt", "payload.exe"]),
+            StringRecord::from(vec!["hardlink", "report.txt", "report_link.txt"]),
+            StringRecord::from(vec!["chmod_file", "report.txt", "0400"]),
+            StringRecord::from(vec!["persist_cron", "@reboot", "/tmp/payload"]),
+            StringRecord::from(vec!["schedule_task", "EdrUpdate", "daily", "/tmp/payload"]),
+            StringRecord::from(vec!["tamper_service", "WinDefend"]),
+            StringRecord::from(vec!["tamper_rename", "/opt/agent/bin/agent"]),
+            StringRecord::from(vec!["add_to_group", "edr_test", "Administrators"]),
+            StringRecord::from(vec!["wait_process", "slow", "5000"]),
+            StringRecord::from(vec!["pause", "10"]),
+        ]);
+    }
+
+    #[test]
+    fn builder_run() {
+        let output = TempPath::new("builder.csv");
+        let file = TempPath::new("builder.txt");
+        let path = file.as_string();
+        let report = ScenarioBuilder::new()
+            .output(&output.as_string())
+            .connect_self("hello world")
+            .file_mod("${TMP}/edr_builder_does_not_exist.txt")
+            .file_new(&path)
+            .file_delete(&path)
+            .run()
+            .unwrap();
+        let log = fs::read_to_string(&output).unwrap();
+        assert_eq!(report, RunReport { instructions: 4, errors: 1, skipped: 0 });
+        assert!(log.contains("Network Connection"));
+        assert!(log.contains("New File"));
+        assert!(log.contains("Run Summary"));
+    }
+
+    #[test]
+    fn instructions_timed_out() {
+        let output = TempPath::new("builder_timeout.csv");
+        let report = ScenarioBuilder::new()
+            .output(&output.as_string())
+            .instruction_timeout(5000)
+            .instruction(&["listen", "127.0.0.1", "0", "timeout=200"])
+            .connect_self("still running")
+            .instruction(&["connect_self", "hello", "timeout=soon"])
+            .run()
+            .unwrap();
+        let log = fs::read_to_string(&output).unwrap();
+        assert_eq!(report, RunReport { instructions: 3, errors: 2, skipped: 0 });
+        // the row's own timeout applies rather than the run wide one
+        assert!(log.contains("did not complete within 200 ms"));
+        assert!(log.contains("Listening Port Opened"));
+        assert!(log.contains("Network Connection"));
+    }
+
+    #[test]
+    fn fan_out_in_background() {
+        let output = TempPath::new("builder_fan_out.csv");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            std::io::Read::read_to_end(&mut stream, &mut received).unwrap();
+            received
+        });
+        let report = ScenarioBuilder::new()
+            .output(&output.as_string())
+            .instruction(&["fan_out", "127.0.0.1", &port.to_string(), "hello", "hold=2000"])
+            .connect_self("after fan-out")
+            .run()
+            .unwrap();
+        let log = fs::read_to_string(&output).unwrap();
+        assert_eq!(report, RunReport { instructions: 2, errors: 0, skipped: 0 });
+        assert_eq!(server.join().unwrap(), b"hello");
+        // the next row runs while the connection is held, so it is logged before the connection
+        let connection = log.find("(fan-out 1 of 1)").unwrap();
+        assert!(log.find("Network Connection,").unwrap() < connection);
+        assert!(connection < log.find("Run Summary").unwrap());
+    }
+
+    #[test]
+    fn unsupported_instructions_skipped() {
+        let output = TempPath::new("builder_skipped.csv");
+        let builder = ScenarioBuilder::new().output(&output.as_string());
+        let builder = if cfg!(windows) {
+            builder.persist_cron("@reboot", "cmd /c exit")
+        } else {
+            builder.persist_runkey("EdrBuilderSkipped", "/bin/true")
+        };
+        let report = builder.connect_self("hello world").run().unwrap();
+        let log = fs::read_to_string(&output).unwrap();
+        assert_eq!(report, RunReport { instructions: 2, errors: 0, skipped: 1 });
+        assert!(log.contains("Skipped,"));
+        assert!(log.contains("1 skipped"));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::modules::common::GenerationError;
+use crate::modules::scenario::{self, Step};
+use crate::modules::script::{self, Script};
+use chrono::{SecondsFormat, Utc};
+use csv::StringRecord;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::distributions::{WeightedIndex, Distribution};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::vec::IntoIter;
+
+/// Structure defining a campaign library, a YAML or JSON file listing the scenarios a campaign
+/// samples from
+///
+/// # Parameters
+///
+/// - `duration`: time the campaign keeps starting scenarios for (e.g. `8h`, `90m`, `30s`)
+/// - `min_gap`: minimum number of milliseconds to pause between two scenarios
+/// - `max_gap`: maximum number of milliseconds to pause between two scenarios
+/// - `scenarios`: scenarios to sample from along with their relative weights
+#[derive(Deserialize)]
+pub struct Library {
+    pub duration: String,
+    #[serde(default)]
+    pub min_gap: u64,
+    #[serde(default)]
+    pub max_gap: u64,
+    pub scenarios: Vec<LibraryEntry>,
+}
+
+/// Structure defining a scenario of a campaign library
+///
+/// # Parameters
+///
+/// - `path`: path of the csv, JSON, or YAML scenario, relative paths resolve against the directory
+///   of the library
+/// - `weight`: relative weight of the scenario, 0 disables it
+#[derive(Deserialize)]
+pub struct LibraryEntry {
+    pub path: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Structure defining the manifest of a campaign, recording which scenario ran when
+///
+/// # Parameters
+///
+/// - `library`: path of the campaign library
+/// - `seed`: seed the scenarios were selected with, reproduces the same selection
+/// - `duration_seconds`: time the campaign was configured to run for
+/// - `scenarios`: scenarios started by the campaign, in the order they ran
+#[derive(Serialize, Debug, Clone)]
+pub struct Manifest {
+    pub library: String,
+    pub seed: u64,
+    pub duration_seconds: u64,
+    pub scenarios: Vec<ManifestEntry>,
+}
+
+/// Structure defining a scenario started by a campaign
+///
+/// # Parameters
+///
+/// - `index`: number of the scenario in the campaign, starting at 1
+/// - `scenario`: path of the scenario
+/// - `weight`: relative weight the scenario was selected with
+/// - `started`: RFC 3339 time the first instruction of the scenario was read
+/// - `finished`: RFC 3339 time the last instruction of the scenario was processed, empty while it runs
+/// - `instructions`: number of instructions of the scenario processed
+/// - `completed`: true if every instruction of the scenario was processed
+/// - `error`: reason the scenario could not be run, empty if it was run
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub index: usize,
+    pub scenario: String,
+    pub weight: u32,
+    pub started: String,
+    pub finished: String,
+    pub instructions: u64,
+    pub completed: bool,
+    pub error: String,
+}
+
+impl Manifest {
+    /// Writes the manifest as JSON
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the manifest file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The manifest was written
+    /// - `Err`: The manifest could not be serialized or written
+    pub fn write(&self, path: &String) -> Result<(), GenerationError> {
+        match serde_json::to_string_pretty(self) {
+            Ok(inner) => Ok(fs::write(path, inner + "\n")?),
+            Err(_) => Err(GenerationError::new("logging".to_string(), "Unable to Serialize Campaign Manifest".to_string()))
+        }
+    }
+}
+
+/// An instruction row of a campaign along with the name of its structured step and its delay
+pub type Row = (StringRecord, Option<String>, Option<u64>);
+
+/// Instructions of the scenario currently executing in a campaign
+///
+/// # Variants
+///
+/// - `Csv`: rows of a csv scenario, including scripting constructs
+/// - `Structured`: remaining steps of a JSON or YAML scenario
+enum Running {
+    Csv(Script),
+    Structured(IntoIter<Step>),
+}
+
+/// Structure defining a campaign which repeatedly selects a scenario from a library by weight and
+/// executes it, until the duration of the campaign has elapsed. A scenario which is started always
+/// runs to completion, the campaign stops once no new scenario may be started.
+///
+/// # Parameters
+///
+/// - `library`: path of the campaign library
+/// - `deliminator`: deliminator used by csv scenarios
+/// - `paths`: resolved paths of the scenarios of the library
+/// - `weights`: weights of the scenarios, set to 0 once a scenario fails to load
+/// - `choices`: distribution used to select the next scenario
+/// - `rng`: seeded random number generator, the same seed selects the same scenarios
+/// - `seed`: seed of the random number generator
+/// - `duration`: time the campaign keeps starting scenarios for
+/// - `gap`: minimum and maximum milliseconds to pause between two scenarios
+/// - `started`: time the campaign read its first instruction
+/// - `running`: scenario currently executing
+/// - `pending_gap`: true if a pause must be inserted before the next scenario is started
+/// - `entries`: scenarios started so far
+pub struct Campaign {
+    library: String,
+    deliminator: u8,
+    paths: Vec<String>,
+    weights: Vec<u32>,
+    choices: Option<WeightedIndex<u32>>,
+    rng: StdRng,
+    seed: u64,
+    duration: Duration,
+    gap: (u64, u64),
+    started: Option<Instant>,
+    running: Option<Running>,
+    pending_gap: bool,
+    entries: Vec<ManifestEntry>,
+}
+
+impl Campaign {
+    /// Reads a campaign library. Files with a `.json` extension are read as JSON, all others as
+    /// YAML. Every scenario of the library is parsed up front so a broken scenario is reported
+    /// before the campaign starts rather than hours into it.
+    ///
+    /// # Parameters
+    ///
+    /// - `library`: path of the campaign library
+    /// - `deliminator`: deliminator used by csv scenarios
+    /// - `seed`: seed of the random number generator selecting the scenarios
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Campaign instance
+    /// - `Err`: The library or one of its scenarios could not be read, or no scenario has a weight
+    pub fn load(library: &String, deliminator: u8, seed: u64) -> Result<Campaign, GenerationError> {
+        let contents = fs::read_to_string(library)?;
+        let parsed = if library.to_lowercase().ends_with(".json") {
+            serde_json::from_str::<Library>(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_str::<Library>(&contents).map_err(|e| e.to_string())
+        };
+        let parsed = match parsed {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid campaign library: {}", library, e)))
+        };
+        let duration = parse_duration(&parsed.duration)?;
+        if parsed.min_gap > parsed.max_gap {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} has a min_gap greater than its max_gap", library)));
+        }
+        let directory = Path::new(library).parent().unwrap_or_else(|| Path::new(""));
+        let paths: Vec<String> = parsed.scenarios.iter()
+            .map(|entry| directory.join(&entry.path).to_string_lossy().to_string())
+            .collect();
+        let weights: Vec<u32> = parsed.scenarios.iter().map(|entry| entry.weight).collect();
+        let choices = match WeightedIndex::new(&weights) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("{} has no scenario to select: {}", library, e)))
+        };
+        for (path, _) in paths.iter().zip(&weights).filter(|(_, weight)| **weight > 0) {
+            if !has_instructions(&mut open(path, deliminator)?) {
+                return Err(GenerationError::new("input_format".to_string(), format!("{} has no instructions", path)));
+            }
+        }
+        Ok(Campaign {
+            library: library.clone(),
+            deliminator,
+            paths,
+            weights,
+            choices: Some(choices),
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            duration,
+            gap: (parsed.min_gap, parsed.max_gap),
+            started: None,
+            running: None,
+            pending_gap: false,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Retrieves the time the campaign keeps starting scenarios for
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Retrieves the number of scenarios the campaign may select
+    pub fn scenario_count(&self) -> usize {
+        self.weights.iter().filter(|weight| **weight > 0).count()
+    }
+
+    /// Retrieves the next instruction of the campaign, starting a new scenario when the current
+    /// one is complete and the duration has not elapsed. A `pause` row is produced between two
+    /// scenarios when the library sets a gap, it never extends past the end of the campaign.
+    ///
+    /// # Returns
+    ///
+    /// The next row along with the name of its structured step and its delay, an error when a
+    /// selected scenario could not be read, or None when the campaign is complete
+    pub fn next_row(&mut self) -> Option<Result<Row, GenerationError>> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        loop {
+            if let Some(row) = self.running.as_mut().and_then(next_instruction) {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.instructions += 1;
+                }
+                return Some(Ok(row));
+            }
+            if self.running.take().is_some() {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.finished = timestamp();
+                    entry.completed = true;
+                }
+            }
+            let remaining = self.duration.checked_sub(started.elapsed()).filter(|remaining| !remaining.is_zero())?;
+            if self.pending_gap {
+                self.pending_gap = false;
+                let gap = self.rng.gen_range(self.gap.0..=self.gap.1).min(remaining.as_millis() as u64);
+                if gap > 0 {
+                    return Some(Ok((StringRecord::from(vec!["pause".to_string(), gap.to_string()]), None, None)));
+                }
+            }
+            let index = self.choices.as_ref()?.sample(&mut self.rng);
+            self.pending_gap = true;
+            let mut entry = ManifestEntry {
+                index: self.entries.len() + 1,
+                scenario: self.paths[index].clone(),
+                weight: self.weights[index],
+                started: timestamp(),
+                finished: "".to_string(),
+                instructions: 0,
+                completed: false,
+                error: "".to_string(),
+            };
+            match open(&self.paths[index], self.deliminator) {
+                Ok(running) => {
+                    self.running = Some(running);
+                    self.entries.push(entry);
+                }
+                Err(e) => {
+                    // the scenario changed since the library was loaded, it is not selected again
+                    self.weights[index] = 0;
+                    if self.choices.as_mut().is_none_or(|choices| choices.update_weights(&[(index, &0)]).is_err()) {
+                        self.choices = None;
+                    }
+                    entry.finished = entry.started.clone();
+                    entry.error = e.message.clone();
+                    self.entries.push(entry);
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Builds the manifest of the scenarios started so far
+    ///
+    /// # Returns
+    ///
+    /// Manifest of the campaign, the scenario still executing has no finish time
+    pub fn manifest(&self) -> Manifest {
+        Manifest {
+            library: self.library.clone(),
+            seed: self.seed,
+            duration_seconds: self.duration.as_secs(),
+            scenarios: self.entries.clone(),
+        }
+    }
+}
+
+/// Parses a campaign duration, a number followed by `ms`, `s`, `m`, or `h` (e.g. `8h`, `90m`)
+///
+/// # Parameters
+///
+/// - `value`: duration to parse
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The duration, greater than 0
+/// - `Err`: The value is not a valid duration
+pub fn parse_duration(value: &str) -> Result<Duration, GenerationError> {
+    let value = value.trim().to_lowercase();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let milliseconds = match (number.parse::<u64>(), unit.trim()) {
+        (Ok(number), "ms") => Some(number),
+        (Ok(number), "s") => number.checked_mul(1000),
+        (Ok(number), "m") => number.checked_mul(60 * 1000),
+        (Ok(number), "h") => number.checked_mul(60 * 60 * 1000),
+        _ => None
+    };
+    match milliseconds {
+        Some(milliseconds) if milliseconds > 0 => Ok(Duration::from_millis(milliseconds)),
+        _ => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid campaign duration, expected e.g. 8h, 90m, or 30s", value)))
+    }
+}
+
+/// Reads a scenario of the library
+fn open(path: &String, deliminator: u8) -> Result<Running, GenerationError> {
+    if scenario::is_structured(path) {
+        let contents = fs::read_to_string(path)?;
+        Ok(Running::Structured(scenario::parse(path, &contents)?.steps.into_iter()))
+    } else {
+        Ok(Running::Csv(Script::new(path, deliminator)?))
+    }
+}
+
+/// Determines if a scenario produces at least one instruction, a campaign selecting an empty
+/// scenario would never make progress
+fn has_instructions(running: &mut Running) -> bool {
+    next_instruction(running).is_some()
+}
+
+/// Retrieves the next instruction of a scenario, with variables substituted
+fn next_instruction(running: &mut Running) -> Option<Row> {
+    match running {
+        Running::Csv(script) => script.next_row().map(|(record, index)| (script::substitute(&record, index), None, None)),
+        Running::Structured(steps) => steps.next().map(|step| (script::substitute(&step.to_record(), 0), step.name, step.delay))
+    }
+}
+
+/// Formats the current time for the manifest
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn library(name: &str, contents: &str) -> String {
+        let directory = env::temp_dir().join(format!("edr_campaign_{}", name));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("quiet.csv"), "pause,1\n").unwrap();
+        fs::write(directory.join("noisy.yaml"), "steps:\n  - name: first\n    instruction: pause\n    duration: 1\n  - instruction: pause\n    duration: 1\n").unwrap();
+        let path = directory.join("library.yaml");
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn rows(campaign: &mut Campaign, limit: usize) -> Vec<StringRecord> {
+        let mut rows = vec![];
+        while let Some(row) = campaign.next_row() {
+            rows.push(row.unwrap().0);
+            if rows.len() == limit {
+                break;
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn durations_parsed() {
+        assert_eq!(parse_duration("8h").unwrap(), Duration::from_secs(8 * 60 * 60));
+        assert_eq!(parse_duration("90m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_duration(" 30 S").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("8").is_err());
+        assert!(parse_duration("eight hours").is_err());
+    }
+
+    #[test]
+    fn library_validated() {
+        assert!(Campaign::load(&library("invalid_duration", "duration: forever\nscenarios:\n  - path: quiet.csv\n"), b',', 1).is_err());
+        assert!(Campaign::load(&library("no_weight", "duration: 1h\nscenarios:\n  - path: quiet.csv\n    weight: 0\n"), b',', 1).is_err());
+        assert!(Campaign::load(&library("missing", "duration: 1h\nscenarios:\n  - path: missing.csv\n"), b',', 1).is_err());
+        assert!(Campaign::load(&library("gap", "duration: 1h\nmin_gap: 10\nmax_gap: 5\nscenarios:\n  - path: quiet.csv\n"), b',', 1).is_err());
+        let campaign = Campaign::load(&library("valid", "duration: 1h\nscenarios:\n  - path: quiet.csv\n  - path: noisy.yaml\n    weight: 3\n  - path: missing.csv\n    weight: 0\n"), b',', 1).unwrap();
+        assert_eq!(campaign.scenario_count(), 2);
+        assert_eq!(campaign.duration(), Duration::from_secs(60 * 60));
+    }
+
+    #[test]
+    fn selection_reproducible() {
+        let path = library("seeded", "duration: 1h\nmin_gap: 1\nmax_gap: 5\nscenarios:\n  - path: quiet.csv\n  - path: noisy.yaml\n    weight: 3\n");
+        let mut first = Campaign::load(&path, b',', 42).unwrap();
+        let mut second = Campaign::load(&path, b',', 42).unwrap();
+        assert_eq!(rows(&mut first, 200), rows(&mut second, 200));
+        let manifest = first.manifest();
+        assert_eq!(manifest.seed, 42);
+        let scenarios: Vec<&String> = manifest.scenarios.iter().map(|entry| &entry.scenario).collect();
+        assert_eq!(scenarios, second.manifest().scenarios.iter().map(|entry| &entry.scenario).collect::<Vec<&String>>());
+        let noisy = scenarios.iter().filter(|scenario| scenario.ends_with("noisy.yaml")).count();
+        assert!(noisy > scenarios.len() / 2);
+        assert!(manifest.scenarios.iter().rev().skip(1).all(|entry| entry.completed && !entry.finished.is_empty()));
+    }
+
+    #[test]
+    fn stops_after_duration() {
+        let mut campaign = Campaign::load(&library("duration", "duration: 50ms\nmin_gap: 20\nmax_gap: 20\nscenarios:\n  - path: quiet.csv\n"), b',', 7).unwrap();
+        let started = Instant::now();
+        let mut rows = 0;
+        while let Some(row) = campaign.next_row() {
+            let row = row.unwrap().0;
+            if &row[0] == "pause" {
+                std::thread::sleep(Duration::from_millis(row[1].parse().unwrap()));
+            }
+            rows += 1;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(rows > 1);
+        let manifest = campaign.manifest();
+        assert!(manifest.scenarios.iter().all(|entry| entry.completed && entry.instructions == 1));
+        assert_eq!(manifest.duration_seconds, 0);
+    }
+}
@@ -1,35 +1,285 @@
-use crate::modules::process::ProcessManager;
+use crate::modules::process::{ProcessManager, ProcessOptions};
+use crate::modules::instruction::{FileCmd, FileOperation, Instruction, NetworkCmd, PauseCmd, ProcessCmd};
 use crate::modules::file_system;
-use crate::modules::network;
-use std::time::Duration;
-use crate::modules::logger::{Logger};
-use csv::{ReaderBuilder, Reader, StringRecord};
-use std::fs::File;
-use crate::modules::common::GenerationError;
-use std::thread;
+use crate::modules::network::{self, Shaping};
+use std::time::{Duration, Instant};
+use crate::modules::logger::{self, Logger, Log};
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter};
+use crate::modules::common::{GenerationError, hash_file};
+use crate::modules::scenario::{self, Step};
+use crate::modules::script::{self, Script};
+use crate::modules::synthetic::{RandomScenario, Weights};
+use crate::modules::campaign::{Campaign, Manifest};
+use crate::modules::ingest::LoggedEvent;
+use crate::modules::alias::Aliases;
+use crate::modules::rate::RateLimiter;
+use crate::modules::worker::WorkerPool;
+use crate::modules::report::RunSummary;
+use crate::modules::inventory::HostInventory;
+use crate::modules::signature::TrustedKey;
+use crate::modules::policy::Permissions;
+use crate::modules::workdir::WorkDir;
+use crate::modules::persistence::{self, Artifact};
+use crate::modules::tamper;
+use crate::modules::account;
+use crate::modules::events::{EventBus, RunEvent};
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::vec::IntoIter;
+use std::io::Read;
+use std::fs;
+use std::env;
+use std::path::Path;
+use std::convert::TryFrom;
+use rand::Rng;
 
-/// Structure defining the Logger Class
+/// Source of the instructions processed by the TaskCommander
+///
+/// # Variants
+///
+/// - `Csv`: rows of a csv scenario file, including scripting constructs
+/// - `Structured`: remaining steps of a JSON or YAML scenario file
+/// - `Random`: randomly generated instructions
+/// - `Records`: instructions built programmatically
+/// - `Stream`: csv instructions read from a stream (e.g. stdin) as they arrive
+/// - `Campaign`: scenarios selected by weight from a campaign library over a period of time
+enum ScenarioSource {
+    Csv(Script),
+    Structured(IntoIter<Step>),
+    Random(Box<RandomScenario>),
+    Records(IntoIter<StringRecord>),
+    Stream(StringRecordsIntoIter<Box<dyn Read>>),
+    Campaign(Box<Campaign>),
+}
+
+/// Structure defining an instruction queued for a worker thread
+///
+/// # Parameters
+///
+/// - `record`: instruction to execute, without overrides
+/// - `index`: number of the instruction in the run, starting at 1
+/// - `step`: name of the structured scenario step the instruction belongs to
+/// - `overrides`: log field values supplied by the instruction
+/// - `save`: optional name the events logged by the instruction are saved as
+/// - `timeout`: optional time the instruction may take before it is abandoned
+struct Job {
+    record: StringRecord,
+    index: usize,
+    step: Option<String>,
+    overrides: Vec<(String, String)>,
+    save: Option<String>,
+    timeout: Option<Duration>,
+}
+
+/// Default wait before the first retry of a `connect` instruction which sets `retries` without
+/// `backoff`
+const DEFAULT_BACKOFF_MS: u64 = 500;
+
+/// Extension appended to the files of an `encrypt_burst` instruction when none is given
+pub(crate) const DEFAULT_ENCRYPTED_EXTENSION: &str = "locked";
+
+/// Number of times the text of a decoy file is repeated, about 4 KiB per file
+const DECOY_REPEAT: usize = 128;
+
+/// Interval a process and its output are polled at while waiting for the process to exit, see
+/// `capture=<bytes>` and `wait_process`
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Format of `wait_process` instructions, reported when an instruction does not match it
+pub(crate) const WAIT_FORMAT: &str = "a process wait (wait_process,<alias|pid>,[timeout_ms])";
+
+/// Format of `beacon` instructions, reported when an instruction does not match it
+pub(crate) const BEACON_FORMAT: &str = "a beacon (beacon,<destination_host>,<destination_port>,<interval_ms>,<count>,[payload_size],[jitter_percent])";
+
+/// Format of the shaping parameters of a network instruction, see `split_shaping`
+pub(crate) const SHAPING_FORMAT: &str = "network shaping (latency=<msec>,loss=<percent>,bandwidth=<bytes_per_second>,loss_seed=<number>,connect_timeout=<msec>)";
+
+/// Instructions which accept a trailing `netns=<name>` parameter, see `split_netns`
+pub(crate) const NETWORK_INSTRUCTIONS: [&str; 6] = ["connect", "connect_self", "listen", "beacon", "exfil", "fan_out"];
+
+/// Instructions which change or remove existing state of the host, or persist on it. With
+/// `--write-ahead` an `Intent` record is synced to disk before they are executed.
+pub(crate) const DESTRUCTIVE_INSTRUCTIONS: [&str; 18] = ["mod_file", "delete_file", "encrypt_burst", "ads_write", "chmod_file",
+    "persist_runkey", "persist_startup", "persist_cron", "persist_service", "schedule_task", "tamper_service", "tamper_files",
+    "tamper_rename", "create_user", "delete_user", "add_to_group", "symlink", "hardlink"];
+
+/// Platforms an instruction may be restricted to with a trailing `os=<platform>` parameter, see
+/// `split_os`. `unix` matches every platform other than Windows.
+pub(crate) const PLATFORMS: [&str; 4] = ["windows", "linux", "macos", "unix"];
+
+/// Error kinds which mean an instruction was not executed rather than failed: it is not supported
+/// on this platform, the generator lacks the privileges, the policy filtered it out, or it depends on
+/// the result of an instruction which failed
+const SKIP_KINDS: [&str; 4] = ["unsupported_platform", "user_permissions", "policy", "dependency"];
+
+/// Structure defining how a failed `connect` instruction is retried
+///
+/// # Parameters
+///
+/// - `retries`: number of attempts made after the first one fails
+/// - `backoff`: wait before the first retry in milliseconds, doubled before every further retry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RetryPolicy {
+    pub(crate) retries: u32,
+    pub(crate) backoff: u64,
+}
+
+/// Structure defining the cadence of a `beacon` instruction
+///
+/// # Parameters
+///
+/// - `host`: destination IP address or hostname
+/// - `port`: destination port
+/// - `interval`: time between two beacons in milliseconds
+/// - `count`: number of beacons sent
+/// - `size`: size of the payload of each beacon in bytes
+/// - `jitter`: fraction (0-1) by which each interval is randomly shortened or lengthened
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Beacon {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) interval: u64,
+    pub(crate) count: u64,
+    pub(crate) size: usize,
+    pub(crate) jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Builds the retry policy of a `connect` instruction from its named parameters
+    ///
+    /// # Parameters
+    ///
+    /// - `retries`: value of the `retries=<count>` parameter, if given
+    /// - `backoff`: value of the `backoff=<msec>` parameter, if given
+    ///
+    /// # Returns
+    ///
+    /// The retry policy if either parameter was given, `backoff` defaulting to `DEFAULT_BACKOFF_MS`
+    pub(crate) fn from_parameters(retries: Option<u64>, backoff: Option<u64>) -> Option<RetryPolicy> {
+        if retries.is_none() && backoff.is_none() {
+            return None;
+        }
+        Some(RetryPolicy {
+            retries: u32::try_from(retries.unwrap_or(0)).unwrap_or(u32::MAX),
+            backoff: backoff.unwrap_or(DEFAULT_BACKOFF_MS),
+        })
+    }
+
+    /// Determines the wait before a retry
+    ///
+    /// # Parameters
+    ///
+    /// - `attempt`: number of the attempt which failed, starting at 1
+    ///
+    /// # Returns
+    ///
+    /// Time to wait before the next attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << (attempt.max(1) - 1).min(16);
+        Duration::from_millis(self.backoff.saturating_mul(factor))
+    }
+}
+
+/// Structure defining the state needed to execute instructions. The process manager, logger, and
+/// error count are shared so an Executor may be cloned for every worker thread.
 ///
 /// # Parameters
 ///
-/// - `reader`: CSV Reader used for reading input commands in csv format
-/// - `process_manager`: process_manager instance to handle process event commands
+/// - `process_manager`: process_manager instance to handle process event commands, declared first
+///   so child processes are stopped before the working directory is removed
 /// - `logger`: Logger instance to handle logging of events
 /// - `errors_encountered`: number of errors encountered during commanding
+/// - `error_kinds`: number of errors encountered during commanding by error kind
+/// - `skip_reasons`: number of instructions skipped during commanding by reason
+/// - `events`: channel the progress of the run is reported over instead of the console
+/// - `index`: number of the instruction being processed, starting at 1
+/// - `current_step`: name of the structured scenario step being processed
+/// - `overrides`: log field values supplied by the instruction being processed
+/// - `save`: optional name the events logged by the instruction being processed are saved as
+/// - `results`: events logged by the instructions which saved their result, by name, see
+///   `script::substitute_results`
+/// - `timers`: threads completing instructions in the background, waiting to stop processes
+///   started with `run_for` or making the connections of `fan_out` rows
+/// - `interrupted`: set when the run is interrupted, ends pauses, listeners, and `run_for` waits early
+/// - `cancelled`: set when the instruction being processed timed out, ends its pauses, listeners,
+///   and retries early
+/// - `permissions`: optional instructions the controller of a remote run may trigger
+/// - `workdir`: optional directory relative paths of the run resolve inside
+/// - `cleanup`: persistence artifacts created by the run, removed at the end of the run when set
+/// - `write_ahead`: whether destructive instructions are preceded by an `Intent` record and
+///   followed by a `Completed` record
+/// - `outcome`: how the instruction being processed failed or why it was skipped, if it did not succeed
+#[derive(Clone)]
+struct Executor {
+    process_manager: Option<Arc<Mutex<ProcessManager>>>,
+    logger: Arc<Mutex<Logger>>,
+    errors_encountered: Arc<AtomicUsize>,
+    error_kinds: Arc<Mutex<BTreeMap<String, usize>>>,
+    skip_reasons: Arc<Mutex<BTreeMap<String, usize>>>,
+    events: EventBus,
+    index: usize,
+    current_step: Option<String>,
+    overrides: Vec<(String, String)>,
+    save: Option<String>,
+    results: Arc<Mutex<HashMap<String, Log>>>,
+    timers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    interrupted: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+    permissions: Option<Arc<Permissions>>,
+    workdir: Option<Arc<WorkDir>>,
+    cleanup: Option<Arc<Mutex<Vec<Artifact>>>>,
+    write_ahead: bool,
+    outcome: Option<String>,
+}
+
+/// Structure defining the Logger Class
+///
+/// # Parameters
+///
+/// - `source`: CSV Script or structured scenario steps used for reading input commands
+/// - `executor`: executes instructions on the current thread
+/// - `workers`: optional pool of threads executing instructions concurrently
+/// - `scenario`: path of the input scenario file, or a description of a randomized scenario
+/// - `scenario_hash`: SHA-256 digest of the input scenario file (empty for randomized scenarios)
+/// - `rate_limiter`: optional limit on the rate instructions are executed at
+/// - `started`: time the run started
+/// - `inventory`: inventory of the host captured when the run started
+/// - `instruction_counts`: number of instructions read by instruction name
+/// - `instruction_timeout`: optional time any instruction may take, unless it sets its own
+/// - `sampler`: optional thread sampling the resource usage of child processes, and the flag which
+///   stops it
+/// - `flusher`: optional thread flushing the output every flush interval, and the flag which stops
+///   it
+/// - `aliases`: alternative names of instructions, resolved as each instruction is read
 pub struct TaskCommander {
-    reader: Reader<File>,
-    process_manager: Option<ProcessManager>,
-    logger: Logger,
-    errors_encountered: usize,
+    source: ScenarioSource,
+    executor: Executor,
+    workers: Option<WorkerPool<Job>>,
+    scenario: String,
+    scenario_hash: String,
+    rate_limiter: Option<RateLimiter>,
+    started: Instant,
+    inventory: HostInventory,
+    instruction_counts: BTreeMap<String, usize>,
+    instruction_timeout: Option<Duration>,
+    sampler: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    flusher: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    aliases: Aliases,
 }
 
 impl TaskCommander {
-    /// Instantiates the Commander with specific information about the input CSV
+    /// Instantiates the Commander with specific information about the input file. Files with a
+    /// `.json`, `.yaml`, or `.yml` extension are read as structured scenarios, all others as csv.
+    /// The input file is hashed and a run header containing the digest is logged.
     ///
     /// # Parameters
     ///
-    /// - `path`: path for the input file where the csv data will be retrieved
-    /// - `deliminator`: deliminator that will be used when reading the csv file
+    /// - `path`: path for the input file where the scenario data will be retrieved
+    /// - `deliminator`: deliminator that will be used when reading a csv file
     /// - `logger`: logger instance to use for logging
     ///
     /// # Returns
@@ -39,218 +289,2699 @@ impl TaskCommander {
     /// - `Ok`: TaskCommander Instance
     /// - `Err`: Error in reading the input file
     pub fn new(path: &String, deliminator: u8, logger: Logger) -> Result<TaskCommander, GenerationError> {
-        Ok(TaskCommander {
-            reader: match ReaderBuilder::new().delimiter(deliminator).has_headers(false).flexible(true).from_path(path) {
-                Ok(inner) => inner,
-                Err(e) => return Err(GenerationError::new("io".to_string(), format!("The following error was encountered when attempting to open {} for processing: {}", path, e.to_string())))
-            },
-            process_manager: match ProcessManager::new() {
-                Ok(inner) => Some(inner),
-                Err(_) => None
-            },
-            logger,
-            errors_encountered: 0,
-        })
+        TaskCommander::open(path, deliminator, None, logger)
     }
 
-    /// Retrieves the number of errors TaskCommander has encountered
+    /// Instantiates the Commander like `new`, but refuses to run the scenario unless the input file
+    /// and every file it includes are signed with the trusted key. Signatures are verified against
+    /// the contents as they were read for execution, before the run header is logged.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path for the input file where the scenario data will be retrieved
+    /// - `deliminator`: deliminator that will be used when reading a csv file
+    /// - `key`: public key the scenario files must be signed with
+    /// - `logger`: logger instance to use for logging
     ///
     /// # Returns
     ///
-    /// The number of errors encountered
-    pub fn get_num_errors(self) -> usize {
-        self.errors_encountered
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: TaskCommander Instance
+    /// - `Err`: Error in reading the input file, or a file is not signed with the key
+    pub fn new_signed(path: &String, deliminator: u8, key: &TrustedKey, logger: Logger) -> Result<TaskCommander, GenerationError> {
+        TaskCommander::open(path, deliminator, Some(key), logger)
     }
 
+    /// Reads the input file, verifying its signatures when a key is given, and instantiates the
+    /// Commander
+    fn open(path: &String, deliminator: u8, key: Option<&TrustedKey>, logger: Logger) -> Result<TaskCommander, GenerationError> {
+        let scenario_hash = match hash_file(path) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("io".to_string(), format!("The following error was encountered when attempting to hash {}: {}", path, e.message)))
+        };
+        let mut description = "".to_string();
+        let source = if scenario::is_structured(path) {
+            let contents = fs::read(path)?;
+            if let Some(key) = key {
+                key.verify(path, &contents)?;
+            }
+            let structured = scenario::parse(path, &String::from_utf8_lossy(&contents))?;
+            description = structured.description.unwrap_or_default();
+            ScenarioSource::Structured(structured.steps.into_iter())
+        } else {
+            let script = Script::new(path, deliminator)?;
+            if let Some(key) = key {
+                for (file, contents) in script.files() {
+                    key.verify(file, contents)?;
+                }
+            }
+            ScenarioSource::Csv(script)
+        };
+        Ok(TaskCommander::from_source(source, path.clone(), scenario_hash, description, logger))
+    }
 
-    /// Reads the next entry in the command list and processes the instructions
+    /// Instantiates the Commander with a randomized scenario instead of an input file. The seed is
+    /// recorded in the run header so the same events can be reproduced.
+    ///
+    /// # Parameters
+    ///
+    /// - `count`: number of events to generate
+    /// - `weights`: relative weights of the process, file, and network event categories
+    /// - `seed`: seed of the random number generator
+    /// - `logger`: logger instance to use for logging
     ///
     /// # Returns
     ///
-    /// A boolean representing if there is an entry to be processed. False returned when EOF.
-    pub fn read_next(&mut self) -> bool {
-        if let Some(result) = self.reader.records().next() {
-            let new_record = result.unwrap();
-            match &new_record[0] {
-                "process" => self.run_process(new_record),
-                "pause" => self.pause(new_record),
-                "new_file" | "mod_file" | "delete_file" => self.file_system(new_record),
-                "connect" | "connect_self" => self.network(new_record),
-                _ => self.error_print(GenerationError::new("input_format".to_string(), format!("{} is not a valid instruction)", &new_record[0])))
-            }
-            true
-        } else {
-            false
-        }
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: TaskCommander Instance
+    /// - `Err`: The weights can not be used for sampling
+    pub fn new_random(count: u64, weights: &Weights, seed: u64, logger: Logger) -> Result<TaskCommander, GenerationError> {
+        let source = ScenarioSource::Random(Box::new(RandomScenario::new(count, weights, seed)?));
+        let scenario = format!("random({} events, seed {})", count, seed);
+        let description = format!("weights: process={}, file={}, network={}", weights.process, weights.file, weights.network);
+        Ok(TaskCommander::from_source(source, scenario, "".to_string(), description, logger))
     }
 
-    /// Runs a process by verifying the providing instructions, formatting data, and logging
+    /// Instantiates the Commander with a campaign which selects scenarios from a library by weight
+    /// until the duration of the library has elapsed, see `campaign_manifest`. The seed is recorded
+    /// in the run header so the same selection can be reproduced.
     ///
     /// # Parameters
     ///
-    /// - `params`: a StringRecord representing the row within the CSV document containing
-    /// instructions on how to create the process
+    /// - `library`: path of the campaign library
+    /// - `deliminator`: deliminator used by csv scenarios of the library
+    /// - `seed`: seed of the random number generator selecting the scenarios
+    /// - `logger`: logger instance to use for logging
     ///
     /// # Returns
     ///
-    /// Nothing
+    /// A `Result` which is:
     ///
-    /// # Panics
+    /// - `Ok`: TaskCommander Instance
+    /// - `Err`: The library or one of its scenarios could not be read
+    pub fn new_campaign(library: &String, deliminator: u8, seed: u64, logger: Logger) -> Result<TaskCommander, GenerationError> {
+        let library_hash = match hash_file(library) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("io".to_string(), format!("The following error was encountered when attempting to hash {}: {}", library, e.message)))
+        };
+        let campaign = Campaign::load(library, deliminator, seed)?;
+        let scenario = format!("campaign({}, seed {})", library, seed);
+        let description = format!("{} scenario(s) over {}s", campaign.scenario_count(), campaign.duration().as_secs());
+        Ok(TaskCommander::from_source(ScenarioSource::Campaign(Box::new(campaign)), scenario, library_hash, description, logger))
+    }
+
+    /// Instantiates the Commander with instructions built programmatically, e.g. by a ScenarioBuilder.
+    /// Each record is processed exactly like a row of a csv scenario.
     ///
-    /// Should not panic as all errors are sent to the error logger.
-    fn run_process(&mut self, params: StringRecord) {
-        // check if process_manager is available
-        if self.process_manager.is_none() {
-            self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()));
-            return;
-        }
-        //ensure correct number of parameters have been provided
-        if params.len() < 2 {
-            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process (process,<path>,[arguments...])", params)));
-            return;
-        }
-        let mut arguments = None;
-        if params.len() > 2 {
-            //concatenate additional parameter into a single space separated string to be used as process arguments
-            let mut arguments_str = "".to_string();
-            for index in 2..params.len() {
-                arguments_str.push_str(&format!("{} ", &params[index]));
-            }
-            arguments = Some(arguments_str);
+    /// # Parameters
+    ///
+    /// - `records`: instructions to process, in order
+    /// - `description`: free text describing the scenario, recorded in the run header
+    /// - `logger`: logger instance to use for logging
+    ///
+    /// # Returns
+    ///
+    /// TaskCommander Instance
+    pub fn from_records(records: Vec<StringRecord>, description: String, logger: Logger) -> TaskCommander {
+        let scenario = format!("builder({} instructions)", records.len());
+        TaskCommander::from_source(ScenarioSource::Records(records.into_iter()), scenario, "".to_string(), description, logger)
+    }
+
+    /// Instantiates the Commander with csv instructions read from a stream, e.g. stdin. Each row is
+    /// executed as soon as it is read and every record is flushed to the output file once logged,
+    /// so instructions can be piped from another tool or typed by an operator. Scripting constructs
+    /// (`repeat`, `include`) are not supported.
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the stream, recorded as the scenario in the run header
+    /// - `reader`: stream the instructions are read from
+    /// - `deliminator`: deliminator used by the instructions
+    /// - `logger`: logger instance to use for logging
+    ///
+    /// # Returns
+    ///
+    /// TaskCommander Instance
+    pub fn from_stream(name: String, reader: Box<dyn Read>, deliminator: u8, mut logger: Logger) -> TaskCommander {
+        logger.set_flush_records(true);
+        let records = ReaderBuilder::new().delimiter(deliminator).has_headers(false).flexible(true).from_reader(reader).into_records();
+        TaskCommander::from_source(ScenarioSource::Stream(records), name, "".to_string(), "".to_string(), logger)
+    }
+
+    /// Instantiates the Commander for an instruction source and logs the run header, which notes
+    /// the uptime of the host for boot-time runs
+    fn from_source(source: ScenarioSource, scenario: String, scenario_hash: String, description: String, mut logger: Logger) -> TaskCommander {
+        let description = match logger.boot_uptime() {
+            Some(uptime) if description.is_empty() => format!("Boot-time run started {}s after boot", uptime),
+            Some(uptime) => format!("{} (boot-time run started {}s after boot)", description, uptime),
+            None => description
+        };
+        logger.log_run("Run Start".to_string(), scenario.clone(), scenario_hash.clone(), description);
+        let interval = logger.flush_interval();
+        let mut commander = TaskCommander {
+            source,
+            executor: Executor {
+                process_manager: match ProcessManager::new() {
+                    Ok(inner) => Some(Arc::new(Mutex::new(inner))),
+                    Err(_) => None
+                },
+                logger: Arc::new(Mutex::new(logger)),
+                errors_encountered: Arc::new(AtomicUsize::new(0)),
+                error_kinds: Arc::new(Mutex::new(BTreeMap::new())),
+                skip_reasons: Arc::new(Mutex::new(BTreeMap::new())),
+                events: EventBus::new(),
+                index: 0,
+                current_step: None,
+                overrides: Vec::new(),
+                save: None,
+                results: Arc::new(Mutex::new(HashMap::new())),
+                timers: Arc::new(Mutex::new(Vec::new())),
+                interrupted: Arc::new(AtomicBool::new(false)),
+                cancelled: Arc::new(AtomicBool::new(false)),
+                permissions: None,
+                workdir: None,
+                cleanup: None,
+                write_ahead: false,
+                outcome: None,
+            },
+            workers: None,
+            scenario,
+            scenario_hash,
+            rate_limiter: None,
+            started: Instant::now(),
+            inventory: HostInventory::capture(),
+            instruction_counts: BTreeMap::new(),
+            instruction_timeout: None,
+            sampler: None,
+            flusher: None,
+            aliases: Aliases::default(),
+        };
+        if let Some(interval) = interval {
+            commander.start_flusher(interval);
         }
+        commander
+    }
 
-        match self.process_manager.as_mut().unwrap().new_process(String::from(&params[1]), arguments) {
-            Ok(result_log) => self.logger.log_event(result_log),
-            Err(e) => {
-                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+    /// Starts a thread flushing the output whenever the flush interval has elapsed since the last
+    /// flush, until the end of the run. Records logged before a long pause are flushed without
+    /// waiting for the next record. When the thread can not be started, the output is only flushed
+    /// as records are logged.
+    ///
+    /// # Parameters
+    ///
+    /// - `interval`: longest time records may stay buffered
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    fn start_flusher(&mut self, interval: Duration) {
+        let logger = self.executor.logger.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let spawned = thread::Builder::new().name("flusher".to_string()).spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                thread::sleep(interval.min(Duration::from_millis(50)));
+                // a failing output is reported by the next record logged
+                let _ = logger.lock().unwrap().flush_due();
             }
-        }
+        });
+        self.flusher = spawned.ok().map(|handle| (stop, handle));
     }
 
-    /// Runs file operations by verifying the providing instructions, formatting data, and logging
+    /// Limits the rate instructions are executed at. `pause` instructions are not rate limited so
+    /// they still add their full duration on top of the limit.
     ///
     /// # Parameters
     ///
-    /// - `params`: a StringRecord representing the row within the CSV document containing
-    /// instructions on how to work the file system
+    /// - `rate_limiter`: rate limiter consulted before every instruction
     ///
     /// # Returns
     ///
     /// Nothing
+    pub fn set_rate_limit(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    /// Executes process, file, and network instructions on a pool of worker threads so events are
+    /// generated concurrently. Instructions are still read in order, but may complete out of order,
+    /// except for file instructions on the same path which always complete in order. `pause`
+    /// instructions delay the instructions after them without waiting for those before them.
     ///
-    /// # Panics
+    /// # Parameters
     ///
-    /// Should not panic as all errors are sent to the error logger.
-    fn file_system(&mut self, params: StringRecord) {
-        //ensure correct number of parameters have been provided
-        if params.len() < 2 {
-            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process (<file_op>,<path>)", params)));
-            return;
-        }
-        //determine which file operation to perform
-        let result = match &params[0] {
-            "new_file" => file_system::new_file(&String::from(&params[1])),
-            "mod_file" => file_system::mod_file(&String::from(&params[1])),
-            "delete_file" => file_system::delete_file(&String::from(&params[1])),
-            _ => return self.error_print(GenerationError::new("input_format".to_string(), format!("{} is not a valid File Operation Command", &params[1])))
-        };
-        match result {
-            Ok(result_log) => self.logger.log_event(result_log),
-            Err(e) => {
-                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
-            }
-        }
+    /// - `workers`: number of worker threads
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The worker threads were started
+    /// - `Err`: The number of workers is zero or a thread could not be started
+    pub fn set_workers(&mut self, workers: usize) -> Result<(), GenerationError> {
+        let mut executor = self.executor.clone();
+        self.workers = Some(WorkerPool::new(workers, move |job: Job| {
+            executor.index = job.index;
+            executor.current_step = job.step;
+            executor.overrides = job.overrides;
+            executor.save = job.save;
+            executor.execute_within(job.record, job.timeout);
+        })?);
+        Ok(())
     }
 
-    /// Runs network operations by verifying the providing instructions, formatting data, and logging
+    /// Abandons instructions which do not complete within a time limit, see `--instruction-timeout`.
+    /// Overruns are logged as `timeout` errors and the run continues with the next instruction.
+    /// An instruction ending with `timeout=<msec>` uses its own limit instead, `pause` instructions
+    /// are never abandoned.
     ///
     /// # Parameters
     ///
-    /// - `params`: a StringRecord representing the row within the CSV document containing
-    /// instructions on how to set up a network connection
+    /// - `timeout`: time any instruction may take
     ///
     /// # Returns
     ///
     /// Nothing
+    pub fn set_instruction_timeout(&mut self, timeout: Duration) {
+        self.instruction_timeout = Some(timeout);
+    }
+
+    /// Tracks processes started by child processes, logging them as "New Descendant Process" events
+    /// after each instruction and stopping them with the child processes at the end of the run
     ///
-    /// # Panics
+    /// # Returns
     ///
-    /// Should not panic as all errors are sent to the error logger.
-    fn network(&mut self, params: StringRecord) {
-        //ensure correct number of parameters have been provided for the correct command
-        if (params.len() < 2 && &params[0] == "connect_self") || (params.len() < 4 && &params[0] == "connect") {
-            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process (<connect>,[destination_host],[destination_port],<message>)", params)));
-            return;
+    /// Nothing
+    pub fn set_track_descendants(&mut self) {
+        if let Some(process_manager) = self.executor.process_manager.as_ref() {
+            process_manager.lock().unwrap().set_track_descendants(true);
         }
-        //determine which network operation to perform
-        let result = match &params[0] {
-            "connect" => {
-                //ensure that port number can be parsed into a u16 correctly
-                let port = match params[2].parse::<u16>() {
-                    Ok(inner) => inner,
-                    _ => {
-                        self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process (<connect>,[destination_host],[destination_port],<message>)", params)));
-                        return;
-                    }
-                };
-                network::send_message(&String::from(&params[1]), port, &Vec::from(params[3].to_string().as_bytes()))
-            }
-            "connect_self" => network::send_loopback_message(&Vec::from(params[1].to_string().as_bytes())),
-            _ => return self.error_print(GenerationError::new("input_format".to_string(), format!("{} is not a valid Network Operation Command", &params[1])))
-        };
-        match result {
-            Ok(result_log) => self.logger.log_event(result_log),
-            Err(e) => {
-                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
-            }
+    }
+
+    /// Runs in a directory of its own, see `--workdir`. Relative file paths, and relative executable
+    /// paths with a directory, resolve inside it and child processes are started in it. The
+    /// directory is removed once the TaskCommander is dropped and its child processes are stopped.
+    /// Must be called before `set_workers`.
+    ///
+    /// # Parameters
+    ///
+    /// - `workdir`: directory created for the run
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_workdir(&mut self, workdir: WorkDir) {
+        if let Some(process_manager) = self.executor.process_manager.as_ref() {
+            process_manager.lock().unwrap().set_working_directory(Some(workdir.path().to_path_buf()));
         }
+        self.executor.workdir = Some(Arc::new(workdir));
+    }
+
+    /// Removes the persistence artifacts (run keys, startup folder files, crontab entries, services,
+    /// and scheduled tasks) created by the run once it ends, see `--cleanup`. Every removal is
+    /// logged. Must be called before `set_workers`.
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_cleanup(&mut self) {
+        self.executor.cleanup = Some(Arc::new(Mutex::new(Vec::new())));
+    }
+
+    /// Logs an `Intent` record, synced to disk, before every destructive instruction and a
+    /// `Completed` record with its outcome after it, see `--write-ahead`. After a crash, an
+    /// `Intent` without a `Completed` record names an instruction which may have changed the host.
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_write_ahead(&mut self) {
+        self.executor.write_ahead = true;
+    }
+
+    /// Keeps a copy of every event logged by the run for exporters, see `take_events`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_record_events(&mut self) {
+        self.executor.logger.lock().unwrap().set_record_events();
     }
 
-    /// Pauses execution by verifying the providing instructions
+    /// Hands a copy of every event logged from now on to an ingest lag probe, see `--ingest-lag`
     ///
     /// # Parameters
     ///
-    /// - `params`: a StringRecord representing the row within the CSV document containing
-    /// instructions on how long to pause
+    /// - `probe`: sender of the probe, None releases the probe once the run is over
     ///
     /// # Returns
     ///
     /// Nothing
+    pub fn set_ingest_probe(&mut self, probe: Option<mpsc::Sender<LoggedEvent>>) {
+        self.executor.logger.lock().unwrap().set_ingest_probe(probe);
+    }
+
+    /// Resolves instructions named by an alias to their built-in instruction before they are
+    /// checked against the policy and executed, see `--aliases`
     ///
-    /// # Panics
+    /// # Parameters
     ///
-    /// Should not panic as all errors are sent to the error logger.
-    fn pause(&mut self, params: StringRecord) {
-        //ensure correct number of parameters have been provided
-        if params.len() < 2  {
-            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a connect ([connect],[msec])", params)));
+    /// - `aliases`: alternative names of instructions
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_aliases(&mut self, aliases: Aliases) {
+        self.aliases = aliases;
+    }
+
+    /// Retrieves the manifest of a campaign run, recording which scenario of the library ran when
+    ///
+    /// # Returns
+    ///
+    /// The manifest, or None if the Commander was not instantiated with `new_campaign`
+    pub fn campaign_manifest(&self) -> Option<Manifest> {
+        match &self.source {
+            ScenarioSource::Campaign(campaign) => Some(campaign.manifest()),
+            _ => None
         }
-        //ensure that delay time can be parsed into a u64 correctly
-        let delay = match params[1].parse::<u64>() {
-            Ok(inner) => inner,
-            _ => {
-                self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a connect ([connect],[msec]", params)));
-                return;
-            }
-        };
-        thread::sleep(Duration::from_millis(delay))
     }
 
-    /// Helper function for handling errors. Logs the error to the logger, displays error to console
-    /// and increments number of errors that were encountered.
+    /// Retrieves the events logged since `set_record_events` was called
+    ///
+    /// # Returns
+    ///
+    /// The events in the order they were logged
+    pub fn take_events(&mut self) -> Vec<Log> {
+        self.executor.logger.lock().unwrap().take_events()
+    }
+
+    /// Restricts the instructions which may be executed, see `serve --policy`. Refused instructions
+    /// are logged as `policy` errors and not executed.
     ///
     /// # Parameters
     ///
-    /// - `error`: Generation Error that will be logged and displayed.
+    /// - `permissions`: instructions the controller of the run may trigger
     ///
     /// # Returns
     ///
     /// Nothing
-    fn error_print(&mut self, error: GenerationError) {
-        eprintln!("{}", error);
-        self.logger.log_error(error);
-        self.errors_encountered += 1;
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.executor.permissions = Some(Arc::new(permissions));
+    }
+
+    /// Samples the CPU, memory, and disk usage of every running child process at a fixed interval
+    /// until the end of the run, logging each sample as a "Resource Usage" record
+    ///
+    /// # Parameters
+    ///
+    /// - `interval`: time between samples
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Sampling was started
+    /// - `Err`: The interval is zero or the sampling thread could not be started
+    pub fn set_sample_interval(&mut self, interval: Duration) -> Result<(), GenerationError> {
+        if interval.as_millis() == 0 {
+            return Err(GenerationError::new("input_format".to_string(), "Sample interval must be at least 1 millisecond".to_string()));
+        }
+        let process_manager = match self.executor.process_manager.as_ref() {
+            Some(inner) => inner.clone(),
+            None => return Ok(())
+        };
+        let logger = self.executor.logger.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::Builder::new().name("sampler".to_string()).spawn(move || {
+            let mut next = Instant::now() + interval;
+            while !stopped.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now < next {
+                    thread::sleep((next - now).min(Duration::from_millis(50)));
+                    continue;
+                }
+                next += interval;
+                let samples = process_manager.lock().unwrap().sample_usage();
+                for sample in samples {
+                    logger.lock().unwrap().log_usage(sample);
+                }
+            }
+        })?;
+        self.sampler = Some((stop, handle));
+        Ok(())
+    }
+
+    /// Retrieves the flag which interrupts the run when set, e.g. from a Ctrl+C handler. Once set no
+    /// further instructions are read, pauses end early, and processes started with `run_for` are
+    /// stopped immediately.
+    ///
+    /// # Returns
+    ///
+    /// Flag shared with the TaskCommander
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.executor.interrupted.clone()
     }
-}
 
+    /// Subscribes to the events of the run, see `RunEvent`. Errors and the end of the run are only
+    /// reported as events, they are printed to the console by a consumer such as `events::console`.
+    ///
+    /// # Returns
+    ///
+    /// Receiver of the events emitted from now on
+    pub fn subscribe(&mut self) -> std::sync::mpsc::Receiver<RunEvent> {
+        self.executor.events.subscribe()
+    }
 
+    /// Reports the events of the run over an existing event bus instead of its own, e.g. so the
+    /// runs of a server share the subscribers of the server. Subscribers of the previous bus no
+    /// longer receive events.
+    ///
+    /// # Parameters
+    ///
+    /// - `events`: event bus to emit to
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_events(&mut self, events: EventBus) {
+        self.executor.events = events;
+    }
+
+    /// Retrieves the event bus of the run, e.g. to close it once the run is over
+    ///
+    /// # Returns
+    ///
+    /// Event bus shared with the TaskCommander
+    pub fn events(&self) -> EventBus {
+        self.executor.events.clone()
+    }
+
+    /// Retrieves the number of errors TaskCommander has encountered, waiting for instructions
+    /// still being executed by worker threads
+    ///
+    /// # Returns
+    ///
+    /// The number of errors encountered
+    pub fn get_num_errors(mut self) -> usize {
+        self.wait();
+        self.executor.errors_encountered.load(Ordering::SeqCst)
+    }
+
+    /// Retrieves the SHA-256 digest of the input scenario file
+    ///
+    /// # Returns
+    ///
+    /// Lowercase hex encoded SHA-256 digest
+    pub fn get_scenario_hash(&self) -> String {
+        self.scenario_hash.clone()
+    }
+
+    /// Logs a run summary record containing the scenario digest and the outcome of the run,
+    /// including its duration and whether it was interrupted. The returned summary also breaks the
+    /// instructions down by name and the errors down by kind, carries the host inventory captured
+    /// when the run started, and is emitted as the `RunFinished` event.
+    ///
+    /// # Parameters
+    ///
+    /// - `commands_processed`: number of instructions that were read from the input file
+    ///
+    /// # Returns
+    ///
+    /// The logged summary, e.g. for pushing to a reporting backend
+    pub fn log_summary(&mut self, commands_processed: usize) -> RunSummary {
+        self.wait();
+        let mut summary = RunSummary::new(self.scenario.clone(), self.scenario_hash.clone(), commands_processed,
+                                          self.executor.errors_encountered.load(Ordering::SeqCst), self.started.elapsed(),
+                                          self.executor.interrupted.load(Ordering::SeqCst));
+        summary.instruction_counts = self.instruction_counts.clone();
+        summary.error_kinds = self.executor.error_kinds.lock().unwrap().clone();
+        summary.skip_reasons = self.executor.skip_reasons.lock().unwrap().clone();
+        summary.skipped = summary.skip_reasons.values().sum();
+        summary.inventory = Some(self.inventory.clone());
+        let mut logger = self.executor.logger.lock().unwrap();
+        summary.boot_uptime_seconds = logger.boot_uptime();
+        // an abandoned instruction may hold the logger past the end of the run, the output is
+        // flushed from the summary on instead of when the logger is dropped
+        logger.set_flush_records(true);
+        logger.log_run("Run Summary".to_string(), self.scenario.clone(), self.scenario_hash.clone(), summary.message());
+        self.executor.events.emit(RunEvent::RunFinished(Box::new(summary.clone())));
+        summary
+    }
+
+    /// Waits for the worker threads, if any, to execute every queued instruction, then starts as
+    /// many worker threads again
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn drain_workers(&mut self) {
+        if let Some(mut workers) = self.workers.take() {
+            let count = workers.workers();
+            workers.join();
+            if let Err(e) = self.set_workers(count) {
+                self.executor.error_print(e);
+            }
+        }
+    }
+
+    /// Waits for the worker threads, if any, to execute every queued instruction, for every
+    /// process started with `run_for` to be stopped, and for every `fan_out` connection to close,
+    /// then logs descendants started since the last
+    /// instruction, removes persistence artifacts when cleanup is enabled, and stops resource usage
+    /// sampling and periodic flushing
+    fn wait(&mut self) {
+        if let Some(mut workers) = self.workers.take() {
+            workers.join();
+        }
+        let timers: Vec<JoinHandle<()>> = self.executor.timers.lock().unwrap().drain(..).collect();
+        for timer in timers {
+            if timer.join().is_err() {
+                self.executor.events.emit(RunEvent::Warning("A background instruction panicked, a process started with run_for may not have been stopped or fan_out connections may be missing".to_string()));
+            }
+        }
+        self.executor.log_descendants();
+        self.executor.remove_persistence();
+        if let Some((stop, handle)) = self.sampler.take() {
+            stop.store(true, Ordering::SeqCst);
+            if handle.join().is_err() {
+                self.executor.events.emit(RunEvent::Warning("The resource usage sampler panicked, samples may be missing".to_string()));
+            }
+        }
+        if let Some((stop, handle)) = self.flusher.take() {
+            stop.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+
+
+    /// Reads the next entry in the command list and processes the instructions. Structured steps
+    /// wait for their optional delay before being processed. Variables are substituted in every
+    /// entry before it is processed. When a rate limit is set, instructions other than `pause` wait
+    /// for the rate limiter before being processed. Trailing `@field=value` entries are removed from
+    /// the instruction and replace the matching fields of the event it logs. When worker threads
+    /// are started, instructions other than `pause` are queued for a worker instead of executed,
+    /// and `wait_process` waits for the queued instructions to complete before it is executed.
+    ///
+    /// # Returns
+    ///
+    /// A boolean representing if there is an entry to be processed. False returned when EOF or
+    /// when the run has been interrupted.
+    pub fn read_next(&mut self) -> bool {
+        if self.executor.interrupted.load(Ordering::SeqCst) {
+            return false;
+        }
+        let next_record = match &mut self.source {
+            ScenarioSource::Csv(script) => script.next_row().map(|(record, index)| (script::substitute(&record, index), None, None)),
+            ScenarioSource::Structured(steps) => steps.next().map(|step| (script::substitute(&step.to_record(), 0), step.name, step.delay)),
+            ScenarioSource::Random(random) => random.next_row().map(|record| (record, None, None)),
+            ScenarioSource::Records(records) => records.next().map(|record| (script::substitute(&record, 0), None, None)),
+            ScenarioSource::Stream(records) => match records.next() {
+                Some(Ok(record)) => Some((script::substitute(&record, 0), None, None)),
+                Some(Err(e)) => {
+                    // a malformed row is skipped, but the stream can not be read past an io error
+                    let readable = !e.is_io_error();
+                    self.executor.error_print(GenerationError::new("input_format".to_string(), format!("Unable to read instruction: {}", e)));
+                    return readable;
+                }
+                None => None
+            },
+            ScenarioSource::Campaign(campaign) => match campaign.next_row() {
+                Some(Ok(row)) => Some(row),
+                Some(Err(e)) => {
+                    // the scenario is recorded as failed in the manifest, the campaign continues
+                    self.executor.error_print(e);
+                    return true;
+                }
+                None => None
+            }
+        };
+        if let Some((new_record, step_name, delay)) = next_record {
+            let mut new_record = self.aliases.resolve(new_record);
+            let mut unresolved = Vec::new();
+            if !script::referenced_results(&new_record).is_empty() {
+                // the instructions saving the results may still be queued for a worker
+                self.drain_workers();
+                new_record = script::substitute_results(&new_record, &self.executor.results.lock().unwrap());
+                unresolved = script::referenced_results(&new_record);
+            }
+            *self.instruction_counts.entry(new_record.get(0).unwrap_or("").to_string()).or_insert(0) += 1;
+            self.executor.index = self.instruction_counts.values().sum();
+            if let Some(delay) = delay {
+                if !self.executor.sleep(Duration::from_millis(delay)) {
+                    return false;
+                }
+            }
+            self.executor.current_step = step_name;
+            if let Some(name) = unresolved.first() {
+                self.executor.error_print(GenerationError::new("dependency".to_string(), format!("Record {:?} references the result {} which was not saved", new_record, name)));
+                return true;
+            }
+            let (new_record, overrides) = match split_overrides(new_record) {
+                Ok(inner) => inner,
+                Err(e) => {
+                    self.executor.error_print(e);
+                    return true;
+                }
+            };
+            let (new_record, save) = match split_save(new_record) {
+                Ok(inner) => inner,
+                Err(e) => {
+                    self.executor.error_print(e);
+                    return true;
+                }
+            };
+            let (new_record, platforms) = match split_os(new_record) {
+                Ok(inner) => inner,
+                Err(e) => {
+                    self.executor.error_print(e);
+                    return true;
+                }
+            };
+            if let Some(platforms) = platforms.filter(|platforms| !runs_on(platforms)) {
+                self.executor.error_print(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} is only run on {}, not {}", new_record, platforms, env::consts::OS)));
+                return true;
+            }
+            let (new_record, timeout) = match split_timeout(new_record) {
+                Ok((record, timeout)) => (record, timeout.or(self.instruction_timeout)),
+                Err(e) => {
+                    self.executor.error_print(e);
+                    return true;
+                }
+            };
+            let name = new_record.get(0).unwrap_or("").to_string();
+            if name == "pause" {
+                self.executor.execute(new_record);
+                return true;
+            }
+            if let Some(limiter) = self.rate_limiter.as_mut() {
+                limiter.acquire();
+            }
+            if name == "wait_process" {
+                // the process may have been started by any worker
+                self.drain_workers();
+            }
+            match self.workers.as_mut() {
+                Some(workers) if name != "wait_process" => {
+                    let affinity = affinity(&new_record);
+                    workers.submit(Job { record: new_record, index: self.executor.index, step: self.executor.current_step.clone(), overrides, save, timeout }, affinity)
+                }
+                _ => {
+                    self.executor.overrides = overrides;
+                    self.executor.save = save;
+                    self.executor.execute_within(new_record, timeout);
+                }
+            }
+            self.executor.events.emit(RunEvent::Progress {
+                processed: self.executor.index,
+                errors: self.executor.errors_encountered.load(Ordering::SeqCst),
+                skipped: self.executor.skip_reasons.lock().unwrap().values().sum(),
+            });
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Drop for TaskCommander {
+    fn drop(&mut self) {
+        // timers hold the process manager, they must finish for its children to be cleaned up
+        self.wait();
+    }
+}
+
+impl Executor {
+    /// Executes a single instruction, emitting `InstructionStarted` before and `InstructionFinished`
+    /// after it. An instruction which timed out does not emit `InstructionFinished`.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction to execute, without overrides
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn execute(&mut self, record: StringRecord) {
+        if let Some(name) = &self.save {
+            // a result saved again, e.g. in a repeat block, only holds the events of the latest run
+            self.results.lock().unwrap().remove(name);
+        }
+        let instruction = record.iter().collect::<Vec<&str>>().join(",");
+        let intent = self.write_ahead && DESTRUCTIVE_INSTRUCTIONS.contains(&record.get(0).unwrap_or(""));
+        if intent {
+            self.outcome = None;
+            self.logger.lock().unwrap().log_intent("Intent", format!("#{} {}", self.index, instruction));
+        }
+        let started = Instant::now();
+        self.events.emit(RunEvent::InstructionStarted { index: self.index, instruction: instruction.clone(), step: self.current_step.clone() });
+        self.dispatch(record);
+        if intent {
+            // an abandoned instruction still records its completion, it may have changed the host since
+            let outcome = self.outcome.take().unwrap_or_else(|| "succeeded".to_string());
+            self.logger.lock().unwrap().log_intent("Completed", format!("#{} {}: {}", self.index, instruction, outcome));
+        }
+        if !self.cancelled.load(Ordering::SeqCst) {
+            self.events.emit(RunEvent::InstructionFinished { index: self.index, instruction, elapsed: started.elapsed() });
+        }
+    }
+
+    /// Logs an event of the instruction being processed with its overrides, and saves it when the
+    /// instruction saves its result. The non-empty fields of every event the instruction logs are
+    /// saved, so a `process` with `capture=` saves both its Process ID and its output.
+    ///
+    /// # Parameters
+    ///
+    /// - `log`: event logged by the instruction
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn log_event(&self, log: Log) {
+        if let Some(name) = &self.save {
+            let mut saved = log.clone();
+            for (field, value) in &self.overrides {
+                let _ = saved.set_field(field, value.clone());
+            }
+            self.results.lock().unwrap().entry(name.clone()).or_default().merge(&saved);
+        }
+        self.logger.lock().unwrap().log_event(log, &self.overrides);
+    }
+
+    /// Executes a single instruction by its name, see `execute`
+    fn dispatch(&mut self, record: StringRecord) {
+        let name = record.get(0).unwrap_or("").to_string();
+        if let Some(permissions) = self.permissions.clone() {
+            if !permissions.permits(&name) {
+                self.error_print(GenerationError::new("policy".to_string(), format!("{} is not permitted for controller role {}", name, permissions.role)));
+                return;
+            }
+        }
+        let (record, netns) = match split_netns(record) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        if let Some(name) = netns {
+            return self.dispatch_in_namespace(record, name);
+        }
+        match name.as_str() {
+            "process" | "pause" | "new_file" | "mod_file" | "delete_file" | "connect" | "connect_self" | "fan_out" => self.run_typed(record),
+            "process_storm" => self.process_storm(record),
+            "process_with_parent" => self.run_process_with_parent(record),
+            "process_in_session" => self.run_process_in_session(record),
+            "wait_process" => self.wait_process(record),
+            "listen" => self.listen(record),
+            "beacon" => self.beacon(record),
+            "exfil" => self.exfil(record),
+            "encrypt_burst" => self.encrypt_burst(record),
+            "ads_write" => self.ads_write(record),
+            "symlink" | "hardlink" => self.link(record),
+            "chmod_file" => self.chmod_file(record),
+            "persist_runkey" | "persist_startup" | "persist_cron" | "persist_service" => self.persist(record),
+            "schedule_task" => self.schedule_task(record),
+            "tamper_service" | "tamper_files" | "tamper_rename" => self.tamper(record),
+            "create_user" | "delete_user" | "add_to_group" => self.account(record),
+            _ => self.error_print(GenerationError::new("input_format".to_string(), format!("{} is not a valid instruction)", name)))
+        }
+        self.log_descendants();
+    }
+
+    /// Executes an instruction with a typed form, see `Instruction::parse`. The instruction is
+    /// parsed before anything is done, so a short or invalid row is logged as an error.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction to execute, without overrides, timeout, or namespace
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn run_typed(&mut self, record: StringRecord) {
+        let result = split_shaping(record).and_then(|(record, shaping)| Instruction::parse(&record).map(|instruction| (record, instruction, shaping)));
+        let (record, instruction, shaping) = match result {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        match instruction {
+            Instruction::Process(command) => self.run_process(command, &record),
+            Instruction::File(command) => self.file_system(command, &record),
+            Instruction::Network(command) => self.network(command, &record, &shaping),
+            Instruction::Pause(command) => self.pause(command),
+        }
+    }
+
+    /// Executes a network instruction on a thread of its own which entered a Linux network namespace,
+    /// see `network::enter_namespace`. The thread ends with the instruction, so other instructions
+    /// are never executed in the namespace.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction to execute, without its `netns=` parameter
+    /// - `name`: name or path of the network namespace
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn dispatch_in_namespace(&mut self, record: StringRecord, name: String) {
+        let mut executor = self.clone();
+        let spawned = thread::Builder::new().name(format!("netns {}", name)).spawn(move || {
+            match network::enter_namespace(&name) {
+                Ok(_) => executor.dispatch(record),
+                Err(e) => executor.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {}", record, e.message)))
+            }
+        });
+        match spawned {
+            Ok(handle) => {
+                let _ = handle.join();
+            }
+            Err(e) => self.error_print(GenerationError::from(e))
+        }
+    }
+
+    /// Executes a single instruction on a thread of its own, abandoning it when it does not complete
+    /// within the timeout. The pauses, listeners, and retries of an abandoned instruction end early,
+    /// but a call blocked by the operating system (a process start, a connection attempt, or a file
+    /// operation on an unresponsive network mount) can not be stopped and finishes in the
+    /// background. Events it logs afterwards are still recorded, its errors are not as the timeout
+    /// was already reported.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction to execute, without overrides or timeout
+    /// - `timeout`: optional time the instruction may take, executed on the current thread when not given
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn execute_within(&mut self, record: StringRecord, timeout: Option<Duration>) {
+        let timeout = match timeout {
+            Some(inner) => inner,
+            None => return self.execute(record)
+        };
+        let description = format!("{:?}", record);
+        let mut executor = self.clone();
+        executor.cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled = executor.cancelled.clone();
+        let (sender, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new().name(format!("instruction {}", record.get(0).unwrap_or(""))).spawn(move || {
+            executor.execute(record);
+            let _ = sender.send(());
+        });
+        if let Err(e) = spawned {
+            return self.error_print(GenerationError::from(e));
+        }
+        if let Err(mpsc::RecvTimeoutError::Timeout) = receiver.recv_timeout(timeout) {
+            cancelled.store(true, Ordering::SeqCst);
+            self.error_print(GenerationError::new("timeout".to_string(), format!("Record {} did not complete within {} ms and was abandoned", description, timeout.as_millis())));
+        }
+    }
+
+    /// Resolves a file path of an instruction inside the working directory, when one is set
+    fn resolve(&self, path: &str) -> String {
+        match &self.workdir {
+            Some(workdir) => workdir.resolve(path),
+            None => path.to_string()
+        }
+    }
+
+    /// Resolves an executable path of an instruction inside the working directory, when one is set
+    fn resolve_executable(&self, path: &str) -> String {
+        match &self.workdir {
+            Some(workdir) => workdir.resolve_executable(path),
+            None => path.to_string()
+        }
+    }
+
+    /// Logs processes started by child processes since the last poll, when descendant tracking is
+    /// enabled
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn log_descendants(&self) {
+        let descendants = match self.process_manager.as_ref() {
+            Some(process_manager) => process_manager.lock().unwrap().poll_descendants(),
+            None => return
+        };
+        for descendant in descendants {
+            self.logger.lock().unwrap().log_event(descendant, &[]);
+        }
+    }
+
+    /// Runs a process by formatting data and logging. A `run_for=<ms>` parameter stops the process
+    /// after the given number of milliseconds, a `stdin=<text>` parameter is written to the standard
+    /// input of the process, and a `capture=<bytes>` parameter waits for the process to exit and
+    /// logs its output as a `Process Output` event, see `ProcessCmd`.
+    ///
+    /// # Parameters
+    ///
+    /// - `command`: the parsed `process` instruction
+    /// - `params`: a StringRecord representing the row within the CSV document, for error messages
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn run_process(&mut self, command: ProcessCmd, params: &StringRecord) {
+        // check if process_manager is available
+        if self.process_manager.is_none() {
+            self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()));
+            return;
+        }
+        let (run_for, options) = (command.run_for, &command.options);
+        let result = self.process_manager.as_ref().unwrap().lock().unwrap().new_process_with(self.resolve_executable(&command.path), command.argument_string(), options);
+        match result {
+            Ok((result_log, capture)) => {
+                let pid = result_log.proc_id.parse::<usize>();
+                self.log_event(result_log);
+                if let (Some(duration), Ok(pid)) = (run_for, pid) {
+                    self.stop_after(pid, duration);
+                }
+                if let Some(capture) = capture {
+                    // the output still running processes produced so far is logged when the
+                    // instruction times out or the run is interrupted
+                    while !capture.finished() && !self.stopped() {
+                        thread::sleep(PROCESS_POLL_INTERVAL);
+                    }
+                    self.log_event(capture.into_log());
+                }
+            }
+            Err(e) => {
+                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+        }
+    }
+
+    /// Stops a child process and logs the stop event after a delay, without blocking the current
+    /// instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `pid`: Process ID of the child process to stop
+    /// - `duration`: number of milliseconds to wait before stopping the process
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn stop_after(&mut self, pid: usize, duration: u64) {
+        let mut executor = self.clone();
+        executor.overrides = Vec::new();
+        executor.save = None;
+        // the process keeps running for its full duration even if the instruction times out
+        executor.cancelled = Arc::new(AtomicBool::new(false));
+        let timer = thread::spawn(move || {
+            executor.sleep(Duration::from_millis(duration));
+            let result = executor.process_manager.as_ref().unwrap().lock().unwrap().stop(pid);
+            match result {
+                Ok(result_log) => executor.logger.lock().unwrap().log_event(result_log, &[]),
+                Err(e) => executor.error_print(e)
+            }
+        });
+        self.timers.lock().unwrap().push(timer);
+    }
+
+    /// Waits for a child process to exit and logs a `Process Exited` event with its exit code, so
+    /// later instructions do not race with it. A process which does not exit within the optional
+    /// timeout is left running and a `timeout` error is logged. The wait ends early when the
+    /// instruction times out or the run is interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the alias or Process ID of the process and the optional timeout
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn wait_process(&mut self, params: StringRecord) {
+        let process_manager = match self.process_manager.clone() {
+            Some(inner) => inner,
+            None => return self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()))
+        };
+        let (target, timeout) = match parse_wait(&params) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        let started = Instant::now();
+        loop {
+            let result = process_manager.lock().unwrap().exited(&target);
+            match result {
+                Ok(Some(result_log)) => return self.log_event(result_log),
+                Ok(None) => {}
+                Err(e) => return self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+            if let Some(timeout) = timeout.filter(|timeout| started.elapsed() >= Duration::from_millis(*timeout)) {
+                return self.error_print(GenerationError::new("timeout".to_string(), format!("Record {:?} encountered an error Process {} did not exit within {} ms)", params, target, timeout)));
+            }
+            if self.stopped() {
+                return;
+            }
+            thread::sleep(PROCESS_POLL_INTERVAL);
+        }
+    }
+
+    /// Runs a process as a child of an already running process. Where assigning the parent is not
+    /// supported, an error is logged and the process is run as a child of the generator instead so
+    /// the rest of the scenario is unaffected.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    /// instructions on how to create the process (process_with_parent,<parent_name>,<path>,[arguments...])
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn run_process_with_parent(&mut self, params: StringRecord) {
+        if self.process_manager.is_none() {
+            self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()));
+            return;
+        }
+        if params.len() < 3 {
+            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process with a parent (process_with_parent,<parent_name>,<path>,[arguments...])", params)));
+            return;
+        }
+        let arguments = if params.len() > 3 {
+            Some(params.iter().skip(3).collect::<Vec<&str>>().join(" "))
+        } else {
+            None
+        };
+        let result = self.process_manager.as_ref().unwrap().lock().unwrap().new_process_with_parent(&params[1], self.resolve_executable(&params[2]), arguments);
+        match result {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) if e.kind == "unsupported_platform" => {
+                // the process is still started, so the missing parent is an error rather than a skip
+                self.report(GenerationError::new(e.kind, format!("Record {:?} launched as a child of the generator instead: {}", params, e.message)), false);
+                let fallback = ProcessCmd {
+                    path: params[2].to_string(),
+                    arguments: params.iter().skip(3).map(str::to_string).collect(),
+                    run_for: None,
+                    options: ProcessOptions::default(),
+                };
+                self.run_process(fallback, &params);
+            }
+            Err(e) => {
+                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+        }
+    }
+
+    /// Spawns a process in another active user session, attributed to the user logged on to it, and
+    /// logs it. Outside Windows, or when the generator is not running as `SYSTEM`, the instruction is
+    /// skipped.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   instructions on how to create the process (process_in_session,<session|user|other>,<path>,[arguments...])
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn run_process_in_session(&mut self, params: StringRecord) {
+        if self.process_manager.is_none() {
+            self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()));
+            return;
+        }
+        if params.len() < 3 || params[1].trim().is_empty() {
+            self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process in a session (process_in_session,<session|user|other>,<path>,[arguments...])", params)));
+            return;
+        }
+        let arguments = if params.len() > 3 {
+            Some(params.iter().skip(3).collect::<Vec<&str>>().join(" "))
+        } else {
+            None
+        };
+        let result = self.process_manager.as_ref().unwrap().lock().unwrap().new_process_in_session(params[1].trim(), self.resolve_executable(&params[2]), arguments);
+        match result {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => {
+                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+        }
+    }
+
+    /// Rapidly spawns many child processes, logging each one. The storm ends early if a process can
+    /// not be spawned or the run is interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    /// instructions on how many processes to create (process_storm,<path>,<count>,<interval_ms>,[arguments...])
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn process_storm(&mut self, params: StringRecord) {
+        if self.process_manager.is_none() {
+            self.error_print(GenerationError::new("user_permissions".to_string(), "Child processes are not allowed to be spawned".to_string()));
+            return;
+        }
+        let (count, interval) = match (params.get(2).map(|count| count.parse::<u64>()), params.get(3).map(|interval| interval.parse::<u64>())) {
+            (Some(Ok(count)), Some(Ok(interval))) => (count, interval),
+            _ => {
+                self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a process storm (process_storm,<path>,<count>,<interval_ms>,[arguments...])", params)));
+                return;
+            }
+        };
+        let arguments = if params.len() > 4 {
+            Some(params.iter().skip(4).collect::<Vec<&str>>().join(" "))
+        } else {
+            None
+        };
+        for index in 0..count {
+            if index > 0 && !self.sleep(Duration::from_millis(interval)) {
+                return;
+            }
+            let result = self.process_manager.as_ref().unwrap().lock().unwrap().new_process(self.resolve_executable(&params[1]), arguments.clone());
+            match result {
+                Ok(result_log) => self.log_event(result_log),
+                Err(e) => {
+                    self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error after {} of {} process(es) {})", params, index, count, e.message)));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Runs file operations by formatting data and logging
+    ///
+    /// # Parameters
+    ///
+    /// - `command`: the parsed file instruction
+    /// - `params`: a StringRecord representing the row within the CSV document, for error messages
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn file_system(&mut self, command: FileCmd, params: &StringRecord) {
+        let path = self.resolve(&command.path);
+        //determine which file operation to perform
+        let result = match command.operation {
+            FileOperation::New => file_system::new_file(&path),
+            FileOperation::Modify => file_system::mod_file(&path),
+            FileOperation::Delete => file_system::delete_file(&path),
+        };
+        match result {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => {
+                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+        }
+    }
+
+    /// Writes a payload to an alternate data stream of a file, creating the file when it does not
+    /// exist. Windows (NTFS) only.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the file path, the stream name, and an optional payload
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn ads_write(&mut self, params: StringRecord) {
+        if params.len() < 3 || params[1].is_empty() || !file_system::valid_stream_name(&params[2]) {
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for an alternate data stream write (ads_write,<path>,<stream>,[payload])", params)));
+        }
+        let payload = params.get(3).filter(|payload| !payload.is_empty()).unwrap_or(file_system::DEFAULT_ADS_PAYLOAD);
+        match file_system::ads_write(&self.resolve(&params[1]), &params[2].to_string(), payload.as_bytes()) {
+            Ok(logs) => {
+                for log in logs {
+                    self.log_event(log);
+                }
+            }
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Creates a symbolic or hard link to a target
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the target and the path of the new link
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn link(&mut self, params: StringRecord) {
+        if params.len() < 3 || params[1].is_empty() || params[2].is_empty() {
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a link ({},<target>,<link>)", params, &params[0])));
+        }
+        let (target, link) = (self.resolve(&params[1]), self.resolve(&params[2]));
+        let result = match &params[0] {
+            "symlink" => file_system::symlink(&target, &link),
+            _ => file_system::hardlink(&target, &link)
+        };
+        match result {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Changes the permissions of a file
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the file path and the new permissions
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn chmod_file(&mut self, params: StringRecord) {
+        let permissions = params.get(2).and_then(file_system::FilePermissions::parse);
+        let permissions = match permissions {
+            Some(permissions) if !params[1].is_empty() => permissions,
+            _ => return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a permission change (chmod_file,<path>,<mode|attributes>)", params)))
+        };
+        match file_system::chmod_file(&self.resolve(&params[1]), &permissions) {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Simulates ransomware: creates a new directory of decoy files, then reads every file,
+    /// overwrites it with pseudo-encrypted (randomized) contents, and renames it with a new
+    /// extension as fast as possible, logging every operation. Nothing outside the new directory is
+    /// touched. The burst ends early if an operation fails or the run is interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the directory to create, the number of files, and an optional extension
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn encrypt_burst(&mut self, params: StringRecord) {
+        let format_error = GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for an encryption burst (encrypt_burst,<directory>,<count>,[extension])", params));
+        if params.len() < 3 || params[1].is_empty() {
+            return self.error_print(format_error);
+        }
+        let count = match params[2].parse::<u64>() {
+            Ok(inner) => inner,
+            Err(_) => return self.error_print(format_error)
+        };
+        let extension = params.get(3).filter(|extension| !extension.is_empty()).unwrap_or(DEFAULT_ENCRYPTED_EXTENSION).trim_start_matches('.').to_string();
+        if extension.is_empty() || extension.contains(&['/', '\\'][..]) {
+            return self.error_print(format_error);
+        }
+        let directory = self.resolve(&params[1]);
+        let result = file_system::new_directory(&directory).and_then(|created| {
+            self.log_event(created);
+            let mut files = vec![];
+            for index in 0..count {
+                let path = Path::new(&directory).join(format!("document_{:04}.txt", index)).to_string_lossy().to_string();
+                let decoy = format!("EDR generator decoy document {}\n", index).repeat(DECOY_REPEAT);
+                self.log_event(file_system::write_file(&path, decoy.as_bytes())?);
+                files.push(path);
+            }
+            let mut rng = rand::thread_rng();
+            for path in &files {
+                if self.stopped() {
+                    break;
+                }
+                let (contents, read) = file_system::read_file(path)?;
+                self.log_event(read);
+                let encrypted: Vec<u8> = contents.iter().map(|byte| byte ^ rng.gen::<u8>()).collect();
+                self.log_event(file_system::overwrite_file(path, &encrypted)?);
+                let renamed = format!("{}.{}", path, extension);
+                self.log_event(file_system::rename_file(path, &renamed)?);
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)));
+        }
+    }
+
+    /// Creates a persistence artifact which starts a command: a `Run` registry value, a startup
+    /// folder file, a crontab entry, or a service. When cleanup is enabled the artifact is
+    /// remembered so it is removed at the end of the run.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the name of the artifact (the schedule for `persist_cron`) and the command it starts
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn persist(&mut self, params: StringRecord) {
+        if params.len() < 3 || !persistence::valid_fields(&params[0], &params[1], &params[2]) {
+            let target = if &params[0] == "persist_cron" { "<schedule>" } else { "<name>" };
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a persistence mechanism ({},{},<command>)", params, &params[0], target)));
+        }
+        let result = match &params[0] {
+            "persist_runkey" => persistence::run_key(&params[1], &params[2]),
+            "persist_startup" => persistence::startup_file(&params[1], &params[2]),
+            "persist_cron" => persistence::cron_entry(&params[1], &params[2]),
+            _ => persistence::service(&params[1], &params[2])
+        };
+        self.log_persistence(&params, result);
+    }
+
+    /// Schedules a task which starts a command, with `schtasks` on Windows or in the crontab
+    /// elsewhere. When cleanup is enabled the task is remembered so it is removed at the end of the
+    /// run.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the name of the task, its trigger, and the command it starts
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn schedule_task(&mut self, params: StringRecord) {
+        if params.len() < 4 || !persistence::valid_fields(&params[0], &params[1], &params[3]) {
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a scheduled task (schedule_task,<name>,<onstart|minute|hourly|daily|weekly>,<command>)", params)));
+        }
+        let result = persistence::scheduled_task(&params[1], &params[2], &params[3]);
+        self.log_persistence(&params, result);
+    }
+
+    /// Interacts with an installed security agent the way an attacker disabling it would, logging
+    /// whether each attempt was allowed or denied: `tamper_service` queries the service of the
+    /// agent and attempts to stop it, `tamper_files` opens the files of its install directory
+    /// read-only, and `tamper_rename` attempts to rename its binary. A service which does stop is
+    /// started again and a binary which is renamed is renamed back immediately.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the name of the service, the install directory, or the path of the binary
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn tamper(&mut self, params: StringRecord) {
+        if params.len() < 2 || params[1].is_empty() {
+            let target = match &params[0] {
+                "tamper_service" => "service",
+                "tamper_files" => "directory",
+                _ => "path"
+            };
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for agent tampering ({},<{}>)", params, &params[0], target)));
+        }
+        let result = match &params[0] {
+            "tamper_service" => tamper::query_service(&params[1]).and_then(|queried| {
+                self.log_event(queried);
+                let (attempt, stopped) = tamper::stop_service(&params[1])?;
+                self.log_event(attempt);
+                if stopped {
+                    self.log_event(tamper::start_service(&params[1])?);
+                }
+                Ok(())
+            }),
+            "tamper_files" => tamper::agent_files(Path::new(&self.resolve(&params[1]))).and_then(|files| {
+                for path in files {
+                    if self.stopped() {
+                        break;
+                    }
+                    self.log_event(tamper::open_file(&path)?);
+                }
+                Ok(())
+            }),
+            _ => {
+                let path = self.resolve(&params[1]);
+                tamper::rename_binary(Path::new(&path)).and_then(|(attempt, renamed)| {
+                    self.log_event(attempt);
+                    if renamed {
+                        self.log_event(tamper::restore_binary(Path::new(&path))?);
+                    }
+                    Ok(())
+                })
+            }
+        };
+        if let Err(e) = result {
+            self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)));
+        }
+    }
+
+    /// Creates or deletes a local account, or adds an account to a local group, with the account
+    /// management tool of the platform. The generator must be elevated. When cleanup is enabled a
+    /// created account is remembered so it is deleted at the end of the run.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the name of the account, and the name of the group for `add_to_group`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn account(&mut self, params: StringRecord) {
+        let group = &params[0] == "add_to_group";
+        let fields = if group { 3 } else { 2 };
+        if params.len() < fields || !account::valid_name(&params[1]) || (group && !account::valid_group(&params[2])) {
+            let format = if group { "add_to_group,<name>,<group>".to_string() } else { format!("{},<name>", &params[0]) };
+            return self.error_print(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for an account instruction ({})", params, format)));
+        }
+        match &params[0] {
+            "create_user" => {
+                let result = account::create_user(&params[1]).map(|log| (log, Artifact::UserAccount { name: params[1].to_string() }));
+                self.log_persistence(&params, result);
+            }
+            instruction => {
+                let result = if instruction == "delete_user" { account::delete_user(&params[1]) } else { account::add_to_group(&params[1], &params[2]) };
+                match result {
+                    Ok(result_log) => self.log_event(result_log),
+                    Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+                }
+            }
+        }
+    }
+
+    /// Logs the outcome of a persistence instruction, remembering the artifact when cleanup is
+    /// enabled
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: instruction which created the artifact
+    /// - `result`: event and artifact created, or the error encountered
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn log_persistence(&mut self, params: &StringRecord, result: Result<(Log, Artifact), GenerationError>) {
+        match result {
+            Ok((result_log, artifact)) => {
+                self.log_event(result_log);
+                if let Some(cleanup) = &self.cleanup {
+                    cleanup.lock().unwrap().push(artifact);
+                }
+            }
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Removes the persistence artifacts created so far, newest first, when cleanup is enabled
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn remove_persistence(&mut self) {
+        let artifacts: Vec<Artifact> = match &self.cleanup {
+            Some(cleanup) => cleanup.lock().unwrap().drain(..).rev().collect(),
+            None => return
+        };
+        self.current_step = None;
+        for artifact in artifacts {
+            match artifact.remove() {
+                Ok(result_log) => self.logger.lock().unwrap().log_event(result_log, &[]),
+                Err(e) => self.error_print(GenerationError::new(e.kind, format!("Unable to remove {:?}: {}", artifact, e.message)))
+            }
+        }
+    }
+
+    /// Runs network operations by formatting data and logging. A `connect` with `retries=<count>`
+    /// or `backoff=<msec>` parameters is retried with exponential backoff, and its event records the
+    /// attempt which connected.
+    ///
+    /// # Parameters
+    ///
+    /// - `command`: the parsed network instruction
+    /// - `params`: a StringRecord representing the row within the CSV document, for error messages
+    /// - `shaping`: network conditions the connection is made under
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn network(&mut self, command: NetworkCmd, params: &StringRecord, shaping: &Shaping) {
+        //determine which network operation to perform
+        let result = match command {
+            NetworkCmd::Connect { host, port, message, retries, backoff } => self.connect(&host, port, message.as_bytes(), RetryPolicy::from_parameters(retries, backoff), shaping),
+            NetworkCmd::ConnectSelf { message } => network::send_loopback_message(&Vec::from(message.as_bytes())),
+            NetworkCmd::FanOut { hosts, ports, message, concurrency, hold } => {
+                let targets: Vec<(String, u16)> = hosts.iter().flat_map(|host| ports.iter().map(move |port| (host.clone(), *port))).collect();
+                return self.fan_out(targets, message.into_bytes(), concurrency, Duration::from_millis(hold), params, shaping);
+            }
+        };
+        match result {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => {
+                self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+            }
+        }
+    }
+
+    /// Sends a message to a remote host, retrying failed attempts when a retry policy is given
+    ///
+    /// # Parameters
+    ///
+    /// - `host`: destination IP address or hostname
+    /// - `port`: destination port
+    /// - `message`: data sent once connected
+    /// - `retry`: optional retry policy of the instruction
+    /// - `shaping`: network conditions every attempt is made under
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data of the connection, its activity records the attempt when retries are enabled
+    /// - `Err`: Every attempt failed, or the run was interrupted while waiting to retry
+    fn connect(&self, host: &str, port: u16, message: &[u8], retry: Option<RetryPolicy>, shaping: &Shaping) -> Result<Log, GenerationError> {
+        let policy = match retry {
+            Some(inner) => inner,
+            None => return network::send_message_shaped(host, port, message, shaping, || self.stopped())
+        };
+        let attempts = policy.retries.saturating_add(1);
+        let mut attempt = 1;
+        loop {
+            match network::send_message_shaped(host, port, message, shaping, || self.stopped()) {
+                Ok(mut log) => {
+                    log.activity = format!("{} (attempt {} of {})", log.activity, attempt, attempts);
+                    return Ok(log);
+                }
+                Err(e) if attempt < attempts => {
+                    if !self.sleep(policy.delay(attempt)) {
+                        return Err(GenerationError::new(e.kind, format!("{}, interrupted after {} attempt(s)", e.message, attempt)));
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(GenerationError::new(e.kind, format!("{} after {} attempt(s)", e.message, attempt)))
+            }
+        }
+    }
+
+    /// Sends a message to every port of every destination, holding up to `concurrency` connections
+    /// open at the same time on threads of their own (see `network::fan_out`), so scans and
+    /// implants fanning out to many hosts can be simulated without waiting for each connection in
+    /// turn. The connections are made on a background thread, so the following instructions are
+    /// executed meanwhile, and the run waits for them before it ends. Every connection is logged as
+    /// it completes, recording its number. Connection attempts time out after
+    /// `network::DEFAULT_FAN_OUT_TIMEOUT` unless a `connect_timeout=` is given. A failed connection
+    /// does not stop the instruction, failures are reported as a single error once every
+    /// destination was attempted. Destinations not attempted yet are skipped when the run is
+    /// interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `targets`: destination IP address or hostname, and port, of every connection
+    /// - `message`: data sent once connected
+    /// - `concurrency`: largest number of connections open at the same time
+    /// - `hold`: time each connection is held open once the message was sent
+    /// - `params`: instruction, for error messages
+    /// - `shaping`: network conditions every connection is made under
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn fan_out(&mut self, targets: Vec<(String, u16)>, message: Vec<u8>, concurrency: usize, hold: Duration, params: &StringRecord, shaping: &Shaping) {
+        let shaping = Shaping { connect_timeout: shaping.connect_timeout.or(Some(network::DEFAULT_FAN_OUT_TIMEOUT)), ..*shaping };
+        let record = params.clone();
+        let mut executor = self.clone();
+        // the connections outlive the instruction, only interrupting the run stops them
+        executor.cancelled = Arc::new(AtomicBool::new(false));
+        let spawned = thread::Builder::new().name("fan-out".to_string()).spawn(move || {
+            // the connection threads check the flag themselves rather than borrowing the executor
+            let interrupted = executor.interrupted.clone();
+            let stop = move || interrupted.load(Ordering::SeqCst);
+            let mut failures = 0;
+            let mut last_error = String::new();
+            let result = network::fan_out(&targets, &message, concurrency, hold, &shaping, &stop, |index, result| match result {
+                Ok(mut log) => {
+                    log.activity = format!("{} (fan-out {} of {})", log.activity, index + 1, targets.len());
+                    executor.log_event(log);
+                }
+                Err(e) => {
+                    failures += 1;
+                    last_error = format!("{} {}", network::host_port(&targets[index].0, targets[index].1), e.message);
+                }
+            });
+            if let Err(e) = result {
+                return executor.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", record, e.message)));
+            }
+            if failures > 0 {
+                executor.error_print(GenerationError::new("network".to_string(), format!("Record {:?} encountered an error {} of {} connection(s) failed: {})", record, failures, targets.len(), last_error)));
+            }
+        });
+        match spawned {
+            Ok(handle) => self.timers.lock().unwrap().push(handle),
+            Err(e) => self.error_print(GenerationError::new("io".to_string(), format!("Record {:?} encountered an error {})", params, e)))
+        }
+    }
+
+    /// Sends small messages to a remote host on a fixed cadence, so detections based on the
+    /// periodicity of many connections can be exercised. Each beacon is logged as a network
+    /// connection recording its number. Beacons are scheduled from the start of the instruction,
+    /// so the time a connection takes does not shift the beacons after it. A failed beacon does
+    /// not stop the instruction, failures are reported as a single error once every beacon was
+    /// attempted. Beacons stop early when the run is interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the destination, interval, count, and optional payload size and jitter
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn beacon(&mut self, params: StringRecord) {
+        let (params, shaping) = match split_shaping(params) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        let beacon = match parse_beacon(&params) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        let mut failures = 0;
+        let mut last_error = String::new();
+        let mut next = Instant::now();
+        for index in 1..=beacon.count {
+            if index > 1 {
+                let mut wait = beacon.interval as f64;
+                if beacon.jitter > 0.0 {
+                    wait *= 1.0 + rand::thread_rng().gen_range(-beacon.jitter..=beacon.jitter);
+                }
+                next += Duration::from_millis(wait.round() as u64);
+                if !self.sleep(next.saturating_duration_since(Instant::now())) {
+                    break;
+                }
+            }
+            match network::send_message_shaped(&beacon.host, beacon.port, &network::beacon_payload(beacon.size), &shaping, || self.stopped()) {
+                Ok(mut log) => {
+                    log.activity = format!("{} (beacon {} of {})", log.activity, index, beacon.count);
+                    self.log_event(log);
+                }
+                Err(e) => {
+                    failures += 1;
+                    last_error = e.message;
+                }
+            }
+        }
+        if failures > 0 {
+            self.error_print(GenerationError::new("network".to_string(), format!("Record {:?} encountered an error {} of {} beacon(s) failed: {})", params, failures, beacon.count, last_error)));
+        }
+    }
+
+    /// Opens a listening socket and waits for a single inbound connection, logging the opened port
+    /// and the inbound connection. An optional timeout (in milliseconds) bounds the wait, and the
+    /// wait ends early when the run is interrupted.
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    /// instructions on which interface and port to listen on
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    ///
+    /// # Panics
+    ///
+    /// Should not panic as all errors are sent to the error logger.
+    fn listen(&mut self, params: StringRecord) {
+        let format_error = GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a listener (listen,<interface>,<port>,[timeout_ms])", params));
+        if params.len() < 3 || params[1].trim().is_empty() {
+            return self.error_print(format_error);
+        }
+        let port = match params[2].parse::<u16>() {
+            Ok(inner) => inner,
+            Err(_) => return self.error_print(format_error)
+        };
+        let timeout = match params.get(3).filter(|timeout| !timeout.is_empty()).map(|timeout| timeout.parse::<u64>()) {
+            Some(Ok(inner)) => Some(Duration::from_millis(inner)),
+            Some(Err(_)) => return self.error_print(format_error),
+            None => None
+        };
+        let (listener, opened) = match network::open_listener(&String::from(&params[1]), port) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        };
+        self.log_event(opened);
+        match network::accept_message(listener, timeout, || self.stopped()) {
+            Ok(inbound) => self.log_event(inbound),
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Sends the contents of a local file to a remote host, logging a single event which records
+    /// the file, the destination, and the number of bytes sent
+    ///
+    /// # Parameters
+    ///
+    /// - `params`: a StringRecord representing the row within the CSV document containing
+    ///   the file, destination, and optional protocol (`tcp` or `http`)
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn exfil(&mut self, params: StringRecord) {
+        let (params, shaping) = match split_shaping(params) {
+            Ok(inner) => inner,
+            Err(e) => return self.error_print(e)
+        };
+        let format_error = GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for an exfiltration (exfil,<file_path>,<destination_host>,<destination_port>,[tcp|http])", params));
+        if params.len() < 4 || params[1].is_empty() || params[2].trim().is_empty() {
+            return self.error_print(format_error);
+        }
+        let port = match params[3].parse::<u16>() {
+            Ok(inner) => inner,
+            Err(_) => return self.error_print(format_error)
+        };
+        let http = match params.get(4).unwrap_or("tcp").to_lowercase().as_str() {
+            "tcp" | "" => false,
+            "http" => true,
+            _ => return self.error_print(format_error)
+        };
+        match network::send_file_shaped(&self.resolve(&params[1]), &params[2], port, http, &shaping, || self.stopped()) {
+            Ok(result_log) => self.log_event(result_log),
+            Err(e) => self.error_print(GenerationError::new(e.kind, format!("Record {:?} encountered an error {})", params, e.message)))
+        }
+    }
+
+    /// Pauses execution
+    ///
+    /// # Parameters
+    ///
+    /// - `command`: the parsed `pause` instruction
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn pause(&mut self, command: PauseCmd) {
+        self.sleep(Duration::from_millis(command.duration));
+    }
+
+    /// Determines if the instruction being processed should stop early
+    ///
+    /// # Returns
+    ///
+    /// True if the run was interrupted or the instruction timed out
+    fn stopped(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst) || self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for a duration, waking early if the run is interrupted or the instruction timed out
+    ///
+    /// # Parameters
+    ///
+    /// - `duration`: time to sleep
+    ///
+    /// # Returns
+    ///
+    /// True if the full duration elapsed, false if the run was interrupted or the instruction timed out
+    fn sleep(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        loop {
+            if self.stopped() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            thread::sleep((end - now).min(Duration::from_millis(50)));
+        }
+    }
+
+    /// Helper function for handling errors. Logs the error to the logger, emits it as an `Error`
+    /// event and increments number of errors that were encountered. Errors of a kind in `SKIP_KINDS` mean
+    /// the instruction was not executed, they are logged as skipped instead and not counted as
+    /// errors and emitted as `Skipped` events. Errors raised by a named structured step are prefixed with the step name. Errors of
+    /// an instruction which timed out are dropped.
+    ///
+    /// # Parameters
+    ///
+    /// - `error`: Generation Error that will be logged and displayed.
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn error_print(&mut self, error: GenerationError) {
+        let skipped = SKIP_KINDS.contains(&error.kind.as_str());
+        self.report(error, skipped);
+    }
+
+    /// Logs and counts an error, or a skipped instruction, see `error_print`
+    ///
+    /// # Parameters
+    ///
+    /// - `error`: Generation Error that will be logged and displayed, its kind is the skip reason
+    /// - `skipped`: whether the instruction was skipped rather than failed
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    fn report(&mut self, mut error: GenerationError, skipped: bool) {
+        self.outcome = Some(format!("{} ({})", if skipped { "skipped" } else { "failed" }, error.kind));
+        if self.cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(name) = &self.current_step {
+            error.message = format!("Step '{}': {}", name, error.message);
+        }
+        if skipped {
+            self.events.emit(RunEvent::Skipped(error.clone()));
+            *self.skip_reasons.lock().unwrap().entry(error.kind.clone()).or_insert(0) += 1;
+            self.logger.lock().unwrap().log_skipped(error);
+            return;
+        }
+        self.events.emit(RunEvent::Error(error.clone()));
+        *self.error_kinds.lock().unwrap().entry(error.kind.clone()).or_insert(0) += 1;
+        self.logger.lock().unwrap().log_error(error);
+        self.errors_encountered.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Selects the worker affinity of an instruction. File instructions on the same path are executed
+/// by the same worker so they keep the order they were written in.
+///
+/// # Parameters
+///
+/// - `record`: instruction to execute
+///
+/// # Returns
+///
+/// A hash of the file path for file instructions, None for all other instructions
+fn affinity(record: &StringRecord) -> Option<u64> {
+    match (record.get(0).unwrap_or(""), record.get(1)) {
+        ("new_file" | "mod_file" | "delete_file" | "exfil" | "ads_write" | "symlink" | "hardlink" | "chmod_file" | "tamper_files" | "tamper_rename", Some(path)) => {
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            Some(hasher.finish())
+        }
+        _ => None
+    }
+}
+
+/// Parses a `wait_process` instruction
+///
+/// # Parameters
+///
+/// - `record`: instruction, without overrides or timeout
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The alias or Process ID of the process, and the number of milliseconds to wait for it
+///   when a timeout was given
+/// - `Err`: The instruction does not match `WAIT_FORMAT`
+pub(crate) fn parse_wait(record: &StringRecord) -> Result<(String, Option<u64>), GenerationError> {
+    let format_error = || GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}", record, WAIT_FORMAT));
+    let target = match record.get(1).map(str::trim) {
+        Some(inner) if !inner.is_empty() => inner.to_string(),
+        _ => return Err(format_error())
+    };
+    match record.get(2).filter(|timeout| !timeout.is_empty()).map(|timeout| timeout.trim().parse::<u64>()) {
+        Some(Ok(timeout)) => Ok((target, Some(timeout))),
+        Some(Err(_)) => Err(format_error()),
+        None => Ok((target, None))
+    }
+}
+
+/// Parses a `beacon` instruction. The payload size defaults to `DEFAULT_BEACON_SIZE` bytes and
+/// the jitter to 0, an empty payload size uses the default so a jitter can be given without one.
+///
+/// # Parameters
+///
+/// - `record`: instruction, without overrides or timeout
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The cadence of the beacon
+/// - `Err`: The instruction does not match `BEACON_FORMAT`, or a value is out of range (port 0,
+///   interval 0, count 0, a payload larger than `MAX_BEACON_SIZE`, or a jitter outside of 0-100)
+pub(crate) fn parse_beacon(record: &StringRecord) -> Result<Beacon, GenerationError> {
+    let format_error = |problem: &str| GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}{}", record, BEACON_FORMAT, problem));
+    if record.len() < 5 || record[1].trim().is_empty() {
+        return Err(format_error(""));
+    }
+    let port = match record[2].parse::<u16>() {
+        Ok(inner) if inner > 0 => inner,
+        _ => return Err(format_error(&format!(": {} is not a valid port", &record[2])))
+    };
+    let (interval, count) = match (record[3].parse::<u64>(), record[4].parse::<u64>()) {
+        (Ok(interval), Ok(count)) if interval > 0 && count > 0 => (interval, count),
+        _ => return Err(format_error(": the interval and count must be positive numbers"))
+    };
+    let size = match record.get(5).filter(|size| !size.is_empty()).map(|size| size.parse::<usize>()) {
+        None => network::DEFAULT_BEACON_SIZE,
+        Some(Ok(inner)) if inner <= network::MAX_BEACON_SIZE => inner,
+        Some(_) => return Err(format_error(&format!(": the payload size must be at most {} bytes", network::MAX_BEACON_SIZE)))
+    };
+    let jitter = match record.get(6).filter(|jitter| !jitter.is_empty()).map(|jitter| jitter.parse::<f64>()) {
+        None => 0.0,
+        Some(Ok(inner)) if (0.0..=100.0).contains(&inner) => inner / 100.0,
+        Some(_) => return Err(format_error(": the jitter must be a percentage (0-100)"))
+    };
+    Ok(Beacon { host: record[1].trim().to_string(), port, interval, count, size, jitter })
+}
+
+/// Separates trailing `latency=<msec>`, `loss=<percent>`, `bandwidth=<bytes_per_second>`,
+/// `loss_seed=<number>`, and `connect_timeout=<msec>` parameters from a `connect`, `beacon`,
+/// `exfil`, or `fan_out` instruction, see `network::Shaping`. The parameters follow the retry parameters. Parameters before the last
+/// required parameter are never treated as shaping parameters.
+///
+/// # Parameters
+///
+/// - `record`: instruction without overrides, timeout, or namespace
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without shaping parameters, and the conditions it is executed under
+/// - `Err`: A shaping parameter is given twice or is out of range
+pub(crate) fn split_shaping(record: StringRecord) -> Result<(StringRecord, Shaping), GenerationError> {
+    let required = match record.get(0).unwrap_or("") {
+        "connect" | "exfil" | "fan_out" => 4,
+        "beacon" => 5,
+        _ => return Ok((record, Shaping::default()))
+    };
+    let format_error = || GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}", record, SHAPING_FORMAT));
+    let mut shaping = Shaping::default();
+    let mut given = Vec::new();
+    let mut end = record.len();
+    while end > required {
+        let (name, value) = match record[end - 1].split_once('=') {
+            Some((name, value)) if ["latency", "loss", "bandwidth", "loss_seed", "connect_timeout"].contains(&name) => (name, value.trim()),
+            _ => break
+        };
+        if given.contains(&name) {
+            return Err(format_error());
+        }
+        match name {
+            "latency" => shaping.latency = Duration::from_millis(value.parse::<u64>().map_err(|_| format_error())?),
+            "loss" => match value.trim_end_matches('%').parse::<f64>() {
+                Ok(inner) if (0.0..100.0).contains(&inner) => shaping.loss = inner / 100.0,
+                _ => return Err(format_error())
+            },
+            "bandwidth" => match logger::parse_size(value) {
+                Ok(inner) if inner > 0 => shaping.bandwidth = Some(inner),
+                _ => return Err(format_error())
+            },
+            "connect_timeout" => match value.parse::<u64>() {
+                Ok(inner) if inner > 0 => shaping.connect_timeout = Some(Duration::from_millis(inner)),
+                _ => return Err(format_error())
+            },
+            _ => shaping.seed = value.parse::<u64>().map_err(|_| format_error())?
+        }
+        given.push(name);
+        end -= 1;
+    }
+    Ok((record.iter().take(end).collect(), shaping))
+}
+
+/// Separates a trailing `netns=<name>` parameter from a network instruction. The parameter follows
+/// every other parameter of the instruction, including retry parameters, but precedes the
+/// `timeout=<msec>` parameter.
+///
+/// # Parameters
+///
+/// - `record`: instruction without overrides or timeout
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without the namespace parameter, and the namespace if it was given
+/// - `Err`: The namespace name is empty
+pub(crate) fn split_netns(record: StringRecord) -> Result<(StringRecord, Option<String>), GenerationError> {
+    if !NETWORK_INSTRUCTIONS.contains(&record.get(0).unwrap_or("")) {
+        return Ok((record, None));
+    }
+    let end = record.len();
+    match record.get(end - 1).and_then(|field| field.strip_prefix("netns=")).map(str::trim) {
+        Some(name) if end > 1 && !name.is_empty() => Ok((record.iter().take(end - 1).collect(), Some(name.to_string()))),
+        Some(_) => Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a network namespace (netns=<name>)", record))),
+        None => Ok((record, None))
+    }
+}
+
+/// Separates a trailing `save=<name>` parameter from an instruction, saving the events it logs so
+/// later instructions can reference their fields as `${name.field}`, see
+/// `script::substitute_results`. The parameter follows every other parameter, including `os`, but
+/// precedes overrides. `fan_out` rows can not save their result, as their connections complete
+/// after the row.
+///
+/// # Parameters
+///
+/// - `record`: instruction without overrides
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without the parameter, and the name its result is saved as if it was
+///   given
+/// - `Err`: The name is not made of letters, digits, and underscores, or the instruction is a
+///   `fan_out`
+pub(crate) fn split_save(record: StringRecord) -> Result<(StringRecord, Option<String>), GenerationError> {
+    let end = record.len();
+    match record.get(end.saturating_sub(1)).and_then(|field| field.strip_prefix("save=")).map(str::trim) {
+        Some(_) if end > 1 && record.get(0) == Some("fan_out") => Err(GenerationError::new("input_format".to_string(), format!("Record {:?} can not save its result, fan_out connections complete after the row", record))),
+        Some(name) if end > 1 && script::is_result_name(name) => Ok((record.iter().take(end - 1).collect(), Some(name.to_string()))),
+        Some(_) if end > 1 => Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a saved result (save=<name>, letters, digits, and underscores only)", record))),
+        _ => Ok((record, None))
+    }
+}
+
+/// Separates a trailing `os=<platform>` parameter from an instruction, restricting it to one or more
+/// of `PLATFORMS` separated by `|` (e.g. `os=linux|macos`). The parameter follows every other
+/// parameter, including `timeout`, but precedes `save=` and overrides.
+///
+/// # Parameters
+///
+/// - `record`: instruction without overrides
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without the parameter, and the platforms it is restricted to if it was
+///   given, see `runs_on`
+/// - `Err`: A platform is not one of `PLATFORMS`
+pub(crate) fn split_os(record: StringRecord) -> Result<(StringRecord, Option<String>), GenerationError> {
+    let end = record.len();
+    let value = match record.get(end.saturating_sub(1)).and_then(|field| field.strip_prefix("os=")) {
+        Some(inner) if end > 1 => inner.trim().to_lowercase(),
+        _ => return Ok((record, None))
+    };
+    if value.split('|').any(|platform| !PLATFORMS.contains(&platform.trim())) {
+        return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for a platform (os=<{}>)", record, PLATFORMS.join("|"))));
+    }
+    let platforms: Vec<&str> = value.split('|').map(str::trim).collect();
+    Ok((record.iter().take(end - 1).collect(), Some(platforms.join("|"))))
+}
+
+/// Determines if an instruction restricted with `os=<platform>` runs on this platform
+///
+/// # Parameters
+///
+/// - `platforms`: platforms the instruction is restricted to, as returned by `split_os`
+///
+/// # Returns
+///
+/// True if one of the platforms is the current platform
+pub(crate) fn runs_on(platforms: &str) -> bool {
+    platforms.split('|').any(|platform| match platform {
+        "unix" => !cfg!(windows),
+        platform => platform == env::consts::OS
+    })
+}
+
+/// Separates a trailing `timeout=<msec>` parameter from an instruction. The parameter follows every
+/// other parameter, including `run_for` and retry parameters, but precedes `os=` and overrides.
+///
+/// # Parameters
+///
+/// - `record`: instruction without overrides
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without the timeout parameter, and the timeout if it was given
+/// - `Err`: The timeout is not a number of milliseconds
+pub(crate) fn split_timeout(record: StringRecord) -> Result<(StringRecord, Option<Duration>), GenerationError> {
+    let end = record.len();
+    let value = match record.get(end.saturating_sub(1)).and_then(|field| field.strip_prefix("timeout=")) {
+        Some(inner) if end > 1 => inner,
+        _ => return Ok((record, None))
+    };
+    match value.trim().parse::<u64>() {
+        Ok(inner) if inner > 0 => Ok((record.iter().take(end - 1).collect(), Some(Duration::from_millis(inner)))),
+        _ => Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for an instruction timeout (timeout=<msec>)", record)))
+    }
+}
+
+/// Separates trailing `@field=value` log field overrides from an instruction
+///
+/// # Parameters
+///
+/// - `record`: instruction which may end with overrides
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without overrides, and the overrides in the order they were written
+/// - `Err`: An override names a field which does not exist
+pub(crate) fn split_overrides(record: StringRecord) -> Result<(StringRecord, Vec<(String, String)>), GenerationError> {
+    let mut end = record.len();
+    while end > 1 && record[end - 1].starts_with('@') && record[end - 1].contains('=') {
+        end -= 1;
+    }
+    if end == record.len() {
+        return Ok((record, Vec::new()));
+    }
+    let mut overrides = Vec::new();
+    for field in record.iter().skip(end) {
+        let mut parts = field[1..].splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        if !Log::is_field(name) {
+            return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} overrides {} which is not a valid log field", record, name)));
+        }
+        overrides.push((name.to_string(), parts.next().unwrap_or("").to_string()));
+    }
+    Ok((record.iter().take(end).collect(), overrides))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::formatter::LogFormat;
+    use crate::modules::logger::OutputOptions;
+    use crate::modules::stix;
+    use crate::modules::testing::TempPath;
+    use crate::modules::validate;
+    use crate::modules::verify;
+
+    fn temp_logger() -> (Logger, TempPath) {
+        let path = TempPath::new("log.csv");
+        (Logger::new(&path.as_string(), LogFormat::Csv), path)
+    }
+
+
+    #[test]
+    fn test_good_inputs() {
+        let (logger, _log) = temp_logger();
+        let mut commander =  TaskCommander::new(&"tests/good_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_bad_inputs() {
+        let (logger, _log) = temp_logger();
+        let mut commander =  TaskCommander::new(&"tests/bad_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        assert_eq!(9, commander.get_num_errors())
+    }
+    #[test]
+    fn test_good_structured_inputs() {
+        let (logger, _log) = temp_logger();
+        let mut commander =  TaskCommander::new(&"tests/good_test.json".to_string(), ",".as_bytes()[0], logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        assert_eq!(7, commands_processed);
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_script_inputs() {
+        let (logger, _log) = temp_logger();
+        let mut commander =  TaskCommander::new(&"tests/script_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        assert_eq!(11, commands_processed);
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_scenario_hash() {
+        let (logger, _log) = temp_logger();
+        let commander =  TaskCommander::new(&"tests/good_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        let expected = crate::modules::common::hash_file(&"tests/good_test.csv".to_string()).unwrap();
+        assert_eq!(64, expected.len());
+        assert_eq!(expected, commander.get_scenario_hash())
+    }
+    #[test]
+    fn test_random_inputs() {
+        let (logger, _log) = temp_logger();
+        let weights = Weights { process: 0, file: 2, network: 1 };
+        let mut commander = TaskCommander::new_random(20, &weights, 2271, logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        assert!(commands_processed >= 20);
+        assert_eq!("", commander.get_scenario_hash());
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_campaign_inputs() {
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::new_campaign(&"tests/campaign_test.yaml".to_string(), ",".as_bytes()[0], 2299, logger).unwrap();
+        while commander.read_next() {}
+        let manifest = commander.campaign_manifest().unwrap();
+        assert_eq!(2299, manifest.seed);
+        assert!(!manifest.scenarios.is_empty());
+        assert!(manifest.scenarios.iter().all(|entry| entry.scenario.ends_with("campaign_files.csv") && entry.completed && entry.instructions == 3));
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_write_ahead() {
+        let file = TempPath::new("write_ahead.txt");
+        let path = file.as_string();
+        let input = std::io::Cursor::new(format!("new_file,{0}\ndelete_file,{0}\ndelete_file,{0}\n", path).into_bytes());
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        commander.set_write_ahead();
+        while commander.read_next() {}
+        commander.log_summary(3);
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        let records: Vec<&str> = output.lines().filter(|line| line.starts_with("Intent") || line.starts_with("Completed")).collect();
+        assert_eq!(records.len(), 4);
+        assert!(records[0].starts_with("Intent,") && records[0].ends_with(&format!("#2 delete_file,{}\"", path)));
+        assert!(records[1].starts_with("Completed,") && records[1].ends_with(": succeeded\""));
+        assert!(records[2].ends_with(&format!("#3 delete_file,{}\"", path)));
+        assert!(records[3].ends_with(": failed (io)\""));
+        let intent = output.find("Intent,").unwrap();
+        assert!(output[..intent].contains("New File"));
+        assert!(output[intent..].find("Delete File").unwrap() > output[intent..].find("Intent,").unwrap());
+        assert_eq!(1, commander.get_num_errors())
+    }
+    #[test]
+    fn test_aliases() {
+        let aliases = Aliases::load(&"tests/alias_test.yaml".to_string()).unwrap();
+        let scenario = "tests/alias_test.csv".to_string();
+        let findings = validate::validate_scenario(&scenario, ",".as_bytes()[0], &aliases).unwrap();
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|finding| finding.result.is_ok()));
+        assert_eq!(findings[0].record.get(0), Some("new_file"));
+        let findings = validate::validate_scenario(&scenario, ",".as_bytes()[0], &Aliases::default()).unwrap();
+        assert!(findings.iter().all(|finding| finding.result.is_err()));
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario, ",".as_bytes()[0], logger).unwrap();
+        commander.set_aliases(aliases);
+        while commander.read_next() {}
+        assert!(!Path::new("alias_test.txt").exists());
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_field_overrides() {
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&"tests/override_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        while commander.read_next() {}
+        assert_eq!(1, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert!(output.contains(",evil.exe,"));
+        assert!(output.contains(",Dropper Write,"));
+        assert!(output.contains(",HTTPS,IPv4,,,\n"));
+        assert!(!output.lines().any(|line| line.starts_with("Information") && line.contains('@')));
+    }
+    #[test]
+    fn test_parallel_inputs() {
+        let (logger, _log) = temp_logger();
+        let weights = Weights { process: 0, file: 1, network: 1 };
+        let mut commander = TaskCommander::new_random(200, &weights, 2273, logger).unwrap();
+        commander.set_workers(8).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed = commands_processed + 1;
+        }
+        commander.log_summary(commands_processed);
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_process_run_for() {
+        let scenario = TempPath::new("run_for.csv");
+        let (shell, wait) = if cfg!(windows) { ("cmd", "/c \"ping -n 30 127.0.0.1 > nul\"") } else { ("sh", "-c \"sleep 30\"") };
+        std::fs::write(&scenario, format!("process,{0},{1},run_for=200\nprocess,{0},{1},run_for=100\nprocess,{0},{1},run_for=soon\n", shell, wait)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        while commander.read_next() {}
+        assert_eq!(1, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(2, output.lines().filter(|line| line.contains(",New Process,")).count());
+        assert_eq!(2, output.lines().filter(|line| line.contains(",Process Stopped,")).count());
+    }
+    #[test]
+    fn test_interrupted_run() {
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::new(&"tests/good_test.csv".to_string(), ",".as_bytes()[0], logger).unwrap();
+        assert!(commander.read_next());
+        commander.interrupt_flag().store(true, Ordering::SeqCst);
+        assert!(!commander.read_next());
+        assert_eq!(0, commander.get_num_errors())
+    }
+    #[test]
+    fn test_stream_inputs() {
+        let input = std::io::Cursor::new(b"connect_self,hello world\nnot_an_instruction\n".to_vec());
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        assert!(commander.read_next());
+        // records are flushed as soon as they are logged
+        assert!(std::fs::read_to_string(&out_file).unwrap().contains("Network Connection"));
+        while commander.read_next() {}
+        assert_eq!(1, commander.get_num_errors());
+    }
+    #[test]
+    fn test_flush_interval() {
+        let input = TempPath::new("flush.csv");
+        std::fs::write(&input, "connect_self,hello world\nconnect_self,again\n").unwrap();
+        let (mut logger, out_file) = temp_logger();
+        logger.set_output_options(OutputOptions { flush_interval: Some(Duration::from_millis(200)), ..OutputOptions::default() });
+        let mut commander = TaskCommander::new(&input.as_string(), ",".as_bytes()[0], logger).unwrap();
+        assert!(commander.read_next());
+        // records are flushed on a timer while the run is still executing, without another record
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(std::fs::read_to_string(&out_file).unwrap().contains("Network Connection"));
+    }
+    #[test]
+    fn test_network_namespace() {
+        let input = std::io::Cursor::new(b"connect_self,hello,netns=/proc/self/ns/net\nconnect_self,hello,netns=edr_missing_x24\n".to_vec());
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        // entering the namespace is skipped without CAP_SYS_ADMIN or off Linux, a missing namespace is an error
+        assert_eq!(1, commander.get_num_errors());
+    }
+    #[test]
+    fn test_os_qualifier() {
+        let other = if cfg!(windows) { "linux|macos" } else { "windows" };
+        let scenario = format!("connect_self,hello,os={}\nconnect_self,hello,timeout=5000,os={}\nnew_file,%TEMP%/edr_os_{}.txt,os={}\npause,1,os=beos\n", other, std::env::consts::OS, std::process::id(), other);
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(std::io::Cursor::new(scenario.into_bytes())), ",".as_bytes()[0], logger);
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed += 1;
+        }
+        let summary = commander.log_summary(commands_processed);
+        assert_eq!((1, 2), (summary.errors, summary.skipped));
+        assert_eq!(Some(&2), summary.skip_reasons.get("unsupported_platform"));
+    }
+    #[test]
+    fn test_run_summary() {
+        let input = std::io::Cursor::new(b"connect_self,hello\nconnect_self,world\nnot_an_instruction\npause,soon\n".to_vec());
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed += 1;
+        }
+        let summary = commander.log_summary(commands_processed);
+        assert_eq!((4, 2), (summary.instructions, summary.errors));
+        assert_eq!(Some(&2), summary.instruction_counts.get("connect_self"));
+        assert_eq!(Some(&1), summary.instruction_counts.get("not_an_instruction"));
+        assert_eq!(summary.errors, summary.error_kinds.values().sum::<usize>());
+        assert!(!summary.interrupted);
+        assert!(!summary.inventory.unwrap().os.is_empty());
+    }
+    #[test]
+    fn test_boot_run() {
+        let input = std::io::Cursor::new(b"connect_self,hello\n".to_vec());
+        let (mut logger, out_file) = temp_logger();
+        logger.set_boot(42);
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        let summary = commander.log_summary(1);
+        drop(commander);
+        let log = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(Some(42), summary.boot_uptime_seconds);
+        assert!(log.contains("Boot-time run started 42s after boot"));
+        assert!(log.contains("Network Connection (boot)"));
+    }
+
+    #[test]
+    fn test_verify_expectations() {
+        let expected = TempPath::new("verify.csv");
+        std::fs::write(&expected, "name,activity,file_path,dest_addr\nloopback,Network Connection,,127.0.0.1\ndropped,New File,~edr_verify_dropped\\.txt$,\n").unwrap();
+        let expectations = verify::load(&expected.as_string()).unwrap();
+        let input = std::io::Cursor::new(b"connect_self,hello
+".to_vec());
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        commander.set_record_events();
+        while commander.read_next() {}
+        commander.log_summary(1);
+        let verification = verify::verify(&expectations, &commander.take_events());
+        assert_eq!(verification.generated, vec!["loopback"]);
+        assert_eq!(verification.missing, vec!["dropped"]);
+    }
+
+    #[test]
+    fn test_stix_export() {
+        let input = std::io::Cursor::new(b"connect_self,hello\nconnect_self,world\nnot_an_instruction\n".to_vec());
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        commander.set_record_events();
+        while commander.read_next() {}
+        let summary = commander.log_summary(3);
+        let events = commander.take_events();
+        assert_eq!(2, events.len());
+        let bundle = stix::bundle(&summary, &events);
+        let objects = bundle["objects"].as_array().unwrap();
+        assert_eq!(2, objects.iter().filter(|object| object["type"] == "observed-data").count());
+        assert_eq!(1, objects.iter().filter(|object| object["type"] == "sighting" && object["count"] == 2).count());
+    }
+    #[test]
+    fn test_connect_retries() {
+        let scenario = TempPath::new("retry.csv");
+        // reserve a free port, then start listening on it only after the first attempts have failed
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let listener = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let server = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap();
+            server.accept().unwrap();
+        });
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        std::fs::write(&scenario, format!("connect,127.0.0.1,{},hello,retries=8,backoff=50\nconnect,127.0.0.1,{},hello,retries=1,backoff=10\n", port, closed)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        while commander.read_next() {}
+        assert_eq!(1, commander.get_num_errors());
+        listener.join().unwrap();
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert!(output.contains("Network Connection (attempt "));
+        assert!(!output.contains("(attempt 1 of 9)"));
+        assert!(output.contains("Unable to Connect after 2 attempt(s)"));
+    }
+    #[test]
+    fn test_beacon() {
+        let scenario = TempPath::new("beacon.csv");
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let listener = std::thread::spawn(move || {
+            for _ in 0..3 {
+                server.accept().unwrap();
+            }
+        });
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        std::fs::write(&scenario, format!("beacon,127.0.0.1,{},100,3,16,10\nbeacon,127.0.0.1,{},10,2\n", port, closed)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        let started = std::time::Instant::now();
+        while commander.read_next() {}
+        assert!(started.elapsed() >= Duration::from_millis(180));
+        assert_eq!(1, commander.get_num_errors());
+        listener.join().unwrap();
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert!(output.contains("Network Connection (beacon 3 of 3)"));
+        assert!(output.contains(&format!("127.0.0.1,{},16,TCP/IP", port)));
+        assert!(output.contains("2 of 2 beacon(s) failed: Unable to Connect"));
+    }
+    #[test]
+    fn test_signed_inputs() {
+        let key = TrustedKey::load("tests/minisign.pub").unwrap();
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::new_signed(&"tests/signed_test.csv".to_string(), ",".as_bytes()[0], &key, logger).unwrap();
+        let mut commands_processed = 0;
+        while commander.read_next() {
+            commands_processed += 1;
+        }
+        assert_eq!(2, commands_processed);
+        assert_eq!(0, commander.get_num_errors());
+        // the included file must be signed as well
+        let (logger, _log) = temp_logger();
+        assert!(TaskCommander::new_signed(&"tests/signed_partial.csv".to_string(), ",".as_bytes()[0], &key, logger).is_err());
+        let (logger, _log) = temp_logger();
+        assert!(TaskCommander::new_signed(&"tests/good_test.json".to_string(), ",".as_bytes()[0], &key, logger).is_err());
+    }
+    #[test]
+    fn test_isolated_workdir() {
+        let workdir = WorkDir::create(&std::env::temp_dir().to_string_lossy()).unwrap();
+        let path = workdir.path().to_path_buf();
+        let input = std::io::Cursor::new(b"new_file,edr_workdir_artifact.txt\nmod_file,edr_workdir_artifact.txt\n".to_vec());
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        commander.set_workdir(workdir);
+        while commander.read_next() {}
+        assert!(path.join("edr_workdir_artifact.txt").is_file());
+        assert!(!std::path::Path::new("edr_workdir_artifact.txt").exists());
+        // the commander is dropped, removing the directory
+        assert_eq!(0, commander.get_num_errors());
+        assert!(!path.exists());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert!(output.contains(&path.join("edr_workdir_artifact.txt").to_string_lossy().to_string()));
+    }
+    #[test]
+    fn test_encrypt_burst() {
+        let directory = TempPath::new("burst");
+        let input = std::io::Cursor::new(format!("encrypt_burst,{0},5,.crypt\nencrypt_burst,{0},5\n", directory.display()).into_bytes());
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(input), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        // the second burst is refused as the directory already exists
+        assert_eq!(1, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        files.sort();
+        assert_eq!(files.len(), 5);
+        assert_eq!(files[0], "document_0000.txt.crypt");
+        assert_eq!(1, output.lines().filter(|line| line.contains(",New Directory,")).count());
+        for activity in [",New File,", ",Read File,", ",Overwrite File,", ",Rename File (from document_"].iter() {
+            assert_eq!(5, output.lines().filter(|line| line.contains(activity)).count());
+        }
+    }
+    #[test]
+    fn test_listen_inbound() {
+        let scenario = TempPath::new("listen.csv");
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        // the listener blocks its worker, the connection is made from the second worker
+        std::fs::write(&scenario, format!("listen,127.0.0.1,{},5000\nconnect,127.0.0.1,{},hello,retries=20,backoff=25\nlisten,127.0.0.1,0,50\n", port, port)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        commander.set_workers(2).unwrap();
+        while commander.read_next() {}
+        assert_eq!(1, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(2, output.lines().filter(|line| line.contains(",Listening Port Opened,")).count());
+        assert_eq!(1, output.lines().filter(|line| line.contains(",Inbound Network Connection,") && line.contains(&format!(",127.0.0.1,{},5,", port))).count());
+        assert!(output.contains("before the timeout"));
+    }
+    #[test]
+    fn test_saved_results() {
+        let scenario = TempPath::new("saved.csv");
+        let (shell, exit) = if cfg!(windows) { ("cmd", "/c exit") } else { ("sh", "-c exit") };
+        // the pid, the random file name, and the ephemeral port are only known once the instruction ran
+        std::fs::write(&scenario, format!("process,{},{},save=shell\nwait_process,${{shell.proc_id}}\nnew_file,${{TMP}}/edr_saved_${{RANDOM}}.txt,save=drop\ndelete_file,${{drop.file_path}}\nconnect_self,hello,save=loopback\nconnect_self,${{loopback.source_port}}\n", shell, exit)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        commander.set_workers(2).unwrap();
+        while commander.read_next() {}
+        assert_eq!(0, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(1, output.lines().filter(|line| line.contains(",Process Exited,")).count());
+        assert_eq!(1, output.lines().filter(|line| line.contains(",Delete File,") && line.contains("edr_saved_")).count());
+    }
+    #[test]
+    fn test_failed_dependency() {
+        let scenario = "process,edr_missing_binary,save=shell\nwait_process,${shell.proc_id}\nconnect_self,hello,save=loopback\nconnect_self,${loopback.source_port}\n";
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(std::io::Cursor::new(scenario.as_bytes().to_vec())), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        let summary = commander.log_summary(4);
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!((1, 1), (summary.errors, summary.skipped));
+        assert_eq!(Some(&1), summary.skip_reasons.get("dependency"));
+        assert!(output.contains("references the result shell which was not saved"));
+        assert!(!output.contains(",Process Exited,"));
+        assert!(split_save(StringRecord::from(vec!["fan_out", "127.0.0.1", "9", "hello", "save=scan"])).is_err());
+        assert!(split_save(StringRecord::from(vec!["connect_self", "hello", "save=loopback"])).unwrap().1.is_some());
+    }
+    #[test]
+    fn test_resource_sampling() {
+        let scenario = TempPath::new("sampling.csv");
+        let (shell, wait) = if cfg!(windows) { ("cmd", "/c ping -n 2 127.0.0.1") } else { ("sh", "-c \"sleep 1; exit\"") };
+        std::fs::write(&scenario, format!("process,{},{},run_for=500\npause,400\n", shell, wait)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        assert!(commander.set_sample_interval(Duration::from_millis(0)).is_err());
+        commander.set_sample_interval(Duration::from_millis(100)).unwrap();
+        while commander.read_next() {}
+        assert_eq!(0, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert!(output.lines().filter(|line| line.starts_with("Resource Usage,")).count() >= 2);
+    }
+    #[test]
+    fn test_dry_run() {
+        let scenario = TempPath::new("dry_run.csv");
+        std::fs::write(&scenario, "new_file,${TMP}/edr_dry_run.txt\nrepeat,2\nmod_file,${TMP}/edr_dry_run.txt\nend\ndelete_file,${TMP}/edr_dry_run.txt\nconnect_self,hello world\n").unwrap();
+        let findings = validate::validate_scenario(&scenario.as_string(), ",".as_bytes()[0], &Aliases::default()).unwrap();
+        assert_eq!(5, findings.len());
+        assert!(findings.iter().all(|finding| finding.result.is_ok()));
+        assert_eq!("line 3 (iteration 2)", findings[2].location);
+        // nothing was executed
+        assert!(!std::env::temp_dir().join("edr_dry_run.txt").exists());
+        let findings = validate::validate_scenario(&"tests/bad_test.csv".to_string(), ",".as_bytes()[0], &Aliases::default()).unwrap();
+        assert_eq!(9, findings.iter().filter(|finding| finding.result.is_err()).count());
+    }
+    #[test]
+    fn test_process_storm() {
+        let scenario = TempPath::new("storm.csv");
+        let (shell, exit) = if cfg!(windows) { ("cmd", "/c exit") } else { ("sh", "-c exit") };
+        std::fs::write(&scenario, format!("process_storm,{},10,5,{}\nprocess_storm,not_a_real_binary_x24,10,5\nprocess_storm,{},ten,5\n", shell, exit, shell)).unwrap();
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        while commander.read_next() {}
+        assert_eq!(2, commander.get_num_errors());
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(10, output.lines().filter(|line| line.contains(",New Process,")).count());
+    }
+    #[test]
+    fn test_process_with_parent() {
+        let scenario = TempPath::new("parent.csv");
+        let (shell, exit) = if cfg!(windows) { ("cmd", "/c exit") } else { ("sh", "-c exit") };
+        std::fs::write(&scenario, format!("process_with_parent,not_a_real_parent_x24.exe,{},{}\nprocess_with_parent,{}\n", shell, exit, shell)).unwrap();
+        let (logger, _log) = temp_logger();
+        let mut commander = TaskCommander::new(&scenario.as_string(), ",".as_bytes()[0], logger).unwrap();
+        while commander.read_next() {}
+        // the missing parent (or the fallback on unsupported platforms) and the missing path
+        assert_eq!(2, commander.get_num_errors())
+    }
+
+    #[test]
+    fn test_process_output() {
+        let (shell, input) = if cfg!(windows) { ("cmd", "\"stdin=echo edr_%USERNAME%\r\nexit\r\n\"") } else { ("sh", "\"stdin=echo edr_$USER; exit\n\"") };
+        let scenario = format!("process,{},{},capture=4096\nprocess,{},capture=none\n", shell, input, shell);
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(std::io::Cursor::new(scenario.into_bytes())), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        commander.log_summary(2);
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        assert_eq!(1, commander.get_num_errors());
+        assert!(output.lines().next().unwrap().ends_with(",stdout,stderr,exit_code"));
+        assert!(output.contains(",Process Output,"));
+        assert!(output.contains(",\"edr_"));
+    }
+
+    #[test]
+    fn test_wait_process() {
+        let (shell, script) = if cfg!(windows) { ("cmd", "\"/c ping -n 2 127.0.0.1 >NUL & exit 3\"") } else { ("sh", "\"-c 'sleep 1; exit 3'\"") };
+        let scenario = format!("process,{},{},alias=slow\nwait_process,slow,10000\nprocess,{},{},alias=stuck\nwait_process,stuck,50\nwait_process,missing\n", shell, script, shell, script);
+        let (logger, out_file) = temp_logger();
+        let mut commander = TaskCommander::from_stream("stdin".to_string(), Box::new(std::io::Cursor::new(scenario.into_bytes())), ",".as_bytes()[0], logger);
+        while commander.read_next() {}
+        commander.log_summary(5);
+        let output = std::fs::read_to_string(&out_file).unwrap();
+        // the timeout of the second process and the unknown alias
+        assert_eq!(2, commander.get_num_errors());
+        assert!(output.lines().any(|line| line.contains(",Process Exited,") && line.ends_with(",3")));
+        assert!(output.contains("did not exit within 50 ms"));
+    }
+}
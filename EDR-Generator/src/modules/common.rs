@@ -3,6 +3,8 @@ use std::io::ErrorKind;
 use std::ffi::OsString;
 use std::time::SystemTime;
 use chrono::{DateTime, Utc};
+use sha2::{Sha256, Digest};
+use std::fs;
 
 #[derive(Debug, Clone)] // derive std::fmt::Debug on AppError
 pub struct GenerationError {
@@ -60,4 +62,21 @@ pub fn get_time() -> String {
     let now = SystemTime::now();
     let now: DateTime<Utc> = now.into();
     now.to_rfc3339()
-}
\ No newline at end of file
+}
+
+/// Computes the SHA-256 digest of a file so a run can be tied to an exact scenario revision
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path of the file to hash
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Lowercase hex encoded SHA-256 digest of the file contents
+/// - `Err`: The file could not be read
+pub fn hash_file(path: &String) -> Result<String, GenerationError> {
+    let contents = fs::read(path)?;
+    Ok(format!("{:x}", Sha256::digest(&contents)))
+}
@@ -0,0 +1,277 @@
+use crate::modules::common::GenerationError;
+use crate::modules::events::{self, RunEvent};
+use crate::modules::report::RunSummary;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Time between two redraws of the dashboard
+const REFRESH_MS: u64 = 250;
+
+/// Number of recent instructions and errors kept for display
+const HISTORY: usize = 100;
+
+/// Structure defining what the dashboard shows, built from the events of a run
+///
+/// # Parameters
+///
+/// - `processed`: number of instructions read
+/// - `errors`: number of errors encountered
+/// - `skipped`: number of instructions skipped
+/// - `counters`: number of instructions running and done by instruction name
+/// - `recent`: most recently finished instructions, newest last
+/// - `problems`: most recent errors and skipped instructions, newest last
+/// - `rate`: number of instructions finished during each second of the run
+/// - `interrupting`: whether the run was asked to stop
+/// - `finished`: summary of the run once it ended
+#[derive(Debug, Default)]
+pub struct DashboardState {
+    processed: usize,
+    errors: usize,
+    skipped: usize,
+    counters: BTreeMap<String, (usize, usize)>,
+    recent: VecDeque<String>,
+    problems: VecDeque<String>,
+    rate: Vec<u64>,
+    interrupting: bool,
+    finished: Option<RunSummary>,
+}
+
+impl DashboardState {
+    /// Updates the state with an event of the run
+    ///
+    /// # Parameters
+    ///
+    /// - `event`: event of the run
+    /// - `elapsed`: time since the run started
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn apply(&mut self, event: &RunEvent, elapsed: Duration) {
+        self.tick(elapsed);
+        match event {
+            RunEvent::InstructionStarted { instruction, .. } => {
+                self.counters.entry(name(instruction)).or_insert((0, 0)).0 += 1;
+            }
+            RunEvent::InstructionFinished { index, instruction, elapsed: took } => {
+                let counter = self.counters.entry(name(instruction)).or_insert((0, 0));
+                counter.0 = counter.0.saturating_sub(1);
+                counter.1 += 1;
+                if let Some(bucket) = self.rate.last_mut() {
+                    *bucket += 1;
+                }
+                push(&mut self.recent, format!("#{} {} ({} ms)", index, instruction, took.as_millis()));
+            }
+            RunEvent::Error(error) => {
+                self.errors += 1;
+                push(&mut self.problems, format!("[{}] {}", error.kind, error.message));
+            }
+            RunEvent::Skipped(error) => {
+                self.skipped += 1;
+                push(&mut self.problems, format!("Skipped [{}] {}", error.kind, error.message));
+            }
+            RunEvent::Warning(message) => push(&mut self.problems, message.clone()),
+            RunEvent::Progress { processed, .. } => self.processed = *processed,
+            RunEvent::Connected(_) => {}
+            RunEvent::RunFinished(summary) => self.finished = Some(*summary.clone()),
+        }
+    }
+
+    /// Adds the seconds elapsed since the last event to the rate graph, so it keeps moving while
+    /// no instruction finishes
+    ///
+    /// # Parameters
+    ///
+    /// - `elapsed`: time since the run started
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn tick(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs() as usize + 1;
+        if self.rate.len() < seconds {
+            self.rate.resize(seconds, 0);
+        }
+    }
+}
+
+/// Shows a live dashboard of the events of a run in the terminal on a thread of its own, see
+/// `--tui`. `q`, `Esc`, or `Ctrl+C` interrupt the run, the terminal is in raw mode so `Ctrl+C` does
+/// not raise a signal. Once the run finished the dashboard stays until a key is pressed, then the
+/// terminal is restored and the outcome of the run is printed as with `events::console`.
+///
+/// # Parameters
+///
+/// - `receiver`: events of the run
+/// - `interrupted`: flag interrupting the run, see `TaskCommander::interrupt_flag`
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Handle of the thread, which ends once the dashboard is closed
+/// - `Err`: The thread could not be started
+pub fn dashboard(receiver: Receiver<RunEvent>, interrupted: Arc<AtomicBool>) -> Result<JoinHandle<()>, GenerationError> {
+    let handle = thread::Builder::new().name("dashboard".to_string()).spawn(move || {
+        let mut terminal = ratatui::init();
+        let mut state = DashboardState::default();
+        let result = show(&mut terminal, &receiver, &interrupted, &mut state);
+        ratatui::restore();
+        if let Err(e) = result {
+            eprintln!("The dashboard stopped: {}", e);
+            // the run is still reported on the console
+            for event in receiver {
+                events::print(&event);
+            }
+            return;
+        }
+        if let Some(summary) = state.finished {
+            events::print(&RunEvent::RunFinished(Box::new(summary)));
+        }
+    })?;
+    Ok(handle)
+}
+
+/// Redraws the dashboard until it is closed, see `dashboard`
+fn show(terminal: &mut DefaultTerminal, receiver: &Receiver<RunEvent>, interrupted: &AtomicBool, state: &mut DashboardState) -> Result<(), GenerationError> {
+    let started = Instant::now();
+    let mut closed = false;
+    loop {
+        while !closed {
+            match receiver.try_recv() {
+                Ok(event) => state.apply(&event, started.elapsed()),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => closed = true
+            }
+        }
+        if state.finished.is_none() {
+            state.tick(started.elapsed());
+        }
+        terminal.draw(|frame| render(frame, state))?;
+        if !event::poll(Duration::from_millis(REFRESH_MS))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if state.finished.is_some() || closed {
+                return Ok(());
+            }
+            let interrupt = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+            if interrupt {
+                interrupted.store(true, Ordering::SeqCst);
+                state.interrupting = true;
+            }
+        }
+    }
+}
+
+/// Draws the dashboard: a status line, the counters by instruction, the recently finished
+/// instructions, the rate of instructions, and the error stream
+///
+/// # Parameters
+///
+/// - `frame`: frame to draw on
+/// - `state`: what to show
+///
+/// # Returns
+///
+/// Nothing
+pub fn render(frame: &mut Frame, state: &DashboardState) {
+    let [header, middle, bottom] = Layout::vertical([Constraint::Length(3), Constraint::Percentage(50), Constraint::Fill(1)]).areas(frame.area());
+    let [counters, recent] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(middle);
+    let [rate, problems] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(bottom);
+
+    let status = match (&state.finished, state.interrupting) {
+        (Some(_), _) => "finished, press any key to exit",
+        (None, true) => "interrupting, stopping child processes",
+        (None, false) => "running, press q to interrupt"
+    };
+    let line = format!("{} instruction(s), {} error(s), {} skipped, {}s elapsed | {}",
+                       state.processed, state.errors, state.skipped, state.rate.len().saturating_sub(1), status);
+    frame.render_widget(Paragraph::new(line).block(Block::default().borders(Borders::ALL).title("EDR Generator")), header);
+
+    let rows: Vec<Row> = state.counters.iter()
+        .map(|(name, (running, done))| Row::new(vec![name.clone(), running.to_string(), done.to_string()]))
+        .collect();
+    let table = Table::new(rows, [Constraint::Fill(1), Constraint::Length(8), Constraint::Length(8)])
+        .header(Row::new(vec!["Instruction", "Running", "Done"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title("Instructions"));
+    frame.render_widget(table, counters);
+
+    frame.render_widget(latest(&state.recent, recent.height, Style::default()).block(Block::default().borders(Borders::ALL).title("Recent")), recent);
+
+    let width = rate.width.saturating_sub(2) as usize;
+    let data = &state.rate[state.rate.len().saturating_sub(width)..];
+    let sparkline = Sparkline::default().data(data).style(Style::default().fg(Color::Green))
+        .block(Block::default().borders(Borders::ALL).title(format!("Instructions/s (peak {})", data.iter().max().unwrap_or(&0))));
+    frame.render_widget(sparkline, rate);
+
+    frame.render_widget(latest(&state.problems, problems.height, Style::default().fg(Color::Red)).block(Block::default().borders(Borders::ALL).title("Errors")), problems);
+}
+
+/// Builds a list of the newest entries which fit in a bordered area of the given height
+fn latest(entries: &VecDeque<String>, height: u16, style: Style) -> List<'static> {
+    let shown = height.saturating_sub(2) as usize;
+    List::new(entries.iter().skip(entries.len().saturating_sub(shown)).map(|entry| ListItem::new(entry.clone()).style(style)).collect::<Vec<ListItem>>())
+}
+
+/// Retrieves the instruction name of an instruction
+fn name(instruction: &str) -> String {
+    instruction.split(',').next().unwrap_or("").to_string()
+}
+
+/// Appends an entry to a history, dropping the oldest entry when it is full
+fn push(history: &mut VecDeque<String>, entry: String) {
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn events_counted() {
+        let mut state = DashboardState::default();
+        let started = |index: usize| RunEvent::InstructionStarted { index, instruction: "connect_self,hello".to_string(), step: None };
+        let finished = |index: usize| RunEvent::InstructionFinished { index, instruction: "connect_self,hello".to_string(), elapsed: Duration::from_millis(3) };
+        state.apply(&started(1), Duration::from_millis(100));
+        state.apply(&started(2), Duration::from_millis(200));
+        state.apply(&finished(1), Duration::from_millis(300));
+        state.apply(&RunEvent::Error(GenerationError::new("io".to_string(), "unreachable".to_string())), Duration::from_millis(2100));
+        state.apply(&finished(2), Duration::from_millis(2200));
+        state.apply(&RunEvent::Progress { processed: 2, errors: 1, skipped: 0 }, Duration::from_millis(2300));
+        assert_eq!(state.counters["connect_self"], (0, 2));
+        assert_eq!(state.rate, vec![1, 0, 1]);
+        assert_eq!((state.processed, state.errors, state.skipped), (2, 1, 0));
+        assert_eq!(state.recent.back().unwrap(), "#2 connect_self,hello (3 ms)");
+        assert_eq!(state.problems.back().unwrap(), "[io] unreachable");
+        for _ in 0..HISTORY {
+            state.apply(&RunEvent::Warning("sampler panicked".to_string()), Duration::from_secs(3));
+        }
+        assert_eq!(state.problems.len(), HISTORY);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| render(frame, &state)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("2 instruction(s), 1 error(s), 0 skipped"));
+        assert!(screen.contains("connect_self"));
+        assert!(screen.contains("sampler panicked"));
+    }
+}
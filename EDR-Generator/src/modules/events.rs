@@ -0,0 +1,162 @@
+use crate::modules::common::GenerationError;
+use crate::modules::report::RunSummary;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Event describing the progress of a run. Events are sent to every subscriber of the run's
+/// `EventBus`, so front-ends (the console, the server, a dashboard) present a run without the
+/// TaskCommander knowing about them.
+///
+/// # Variants
+///
+/// - `InstructionStarted`: an instruction is about to be executed, `index` counts the instructions
+///   read from 1 and `step` is the name of the structured scenario step it belongs to
+/// - `InstructionFinished`: an instruction was executed, whether or not it succeeded
+/// - `Error`: an instruction failed
+/// - `Skipped`: an instruction was not executed, the kind of the error is the reason
+/// - `Warning`: a problem which is not attributed to an instruction
+/// - `Progress`: counts of the run after an instruction was read
+/// - `Connected`: a controller connected to the server, its instructions form a run of their own
+/// - `RunFinished`: the run ended, it is the last event of a run
+#[derive(Debug, Clone)]
+pub enum RunEvent {
+    InstructionStarted { index: usize, instruction: String, step: Option<String> },
+    InstructionFinished { index: usize, instruction: String, elapsed: Duration },
+    Error(GenerationError),
+    Skipped(GenerationError),
+    Warning(String),
+    Progress { processed: usize, errors: usize, skipped: usize },
+    Connected(SocketAddr),
+    RunFinished(Box<RunSummary>),
+}
+
+/// Structure defining the channel events of a run are sent over. Clones share their subscribers,
+/// so events emitted by worker threads reach the same subscribers.
+///
+/// # Parameters
+///
+/// - `subscribers`: senders of the subscribed receivers
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<RunEvent>>>>,
+}
+
+impl EventBus {
+    /// Instantiates an event bus without subscribers, events are discarded until one subscribes
+    ///
+    /// # Returns
+    ///
+    /// EventBus instance
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Subscribes to the events emitted from now on
+    ///
+    /// # Returns
+    ///
+    /// Receiver of the events, disconnected once the bus is closed
+    pub fn subscribe(&self) -> Receiver<RunEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends an event to every subscriber. Subscribers whose receiver was dropped are removed.
+    ///
+    /// # Parameters
+    ///
+    /// - `event`: event to send
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn emit(&self, event: RunEvent) {
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Disconnects every subscriber, e.g. so a consumer stops once the run is over even though an
+    /// abandoned instruction still holds a clone of the bus. Later events are discarded.
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// Prints an event to the console. This is the only place run events are worded, errors, skipped
+/// instructions, and warnings are printed to stderr and the end of a run to stdout. Instruction
+/// and progress events are not printed.
+///
+/// # Parameters
+///
+/// - `event`: event to print
+///
+/// # Returns
+///
+/// Nothing
+pub fn print(event: &RunEvent) {
+    match event {
+        RunEvent::Error(error) => eprintln!("{}", error),
+        RunEvent::Skipped(error) => eprintln!("Skipped: {}", error),
+        RunEvent::Warning(message) => eprintln!("{}", message),
+        RunEvent::Connected(controller) => println!("Connection from {}", controller),
+        RunEvent::RunFinished(summary) => {
+            if summary.instructions == 0 {
+                eprintln!("Input File was empty or was of bad format. No Commands Processed")
+            } else {
+                println!("Done. {} Instructions Found. Encountered {} error(s), skipped {}.", summary.instructions, summary.errors, summary.skipped)
+            }
+            if !summary.scenario_sha256.is_empty() {
+                println!("Scenario SHA-256: {}", summary.scenario_sha256);
+            }
+        }
+        RunEvent::InstructionStarted { .. } | RunEvent::InstructionFinished { .. } | RunEvent::Progress { .. } => {}
+    }
+}
+
+/// Prints the events of a receiver to the console on a thread of its own, see `print`
+///
+/// # Parameters
+///
+/// - `receiver`: events to print
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Handle of the thread, which ends once the receiver is disconnected
+/// - `Err`: The thread could not be started
+pub fn console(receiver: Receiver<RunEvent>) -> Result<JoinHandle<()>, GenerationError> {
+    let handle = thread::Builder::new().name("console".to_string()).spawn(move || {
+        for event in receiver {
+            print(&event);
+        }
+    })?;
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_broadcast() {
+        let bus = EventBus::new();
+        bus.emit(RunEvent::Warning("nobody is listening".to_string()));
+        let first = bus.subscribe();
+        let second = bus.clone().subscribe();
+        drop(bus.subscribe());
+        bus.emit(RunEvent::Progress { processed: 1, errors: 0, skipped: 0 });
+        assert!(matches!(first.try_recv(), Ok(RunEvent::Progress { processed: 1, .. })));
+        assert!(matches!(second.try_recv(), Ok(RunEvent::Progress { processed: 1, .. })));
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 2);
+        bus.close();
+        assert!(first.recv().is_err());
+    }
+}
@@ -1,8 +1,18 @@
-use std::fs::{OpenOptions, remove_file, canonicalize};
+use std::fs::{self, OpenOptions, remove_file, canonicalize};
 use crate::modules::common::{GenerationError, get_time};
 use std::io::Write;
 use crate::modules::logger::Log;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Payload written by an `ads_write` instruction when none is given
+pub const DEFAULT_ADS_PAYLOAD: &str = "EDR generator alternate data stream payload";
+
+/// Windows file attributes a `chmod_file` instruction may set, by name
+const ATTRIBUTES: [(&str, u32); 3] = [("readonly", 0x1), ("hidden", 0x2), ("system", 0x4)];
+
+/// Windows error returned when the privilege to create symbolic links is not held
+#[cfg(windows)]
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
 
 /// Create a new file at a given path
 ///
@@ -59,6 +69,355 @@ pub fn delete_file(path: &String, ) -> Result< Log, GenerationError> {
     Ok(adapt_log_file("Delete File".to_string(), orig_path))
 }
 
+/// Create a new directory at a given path. The directory must not exist yet.
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system directory path
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the directory was created.
+/// - `Err`: There was an issue creating the directory. (Already exists, parent does not exist or no permissions)
+pub fn new_directory(path: &String) -> Result<Log, GenerationError> {
+    fs::create_dir(path)?;
+    Ok(adapt_log_file("New Directory".to_string(), canonicalize(path)?.into_os_string().into_string()?))
+}
+
+/// Create a new file at a given path with the given contents
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name)
+/// - `contents`: Data written to the file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the file was created.
+/// - `Err`: There was an issue creating the file. (Already exists, qualified path does not exist or no permissions)
+pub fn write_file(path: &String, contents: &[u8]) -> Result<Log, GenerationError> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents)?;
+    Ok(adapt_log_file("New File".to_string(), canonicalize(path)?.into_os_string().into_string()?))
+}
+
+/// Read the contents of a file at a given path
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name)
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The contents of the file, and Log data confirming the file was read.
+/// - `Err`: There was an issue reading the file. (File does not exist or no permissions)
+pub fn read_file(path: &String) -> Result<(Vec<u8>, Log), GenerationError> {
+    let contents = fs::read(path)?;
+    Ok((contents, adapt_log_file("Read File".to_string(), canonicalize(path)?.into_os_string().into_string()?)))
+}
+
+/// Replace the contents of an existing file at a given path
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name)
+/// - `contents`: Data replacing the contents of the file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the file was overwritten.
+/// - `Err`: There was an issue overwriting the file. (File does not exist or no permissions)
+pub fn overwrite_file(path: &String, contents: &[u8]) -> Result<Log, GenerationError> {
+    let mut file = OpenOptions::new().write(true).truncate(true).open(path)?;
+    file.write_all(contents)?;
+    Ok(adapt_log_file("Overwrite File".to_string(), canonicalize(path)?.into_os_string().into_string()?))
+}
+
+/// Rename a file. The activity records the previous name of the file.
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name)
+/// - `new_path`: A string containing the new file path (including name)
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the new path confirming the file was renamed.
+/// - `Err`: There was an issue renaming the file. (File does not exist, new path exists or no permissions)
+pub fn rename_file(path: &String, new_path: &String) -> Result<Log, GenerationError> {
+    if Path::new(new_path).exists() {
+        return Err(GenerationError::new("io".to_string(), format!("Unable to rename {}: {} already exists", path, new_path)));
+    }
+    fs::rename(path, new_path)?;
+    let previous = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    Ok(adapt_log_file(format!("Rename File (from {})", previous), canonicalize(new_path)?.into_os_string().into_string()?))
+}
+
+/// Determines if a name may be used for an alternate data stream: it must not be empty or contain
+/// path separators or the `:` which separates the stream type
+///
+/// # Parameters
+///
+/// - `stream`: name of the stream
+///
+/// # Returns
+///
+/// True if the name is valid
+pub fn valid_stream_name(stream: &str) -> bool {
+    !stream.trim().is_empty() && !stream.contains(&[':', '/', '\\', '\0'][..])
+}
+
+/// Write a payload to an alternate data stream of a file (`<path>:<stream>`), replacing the stream
+/// if it exists. The file is created when it does not exist. Alternate data streams are an NTFS
+/// feature, on other platforms the path would name a regular file so no attempt is made.
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name) of the file hosting the stream
+/// - `stream`: name of the stream
+/// - `contents`: Data written to the stream
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the file when it was created, followed by Log data of the stream recording
+///   the full stream path
+/// - `Err`: The stream name is invalid, or there was an issue writing the stream. (Not an NTFS
+///   volume, qualified path does not exist or no permissions)
+#[cfg(windows)]
+pub fn ads_write(path: &String, stream: &String, contents: &[u8]) -> Result<Vec<Log>, GenerationError> {
+    if !valid_stream_name(stream) {
+        return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid alternate data stream name", stream)));
+    }
+    let mut logs = vec![];
+    if !Path::new(path).exists() {
+        logs.push(new_file(path)?);
+    }
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(format!("{}:{}", path, stream))?;
+    file.write_all(contents)?;
+    let host = canonicalize(path)?.into_os_string().into_string()?;
+    logs.push(adapt_log_file("Alternate Data Stream Written".to_string(), format!("{}:{}", host, stream)));
+    Ok(logs)
+}
+
+/// Alternate data streams are only supported on Windows (NTFS)
+#[cfg(not(windows))]
+pub fn ads_write(_path: &String, _stream: &String, _contents: &[u8]) -> Result<Vec<Log>, GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Alternate data streams are only supported on Windows (NTFS)".to_string()))
+}
+
+/// Permissions set by a `chmod_file` instruction
+///
+/// # Variants
+///
+/// - `Mode`: Unix mode bits, written in octal (e.g. `0400`)
+/// - `Attributes`: Windows file attributes, written as names joined by `+` (e.g. `readonly+hidden`)
+///   or `normal` for none
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilePermissions {
+    Mode(u32),
+    Attributes(u32),
+}
+
+impl FilePermissions {
+    /// Parses the permissions of a `chmod_file` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: octal mode bits, or attribute names
+    ///
+    /// # Returns
+    ///
+    /// The permissions, or None when the value is neither
+    pub fn parse(value: &str) -> Option<FilePermissions> {
+        let value = value.trim();
+        if !value.is_empty() && value.chars().all(|digit| digit.is_digit(8)) {
+            return u32::from_str_radix(value, 8).ok().filter(|mode| *mode <= 0o7777).map(FilePermissions::Mode);
+        }
+        if value.eq_ignore_ascii_case("normal") {
+            return Some(FilePermissions::Attributes(0));
+        }
+        let mut flags = 0;
+        for name in value.split('+') {
+            flags |= ATTRIBUTES.iter().find(|(attribute, _)| attribute.eq_ignore_ascii_case(name.trim()))?.1;
+        }
+        Some(FilePermissions::Attributes(flags))
+    }
+
+    /// Determines if the permissions can be applied on this platform
+    ///
+    /// # Returns
+    ///
+    /// True for mode bits on Unix and attributes on Windows
+    pub fn supported(&self) -> bool {
+        match self {
+            FilePermissions::Mode(_) => cfg!(unix),
+            FilePermissions::Attributes(_) => cfg!(windows)
+        }
+    }
+}
+
+impl std::fmt::Display for FilePermissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FilePermissions::Mode(mode) => write!(f, "{:04o}", mode),
+            FilePermissions::Attributes(0) => write!(f, "normal"),
+            FilePermissions::Attributes(flags) => {
+                let names: Vec<&str> = ATTRIBUTES.iter().filter(|(_, flag)| flags & flag != 0).map(|(name, _)| *name).collect();
+                write!(f, "{}", names.join("+"))
+            }
+        }
+    }
+}
+
+/// Change the permissions of a file: the mode bits on Unix, or the read-only, hidden, and system
+/// attributes on Windows (other attributes are kept). The activity records the previous and new
+/// permissions, e.g. `Permissions Changed (0644 to 0400)`.
+///
+/// # Parameters
+///
+/// - `path`: A string containing the system file path (including name)
+/// - `permissions`: permissions to set
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the permissions were changed.
+/// - `Err`: There was an issue changing the permissions. (File does not exist, no permissions, or
+///   the permissions are not supported on this platform)
+pub fn chmod_file(path: &String, permissions: &FilePermissions) -> Result<Log, GenerationError> {
+    if !permissions.supported() {
+        let message = match permissions {
+            FilePermissions::Mode(_) => "Mode bits are only supported on Unix",
+            FilePermissions::Attributes(_) => "File attributes are only supported on Windows"
+        };
+        return Err(GenerationError::new("unsupported_platform".to_string(), message.to_string()));
+    }
+    let previous = set_permissions(path, permissions)?;
+    Ok(adapt_log_file(format!("Permissions Changed ({} to {})", previous, permissions), canonicalize(path)?.into_os_string().into_string()?))
+}
+
+/// Applies the mode bits of a file, returning the previous mode bits
+#[cfg(unix)]
+fn set_permissions(path: &String, permissions: &FilePermissions) -> Result<FilePermissions, GenerationError> {
+    use std::os::unix::fs::PermissionsExt;
+    let previous = fs::metadata(path)?.permissions().mode() & 0o7777;
+    if let FilePermissions::Mode(mode) = permissions {
+        fs::set_permissions(path, fs::Permissions::from_mode(*mode))?;
+    }
+    Ok(FilePermissions::Mode(previous))
+}
+
+/// Applies the attributes of a file, returning the previous attributes
+#[cfg(windows)]
+fn set_permissions(path: &String, permissions: &FilePermissions) -> Result<FilePermissions, GenerationError> {
+    use std::ffi::OsStr;
+    use std::iter::once;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    use winapi::um::fileapi::SetFileAttributesW;
+    use winapi::um::winnt::FILE_ATTRIBUTE_NORMAL;
+
+    let current = fs::metadata(path)?.file_attributes();
+    let managed = ATTRIBUTES.iter().fold(0, |flags, (_, flag)| flags | flag);
+    if let FilePermissions::Attributes(flags) = permissions {
+        let mut attributes = (current & !managed) | flags;
+        if attributes == 0 {
+            attributes = FILE_ATTRIBUTE_NORMAL;
+        }
+        let wide: Vec<u16> = OsStr::new(path).encode_wide().chain(once(0)).collect();
+        if unsafe { SetFileAttributesW(wide.as_ptr(), attributes) } == 0 {
+            return Err(GenerationError::from(std::io::Error::last_os_error()));
+        }
+    }
+    Ok(FilePermissions::Attributes(current & managed))
+}
+
+/// Create a symbolic link pointing to a target. The target is recorded as given and does not need
+/// to exist, so dangling links (e.g. for symlink races) can be created as well. On Windows a
+/// directory link is created when the target is a directory, which requires the
+/// `SeCreateSymbolicLinkPrivilege` (held by Administrators, or by every user in Developer Mode).
+///
+/// # Parameters
+///
+/// - `target`: A string containing the path the link points to
+/// - `link`: A string containing the system file path (including name) of the new link
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the link, the activity records the target.
+/// - `Err`: There was an issue creating the link. (Link already exists, qualified path does not
+///   exist, no permissions, or the privilege is not held)
+pub fn symlink(target: &String, link: &String) -> Result<Log, GenerationError> {
+    create_symlink(target, link)?;
+    Ok(adapt_log_file(format!("New Symbolic Link (to {})", target), link_path(link)?))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &String, link: &String) -> Result<(), GenerationError> {
+    Ok(std::os::unix::fs::symlink(target, link)?)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &String, link: &String) -> Result<(), GenerationError> {
+    let result = if Path::new(target).is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    };
+    match result {
+        Err(e) if e.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) => {
+            Err(GenerationError::new("user_permissions".to_string(), "Creating symbolic links requires the SeCreateSymbolicLinkPrivilege (Administrator or Developer Mode)".to_string()))
+        }
+        result => Ok(result?)
+    }
+}
+
+/// Create a hard link to an existing file, so the file is reachable under a second path
+///
+/// # Parameters
+///
+/// - `target`: A string containing the system file path (including name) of the existing file
+/// - `link`: A string containing the system file path (including name) of the new link
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the link, the activity records the target.
+/// - `Err`: There was an issue creating the link. (Target does not exist, link already exists,
+///   the paths are on different volumes, or no permissions)
+pub fn hardlink(target: &String, link: &String) -> Result<Log, GenerationError> {
+    fs::hard_link(target, link)?;
+    let target = canonicalize(target)?.into_os_string().into_string()?;
+    Ok(adapt_log_file(format!("New Hard Link (to {})", target), link_path(link)?))
+}
+
+/// Builds the absolute path of a link without following it, so the event records the link itself
+/// rather than its target
+fn link_path(link: &String) -> Result<String, GenerationError> {
+    let path = Path::new(link);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => canonicalize(parent)?,
+        _ => canonicalize(".")?
+    };
+    Ok(directory.join(path.file_name().unwrap_or_default()).into_os_string().into_string()?)
+}
+
 /// Adapts a file event into a log struct used for logging
 ///
 /// # Parameters
@@ -86,7 +445,11 @@ fn adapt_log_file(activity: String, file_path: String) -> Log {
         dest_addr: String::from(""),
         dest_port: String::from(""),
         bytes_sent: String::from(""),
-        protocol: String::from("")
+        protocol: String::from(""),
+        address_family: String::from(""),
+        stdout: String::from(""),
+        stderr: String::from(""),
+        exit_code: String::from("")
     }
 }
 
@@ -163,4 +526,94 @@ mod tests {
         assert!(delete_file(&String::from(&path)).is_err());
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn file_rewritten_and_renamed()-> Result<(), GenerationError> {
+        let path = rng_filename();
+        let renamed = format!("{}.locked", path);
+        assert!(write_file(&path, b"hello").is_ok());
+        assert!(write_file(&path, b"hello").is_err());
+        let (contents, log) = read_file(&path)?;
+        assert_eq!((contents.as_slice(), log.activity.as_str()), (&b"hello"[..], "Read File"));
+        assert!(overwrite_file(&path, b"olleh!").is_ok());
+        let log = rename_file(&path, &renamed)?;
+        assert_eq!(log.activity, format!("Rename File (from {})", path));
+        assert!(log.file_path.ends_with(&renamed));
+        assert_eq!(fs::read(&renamed)?, b"olleh!");
+        assert!(overwrite_file(&path, b"hello").is_err());
+        remove_file(&renamed)?;
+        Ok(())
+    }
+
+    #[test]
+    fn alternate_data_stream()-> Result<(), GenerationError> {
+        assert!(valid_stream_name("hidden.exe"));
+        assert!(!valid_stream_name("hidden:$DATA"));
+        assert!(!valid_stream_name("..\\hidden"));
+        assert!(!valid_stream_name(" "));
+        let path = rng_filename();
+        let result = ads_write(&path, &String::from("hidden"), b"payload");
+        if cfg!(windows) {
+            let logs = result?;
+            assert_eq!(logs.iter().map(|log| log.activity.as_str()).collect::<Vec<_>>(), vec!["New File", "Alternate Data Stream Written"]);
+            assert!(logs[1].file_path.ends_with(&format!("{}:hidden", path)));
+            assert_eq!(fs::read(format!("{}:hidden", path))?, b"payload");
+            assert_eq!(fs::read(&path)?, b"");
+            remove_file(&path)?;
+        } else {
+            assert!(matches!(result, Err(e) if e.kind == "unsupported_platform"));
+            assert!(!Path::new(&path).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn links_created()-> Result<(), GenerationError> {
+        let target = rng_filename();
+        let hard = format!("{}.hard", target);
+        write_file(&target, b"hello")?;
+        let log = hardlink(&target, &hard)?;
+        assert!(log.activity.starts_with("New Hard Link (to "));
+        assert!(log.activity.ends_with(&format!("{})", target)));
+        assert!(log.file_path.ends_with(&hard));
+        assert_eq!(fs::read(&hard)?, b"hello");
+        assert!(hardlink(&target, &hard).is_err());
+        assert!(hardlink(&format!("{}.missing", target), &format!("{}.other", target)).is_err());
+        if cfg!(unix) {
+            let soft = format!("{}.soft", target);
+            let log = symlink(&String::from("does_not_exist.txt"), &soft)?;
+            assert_eq!(log.activity, "New Symbolic Link (to does_not_exist.txt)");
+            assert!(log.file_path.ends_with(&soft));
+            assert_eq!(fs::read_link(&soft)?, PathBuf::from("does_not_exist.txt"));
+            remove_file(&soft)?;
+        }
+        remove_file(&hard)?;
+        remove_file(&target)?;
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_changed()-> Result<(), GenerationError> {
+        assert_eq!(FilePermissions::parse("0400"), Some(FilePermissions::Mode(0o400)));
+        assert_eq!(FilePermissions::parse("ReadOnly+hidden"), Some(FilePermissions::Attributes(0x3)));
+        assert_eq!(FilePermissions::parse("normal").map(|permissions| permissions.to_string()), Some("normal".to_string()));
+        assert_eq!(FilePermissions::parse("hidden+readonly").map(|permissions| permissions.to_string()), Some("readonly+hidden".to_string()));
+        assert_eq!(FilePermissions::parse("0800"), None);
+        assert_eq!(FilePermissions::parse("17777"), None);
+        assert_eq!(FilePermissions::parse("readonly+archive"), None);
+
+        let path = rng_filename();
+        write_file(&path, b"hello")?;
+        let (native, foreign) = if cfg!(windows) { ("readonly", "0400") } else { ("0400", "readonly") };
+        let log = chmod_file(&path, &FilePermissions::parse(native).unwrap())?;
+        assert!(log.activity.starts_with("Permissions Changed ("));
+        assert!(log.activity.ends_with(&format!(" to {})", native)));
+        assert!(fs::metadata(&path)?.permissions().readonly());
+        assert!(matches!(chmod_file(&path, &FilePermissions::parse(foreign).unwrap()), Err(e) if e.kind == "unsupported_platform"));
+        let mut permissions = fs::metadata(&path)?.permissions();
+        permissions.set_readonly(false);
+        fs::set_permissions(&path, permissions)?;
+        remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+use crate::modules::logger::{Log, LogError, LogRun, LogUsage};
+use crate::modules::common::GenerationError;
+use crate::modules::template::Template;
+use chrono::{DateTime, TimeZone, Utc};
+use csv::WriterBuilder;
+use serde::Serialize;
+
+const VENDOR: &str = "x24git";
+const PRODUCT: &str = "EDR Generator";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Timestamp layout shared by the CEF `rt` and LEEF `devTime` keys
+const DEVICE_TIME_FORMAT: &str = "%b %d %Y %H:%M:%S%.3f UTC";
+const LEEF_TIME_FORMAT: &str = "MMM dd yyyy HH:mm:ss.SSS zzz";
+
+/// Output formats supported by the Logger
+///
+/// # Variants
+///
+/// - `Csv`: comma separated values with a header row (default)
+/// - `Cef`: ArcSight Common Event Format, one event per line
+/// - `Leef`: QRadar Log Event Extended Format 1.0, one event per line
+/// - `Template`: user supplied template, one rendered record per line
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFormat {
+    Csv,
+    Cef,
+    Leef,
+    Template(Template),
+}
+
+impl LogFormat {
+    /// Retrieves the output format matching a user supplied name
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the format (case insensitive)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The matching output format
+    /// - `Err`: The name does not match a supported format
+    pub fn from_name(name: &str) -> Result<LogFormat, GenerationError> {
+        match name.to_lowercase().as_str() {
+            "csv" => Ok(LogFormat::Csv),
+            "cef" => Ok(LogFormat::Cef),
+            "leef" => Ok(LogFormat::Leef),
+            _ => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid output format (csv, cef, leef)", name)))
+        }
+    }
+}
+
+/// Formats a record as a single csv row, used when forwarding csv records to output sinks
+///
+/// # Parameters
+///
+/// - `record`: record to serialize
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A single csv row without a line terminator
+/// - `Err`: The record could not be serialized
+pub fn csv_line<S: Serialize>(record: &S) -> Result<String, GenerationError> {
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+    let serialized = writer.serialize(record).ok().and_then(|_| writer.into_inner().ok());
+    match serialized.map(String::from_utf8) {
+        Some(Ok(line)) => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+        _ => Err(GenerationError::new("logging".to_string(), "Unable to Serialize Log Message".to_string()))
+    }
+}
+
+/// Formats an event as a CEF record populating the standard extension keys
+///
+/// # Parameters
+///
+/// - `data`: A Log structure containing the event
+///
+/// # Returns
+///
+/// A single CEF line without a line terminator
+pub fn cef_event(data: &Log) -> String {
+    let mut extension = vec![
+        ("rt", device_time(&data.timestamp)),
+        ("act", data.activity.clone()),
+        ("duser", data.username.clone()),
+        ("dproc", data.proc_name.clone()),
+        ("dpid", data.proc_id.clone()),
+    ];
+    if !data.proc_cmd.is_empty() {
+        extension.push(("cs1Label", "Command Line".to_string()));
+        extension.push(("cs1", data.proc_cmd.clone()));
+    }
+    extension.push(("fname", file_name(&data.file_path)));
+    extension.push(("filePath", data.file_path.clone()));
+    extension.push(("src", data.source_addr.clone()));
+    extension.push(("spt", data.source_port.clone()));
+    extension.push(("dst", data.dest_addr.clone()));
+    extension.push(("dpt", data.dest_port.clone()));
+    extension.push(("out", data.bytes_sent.clone()));
+    extension.push(("proto", data.protocol.clone()));
+    if !data.address_family.is_empty() {
+        extension.push(("cs2Label", "Address Family".to_string()));
+        extension.push(("cs2", data.address_family.clone()));
+    }
+    if !data.stdout.is_empty() {
+        extension.push(("cs3Label", "Standard Output".to_string()));
+        extension.push(("cs3", data.stdout.clone()));
+    }
+    if !data.stderr.is_empty() {
+        extension.push(("cs4Label", "Standard Error".to_string()));
+        extension.push(("cs4", data.stderr.clone()));
+    }
+    if !data.exit_code.is_empty() {
+        extension.push(("cs5Label", "Exit Code".to_string()));
+        extension.push(("cs5", data.exit_code.clone()));
+    }
+    cef_line(&data.activity, 3, extension)
+}
+
+/// Formats an error as a CEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogError structure containing the error
+///
+/// # Returns
+///
+/// A single CEF line without a line terminator
+pub fn cef_error(data: &LogError) -> String {
+    cef_line(&data.t, 7, vec![
+        ("rt", device_time(&data.timestamp)),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats an intent or completion record of a destructive instruction as a CEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogError structure containing the instruction and its outcome
+///
+/// # Returns
+///
+/// A single CEF line without a line terminator
+pub fn cef_intent(data: &LogError) -> String {
+    cef_line(&data.t, 1, vec![
+        ("rt", device_time(&data.timestamp)),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats a run header or run summary as a CEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogRun structure containing the run information
+///
+/// # Returns
+///
+/// A single CEF line without a line terminator
+pub fn cef_run(data: &LogRun) -> String {
+    cef_line(&data.t, 1, vec![
+        ("rt", device_time(&data.timestamp)),
+        ("fname", file_name(&data.scenario)),
+        ("filePath", data.scenario.clone()),
+        ("fileHash", data.scenario_sha256.clone()),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats a resource usage sample as a CEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogUsage structure containing the sample
+///
+/// # Returns
+///
+/// A single CEF line without a line terminator
+pub fn cef_usage(data: &LogUsage) -> String {
+    cef_line(&data.t, 1, vec![
+        ("rt", device_time(&data.timestamp)),
+        ("dproc", data.proc_name.clone()),
+        ("dpid", data.proc_id.clone()),
+        ("cfp1Label", "CPU Percent".to_string()),
+        ("cfp1", data.cpu_percent.clone()),
+        ("cn1Label", "Memory KB".to_string()),
+        ("cn1", data.memory_kb.clone()),
+        ("cn2Label", "Disk Read Bytes".to_string()),
+        ("cn2", data.disk_read_bytes.clone()),
+        ("cn3Label", "Disk Written Bytes".to_string()),
+        ("cn3", data.disk_written_bytes.clone()),
+    ])
+}
+
+/// Formats an event as a LEEF record populating the predefined attribute keys
+///
+/// # Parameters
+///
+/// - `data`: A Log structure containing the event
+///
+/// # Returns
+///
+/// A single LEEF line without a line terminator
+pub fn leef_event(data: &Log) -> String {
+    leef_line(&data.activity, vec![
+        ("devTime", device_time(&data.timestamp)),
+        ("devTimeFormat", LEEF_TIME_FORMAT.to_string()),
+        ("sev", "3".to_string()),
+        ("usrName", data.username.clone()),
+        ("procName", data.proc_name.clone()),
+        ("procId", data.proc_id.clone()),
+        ("procCmd", data.proc_cmd.clone()),
+        ("fileName", file_name(&data.file_path)),
+        ("filePath", data.file_path.clone()),
+        ("src", data.source_addr.clone()),
+        ("srcPort", data.source_port.clone()),
+        ("dst", data.dest_addr.clone()),
+        ("dstPort", data.dest_port.clone()),
+        ("srcBytes", data.bytes_sent.clone()),
+        ("proto", data.protocol.clone()),
+        ("addrFamily", data.address_family.clone()),
+        ("stdout", data.stdout.clone()),
+        ("stderr", data.stderr.clone()),
+        ("exitCode", data.exit_code.clone()),
+    ])
+}
+
+/// Formats an error as a LEEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogError structure containing the error
+///
+/// # Returns
+///
+/// A single LEEF line without a line terminator
+pub fn leef_error(data: &LogError) -> String {
+    leef_line(&data.t, vec![
+        ("devTime", device_time(&data.timestamp)),
+        ("devTimeFormat", LEEF_TIME_FORMAT.to_string()),
+        ("sev", "7".to_string()),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats an intent or completion record of a destructive instruction as a LEEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogError structure containing the instruction and its outcome
+///
+/// # Returns
+///
+/// A single LEEF line without a line terminator
+pub fn leef_intent(data: &LogError) -> String {
+    leef_line(&data.t, vec![
+        ("devTime", device_time(&data.timestamp)),
+        ("devTimeFormat", LEEF_TIME_FORMAT.to_string()),
+        ("sev", "1".to_string()),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats a run header or run summary as a LEEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogRun structure containing the run information
+///
+/// # Returns
+///
+/// A single LEEF line without a line terminator
+pub fn leef_run(data: &LogRun) -> String {
+    leef_line(&data.t, vec![
+        ("devTime", device_time(&data.timestamp)),
+        ("devTimeFormat", LEEF_TIME_FORMAT.to_string()),
+        ("sev", "1".to_string()),
+        ("filePath", data.scenario.clone()),
+        ("fileHash", data.scenario_sha256.clone()),
+        ("msg", data.message.clone()),
+    ])
+}
+
+/// Formats a resource usage sample as a LEEF record
+///
+/// # Parameters
+///
+/// - `data`: A LogUsage structure containing the sample
+///
+/// # Returns
+///
+/// A single LEEF line without a line terminator
+pub fn leef_usage(data: &LogUsage) -> String {
+    leef_line(&data.t, vec![
+        ("devTime", device_time(&data.timestamp)),
+        ("devTimeFormat", LEEF_TIME_FORMAT.to_string()),
+        ("sev", "1".to_string()),
+        ("procName", data.proc_name.clone()),
+        ("procId", data.proc_id.clone()),
+        ("cpuPercent", data.cpu_percent.clone()),
+        ("memoryKB", data.memory_kb.clone()),
+        ("diskReadBytes", data.disk_read_bytes.clone()),
+        ("diskWrittenBytes", data.disk_written_bytes.clone()),
+    ])
+}
+
+/// Assembles a CEF line from its header values and extension pairs. Empty extension values are
+/// omitted.
+///
+/// # Parameters
+///
+/// - `name`: event name, also used as the signature id
+/// - `severity`: CEF severity (0-10)
+/// - `extension`: key value pairs for the extension section
+///
+/// # Returns
+///
+/// A single CEF line
+fn cef_line(name: &str, severity: u8, extension: Vec<(&str, String)>) -> String {
+    let extension: Vec<String> = extension.iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("{}={}", key, escape_cef_value(value)))
+        .collect();
+    format!("CEF:0|{}|{}|{}|{}|{}|{}|{}",
+            escape_cef_header(VENDOR), escape_cef_header(PRODUCT), escape_cef_header(VERSION),
+            escape_cef_header(name), escape_cef_header(name), severity, extension.join(" "))
+}
+
+/// Assembles a tab delimited LEEF 1.0 line from its event id and attributes. Empty attribute values
+/// are omitted.
+///
+/// # Parameters
+///
+/// - `event_id`: LEEF event id
+/// - `attributes`: key value pairs for the attribute section
+///
+/// # Returns
+///
+/// A single LEEF line
+fn leef_line(event_id: &str, attributes: Vec<(&str, String)>) -> String {
+    let attributes: Vec<String> = attributes.iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("{}={}", key, escape_leef_value(value)))
+        .collect();
+    format!("LEEF:1.0|{}|{}|{}|{}|{}",
+            escape_leef_header(VENDOR), escape_leef_header(PRODUCT), escape_leef_header(VERSION),
+            escape_leef_header(event_id), attributes.join("\t"))
+}
+
+/// Converts an event timestamp (RFC 3339 or Unix epoch seconds) into the device time layout
+///
+/// # Parameters
+///
+/// - `timestamp`: timestamp as recorded in the log
+///
+/// # Returns
+///
+/// The formatted time, or the original value if it could not be parsed
+fn device_time(timestamp: &str) -> String {
+    if let Ok(time) = DateTime::parse_from_rfc3339(timestamp) {
+        return time.with_timezone(&Utc).format(DEVICE_TIME_FORMAT).to_string();
+    }
+    match timestamp.parse::<i64>().ok().and_then(|seconds| Utc.timestamp_opt(seconds, 0).single()) {
+        Some(time) => time.format(DEVICE_TIME_FORMAT).to_string(),
+        None => timestamp.to_string()
+    }
+}
+
+/// Retrieves the final component of a file path, accepting both `/` and `\` separators
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or("").to_string()
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_cef_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('=', "\\=").replace('\r', "\\r").replace('\n', "\\n")
+}
+
+fn escape_leef_header(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn escape_leef_value(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_log() -> Log {
+        Log{
+            t: String::from("Information"),
+            timestamp: String::from("2021-04-15T05:49:10.109806100+00:00"),
+            username: String::from("user1"),
+            proc_name: String::from("edr_generator"),
+            proc_cmd: String::from("edr_generator a=b.csv"),
+            proc_id: String::from("35440"),
+            activity: String::from("Network Connection"),
+            file_path: String::from("C:\\Users\\x24\\test.txt"),
+            source_addr: String::from("127.0.0.1"),
+            source_port: String::from("12712"),
+            dest_addr: String::from("127.0.0.1"),
+            dest_port: String::from("12711"),
+            bytes_sent: String::from("11"),
+            protocol: String::from("TCP/IP"),
+            address_family: String::from("IPv4"),
+            stdout: String::from(""),
+            stderr: String::from(""),
+            exit_code: String::from("")
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(LogFormat::from_name("CEF").unwrap(), LogFormat::Cef);
+        assert_eq!(LogFormat::from_name("leef").unwrap(), LogFormat::Leef);
+        assert!(LogFormat::from_name("xml").is_err());
+    }
+
+    #[test]
+    fn cef_extension_keys() {
+        let line = cef_event(&sample_log());
+        assert!(line.starts_with("CEF:0|x24git|EDR Generator|"));
+        assert!(line.contains("|Network Connection|Network Connection|3|"));
+        assert!(line.contains("rt=Apr 15 2021 05:49:10.109 UTC"));
+        assert!(line.contains("duser=user1"));
+        assert!(line.contains("cs1=edr_generator a\\=b.csv"));
+        assert!(line.contains("fname=test.txt"));
+        assert!(line.contains("filePath=C:\\\\Users\\\\x24\\\\test.txt"));
+        assert!(line.contains("src=127.0.0.1 spt=12712 dst=127.0.0.1 dpt=12711"));
+        assert!(line.ends_with("cs2Label=Address Family cs2=IPv4"));
+    }
+
+    #[test]
+    fn leef_attributes() {
+        let mut log = sample_log();
+        log.file_path = String::from("");
+        let line = leef_event(&log);
+        assert!(line.starts_with("LEEF:1.0|x24git|EDR Generator|"));
+        assert!(line.contains("|Network Connection|devTime="));
+        assert!(line.contains("\tusrName=user1\t"));
+        assert!(line.contains("\tsrcPort=12712\t"));
+        assert!(line.contains("\tdstPort=12711\t"));
+        assert!(line.ends_with("\taddrFamily=IPv4"));
+        assert!(!line.contains("filePath="));
+    }
+
+    #[test]
+    fn csv_row() {
+        let line = csv_line(&sample_log()).unwrap();
+        assert!(line.starts_with("Information,2021-04-15T05:49:10.109806100+00:00,user1,"));
+        assert!(line.ends_with(",11,TCP/IP,IPv4,,,"));
+    }
+
+    #[test]
+    fn usage_records() {
+        let usage = LogUsage {
+            t: String::from("Resource Usage"),
+            timestamp: String::from("1618465748"),
+            proc_name: String::from("cmd.exe"),
+            proc_id: String::from("4242"),
+            cpu_percent: String::from("12.50"),
+            memory_kb: String::from("3072"),
+            disk_read_bytes: String::from("0"),
+            disk_written_bytes: String::from("512"),
+        };
+        let line = cef_usage(&usage);
+        assert!(line.contains("|Resource Usage|Resource Usage|1|"));
+        assert!(line.contains("dpid=4242 cfp1Label=CPU Percent cfp1=12.50 cn1Label=Memory KB cn1=3072"));
+        assert!(leef_usage(&usage).contains("\tmemoryKB=3072\t"));
+    }
+
+    #[test]
+    fn epoch_device_time() {
+        assert_eq!(device_time("1618465748"), "Apr 15 2021 05:49:08.000 UTC");
+        assert_eq!(device_time("unknown"), "unknown");
+        assert_eq!(device_time("9223372036854775807"), "9223372036854775807");
+    }
+}
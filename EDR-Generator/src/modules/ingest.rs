@@ -0,0 +1,426 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use crate::modules::report::ElasticsearchReporter;
+use crate::modules::template::Template;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default number of milliseconds an event is searched for before it is counted as missing
+const DEFAULT_TIMEOUT_MS: u64 = 60000;
+
+/// Default number of milliseconds between two searches for the same event
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+/// Largest number of searches sent in a single `_msearch` request
+const MAX_BATCH: usize = 100;
+
+/// An event handed to the probe by the Logger along with the time it was logged
+pub type LoggedEvent = (Log, Instant);
+
+/// Structure defining the configuration of an ingest lag probe, a YAML or JSON file
+///
+/// # Parameters
+///
+/// - `url`: URL of the Elasticsearch or OpenSearch index (or index pattern) the SIEM stores the
+///   events of the EDR agent in, as `[http[s]://][user[:password]@]host[:port]/<index>`
+/// - `timeout`: number of milliseconds an event is searched for before it is counted as missing
+/// - `interval`: number of milliseconds between two searches for the same event
+/// - `queries`: `_search` request templates by event type, an event is searched with the template
+///   of the longest event type its activity starts with
+#[derive(Deserialize)]
+pub struct IngestConfig {
+    pub url: String,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    pub queries: BTreeMap<String, String>,
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+fn default_interval() -> u64 {
+    DEFAULT_INTERVAL_MS
+}
+
+/// Structure defining the ingestion latency of the events of one type
+///
+/// # Parameters
+///
+/// - `events`: number of events searched for
+/// - `ingested`: number of events found before the timeout
+/// - `missing`: number of events not found before the timeout or the end of the run
+/// - `search_errors`: number of searches which failed, e.g. because the SIEM was unreachable
+/// - `min_ms`: lowest latency of an ingested event
+/// - `mean_ms`: mean latency of the ingested events
+/// - `p95_ms`: 95th percentile latency of the ingested events
+/// - `max_ms`: highest latency of an ingested event
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct IngestLag {
+    pub events: usize,
+    pub ingested: usize,
+    pub missing: usize,
+    pub search_errors: usize,
+    pub min_ms: Option<u64>,
+    pub mean_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+}
+
+/// Structure defining an event the probe is searching for
+///
+/// # Parameters
+///
+/// - `kind`: event type the event was matched to
+/// - `query`: rendered `_search` request of the event
+/// - `logged`: time the event was logged
+/// - `next`: time of the next search
+struct Pending {
+    kind: String,
+    query: String,
+    logged: Instant,
+    next: Instant,
+}
+
+/// Structure defining a probe measuring how long events take to become searchable in the SIEM.
+/// Every event logged by the run is handed to a background thread, which searches the SIEM for
+/// it every `interval` until it is found or `timeout` has elapsed, so the run is not slowed down
+/// by the searches. The searches due at the same time are sent together as `_msearch` requests.
+/// Latencies are measured from the time the event was logged to the time of the search which
+/// found it, with a resolution of `interval`.
+///
+/// # Parameters
+///
+/// - `sender`: sender the Logger hands logged events to, dropped once the run is over
+/// - `handle`: thread searching for the events, returns the latencies by event type
+pub struct IngestProbe {
+    sender: Sender<LoggedEvent>,
+    handle: JoinHandle<BTreeMap<String, IngestLag>>,
+}
+
+impl IngestConfig {
+    /// Reads an ingest lag configuration. Files with a `.json` extension are read as JSON, all
+    /// others as YAML.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the configuration file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: IngestConfig instance
+    /// - `Err`: The file could not be read or is not a valid configuration
+    pub fn load(path: &String) -> Result<IngestConfig, GenerationError> {
+        let contents = fs::read_to_string(path)?;
+        let parsed = if path.to_lowercase().ends_with(".json") {
+            serde_json::from_str::<IngestConfig>(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_str::<IngestConfig>(&contents).map_err(|e| e.to_string())
+        };
+        let config = match parsed {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid ingest lag configuration: {}", path, e)))
+        };
+        if config.queries.is_empty() || config.interval == 0 {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} must have at least one query and an interval greater than 0", path)));
+        }
+        Ok(config)
+    }
+}
+
+impl IngestProbe {
+    /// Starts the thread searching for logged events
+    ///
+    /// # Parameters
+    ///
+    /// - `config`: SIEM index, timing, and queries of the probe
+    /// - `stop`: flag set when the run is interrupted, events still searched for are then counted
+    ///   as missing
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: IngestProbe instance
+    /// - `Err`: The URL or a query template is invalid, or the thread could not be started
+    pub fn start(config: IngestConfig, stop: Arc<AtomicBool>) -> Result<IngestProbe, GenerationError> {
+        let index = ElasticsearchReporter::new(&config.url)?;
+        let mut queries = Vec::new();
+        for (kind, query) in &config.queries {
+            match Template::parse(query) {
+                Ok(template) => queries.push((kind.clone(), template)),
+                Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("The query of {} is not a valid template: {}", kind, e.message)))
+            }
+        }
+        // the longest event type is tried first so `Network Connection (beacon` wins over `Network Connection`
+        queries.sort_by_key(|(kind, _)| std::cmp::Reverse(kind.len()));
+        let timeout = Duration::from_millis(config.timeout);
+        let interval = Duration::from_millis(config.interval);
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::Builder::new().name("ingest-probe".to_string()).spawn(move || {
+            probe(receiver, &index, &queries, (timeout, interval), &stop)
+        })?;
+        Ok(IngestProbe { sender, handle })
+    }
+
+    /// Retrieves a sender the Logger hands logged events to, see `Logger::set_ingest_probe`
+    ///
+    /// # Returns
+    ///
+    /// Sender of the probe
+    pub fn sender(&self) -> Sender<LoggedEvent> {
+        self.sender.clone()
+    }
+
+    /// Waits until every logged event was found or timed out. Every sender given to a Logger must
+    /// have been dropped, otherwise the probe keeps waiting for events.
+    ///
+    /// # Returns
+    ///
+    /// The ingestion latency by event type, event types without logged events are left out
+    pub fn finish(self) -> BTreeMap<String, IngestLag> {
+        let IngestProbe { sender, handle } = self;
+        drop(sender);
+        handle.join().unwrap_or_default()
+    }
+}
+
+/// Searches for the events received from the Logger until the run is over and every event was
+/// found or timed out
+///
+/// # Parameters
+///
+/// - `receiver`: receiver of the logged events
+/// - `index`: SIEM index the events are searched in
+/// - `queries`: `_search` request templates by event type, longest event type first
+/// - `timing`: timeout and interval of the searches
+/// - `stop`: flag set when the run is interrupted
+///
+/// # Returns
+///
+/// The ingestion latency by event type
+fn probe(receiver: Receiver<LoggedEvent>, index: &ElasticsearchReporter, queries: &[(String, Template)],
+         timing: (Duration, Duration), stop: &AtomicBool) -> BTreeMap<String, IngestLag> {
+    let (timeout, interval) = timing;
+    let mut pending: Vec<Pending> = Vec::new();
+    let mut latencies: BTreeMap<String, (IngestLag, Vec<u64>)> = BTreeMap::new();
+    let mut open = true;
+    loop {
+        let now = Instant::now();
+        let wait = pending.iter().map(|event| event.next.saturating_duration_since(now)).min().unwrap_or(interval);
+        if open {
+            match receiver.recv_timeout(wait) {
+                Ok(event) => {
+                    for event in std::iter::once(event).chain(receiver.try_iter()) {
+                        admit(event, queries, interval, &mut pending, &mut latencies);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => open = false
+            }
+        } else if pending.is_empty() {
+            break;
+        } else {
+            // checked often enough to notice an interruption while the last events are searched for
+            thread::sleep(wait.min(Duration::from_millis(100)));
+        }
+        if stop.load(Ordering::SeqCst) {
+            // events logged just before the interruption are counted as missing
+            for event in receiver.try_iter() {
+                admit(event, queries, interval, &mut pending, &mut latencies);
+            }
+            break;
+        }
+        // searches due shortly after the first are sent along with it rather than a moment later
+        let due_by = Instant::now() + interval / 10;
+        let due: Vec<usize> = (0..pending.len()).filter(|&position| pending[position].next <= due_by).collect();
+        let mut done = vec![false; pending.len()];
+        for batch in due.chunks(MAX_BATCH) {
+            let queries: Vec<&str> = batch.iter().map(|&position| pending[position].query.as_str()).collect();
+            // the latency of an event is measured to the search which found it, not to the end of
+            // the request, which may be slowed down by the other searches of the batch
+            let searched = Instant::now();
+            let results = index.multi_search(&queries);
+            for (offset, &position) in batch.iter().enumerate() {
+                let event = &mut pending[position];
+                let (lag, found) = latencies.entry(event.kind.clone()).or_default();
+                match results.as_ref().map(|results| &results[offset]) {
+                    Ok(Ok(hits)) if *hits > 0 => {
+                        found.push(searched.saturating_duration_since(event.logged).as_millis() as u64);
+                        done[position] = true;
+                        continue;
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_)) | Err(_) => lag.search_errors += 1
+                }
+                event.next = searched + interval;
+                done[position] = searched.saturating_duration_since(event.logged) >= timeout;
+            }
+        }
+        let mut done = done.into_iter();
+        pending.retain(|_| !done.next().unwrap_or(false));
+    }
+    latencies.into_iter().map(|(kind, (mut lag, mut found))| {
+        found.sort_unstable();
+        lag.ingested = found.len();
+        lag.missing = lag.events - lag.ingested;
+        lag.min_ms = found.first().copied();
+        lag.max_ms = found.last().copied();
+        lag.mean_ms = (!found.is_empty()).then(|| found.iter().sum::<u64>() / found.len() as u64);
+        lag.p95_ms = (!found.is_empty()).then(|| found[(found.len() * 95).div_ceil(100) - 1]);
+        (kind, lag)
+    }).collect()
+}
+
+/// Queues a logged event to be searched for when its activity matches the event type of a query,
+/// other events are ignored
+fn admit(event: LoggedEvent, queries: &[(String, Template)], interval: Duration, pending: &mut Vec<Pending>,
+         latencies: &mut BTreeMap<String, (IngestLag, Vec<u64>)>) {
+    let (log, logged) = event;
+    if let Some((kind, template)) = queries.iter().find(|(kind, _)| log.activity.starts_with(kind.as_str())) {
+        let (lag, _) = latencies.entry(kind.clone()).or_default();
+        lag.events += 1;
+        match template.render(&log) {
+            Ok(query) => pending.push(Pending { kind: kind.clone(), query, logged, next: logged + interval }),
+            Err(_) => lag.search_errors += 1
+        }
+    }
+}
+
+/// Formats the ingestion latencies as a line of text for the console
+///
+/// # Parameters
+///
+/// - `lags`: ingestion latency by event type
+///
+/// # Returns
+///
+/// Human readable description of the latencies
+pub fn message(lags: &BTreeMap<String, IngestLag>) -> String {
+    if lags.is_empty() {
+        return "no event matched a query".to_string();
+    }
+    lags.iter().map(|(kind, lag)| match (lag.mean_ms, lag.p95_ms) {
+        (Some(mean), Some(p95)) => format!("{} {}/{} ingested (mean {}ms, p95 {}ms)", kind, lag.ingested, lag.events, mean, p95),
+        _ => format!("{} 0/{} ingested", kind, lag.events)
+    }).collect::<Vec<String>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+    use crate::modules::testing::read_request;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Answers `_msearch` requests, reporting a hit once a query was received `after` times. The
+    /// largest number of searches received in one request is recorded.
+    fn siem(after: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let largest = Arc::new(AtomicUsize::new(0));
+        let batch = largest.clone();
+        thread::spawn(move || {
+            let mut searches: BTreeMap<String, usize> = BTreeMap::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&stream);
+                let body = request.split("\r\n\r\n").nth(1).unwrap_or("");
+                // every search is a header line followed by the query
+                let responses: Vec<String> = body.lines().skip(1).step_by(2).map(|query| {
+                    let count = searches.entry(query.to_string()).or_default();
+                    *count += 1;
+                    let total = if *count >= after && !query.contains("never") { 1 } else { 0 };
+                    format!("{{\"hits\":{{\"total\":{{\"value\":{}}},\"hits\":[]}},\"status\":200}}", total)
+                }).collect();
+                batch.fetch_max(responses.len(), Ordering::SeqCst);
+                let body = format!("{{\"responses\":[{}]}}", responses.join(","));
+                let _ = stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).as_bytes());
+            }
+        });
+        (address, largest)
+    }
+
+    fn config(address: &str) -> IngestConfig {
+        let mut queries = BTreeMap::new();
+        queries.insert("New File".to_string(), "{\"query\":{\"match_phrase\":{\"file.path\":{{ file_path | json }}}}}".to_string());
+        queries.insert("Network Connection".to_string(), "{\"never\":{{ dest_port | json }}}".to_string());
+        IngestConfig { url: format!("http://{}/logs-*", address), timeout: 300, interval: 20, queries }
+    }
+
+    fn event(activity: &str, file_path: &str) -> LoggedEvent {
+        (Log { activity: activity.to_string(), file_path: file_path.to_string(), ..Default::default() }, Instant::now())
+    }
+
+    #[test]
+    fn config_loaded() {
+        let temp = TempPath::new("ingest.yaml");
+        let path = temp.as_string();
+        fs::write(&path, "url: http://siem.lab.local:9200/logs-*\nqueries:\n  New File: '{\"query\": {\"term\": {\"file.path\": {{ file_path | json }}}}}'\n").unwrap();
+        let config = IngestConfig::load(&path).unwrap();
+        assert_eq!(config.timeout, DEFAULT_TIMEOUT_MS);
+        assert_eq!(config.interval, DEFAULT_INTERVAL_MS);
+        fs::write(&path, "url: http://siem.lab.local:9200/logs-*\nqueries: {}\n").unwrap();
+        assert!(IngestConfig::load(&path).is_err());
+    }
+
+    #[test]
+    fn invalid_queries() {
+        let mut config = config("127.0.0.1:9");
+        config.queries.insert("Process Created".to_string(), "{{ missing".to_string());
+        assert!(IngestProbe::start(config, Arc::new(AtomicBool::new(false))).is_err());
+        let mut config = self::config("127.0.0.1:9");
+        config.url = "ftp://siem.lab.local/logs".to_string();
+        assert!(IngestProbe::start(config, Arc::new(AtomicBool::new(false))).is_err());
+    }
+
+    #[test]
+    fn latency_measured() {
+        let (address, largest) = siem(2);
+        let probe = IngestProbe::start(config(&address), Arc::new(AtomicBool::new(false))).unwrap();
+        let sender = probe.sender();
+        sender.send(event("New File", "C:\\edr\\first.txt")).unwrap();
+        sender.send(event("New File", "C:\\edr\\second.txt")).unwrap();
+        sender.send(event("Network Connection", "")).unwrap();
+        sender.send(event("Process Created", "")).unwrap();
+        drop(sender);
+        let lags = probe.finish();
+        assert_eq!(lags.len(), 2);
+        let files = &lags["New File"];
+        assert_eq!((files.events, files.ingested, files.missing), (2, 2, 0));
+        assert!(files.min_ms.unwrap() >= 40);
+        assert!(files.p95_ms.unwrap() <= files.max_ms.unwrap());
+        let connections = &lags["Network Connection"];
+        assert_eq!((connections.events, connections.ingested, connections.missing), (1, 0, 1));
+        assert_eq!(connections.mean_ms, None);
+        assert!(message(&lags).starts_with("Network Connection 0/1 ingested, New File 2/2 ingested (mean "));
+        // the three events were logged together, so they are searched for in the same requests
+        assert_eq!(largest.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn stops_when_interrupted() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut config = config(&siem(usize::MAX).0);
+        config.timeout = 60000;
+        let probe = IngestProbe::start(config, stop.clone()).unwrap();
+        probe.sender().send(event("New File", "/tmp/edr.txt")).unwrap();
+        let started = Instant::now();
+        stop.store(true, Ordering::SeqCst);
+        let lags = probe.finish();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(lags["New File"].missing, 1);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::modules::common::GenerationError;
+use crate::modules::network;
+use crate::modules::process::{ProcessOptions, MAX_CAPTURE_SIZE};
+use csv::StringRecord;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// Format of a `process` instruction
+pub const PROCESS_FORMAT: &str = "a process (process,<path>,[arguments...],[run_for=<msec>],[stdin=<text>],[capture=<bytes>],[alias=<name>])";
+
+/// Format of a `new_file`, `mod_file`, or `delete_file` instruction
+pub const FILE_FORMAT: &str = "a file operation (<new_file|mod_file|delete_file>,<path>)";
+
+/// Format of a `connect` instruction
+pub const CONNECT_FORMAT: &str = "a connection (connect,<destination_host>,<destination_port>,<message>,[retries=<count>],[backoff=<msec>])";
+
+/// Format of a `connect_self` instruction
+pub const CONNECT_SELF_FORMAT: &str = "a connection (connect_self,<message>)";
+
+/// Format of a `fan_out` instruction
+pub const FAN_OUT_FORMAT: &str = "a fan-out (fan_out,<destination_hosts>,<destination_ports>,<message>,[concurrency=<count>],[hold=<msec>])";
+
+/// Format of a `pause` instruction
+pub const PAUSE_FORMAT: &str = "a pause (pause,<msec>)";
+
+/// Named parameters of a `process` instruction, see `ProcessCmd`
+const PROCESS_PARAMETERS: [&str; 4] = ["run_for", "stdin", "capture", "alias"];
+
+/// Named parameters of a `connect` instruction, see `NetworkCmd::Connect`
+const RETRY_PARAMETERS: [&str; 2] = ["retries", "backoff"];
+
+/// Named parameters of a `fan_out` instruction, see `NetworkCmd::FanOut`
+const FAN_OUT_PARAMETERS: [&str; 2] = ["concurrency", "hold"];
+
+/// Named parameters whose value is text written as given, the values of all others are trimmed
+const UNTRIMMED_PARAMETERS: [&str; 1] = ["stdin"];
+
+/// Typed form of an instruction, deserialized from its csv record by `Instruction::parse` rather
+/// than indexed by position, so short rows and invalid values are reported instead of panicking
+///
+/// # Parameters
+///
+/// - `Process`: a `process` instruction
+/// - `File`: a `new_file`, `mod_file`, or `delete_file` instruction
+/// - `Network`: a `connect`, `connect_self`, or `fan_out` instruction
+/// - `Pause`: a `pause` instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Process(ProcessCmd),
+    File(FileCmd),
+    Network(NetworkCmd),
+    Pause(PauseCmd),
+}
+
+/// Structure defining a `process` instruction
+///
+/// # Parameters
+///
+/// - `path`: executable to start
+/// - `arguments`: arguments of the process, one per field, joined with spaces
+/// - `run_for`: optional number of milliseconds after which the process is stopped
+/// - `options`: how the standard streams of the process are connected, and its alias
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessCmd {
+    pub path: String,
+    pub arguments: Vec<String>,
+    pub run_for: Option<u64>,
+    pub options: ProcessOptions,
+}
+
+/// Operation of a file instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileOperation {
+    New,
+    Modify,
+    Delete,
+}
+
+/// Structure defining a `new_file`, `mod_file`, or `delete_file` instruction
+///
+/// # Parameters
+///
+/// - `operation`: operation performed on the file, selected by the instruction name
+/// - `path`: path of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCmd {
+    pub operation: FileOperation,
+    pub path: String,
+}
+
+/// Network instructions sending a message over a new connection
+///
+/// # Parameters
+///
+/// - `Connect`: a message sent to a remote host, retried `retries` times waiting `backoff`
+///   milliseconds (doubled before every further retry) when either is given
+/// - `ConnectSelf`: a message sent to a listener opened on the loopback interface
+/// - `FanOut`: a message sent to every port of every host, with up to `concurrency` connections
+///   open at the same time, each held open for `hold` milliseconds once the message was sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkCmd {
+    Connect { host: String, port: u16, message: String, retries: Option<u64>, backoff: Option<u64> },
+    ConnectSelf { message: String },
+    FanOut { hosts: Vec<String>, ports: Vec<u16>, message: String, concurrency: usize, hold: u64 },
+}
+
+/// Structure defining a `pause` instruction
+///
+/// # Parameters
+///
+/// - `duration`: number of milliseconds to pause for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PauseCmd {
+    pub duration: u64,
+}
+
+/// Positional fields of a `process` instruction
+#[derive(Deserialize)]
+struct ProcessFields {
+    path: String,
+    #[serde(default)]
+    arguments: Vec<String>,
+}
+
+/// Named parameters of a `process` instruction
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ProcessParameters {
+    run_for: Option<u64>,
+    stdin: Option<String>,
+    capture: Option<usize>,
+    alias: Option<String>,
+}
+
+/// Named parameters of a `connect` instruction
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RetryParameters {
+    retries: Option<u64>,
+    backoff: Option<u64>,
+}
+
+/// Named parameters of a `fan_out` instruction
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FanOutParameters {
+    concurrency: Option<usize>,
+    hold: Option<u64>,
+}
+
+impl Instruction {
+    /// Parses an instruction into its typed form
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction, without overrides, timeout, namespace, or shaping parameters
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The typed instruction
+    /// - `Err`: The instruction has no typed form, a required field is missing, a field or named
+    ///   parameter has an invalid value, or a named parameter is given twice
+    pub fn parse(record: &StringRecord) -> Result<Instruction, GenerationError> {
+        match record.get(0).unwrap_or("") {
+            "process" => ProcessCmd::parse(record).map(Instruction::Process),
+            "new_file" | "mod_file" | "delete_file" => FileCmd::parse(record).map(Instruction::File),
+            "connect" | "connect_self" | "fan_out" => NetworkCmd::parse(record).map(Instruction::Network),
+            "pause" => PauseCmd::parse(record).map(Instruction::Pause),
+            name => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid instruction", name)))
+        }
+    }
+}
+
+impl ProcessCmd {
+    /// Parses a `process` instruction. Named parameters may be given in any order after the
+    /// arguments.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction, without overrides or timeout
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The typed instruction
+    /// - `Err`: The instruction does not match `PROCESS_FORMAT`, the capture size is not between 1
+    ///   and `MAX_CAPTURE_SIZE`, or the alias is empty or a number (which would be mistaken for a
+    ///   process ID)
+    pub fn parse(record: &StringRecord) -> Result<ProcessCmd, GenerationError> {
+        let (positional, parameters): (StringRecord, ProcessParameters) = split_named(record, &PROCESS_PARAMETERS, 2, PROCESS_FORMAT)?;
+        let fields: ProcessFields = deserialize(&positional, PROCESS_FORMAT)?;
+        if fields.path.is_empty() {
+            return Err(format_error(record, PROCESS_FORMAT, "the path is empty"));
+        }
+        if let Some(capture) = parameters.capture.filter(|capture| *capture == 0 || *capture > MAX_CAPTURE_SIZE) {
+            return Err(format_error(record, PROCESS_FORMAT, &format!("capture={} is not between 1 and {}", capture, MAX_CAPTURE_SIZE)));
+        }
+        let alias = parameters.alias.map(|alias| alias.trim().to_string());
+        if alias.as_ref().is_some_and(|alias| alias.is_empty() || alias.parse::<usize>().is_ok()) {
+            return Err(format_error(record, PROCESS_FORMAT, "the alias must be a name, not a number"));
+        }
+        Ok(ProcessCmd {
+            path: fields.path,
+            arguments: fields.arguments,
+            run_for: parameters.run_for,
+            options: ProcessOptions { stdin: parameters.stdin, capture: parameters.capture, alias },
+        })
+    }
+
+    /// Joins the arguments into the argument string of the process
+    ///
+    /// # Returns
+    ///
+    /// The arguments separated by spaces, or `None` when there are none
+    pub fn argument_string(&self) -> Option<String> {
+        if self.arguments.is_empty() { None } else { Some(self.arguments.join(" ")) }
+    }
+}
+
+impl FileCmd {
+    /// Parses a `new_file`, `mod_file`, or `delete_file` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction, without overrides or timeout
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The typed instruction
+    /// - `Err`: The instruction does not match `FILE_FORMAT`
+    pub fn parse(record: &StringRecord) -> Result<FileCmd, GenerationError> {
+        let operation = match record.get(0).unwrap_or("") {
+            "new_file" => FileOperation::New,
+            "mod_file" => FileOperation::Modify,
+            "delete_file" => FileOperation::Delete,
+            name => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid File Operation Command", name)))
+        };
+        let (path,): (String,) = deserialize(record, FILE_FORMAT)?;
+        if path.is_empty() {
+            return Err(format_error(record, FILE_FORMAT, "the path is empty"));
+        }
+        Ok(FileCmd { operation, path })
+    }
+}
+
+impl NetworkCmd {
+    /// Parses a `connect`, `connect_self`, or `fan_out` instruction. The retry parameters of a
+    /// `connect`, and the parameters of a `fan_out`, may be given in either order after the
+    /// message. The destinations and ports of a `fan_out` are expanded, see `network::expand_hosts`
+    /// and `network::expand_ports`.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction, without overrides, timeout, namespace, or shaping parameters
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The typed instruction
+    /// - `Err`: The instruction does not match `CONNECT_FORMAT`, `CONNECT_SELF_FORMAT`, or
+    ///   `FAN_OUT_FORMAT`, or a `fan_out` would open more than `network::MAX_FAN_OUT_CONNECTIONS`
+    ///   connections or hold more than `MAX_FAN_OUT_CONCURRENCY` open at once
+    pub fn parse(record: &StringRecord) -> Result<NetworkCmd, GenerationError> {
+        match record.get(0).unwrap_or("") {
+            "connect" => {
+                let (positional, parameters): (StringRecord, RetryParameters) = split_named(record, &RETRY_PARAMETERS, 4, CONNECT_FORMAT)?;
+                let (host, port, message): (String, u16, String) = deserialize(&positional, CONNECT_FORMAT)?;
+                if host.trim().is_empty() {
+                    return Err(format_error(record, CONNECT_FORMAT, "the destination host is empty"));
+                }
+                Ok(NetworkCmd::Connect { host, port, message, retries: parameters.retries, backoff: parameters.backoff })
+            }
+            "connect_self" => {
+                let (message,): (String,) = deserialize(record, CONNECT_SELF_FORMAT)?;
+                Ok(NetworkCmd::ConnectSelf { message })
+            }
+            "fan_out" => {
+                let (positional, parameters): (StringRecord, FanOutParameters) = split_named(record, &FAN_OUT_PARAMETERS, 4, FAN_OUT_FORMAT)?;
+                let (hosts, ports, message): (String, String, String) = deserialize(&positional, FAN_OUT_FORMAT)?;
+                let hosts = network::expand_hosts(&hosts).map_err(|problem| format_error(record, FAN_OUT_FORMAT, &problem))?;
+                let ports = network::expand_ports(&ports).map_err(|problem| format_error(record, FAN_OUT_FORMAT, &problem))?;
+                if hosts.len() * ports.len() > network::MAX_FAN_OUT_CONNECTIONS {
+                    return Err(format_error(record, FAN_OUT_FORMAT, &format!("more than {} connections would be opened", network::MAX_FAN_OUT_CONNECTIONS)));
+                }
+                let concurrency = parameters.concurrency.unwrap_or(network::DEFAULT_FAN_OUT_CONCURRENCY);
+                if concurrency == 0 || concurrency > network::MAX_FAN_OUT_CONCURRENCY {
+                    return Err(format_error(record, FAN_OUT_FORMAT, &format!("concurrency={} is not between 1 and {}", concurrency, network::MAX_FAN_OUT_CONCURRENCY)));
+                }
+                Ok(NetworkCmd::FanOut { hosts, ports, message, concurrency, hold: parameters.hold.unwrap_or(0) })
+            }
+            name => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid Network Operation Command", name)))
+        }
+    }
+}
+
+impl PauseCmd {
+    /// Parses a `pause` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction, without overrides
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The typed instruction
+    /// - `Err`: The instruction does not match `PAUSE_FORMAT`
+    pub fn parse(record: &StringRecord) -> Result<PauseCmd, GenerationError> {
+        let (duration,): (u64,) = deserialize(record, PAUSE_FORMAT)?;
+        Ok(PauseCmd { duration })
+    }
+}
+
+/// Deserializes the fields of an instruction after its name, in order. Fields after the last one
+/// of `T` are ignored.
+///
+/// # Parameters
+///
+/// - `record`: instruction, without named parameters
+/// - `format`: format of the instruction, for error messages
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The deserialized fields
+/// - `Err`: A field is missing or has an invalid value
+fn deserialize<T: DeserializeOwned>(record: &StringRecord, format: &str) -> Result<T, GenerationError> {
+    let fields: StringRecord = record.iter().skip(1).collect();
+    fields.deserialize(None).map_err(|e| format_error(record, format, &describe(&e, 1)))
+}
+
+/// Separates trailing `name=value` parameters from an instruction and deserializes them by name.
+/// Parameters may be given in any order, but only names in `names` are parameters and the first
+/// `required` fields never are, so a message such as `retries=3` is not mistaken for one.
+///
+/// # Parameters
+///
+/// - `record`: instruction
+/// - `names`: names of the parameters of the instruction
+/// - `required`: number of fields, including the instruction name, which are never parameters
+/// - `format`: format of the instruction, for error messages
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instruction without the parameters, and the deserialized parameters
+/// - `Err`: A parameter is given twice or has an invalid value
+fn split_named<T: DeserializeOwned + Default>(record: &StringRecord, names: &[&str], required: usize, format: &str) -> Result<(StringRecord, T), GenerationError> {
+    let mut end = record.len();
+    let mut keys = StringRecord::new();
+    let mut values = StringRecord::new();
+    while end > required {
+        let (key, value) = match record[end - 1].split_once('=') {
+            Some((key, value)) if names.contains(&key) => (key, value),
+            _ => break
+        };
+        if keys.iter().any(|given| given == key) {
+            return Err(format_error(record, format, &format!("{} is given more than once", key)));
+        }
+        keys.push_field(key);
+        values.push_field(if UNTRIMMED_PARAMETERS.contains(&key) { value } else { value.trim() });
+        end -= 1;
+    }
+    if keys.is_empty() {
+        return Ok((record.clone(), T::default()));
+    }
+    match values.deserialize(Some(&keys)) {
+        Ok(parameters) => Ok((record.iter().take(end).collect(), parameters)),
+        Err(e) => {
+            let key = match e.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => err.field().and_then(|index| keys.get(index as usize)).unwrap_or(""),
+                _ => ""
+            };
+            Err(format_error(record, format, &format!("{}={}", key, describe(&e, 0).splitn(2, ": ").last().unwrap_or(""))))
+        }
+    }
+}
+
+/// Describes a deserialization error, numbering fields from `offset` so they match the
+/// instruction rather than the deserialized fields
+fn describe(error: &csv::Error, offset: u64) -> String {
+    match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => match err.field() {
+            Some(field) => format!("field {}: {}", field + offset, err.kind()),
+            None => err.kind().to_string()
+        },
+        _ => error.to_string()
+    }
+}
+
+/// Builds the error of an instruction which does not match its format
+fn format_error(record: &StringRecord, format: &str, problem: &str) -> GenerationError {
+    GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}: {}", record, format, problem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    #[test]
+    fn instructions_parsed() {
+        let process = ProcessCmd::parse(&record(&["process", "sh", "-c", "exit 3", "alias=slow ", "run_for= 500", "stdin=whoami"])).unwrap();
+        assert_eq!(process.arguments, vec!["-c", "exit 3"]);
+        assert_eq!(process.argument_string().as_deref(), Some("-c exit 3"));
+        assert_eq!(process.run_for, Some(500));
+        assert_eq!(process.options, ProcessOptions { stdin: Some("whoami".to_string()), capture: None, alias: Some("slow".to_string()) });
+        assert_eq!(ProcessCmd::parse(&record(&["process", "sh"])).unwrap().argument_string(), None);
+        assert_eq!(Instruction::parse(&record(&["mod_file", "a.txt"])).unwrap(), Instruction::File(FileCmd { operation: FileOperation::Modify, path: "a.txt".to_string() }));
+        assert_eq!(NetworkCmd::parse(&record(&["connect", "10.0.0.5", "443", "retries=3", "backoff=200"])).unwrap(),
+                   NetworkCmd::Connect { host: "10.0.0.5".to_string(), port: 443, message: "retries=3".to_string(), retries: None, backoff: Some(200) });
+        assert_eq!(Instruction::parse(&record(&["connect_self", "hello"])).unwrap(), Instruction::Network(NetworkCmd::ConnectSelf { message: "hello".to_string() }));
+        assert_eq!(PauseCmd::parse(&record(&["pause", "250"])).unwrap(), PauseCmd { duration: 250 });
+        assert_eq!(NetworkCmd::parse(&record(&["fan_out", "10.0.0.0/30 c2.lab.local", "22 8000-8002", "hello", "hold=500", "concurrency=16"])).unwrap(),
+                   NetworkCmd::FanOut { hosts: vec!["10.0.0.1".to_string(), "10.0.0.2".to_string(), "c2.lab.local".to_string()], ports: vec![22, 8000, 8001, 8002],
+                                        message: "hello".to_string(), concurrency: 16, hold: 500 });
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        // short rows are errors rather than out of bounds indexes
+        for fields in [vec!["process"], vec!["new_file"], vec!["connect", "10.0.0.5", "443"], vec!["connect_self"], vec!["pause"]].iter() {
+            assert!(Instruction::parse(&record(fields)).is_err());
+        }
+        let error = PauseCmd::parse(&record(&["pause", "1s"])).unwrap_err();
+        assert!(error.message.ends_with("field 1: invalid digit found in string"));
+        let error = NetworkCmd::parse(&record(&["connect", "10.0.0.5", "4444", "hello", "retries=many"])).unwrap_err();
+        assert!(error.message.ends_with("retries=invalid digit found in string"));
+        assert!(NetworkCmd::parse(&record(&["connect", "10.0.0.5", "99999", "hello"])).is_err());
+        assert!(ProcessCmd::parse(&record(&["process", "sh", "run_for=1", "run_for=2"])).is_err());
+        assert!(ProcessCmd::parse(&record(&["process", "sh", "capture=0"])).is_err());
+        assert!(ProcessCmd::parse(&record(&["process", "sh", "alias=123"])).is_err());
+        assert!(NetworkCmd::parse(&record(&["fan_out", "10.0.0.0/8", "80", "hello"])).is_err());
+        assert!(NetworkCmd::parse(&record(&["fan_out", "10.0.0.5", "90-80", "hello"])).is_err());
+        assert!(NetworkCmd::parse(&record(&["fan_out", "10.0.0.5", "80", "hello", "concurrency=0"])).is_err());
+        assert!(NetworkCmd::parse(&record(&["fan_out", " ", "80", "hello"])).is_err());
+        assert!(Instruction::parse(&record(&["teleport"])).is_err());
+    }
+}
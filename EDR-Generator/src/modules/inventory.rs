@@ -0,0 +1,282 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use sysinfo::{ProcessExt, SystemExt};
+
+/// Structure defining a security product recognized on the host
+///
+/// # Parameters
+///
+/// - `name`: name of the product
+/// - `services`: names of the services it installs
+/// - `processes`: names of the processes it runs, without `.exe`
+/// - `packages`: names of the packages it is installed from, which the version is read from
+struct KnownProduct {
+    name: &'static str,
+    services: &'static [&'static str],
+    processes: &'static [&'static str],
+    packages: &'static [&'static str],
+}
+
+/// Security products recognized on the host
+const SECURITY_PRODUCTS: &[KnownProduct] = &[
+    KnownProduct { name: "Microsoft Defender Antivirus", services: &["WinDefend"], processes: &["MsMpEng"], packages: &[] },
+    KnownProduct { name: "Microsoft Defender for Endpoint", services: &["Sense"], processes: &["MsSense", "mdatp", "wdavdaemon"], packages: &["mdatp"] },
+    KnownProduct { name: "CrowdStrike Falcon", services: &["CSFalconService", "falcon-sensor"], processes: &["CSFalconService", "falcond", "falcon-sensor"], packages: &["CrowdStrike", "falcon-sensor"] },
+    KnownProduct { name: "SentinelOne", services: &["SentinelAgent", "sentinelone"], processes: &["SentinelAgent", "sentinelone-agent", "s1-agent"], packages: &["Sentinel Agent", "sentinelagent"] },
+    KnownProduct { name: "Carbon Black", services: &["CbDefense", "CarbonBlack", "cbagentd"], processes: &["RepMgr", "cbdaemon", "cbagentd"], packages: &["Carbon Black", "cb-psc-sensor"] },
+    KnownProduct { name: "Cortex XDR", services: &["cyserver", "traps_pmd"], processes: &["cyserver", "cytool", "traps_pmd"], packages: &["Cortex XDR", "cortex-agent"] },
+    KnownProduct { name: "Sophos", services: &["Sophos Endpoint Defense Service", "sophos-spl"], processes: &["SophosED", "sophos_threat_detector"], packages: &["Sophos", "sophos-spl"] },
+    KnownProduct { name: "Elastic Defend", services: &["ElasticEndpoint", "ElasticEndpoint.service"], processes: &["elastic-endpoint"], packages: &["Elastic Agent", "elastic-agent"] },
+    KnownProduct { name: "Trellix", services: &["mfemms", "xagt"], processes: &["mfemms", "xagt"], packages: &["Trellix", "FireEye Endpoint Agent", "xagt"] },
+    KnownProduct { name: "Sysmon", services: &["Sysmon", "Sysmon64", "sysmon"], processes: &["Sysmon", "Sysmon64", "sysmon"], packages: &["Sysmon", "sysmonforlinux"] },
+    KnownProduct { name: "Wazuh", services: &["WazuhSvc", "wazuh-agent"], processes: &["wazuh-agent", "wazuh-agentd"], packages: &["Wazuh Agent", "wazuh-agent"] },
+    KnownProduct { name: "osquery", services: &["osqueryd"], processes: &["osqueryd"], packages: &["osquery"] },
+    KnownProduct { name: "auditd", services: &["auditd"], processes: &["auditd"], packages: &["auditd", "audit"] },
+];
+
+/// Directories systemd unit files are installed in
+#[cfg(not(windows))]
+const UNIT_DIRECTORIES: &[&str] = &["/etc/systemd/system", "/lib/systemd/system", "/usr/lib/systemd/system"];
+
+/// Structure defining the host a run was executed on, captured when the run starts so detection
+/// results can later be tied to the sensors which were present
+///
+/// # Parameters
+///
+/// - `os`: name of the operating system (e.g. `Windows`, `Ubuntu`)
+/// - `os_version`: version of the operating system
+/// - `patch_level`: build of the operating system including its update revision on Windows (e.g.
+///   `19045.3693`), the kernel release elsewhere
+/// - `domain`: domain the host is joined to, if any
+/// - `security_products`: security products detected on the host
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct HostInventory {
+    pub os: String,
+    pub os_version: String,
+    pub patch_level: String,
+    pub domain: Option<String>,
+    pub security_products: Vec<SecurityProduct>,
+}
+
+/// Structure defining a security product detected on the host
+///
+/// # Parameters
+///
+/// - `name`: name of the product
+/// - `detected_by`: how the product was detected, `service`, `process`, or both
+/// - `version`: installed version, when found in the installed programs or packages
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SecurityProduct {
+    pub name: String,
+    pub detected_by: Vec<String>,
+    pub version: Option<String>,
+}
+
+impl HostInventory {
+    /// Captures the inventory of this host. Nothing is required to be present, information which
+    /// can not be read is left empty.
+    ///
+    /// # Returns
+    ///
+    /// HostInventory instance
+    pub fn capture() -> HostInventory {
+        let mut system = sysinfo::System::new();
+        system.refresh_processes();
+        let processes: Vec<String> = system.get_processes().values().map(|process| process.name().to_string()).collect();
+        HostInventory {
+            os: system.get_name().unwrap_or_default(),
+            os_version: system.get_os_version().unwrap_or_default(),
+            patch_level: patch_level().or_else(|| system.get_kernel_version()).unwrap_or_default(),
+            domain: domain(),
+            security_products: detect(&processes, has_service, package_version),
+        }
+    }
+
+    /// Formats the inventory for the console
+    ///
+    /// # Returns
+    ///
+    /// Human readable description of the host
+    pub fn message(&self) -> String {
+        let mut message = format!("{} {} ({})", self.os, self.os_version, self.patch_level);
+        if let Some(domain) = &self.domain {
+            message.push_str(&format!(", joined to {}", domain));
+        }
+        if self.security_products.is_empty() {
+            message.push_str(", no security product detected");
+        } else {
+            let products: Vec<String> = self.security_products.iter().map(|product| match &product.version {
+                Some(version) => format!("{} {}", product.name, version),
+                None => product.name.clone()
+            }).collect();
+            message.push_str(&format!(", {}", products.join(", ")));
+        }
+        message
+    }
+}
+
+/// Detects the known security products from the running processes and the installed services
+///
+/// # Parameters
+///
+/// - `processes`: names of the running processes
+/// - `service`: whether a service of the given name is installed
+/// - `version`: installed version of a package matching the given name
+///
+/// # Returns
+///
+/// Detected products, in the order of `SECURITY_PRODUCTS`
+fn detect(processes: &[String], service: fn(&str) -> bool, version: fn(&str) -> Option<String>) -> Vec<SecurityProduct> {
+    let running: BTreeSet<String> = processes.iter()
+        .map(|name| name.to_lowercase().trim_end_matches(".exe").to_string())
+        .collect();
+    let mut products = Vec::new();
+    for product in SECURITY_PRODUCTS {
+        let mut detected_by = Vec::new();
+        if product.services.iter().any(|candidate| service(candidate)) {
+            detected_by.push("service".to_string());
+        }
+        if product.processes.iter().any(|candidate| running.contains(&candidate.to_lowercase())) {
+            detected_by.push("process".to_string());
+        }
+        if !detected_by.is_empty() {
+            products.push(SecurityProduct {
+                name: product.name.to_string(),
+                detected_by,
+                version: product.packages.iter().find_map(|package| version(package)),
+            });
+        }
+    }
+    products
+}
+
+/// Retrieves the build and update revision of Windows, which identify the installed cumulative
+/// update
+#[cfg(windows)]
+fn patch_level() -> Option<String> {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion").ok()?;
+    let build: String = key.get_value("CurrentBuild").ok()?;
+    match key.get_value::<u32, _>("UBR") {
+        Ok(revision) => Some(format!("{}.{}", build, revision)),
+        Err(_) => Some(build)
+    }
+}
+
+#[cfg(not(windows))]
+fn patch_level() -> Option<String> {
+    None
+}
+
+/// Retrieves the domain the host is joined to from its primary DNS suffix
+#[cfg(windows)]
+fn domain() -> Option<String> {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey("SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters").ok()?;
+    key.get_value::<String, _>("Domain").ok().filter(|domain| !domain.is_empty())
+}
+
+/// Retrieves the Kerberos realm the host is joined to, as configured by `realm join` or `adcli`
+#[cfg(not(windows))]
+fn domain() -> Option<String> {
+    default_realm(&std::fs::read_to_string("/etc/krb5.conf").ok()?)
+}
+
+/// Retrieves the `default_realm` of a Kerberos configuration
+#[cfg_attr(windows, allow(dead_code))]
+fn default_realm(configuration: &str) -> Option<String> {
+    configuration.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("default_realm"))
+        .filter_map(|rest| rest.trim_start().strip_prefix('='))
+        .map(|realm| realm.trim().to_string())
+        .find(|realm| !realm.is_empty())
+}
+
+/// Whether a service of the given name is installed
+#[cfg(windows)]
+fn has_service(name: &str) -> bool {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(format!("SYSTEM\\CurrentControlSet\\Services\\{}", name)).is_ok()
+}
+
+/// Whether a systemd unit of the given name is installed
+#[cfg(not(windows))]
+fn has_service(name: &str) -> bool {
+    let unit = if name.ends_with(".service") { name.to_string() } else { format!("{}.service", name) };
+    UNIT_DIRECTORIES.iter().any(|directory| std::path::Path::new(directory).join(&unit).exists())
+}
+
+/// Retrieves the version of the first installed program whose display name starts with the given
+/// name
+#[cfg(windows)]
+fn package_version(name: &str) -> Option<String> {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    let uninstall = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey("SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall").ok()?;
+    uninstall.enum_keys().filter_map(|key| key.ok()).find_map(|key| {
+        let program = uninstall.open_subkey(key).ok()?;
+        let display_name: String = program.get_value("DisplayName").ok()?;
+        if !display_name.starts_with(name) {
+            return None;
+        }
+        program.get_value::<String, _>("DisplayVersion").ok()
+    })
+}
+
+/// Retrieves the version of an installed Debian package of the given name
+#[cfg(not(windows))]
+fn package_version(name: &str) -> Option<String> {
+    dpkg_version(&std::fs::read_to_string("/var/lib/dpkg/status").ok()?, name)
+}
+
+/// Retrieves the version of an installed package from a dpkg status database
+#[cfg_attr(windows, allow(dead_code))]
+fn dpkg_version(status: &str, name: &str) -> Option<String> {
+    status.split("\n\n").find_map(|paragraph| {
+        if !paragraph.lines().any(|line| line == format!("Package: {}", name)) {
+            return None;
+        }
+        if !paragraph.lines().any(|line| line.starts_with("Status:") && line.ends_with(" installed")) {
+            return None;
+        }
+        paragraph.lines().find_map(|line| line.strip_prefix("Version: ")).map(|version| version.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn products_detected() {
+        let processes = vec!["MsMpEng.exe".to_string(), "explorer.exe".to_string(), "osqueryd".to_string()];
+        let products = detect(&processes, |name| name == "WinDefend" || name == "Sysmon64",
+                              |name| if name == "osquery" { Some("5.10.2".to_string()) } else { None });
+        let names: Vec<&str> = products.iter().map(|product| product.name.as_str()).collect();
+        assert_eq!(names, vec!["Microsoft Defender Antivirus", "Sysmon", "osquery"]);
+        assert_eq!(products[0].detected_by, vec!["service", "process"]);
+        assert_eq!(products[1].detected_by, vec!["service"]);
+        assert_eq!(products[2].version.as_deref(), Some("5.10.2"));
+        assert!(detect(&[], |_| false, |_| None).is_empty());
+
+        let inventory = HostInventory { os: "Ubuntu".to_string(), os_version: "22.04".to_string(), patch_level: "5.15.0-89-generic".to_string(),
+                                        domain: Some("LAB.LOCAL".to_string()), security_products: products };
+        assert_eq!(inventory.message(), "Ubuntu 22.04 (5.15.0-89-generic), joined to LAB.LOCAL, Microsoft Defender Antivirus, Sysmon, osquery 5.10.2");
+    }
+
+    #[test]
+    fn configuration_parsed() {
+        assert_eq!(default_realm("[libdefaults]\n  # default_realm = OLD.LOCAL\n  default_realm = LAB.LOCAL\n").as_deref(), Some("LAB.LOCAL"));
+        assert_eq!(default_realm("[libdefaults]\n  dns_lookup_realm = false\n"), None);
+        let status = "Package: auditd\nStatus: install ok installed\nVersion: 1:3.0.7-1build1\n\n\
+                      Package: osquery\nStatus: deinstall ok config-files\nVersion: 5.10.2-1.linux\n";
+        assert_eq!(dpkg_version(status, "auditd").as_deref(), Some("1:3.0.7-1build1"));
+        assert_eq!(dpkg_version(status, "osquery"), None);
+        assert_eq!(dpkg_version(status, "wazuh-agent"), None);
+    }
+}
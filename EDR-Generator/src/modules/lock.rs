@@ -0,0 +1,151 @@
+use crate::modules::common::GenerationError;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+use sysinfo::{ProcessExt, SystemExt};
+
+/// Name of the lock file created in the temporary directory while a run is executing
+pub const LOCK_FILE_NAME: &str = "edr_generator.lock";
+/// Time an instance may take to write its process ID to a new lock file, an empty lock file which
+/// is older was left behind by an instance which stopped before writing it
+const EMPTY_LOCK_GRACE: Duration = Duration::from_secs(5);
+
+/// Structure defining the advisory lock held while instructions are executed, so two generator
+/// instances do not interleave their events on the same host. The lock is a file holding the
+/// process ID of its owner; it is removed when the RunLock is dropped. A lock left behind by an
+/// instance which no longer runs (e.g. after a crash) is taken over.
+///
+/// # Parameters
+///
+/// - `path`: path of the lock file
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Acquires the host wide lock in the temporary directory
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: RunLock instance
+    /// - `Err`: Another instance holds the lock, or the lock file could not be created
+    pub fn acquire() -> Result<RunLock, GenerationError> {
+        RunLock::acquire_at(&env::temp_dir().join(LOCK_FILE_NAME))
+    }
+
+    /// Acquires a lock
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the lock file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: RunLock instance
+    /// - `Err`: Another instance holds the lock, or the lock file could not be created
+    pub fn acquire_at(path: &Path) -> Result<RunLock, GenerationError> {
+        // a stale lock is removed once, the second attempt fails if another instance took it first
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(process::id().to_string().as_bytes())?;
+                    return Ok(RunLock { path: path.to_path_buf() });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(path).unwrap_or_default();
+                    if let Ok(pid) = owner.trim().parse::<sysinfo::Pid>() {
+                        if is_running(pid) {
+                            return Err(GenerationError::new("locked".to_string(), format!("Another instance (PID {}) is executing on this host, pass --allow-concurrent to run anyway or remove {} if it is stale", pid, path.display())));
+                        }
+                    }
+                    // the owner is not running, or is still writing its process ID unless the empty
+                    // file is older than the time that takes
+                    if !owner.is_empty() || is_abandoned(path) {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                Err(e) => return Err(GenerationError::new("io".to_string(), format!("Unable to create the lock file {}: {}", path.display(), e)))
+            }
+        }
+        Err(GenerationError::new("locked".to_string(), format!("Another instance is executing on this host, pass --allow-concurrent to run anyway or remove {} if it is stale", path.display())))
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("Unable to remove the lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Determines if an empty lock file was last modified longer than `EMPTY_LOCK_GRACE` ago
+fn is_abandoned(path: &Path) -> bool {
+    let modified = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(inner) => inner,
+        Err(_) => return false
+    };
+    SystemTime::now().duration_since(modified).map(|age| age > EMPTY_LOCK_GRACE).unwrap_or(false)
+}
+
+/// Determines if a process ID belongs to a running generator instance. Process IDs are reused, so
+/// a process with another name does not hold the lock.
+fn is_running(pid: sysinfo::Pid) -> bool {
+    let mut system = sysinfo::System::new();
+    system.refresh_processes();
+    let own_name = system.get_process(process::id() as sysinfo::Pid).map(|own| own.name().to_string());
+    match (system.get_process(pid), own_name) {
+        (Some(owner), Some(own_name)) => owner.name() == own_name,
+        (Some(_), None) => true,
+        (None, _) => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn second_instance_refused() {
+        let path = TempPath::new("run.lock");
+        let lock = RunLock::acquire_at(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), process::id().to_string());
+        assert!(RunLock::acquire_at(&path).is_err());
+        drop(lock);
+        assert!(!path.exists());
+        let lock = RunLock::acquire_at(&path).unwrap();
+        drop(lock);
+    }
+
+    #[test]
+    fn stale_lock_taken_over() {
+        let path = TempPath::new("stale.lock");
+        // no process runs with the largest process ID
+        fs::write(&path, sysinfo::Pid::MAX.to_string()).unwrap();
+        let lock = RunLock::acquire_at(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), process::id().to_string());
+        drop(lock);
+        fs::write(&path, "not a process ID").unwrap();
+        assert!(RunLock::acquire_at(&path).is_ok());
+    }
+
+    #[test]
+    fn empty_lock_taken_over_after_grace() {
+        let path = TempPath::new("empty.lock");
+        let file = fs::File::create(&path).unwrap();
+        // the owner may still be writing its process ID
+        assert!(RunLock::acquire_at(&path).is_err());
+        file.set_modified(SystemTime::now() - EMPTY_LOCK_GRACE * 2).unwrap();
+        let lock = RunLock::acquire_at(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), process::id().to_string());
+        drop(lock);
+    }
+}
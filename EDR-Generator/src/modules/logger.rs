@@ -1,9 +1,21 @@
-use csv::{WriterBuilder, Writer};
+use csv::{WriterBuilder, Writer, QuoteStyle};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use serde::Serialize;
 use crate::modules::common::{GenerationError, get_time};
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use sysinfo::{SystemExt, ProcessExt};
+use crate::modules::formatter::{self, LogFormat};
+use crate::modules::sink::Sink;
+use crate::modules::ingest::LoggedEvent;
 
 /// Structure containing all information and  format for a standard log message
 ///
@@ -17,13 +29,18 @@ use sysinfo::{SystemExt, ProcessExt};
 /// - `proc_id`: process id of process that generated event (or is the event)
 /// - `activity`: short text describing the type of event
 /// - `file_path`: full path to a file involved in the event
-/// - `source_addr`: IPv4 address of the source of a network event
+/// - `source_addr`: IPv4 or IPv6 address of the source of a network event
 /// - `source_port`: port number of the source of a network event
-/// - `dest_addr`: IPv4 address of the destination of a network event
+/// - `dest_addr`: IPv4 or IPv6 address of the destination of a network event, a hostname is
+///   recorded as the address it resolved to
 /// - `dest_port`: port number of the destination of a network event
 /// - `bytes_sent`: number of bytes sent during a network event
 /// - `protocol`: network protocol of the network event
-#[derive(Serialize)]
+/// - `address_family`: address family of the destination of a network event, `IPv4` or `IPv6`
+/// - `stdout`: standard output captured from a process
+/// - `stderr`: standard error captured from a process
+/// - `exit_code`: exit code of a process which exited, or the signal which terminated it
+#[derive(Serialize, Clone, Default)]
 pub struct Log {
     pub t: String,
     pub timestamp: String,
@@ -39,6 +56,119 @@ pub struct Log {
     pub dest_port: String,
     pub bytes_sent: String,
     pub protocol: String,
+    pub address_family: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: String,
+}
+
+/// Column names of the `Log` structure, written once as the header row of the output file
+const LOG_HEADER: [&str; 18] = ["t", "timestamp", "username", "proc_name", "proc_cmd", "proc_id", "activity",
+    "file_path", "source_addr", "source_port", "dest_addr", "dest_port", "bytes_sent", "protocol", "address_family", "stdout", "stderr", "exit_code"];
+
+impl Log {
+    /// Determines if a name matches a field of the `Log` structure
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the field as written in the header row
+    ///
+    /// # Returns
+    ///
+    /// True if the field exists, false otherwise
+    pub fn is_field(name: &str) -> bool {
+        LOG_HEADER.contains(&name)
+    }
+
+    /// Retrieves the value of a field by name
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the field as written in the header row
+    ///
+    /// # Returns
+    ///
+    /// The value of the field, None when the name does not match a field
+    pub fn field(&self, name: &str) -> Option<&str> {
+        let field = match name {
+            "t" => &self.t,
+            "timestamp" => &self.timestamp,
+            "username" => &self.username,
+            "proc_name" => &self.proc_name,
+            "proc_cmd" => &self.proc_cmd,
+            "proc_id" => &self.proc_id,
+            "activity" => &self.activity,
+            "file_path" => &self.file_path,
+            "source_addr" => &self.source_addr,
+            "source_port" => &self.source_port,
+            "dest_addr" => &self.dest_addr,
+            "dest_port" => &self.dest_port,
+            "bytes_sent" => &self.bytes_sent,
+            "protocol" => &self.protocol,
+            "address_family" => &self.address_family,
+            "stdout" => &self.stdout,
+            "stderr" => &self.stderr,
+            "exit_code" => &self.exit_code,
+            _ => return None
+        };
+        Some(field)
+    }
+
+    /// Copies the fields of another event which are not empty over the fields of this one
+    ///
+    /// # Parameters
+    ///
+    /// - `other`: event whose fields take precedence
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn merge(&mut self, other: &Log) {
+        for name in LOG_HEADER.iter() {
+            if let Some(value) = other.field(name).filter(|value| !value.is_empty()) {
+                let _ = self.set_field(name, value.to_string());
+            }
+        }
+    }
+
+    /// Replaces the value of a field by name
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the field as written in the header row
+    /// - `value`: new value of the field
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The field was replaced
+    /// - `Err`: The name does not match a field
+    pub fn set_field(&mut self, name: &str, value: String) -> Result<(), GenerationError> {
+        let field = match name {
+            "t" => &mut self.t,
+            "timestamp" => &mut self.timestamp,
+            "username" => &mut self.username,
+            "proc_name" => &mut self.proc_name,
+            "proc_cmd" => &mut self.proc_cmd,
+            "proc_id" => &mut self.proc_id,
+            "activity" => &mut self.activity,
+            "file_path" => &mut self.file_path,
+            "source_addr" => &mut self.source_addr,
+            "source_port" => &mut self.source_port,
+            "dest_addr" => &mut self.dest_addr,
+            "dest_port" => &mut self.dest_port,
+            "bytes_sent" => &mut self.bytes_sent,
+            "protocol" => &mut self.protocol,
+            "address_family" => &mut self.address_family,
+            "stdout" => &mut self.stdout,
+            "stderr" => &mut self.stderr,
+            "exit_code" => &mut self.exit_code,
+            _ => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid log field", name)))
+        };
+        *field = value;
+        Ok(())
+    }
 }
 
 /// Structure containing all information and  format for an error log message
@@ -50,23 +180,126 @@ pub struct Log {
 /// - `message`: content of the error
 #[derive(Serialize)]
 pub struct LogError {
-    t: String,
-    timestamp: String,
-    message: String,
+    pub t: String,
+    pub timestamp: String,
+    pub message: String,
 }
 
+/// Structure containing all information and  format for a run header or run summary log message
+///
+/// # Parameters
+///
+/// - `t`: Type of log
+/// - `timestamp`: Time of log event
+/// - `scenario`: path of the input scenario file
+/// - `scenario_sha256`: SHA-256 digest of the input scenario file
+/// - `message`: additional details about the run
+#[derive(Serialize)]
+pub struct LogRun {
+    pub t: String,
+    pub timestamp: String,
+    pub scenario: String,
+    pub scenario_sha256: String,
+    pub message: String,
+}
+
+/// Structure containing all information and  format for a resource usage sample of a child process
+///
+/// # Parameters
+///
+/// - `t`: Type of log
+/// - `timestamp`: Time the sample was taken
+/// - `proc_name`: name of the sampled process
+/// - `proc_id`: process id of the sampled process
+/// - `cpu_percent`: CPU usage since the previous sample, 100 per fully used core
+/// - `memory_kb`: resident memory in kilobytes
+/// - `disk_read_bytes`: bytes read from disk since the process started
+/// - `disk_written_bytes`: bytes written to disk since the process started
+#[derive(Serialize)]
+pub struct LogUsage {
+    pub t: String,
+    pub timestamp: String,
+    pub proc_name: String,
+    pub proc_id: String,
+    pub cpu_percent: String,
+    pub memory_kb: String,
+    pub disk_read_bytes: String,
+    pub disk_written_bytes: String,
+}
+
+/// Structure defining how the output file is flushed and rotated
+///
+/// # Parameters
+///
+/// - `flush_interval`: longest time records may stay buffered before the output is flushed
+/// - `max_size`: size in bytes the output file is rotated at, see `rotate_files`
+/// - `compress`: whether rotated files are compressed with gzip
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputOptions {
+    pub flush_interval: Option<Duration>,
+    pub max_size: Option<u64>,
+    pub compress: bool,
+}
+
+/// Output file which counts the bytes written to it, so it can be rotated once it grows too large
+///
+/// # Parameters
+///
+/// - `file`: the output file
+/// - `written`: number of bytes in the file, excluding those still buffered by the writer, shared
+///   with the Logger
+pub struct CountingFile {
+    file: File,
+    written: Arc<AtomicU64>,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.file.write(buf)?;
+        self.written.fetch_add(size as u64, Ordering::Relaxed);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
 
 /// Structure defining the Logger Class
 ///
 /// # Parameters
 ///
 /// - `writer`: Result of CSV Writer used for writing output in csv format
+/// - `path`: path of the output file
+/// - `format`: format used when writing records to the output
+/// - `header_pending`: whether the csv header row still needs to be written
+/// - `flush_records`: whether the output is flushed after every record
+/// - `options`: flushing and rotation of the output file
+/// - `last_flush`: time the output was last flushed
+/// - `written`: number of bytes in the output file, see `CountingFile`
+/// - `compression`: optional thread compressing the most recently rotated file
+/// - `sinks`: additional outputs that receive a copy of every record
+/// - `recorded`: optional copy of every event logged, kept for exporters run at the end of the run
+/// - `ingest`: optional probe every event logged is handed to, see `set_ingest_probe`
+/// - `boot`: uptime of the host when a boot-time run started, events are marked as boot-time when set
 /// - `username`: global username for the current application
 /// - `proc_name`: global process name for the current application
 /// - `proc_cmd`: global process command line arguments for the current application
 /// - `proc_id`: global process id for the current application
 pub struct Logger{
-    writer: csv::Result<Writer<File>>,
+    writer: csv::Result<Writer<CountingFile>>,
+    path: String,
+    format: LogFormat,
+    header_pending: bool,
+    flush_records: bool,
+    options: OutputOptions,
+    last_flush: Instant,
+    written: Arc<AtomicU64>,
+    compression: Option<JoinHandle<io::Result<()>>>,
+    sinks: Vec<Sink>,
+    recorded: Option<Vec<Log>>,
+    ingest: Option<Sender<LoggedEvent>>,
+    boot: Option<u64>,
     username: String,
     proc_name: String,
     proc_cmd: String,
@@ -74,16 +307,18 @@ pub struct Logger{
 }
 
 impl Logger {
-    /// Instantiates the Logger with an output to the specified file. Gathers current process information
+    /// Instantiates the Logger with an output to the specified file in the requested format.
+    /// Gathers current process information
     ///
     /// # Parameters
     ///
-    /// - `path`: path for the output file where the csv data will be stored
+    /// - `path`: path for the output file where the log data will be stored
+    /// - `format`: format used when writing records to the output file
     ///
     /// # Returns
     ///
     /// A Logger Class Instance
-    pub fn new(path: &String) -> Logger {
+    pub fn new(path: &str, format: LogFormat) -> Logger {
         // Retrieve information about the current process
         let mut system = sysinfo::System::new();
         let mut proc_name = "".to_string();
@@ -98,8 +333,21 @@ impl Logger {
                 proc_cmd = process.cmd().join(" "); //command arguments should be joined as a string
             }
         }
+        let written = Arc::new(AtomicU64::new(0));
         Logger {
-            writer: WriterBuilder::new().flexible(true).from_path(path),
+            writer: open_writer(path, &format, false, &written),
+            path: path.to_string(),
+            format,
+            header_pending: true,
+            flush_records: false,
+            options: OutputOptions::default(),
+            last_flush: Instant::now(),
+            written,
+            compression: None,
+            sinks: Vec::new(),
+            recorded: None,
+            ingest: None,
+            boot: None,
             username: whoami::username(),
             proc_name: proc_name,
             proc_cmd: proc_cmd,
@@ -107,10 +355,135 @@ impl Logger {
         }
     }
 
-    /// Logs an event to the CSV output writer
+    /// Adds an output sink which receives a copy of every subsequent record in the configured format
+    ///
+    /// # Parameters
+    ///
+    /// - `sink`: sink to forward records to
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn add_sink(&mut self, sink: Sink) {
+        self.sinks.push(sink);
+    }
+
+    /// Keeps a copy of every event logged from now on, see `take_events`
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn set_record_events(&mut self) {
+        self.recorded.get_or_insert_with(Vec::new);
+    }
+
+    /// Hands a copy of every event logged from now on to an ingest lag probe, see `IngestProbe`
+    ///
+    /// # Parameters
+    ///
+    /// - `probe`: sender of the probe, None stops handing events to the probe and releases it
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn set_ingest_probe(&mut self, probe: Option<Sender<LoggedEvent>>) {
+        self.ingest = probe;
+    }
+
+    /// Retrieves the events kept since `set_record_events` was called, and starts over
+    ///
+    /// # Returns
+    ///
+    /// The events in the order they were logged, with their overrides applied
+    pub fn take_events(&mut self) -> Vec<Log> {
+        self.recorded.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Marks every event logged from now on as a boot-time event by appending ` (boot)` to its
+    /// activity, see `--boot`
+    ///
+    /// # Parameters
+    ///
+    /// - `uptime`: number of seconds the host had been running when the run started
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn set_boot(&mut self, uptime: u64) {
+        self.boot = Some(uptime);
+    }
+
+    /// Retrieves the uptime given to `set_boot`
+    ///
+    /// # Returns
+    ///
+    /// Number of seconds the host had been running when a boot-time run started, `None` for other runs
+    pub fn boot_uptime(&self) -> Option<u64> {
+        self.boot
+    }
+
+    /// Flushes the output file after every record instead of when the buffer fills, so records of a
+    /// long running or streamed scenario can be followed while it runs
+    ///
+    /// # Parameters
+    ///
+    /// - `enabled`: whether the output is flushed after every record
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn set_flush_records(&mut self, enabled: bool) {
+        self.flush_records = enabled;
+    }
+
+    /// Sets how the output file is flushed and rotated, see `OutputOptions`
+    ///
+    /// # Parameters
+    ///
+    /// - `options`: flush interval, rotation size, and compression of rotated files
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn set_output_options(&mut self, options: OutputOptions) {
+        self.options = options;
+    }
+
+    /// Determines the longest time records may stay buffered, see `OutputOptions`
+    ///
+    /// # Returns
+    ///
+    /// The flush interval, if the output is flushed periodically
+    pub fn flush_interval(&self) -> Option<Duration> {
+        self.options.flush_interval
+    }
+
+    /// Flushes the output file when the flush interval has elapsed since the last flush, so records
+    /// logged before a long pause are not left buffered until the next record. Called periodically
+    /// by the commander, see `TaskCommander::start_flusher`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The output was flushed, or did not need to be
+    /// - `Err`: The output file could not be flushed
+    pub fn flush_due(&mut self) -> Result<(), GenerationError> {
+        let due = self.options.flush_interval.is_some_and(|interval| self.last_flush.elapsed() >= interval);
+        if let (true, Ok(inner)) = (due, self.writer.as_mut()) {
+            inner.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Logs an event to the output writer in the configured format after replacing fields with
+    /// values supplied by the scenario. Overrides are applied last so they take precedence over the
+    /// username and process information gathered by the Logger.
     /// # Parameters
     ///
     /// - `data`: A Log structure containing all data needed to be logged
+    /// - `overrides`: pairs of field name and value to replace in the event
     ///
     /// # Returns
     ///
@@ -119,23 +492,35 @@ impl Logger {
     /// # Panics
     ///
     /// Does not panic, but rather if errors occur, they are passed to the error logger.
-    pub fn log_event(&mut self, mut data: Log) {
-        data.username = self.username.clone();
-        //check if the event already has process information, otherwise use the parent process info
+    pub fn log_event(&mut self, mut data: Log, overrides: &[(String, String)]) {
+        //check if the event already has user and process information, otherwise use the parent process info
+        if data.username.is_empty() { data.username = self.username.clone();}
         if data.proc_name == "" { data.proc_name = self.proc_name.clone();}
         if data.proc_id == "" { data.proc_id = self.proc_id.clone();}
         if data.proc_cmd == "" { data.proc_cmd = self.proc_cmd.clone();}
-       match self.writer.as_mut() {
-           Ok(inner) => {
-               match inner.serialize(data) {
-                   Ok(_) => {}
-                   Err(_) => self.log_error(GenerationError::new("logging".to_string(), "Unable to Serialize Log Message".to_string()))
+        if self.boot.is_some() { data.activity.push_str(" (boot)"); }
+        for (name, value) in overrides {
+            if let Err(e) = data.set_field(name, value.clone()) {
+                return self.log_failure(e);
+            }
+        }
+        let copy = (self.recorded.is_some() || self.ingest.is_some()).then(|| data.clone());
+        let result = self.format_line(&data, formatter::cef_event, formatter::leef_event)
+            .and_then(|line| self.write_record(data, line));
+        match result {
+            Ok(_) => {
+                if let (Some(ingest), Some(copy)) = (self.ingest.as_ref(), copy.as_ref()) {
+                    // a probe which stopped after an interruption no longer receives events
+                    let _ = ingest.send((copy.clone(), Instant::now()));
+                }
+                if let (Some(recorded), Some(copy)) = (self.recorded.as_mut(), copy) {
+                    recorded.push(copy);
                 }
-           },
-           Err(_) => self.log_error(GenerationError::new("logging".to_string(), "Unable to Generate Log".to_string()))
-       };
+            }
+            Err(e) => self.log_failure(e)
+        };
     }
-    /// Logs an GenerationError class error to the CSV output writer
+    /// Logs an GenerationError class error to the output writer in the configured format
     /// # Parameters
     ///
     /// - `data`: A Generation Error that will be logged
@@ -149,23 +534,541 @@ impl Logger {
     /// If error occurs in this process, we are unable to log the error, so it is necessary to panic
     /// and bubble the error up to the parent caller.
     pub fn log_error(&mut self, data: GenerationError) {
+        self.log_outcome("Error", data);
+    }
+
+    /// Logs an error raised by the logger itself, e.g. a record which could not be written, and
+    /// prints it to the console as nobody else reports it
+    fn log_failure(&mut self, data: GenerationError) {
+        eprintln!("{}", data);
+        self.log_error(data);
+    }
+
+    /// Logs an instruction which was not executed (e.g. it is not supported on this platform) as
+    /// a `Skipped` record in the configured format. The kind of the error is the reason it was
+    /// skipped.
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: A Generation Error describing why the instruction was skipped
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Panics
+    ///
+    /// If the record can not be written, see `log_error`
+    pub fn log_skipped(&mut self, data: GenerationError) {
+        self.log_outcome("Skipped", data);
+    }
+
+    /// Writes an error or skipped record of the given type
+    fn log_outcome(&mut self, t: &str, data: GenerationError) {
         let error_log = LogError{
-            t: "Error".to_string(),
+            t: t.to_string(),
             timestamp: get_time(),
             message: format!("{}: {}", data.kind, data.message)
         };
-        eprintln!("{}", data);
-        match self.writer.as_mut() {
-            Ok(inner) => { match inner.serialize(error_log) {
-                    Ok(_) => {},
-                    Err(e) => match e.kind() {
-                        csv::ErrorKind::UnequalLengths{ .. } => {},
-                        _ => panic!( "{}", GenerationError::new("logging".to_string(), e.to_string()))
-                    }
+        let result = self.format_line(&error_log, formatter::cef_error, formatter::leef_error)
+            .and_then(|line| self.write_record(error_log, line));
+        match result {
+            Ok(_) => {},
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    /// Logs the intent to execute a destructive instruction, or its completion, as an `Intent` or
+    /// `Completed` record, see `--write-ahead`. The output file is synced to disk before returning,
+    /// so the record survives a crash of the generator or of the host.
+    ///
+    /// # Parameters
+    ///
+    /// - `t`: Type of record (`Intent` or `Completed`)
+    /// - `message`: number of the instruction and the instruction, followed by its outcome for a
+    ///   `Completed` record
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Panics
+    ///
+    /// Does not panic, but rather if errors occur, they are passed to the error logger.
+    pub fn log_intent(&mut self, t: &str, message: String) {
+        let intent_log = LogError {
+            t: t.to_string(),
+            timestamp: get_time(),
+            message
+        };
+        let result = self.format_line(&intent_log, formatter::cef_intent, formatter::leef_intent)
+            .and_then(|line| self.write_record(intent_log, line))
+            .and_then(|_| self.sync());
+        if let Err(e) = result {
+            self.log_failure(e);
+        }
+    }
+
+    /// Logs a run header or run summary record to the output writer in the configured format
+    /// # Parameters
+    ///
+    /// - `t`: Type of run record (`Run Start` or `Run Summary`)
+    /// - `scenario`: path of the input scenario file
+    /// - `scenario_sha256`: SHA-256 digest of the input scenario file
+    /// - `message`: additional details about the run
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Panics
+    ///
+    /// Does not panic, but rather if errors occur, they are passed to the error logger.
+    pub fn log_run(&mut self, t: String, scenario: String, scenario_sha256: String, message: String) {
+        let run_log = LogRun {
+            t,
+            timestamp: get_time(),
+            scenario,
+            scenario_sha256,
+            message
+        };
+        let result = self.format_line(&run_log, formatter::cef_run, formatter::leef_run)
+            .and_then(|line| self.write_record(run_log, line));
+        match result {
+            Ok(_) => {}
+            Err(e) => self.log_failure(e)
+        };
+    }
+
+    /// Logs a resource usage sample of a child process to the output writer in the configured format
+    /// # Parameters
+    ///
+    /// - `data`: A LogUsage structure containing the sample
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Panics
+    ///
+    /// Does not panic, but rather if errors occur, they are passed to the error logger.
+    pub fn log_usage(&mut self, data: LogUsage) {
+        let result = self.format_line(&data, formatter::cef_usage, formatter::leef_usage)
+            .and_then(|line| self.write_record(data, line));
+        match result {
+            Ok(_) => {}
+            Err(e) => self.log_failure(e)
+        };
+    }
+
+    /// Formats a record in the configured output format
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: record to format
+    /// - `cef`: formatter producing the CEF line of the record
+    /// - `leef`: formatter producing the LEEF line of the record
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The formatted line, or None when the output format is csv
+    /// - `Err`: The record could not be rendered with the template
+    fn format_line<S: Serialize>(&self, record: &S, cef: fn(&S) -> String, leef: fn(&S) -> String) -> Result<Option<String>, GenerationError> {
+        match &self.format {
+            LogFormat::Csv => Ok(None),
+            LogFormat::Cef => Ok(Some(cef(record))),
+            LogFormat::Leef => Ok(Some(leef(record))),
+            LogFormat::Template(template) => Ok(Some(template.render(record)?)),
+        }
+    }
+
+    /// Writes a record to the output file and forwards it to every sink. Sinks that fail are
+    /// reported and disabled so they can not interrupt the run.
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: record to serialize when the output format is csv
+    /// - `line`: preformatted record when the output format is CEF, LEEF, or a template
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The record was written to the output file
+    /// - `Err`: The output file is unavailable or the record could not be serialized
+    fn write_record<S: Serialize>(&mut self, record: S, line: Option<String>) -> Result<(), GenerationError> {
+        let header = self.take_header();
+        let flush = self.flush_records || self.options.flush_interval.is_some_and(|interval| self.last_flush.elapsed() >= interval);
+        let result = match self.writer.as_mut() {
+            Ok(inner) => match &line {
+                Some(line) => write_line(inner, line),
+                None => write_csv(inner, header, &record)
+            }.and_then(|_| if flush { inner.flush().map_err(csv::Error::from) } else { Ok(()) }),
+            Err(_) => return Err(GenerationError::new("logging".to_string(), "Unable to Generate Log".to_string()))
+        };
+        if let Err(e) = result {
+            return Err(GenerationError::new("logging".to_string(), format!("Unable to Serialize Log Message: {}", e)));
+        }
+        if flush {
+            self.last_flush = Instant::now();
+        }
+        if let Some(max_size) = self.options.max_size {
+            if self.written.load(Ordering::Relaxed) >= max_size {
+                self.rotate()?;
+            }
+        }
+        if self.sinks.is_empty() {
+            return Ok(());
+        }
+        let line = match line {
+            Some(line) => line,
+            None => formatter::csv_line(&record)?
+        };
+        let mut index = 0;
+        while index < self.sinks.len() {
+            match self.sinks[index].send(&line) {
+                Ok(_) => index += 1,
+                Err(e) => {
+                    let sink = self.sinks.remove(index);
+                    eprintln!("{} (sink {} disabled)", e, sink.name());
                 }
-            },
-            Err(_) =>  panic!( "{}", GenerationError::new("logging".to_string(), "Unable to Generate Log Data".to_string()))
+            }
         }
+        Ok(())
+    }
+
+    /// Closes the output file, rotates it with `rotate_files`, and starts a new output file with its
+    /// own header row. Rotated files are compressed on a background thread, so logging is not held
+    /// up while a large file is compressed; the previous compression is waited for first. When the
+    /// files can not be rotated, records are appended to the output file and rotation is disabled.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The output file was rotated
+    /// - `Err`: The output file could not be rotated or reopened, or the previously rotated file
+    ///   could not be compressed
+    fn rotate(&mut self) -> Result<(), GenerationError> {
+        if let Ok(inner) = self.writer.as_mut() {
+            inner.flush()?;
+        }
+        // the file must be closed before it can be renamed on Windows
+        self.writer = Err(csv::Error::from(io::Error::other("The output file is being rotated")));
+        self.last_flush = Instant::now();
+        let rotated = self.finish_compression().map_err(io::Error::other).and_then(|_| {
+            let first = shift_files(&self.path, self.options.compress)?;
+            if !self.options.compress {
+                return fs::rename(&self.path, first);
+            }
+            let pending = pending_path(&self.path);
+            fs::rename(&self.path, &pending)?;
+            self.compression = Some(thread::Builder::new().name("compression".to_string()).spawn(move || compress_file(&pending, &first))?);
+            Ok(())
+        });
+        self.writer = open_writer(&self.path, &self.format, rotated.is_err(), &self.written);
+        match rotated {
+            Ok(_) => {
+                self.header_pending = true;
+                Ok(())
+            }
+            Err(e) => {
+                self.options.max_size = None;
+                Err(GenerationError::new("logging".to_string(), format!("Unable to rotate {}, rotation is disabled: {}", self.path, e)))
+            }
+        }
+    }
+
+    /// Waits for the most recently rotated file to be compressed, if it is being compressed
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: No file is being compressed, or it was compressed
+    /// - `Err`: The file could not be compressed, it is left uncompressed
+    fn finish_compression(&mut self) -> Result<(), String> {
+        match self.compression.take().map(JoinHandle::join) {
+            None | Some(Ok(Ok(_))) => Ok(()),
+            Some(Ok(Err(e))) => Err(format!("Unable to compress {}: {}", pending_path(&self.path).display(), e)),
+            Some(Err(_)) => Err(format!("Compression of {} panicked", pending_path(&self.path).display()))
+        }
+    }
+
+    /// Flushes the records written so far and waits until the output file is stored on disk
+    fn sync(&mut self) -> Result<(), GenerationError> {
+        if let Ok(inner) = self.writer.as_mut() {
+            inner.flush()?;
+            // the writer does not expose its file, syncing another handle of the file stores the same data
+            OpenOptions::new().append(true).open(&self.path)?.sync_data()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Determines if the csv header row must be written before the next record
+    ///
+    /// # Returns
+    ///
+    /// True exactly once when the output format is csv, false otherwise
+    fn take_header(&mut self) -> bool {
+        let pending = self.header_pending && self.format == LogFormat::Csv;
+        self.header_pending = false;
+        pending
+    }
+}
+
+impl Drop for Logger {
+    /// Waits for the most recently rotated file to be compressed, so no rotated file is left
+    /// uncompressed when the run ends
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_compression() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Serializes a record as a csv row, preceded by the header row when requested
+///
+/// # Parameters
+///
+/// - `writer`: CSV Writer to write the record to
+/// - `header`: whether the header row should be written first
+/// - `record`: record to serialize
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The record was written
+/// - `Err`: The record could not be serialized or written
+fn write_csv<S: Serialize>(writer: &mut Writer<CountingFile>, header: bool, record: &S) -> csv::Result<()> {
+    if header {
+        writer.write_record(LOG_HEADER)?;
+    }
+    writer.serialize(record)
+}
+
+/// Writes a preformatted line (CEF, LEEF, or template) as a single unquoted field
+///
+/// # Parameters
+///
+/// - `writer`: CSV Writer to write the line to
+/// - `line`: formatted record without a line terminator
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The line was written
+/// - `Err`: The line could not be written
+fn write_line(writer: &mut Writer<CountingFile>, line: &str) -> csv::Result<()> {
+    writer.write_record([line])
+}
+
+/// Opens the output file for the configured format
+///
+/// # Parameters
+///
+/// - `path`: path of the output file
+/// - `format`: format used when writing records to the output file
+/// - `append`: whether records are appended to an existing file rather than replacing it
+/// - `written`: counter of the bytes in the file, set to the size of the opened file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: CSV Writer of the output file
+/// - `Err`: The output file could not be opened
+fn open_writer(path: &str, format: &LogFormat, append: bool, written: &Arc<AtomicU64>) -> csv::Result<Writer<CountingFile>> {
+    // CEF, LEEF, and template records are preformatted lines which must not be quoted by the writer
+    let quote_style = match format {
+        LogFormat::Csv => QuoteStyle::Necessary,
+        _ => QuoteStyle::Never
+    };
+    let file = OpenOptions::new().write(true).create(true).append(append).truncate(!append).open(path)?;
+    written.store(file.metadata()?.len(), Ordering::Relaxed);
+    // Header is written explicitly so that run records may precede the first event
+    Ok(WriterBuilder::new().flexible(true).has_headers(false).quote_style(quote_style).from_writer(CountingFile { file, written: written.clone() }))
+}
+
+/// Rotates an output file: `log.csv` is renamed to `log.1.csv` after renaming `log.1.csv` to
+/// `log.2.csv` and so on, so the lowest number is the most recent. Rotated files of earlier runs
+/// are shifted along with the others. Compressed files are written as `log.1.csv.gz` with gzip and
+/// the output file is removed.
+///
+/// # Parameters
+///
+/// - `path`: path of the output file
+/// - `compress`: whether the rotated file is compressed
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The output file was rotated, it no longer exists
+/// - `Err`: A file could not be renamed or compressed
+pub fn rotate_files(path: &str, compress: bool) -> io::Result<()> {
+    let first = shift_files(path, compress)?;
+    if !compress {
+        return fs::rename(path, first);
+    }
+    let pending = pending_path(path);
+    fs::rename(path, &pending)?;
+    compress_file(&pending, &first)
+}
+
+/// Shifts the rotated files of an output file up one number, freeing the most recent name
+///
+/// # Parameters
+///
+/// - `path`: path of the output file
+/// - `compress`: whether the rotated files are compressed
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Path the output file is to be rotated to, e.g. `log.1.csv`
+/// - `Err`: A file could not be renamed
+fn shift_files(path: &str, compress: bool) -> io::Result<PathBuf> {
+    let mut count = 0;
+    while rotated_path(path, count + 1, compress).exists() {
+        count += 1;
+    }
+    for index in (1..=count).rev() {
+        fs::rename(rotated_path(path, index, compress), rotated_path(path, index + 1, compress))?;
+    }
+    Ok(rotated_path(path, 1, compress))
+}
+
+/// Compresses a rotated file with gzip and removes it once compressed
+///
+/// # Parameters
+///
+/// - `source`: rotated file to compress
+/// - `target`: path of the compressed file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The file was compressed and removed
+/// - `Err`: The file could not be compressed, it is left in place
+fn compress_file(source: &Path, target: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+    io::copy(&mut File::open(source)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(source)
+}
+
+/// Builds the path a rotated output file keeps until it is compressed, e.g. `log.csv.rotated`
+fn pending_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.rotated", path))
+}
+
+/// Builds the path of a rotated output file, e.g. `log.2.csv` or `log.2.csv.gz`
+///
+/// # Parameters
+///
+/// - `path`: path of the output file
+/// - `index`: number of the rotated file, 1 being the most recent
+/// - `compress`: whether the rotated file is compressed
+///
+/// # Returns
+///
+/// Path of the rotated file
+fn rotated_path(path: &str, index: usize, compress: bool) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let mut name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, index)
+    };
+    if compress {
+        name.push_str(".gz");
+    }
+    path.with_file_name(name)
+}
+
+/// Parses a size in bytes, optionally followed by a `K`, `M`, or `G` multiplier (powers of 1024)
+/// and `B`, e.g. `500M` or `2GB`
+///
+/// # Parameters
+///
+/// - `size`: size as given on the command line
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Number of bytes
+/// - `Err`: The size is not a positive number of bytes
+pub fn parse_size(size: &str) -> Result<u64, GenerationError> {
+    let upper = size.trim().to_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits, 1)
+    };
+    match digits.trim().parse::<u64>().ok().and_then(|value| value.checked_mul(multiplier)) {
+        Some(bytes) if bytes > 0 => Ok(bytes),
+        _ => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid size, expected a number of bytes optionally followed by K, M, or G", size)))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn sizes_parsed() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("2gb").unwrap(), 2 << 30);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn output_rotated() {
+        let directory = TempPath::new("rotate");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("log.csv").to_string_lossy().to_string();
+        assert_eq!(rotated_path(&path, 2, true), directory.join("log.2.csv.gz"));
+        let mut logger = Logger::new(&path, LogFormat::Csv);
+        logger.set_output_options(OutputOptions { flush_interval: None, max_size: Some(100), compress: false });
+        for _ in 0..3 {
+            logger.log_run("Run Start".to_string(), "scenario.csv".to_string(), "0".repeat(64), "x".repeat(10000));
+        }
+        drop(logger);
+        // every record exceeds the limit and the write buffer, so each one ends up in its own file
+        assert!(directory.join("log.3.csv").exists());
+        let rotated = fs::read_to_string(directory.join("log.1.csv")).unwrap();
+        assert!(rotated.starts_with("t,timestamp,"));
+        assert!(rotated.lines().nth(1).unwrap().starts_with("Run Start,"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        fs::write(&path, "rotated").unwrap();
+        rotate_files(&path, true).unwrap();
+        assert!(!Path::new(&path).exists());
+        assert!(fs::metadata(directory.join("log.1.csv.gz")).unwrap().len() > 0);
+
+        let mut logger = Logger::new(&path, LogFormat::Csv);
+        logger.set_output_options(OutputOptions { flush_interval: None, max_size: Some(100), compress: true });
+        for _ in 0..3 {
+            logger.log_run("Run Start".to_string(), "scenario.csv".to_string(), "0".repeat(64), "x".repeat(10000));
+        }
+        // rotated files are compressed in the background, dropping the logger waits for the last one
+        drop(logger);
+        assert!(directory.join("log.4.csv.gz").exists());
+        assert!(!pending_path(&path).exists());
+    }
+}
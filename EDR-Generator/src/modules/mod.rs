@@ -2,5 +2,39 @@ pub mod process;
 pub mod file_system;
 pub mod network;
 pub mod logger;
+pub mod formatter;
+pub mod template;
+pub mod sink;
 pub mod commander;
-pub mod common;
\ No newline at end of file
+pub mod instruction;
+pub mod scenario;
+pub mod script;
+pub mod synthetic;
+pub mod rate;
+pub mod worker;
+pub mod builder;
+pub mod server;
+pub mod validate;
+pub mod report;
+pub mod inventory;
+pub mod pack;
+pub mod signature;
+pub mod policy;
+pub mod workdir;
+pub mod lock;
+pub mod stix;
+pub mod vectr;
+pub mod events;
+pub mod dashboard;
+pub mod persistence;
+pub mod tamper;
+pub mod account;
+pub mod boot;
+pub mod verify;
+pub mod pcap;
+pub mod campaign;
+pub mod ingest;
+pub mod alias;
+pub mod common;
+#[cfg(test)]
+pub(crate) mod testing;
\ No newline at end of file
@@ -1,15 +1,143 @@
-use std::net::{TcpStream, TcpListener};
-use std::io::{Write, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, TcpListener, ToSocketAddrs};
+use std::fs;
+use std::io::{self, Write, Read, ErrorKind};
+use std::path::{Path, PathBuf};
 use crate::modules::common::{GenerationError, get_time};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 use crate::modules::logger::Log;
 
+/// Interval the listener of a `listen` instruction is polled at while waiting for a connection
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Size in bytes of the payload of each beacon of a `beacon` instruction when none is given
+pub const DEFAULT_BEACON_SIZE: usize = 64;
+
+/// Largest payload a single beacon may send, beacons are small check-ins rather than transfers
+pub const MAX_BEACON_SIZE: usize = 65536;
+
+/// Directory `ip netns add` creates named network namespaces in
+pub const NETNS_DIRECTORY: &str = "/var/run/netns";
+
+/// Size in bytes of the segments shaped data is sent in, the maximum TCP segment over Ethernet
+pub const SEGMENT_SIZE: usize = 1460;
+
+/// Shortest time a lost segment waits before it is retransmitted, the minimum TCP retransmission
+/// timeout
+const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Largest number of connections a single `fan_out` instruction may open
+pub const MAX_FAN_OUT_CONNECTIONS: usize = 65536;
+
+/// Largest number of connections a `fan_out` instruction may hold open at the same time
+pub const MAX_FAN_OUT_CONCURRENCY: usize = 1024;
+
+/// Number of connections a `fan_out` instruction holds open at the same time when none is given
+pub const DEFAULT_FAN_OUT_CONCURRENCY: usize = 64;
+
+/// Longest time each connection attempt of a `fan_out` instruction takes when no
+/// `connect_timeout=` is given, unanswered attempts would otherwise wait for the OS default
+pub const DEFAULT_FAN_OUT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Stack size of the connection threads of a `fan_out` instruction, they only hold a socket
+const CONNECTION_STACK_SIZE: usize = 256 * 1024;
+
+/// Structure defining the degraded network conditions a network instruction is executed under.
+/// Conditions are simulated in userspace on the connection of the instruction only, so no
+/// privileges are needed and other traffic of the host is not affected. Segments are lost at
+/// random, but the generator is seeded so an instruction loses the same segments every run.
+///
+/// # Parameters
+///
+/// - `latency`: delay before the data is sent, and the round trip retransmissions wait for
+/// - `loss`: probability (at least 0, less than 1) that a segment is lost and retransmitted
+/// - `bandwidth`: optional rate in bytes per second the data is paced to
+/// - `seed`: seed of the generator deciding which segments are lost
+/// - `connect_timeout`: optional longest time each connection attempt may take, the OS default
+///   (often more than a minute for an unanswered attempt) when not given
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Shaping {
+    pub latency: Duration,
+    pub loss: f64,
+    pub bandwidth: Option<u64>,
+    pub seed: u64,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Shaping {
+    /// Determines if any condition is degraded
+    ///
+    /// # Returns
+    ///
+    /// True if the data is delayed, lost, or paced, false if it is sent as fast as possible
+    pub fn is_shaped(&self) -> bool {
+        self.latency > Duration::from_millis(0) || self.loss > 0.0 || self.bandwidth.is_some()
+    }
+
+    /// Writes data to a connection under the conditions. The data is held back for the latency,
+    /// then written in segments of `SEGMENT_SIZE` bytes. A lost segment is written once its
+    /// retransmission timeout expires, four times the latency (twice the round trip) and at least
+    /// 200 milliseconds, doubled for every further loss of the same segment. With a bandwidth the
+    /// segments are paced so the average rate never exceeds it.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream`: connection the data is written to
+    /// - `data`: data to send
+    /// - `stop`: whether the instruction must stop, checked while waiting
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Number of segments which were retransmitted
+    /// - `Err`: The data could not be written, or sending was stopped
+    pub fn write(&self, stream: &mut TcpStream, data: &[u8], stop: &dyn Fn() -> bool) -> Result<u32, GenerationError> {
+        use rand::{Rng, SeedableRng};
+
+        let stopped = || GenerationError::new("network".to_string(), "Stopped while the data was delayed".to_string());
+        let failed = |_| GenerationError::new("network".to_string(), "Unable to open stream for writing".to_string());
+        // segments must not be coalesced for pacing and retransmissions to be observable
+        stream.set_nodelay(true).map_err(failed)?;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
+        if !wait(self.latency, stop) {
+            return Err(stopped());
+        }
+        let started = Instant::now();
+        let mut sent = 0;
+        let mut retransmissions = 0;
+        for segment in data.chunks(SEGMENT_SIZE) {
+            let mut timeout = (self.latency * 4).max(MIN_RETRANSMISSION_TIMEOUT);
+            while rng.gen::<f64>() < self.loss {
+                retransmissions += 1;
+                if !wait(timeout, stop) {
+                    return Err(stopped());
+                }
+                timeout *= 2;
+            }
+            stream.write_all(segment).map_err(failed)?;
+            sent += segment.len();
+            if let Some(bandwidth) = self.bandwidth {
+                let due = started + Duration::from_secs_f64(sent as f64 / bandwidth as f64);
+                if !wait(due.saturating_duration_since(Instant::now()), stop) {
+                    return Err(stopped());
+                }
+            }
+        }
+        stream.flush().map_err(failed)?;
+        Ok(retransmissions)
+    }
+}
+
 /// Opens a socket connection to the target at a specified port. Will send provided message
 /// and then close the connection. Connection will not be maintained
 ///
 /// # Parameters
 ///
-/// - `ip`: A string containing the IP address of the target
+/// - `ip`: A string containing the IPv4 or IPv6 address (bracketed or not) or the hostname of the
+///   target
 /// - `port`: An integer containing the port number of the target
 /// - `message`: A u8 vector containing the message contents to send to the target
 ///
@@ -17,18 +145,66 @@ use crate::modules::logger::Log;
 ///
 /// A `Result` which is:
 ///
-/// - `Ok`: Log data confirming the message was successfully sent to the target.
-/// - `Err`: There was an issue sending the message. (Network issue or bad message)
+/// - `Ok`: Log data confirming the message was successfully sent to the target, the destination is
+///   the address the hostname resolved to
+/// - `Err`: There was an issue sending the message. (Unresolved hostname, network issue or bad message)
+///
+///
+pub fn send_message(ip: &str, port: u16, message: &Vec<u8>,) -> Result<Log, GenerationError>{
+    send_message_shaped(ip, port, message, &Shaping::default(), || false)
+}
+
+/// Sends a message to the target as `send_message` does, under the degraded network conditions of
+/// `shaping`
+///
+/// # Parameters
 ///
+/// - `ip`: A string containing the address or hostname of the target
+/// - `port`: An integer containing the port number of the target
+/// - `message`: The message contents to send to the target
+/// - `shaping`: latency, loss, and bandwidth the message is sent with, see `Shaping::write`
+/// - `stop`: whether the instruction must stop, checked while the message is delayed
 ///
-pub fn send_message(ip: &String, port: u16, message: &Vec<u8>,) -> Result<Log, GenerationError>{
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the message was successfully sent to the target
+/// - `Err`: There was an issue sending the message, or sending was stopped
+pub fn send_message_shaped(ip: &str, port: u16, message: &[u8], shaping: &Shaping, stop: impl Fn() -> bool) -> Result<Log, GenerationError> {
+    send_message_held(ip, port, message, shaping, Duration::from_millis(0), &stop)
+}
+
+/// Sends a message to the target as `send_message_shaped` does, then holds the connection open
+/// before closing it
+///
+/// # Parameters
+///
+/// - `ip`: A string containing the address or hostname of the target
+/// - `port`: An integer containing the port number of the target
+/// - `message`: The message contents to send to the target
+/// - `shaping`: latency, loss, bandwidth, and connection timeout the message is sent with
+/// - `hold`: time the connection is held open once the message was sent, cut short when stopped
+/// - `stop`: whether the instruction must stop
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the message was successfully sent to the target
+/// - `Err`: There was an issue sending the message, or sending was stopped
+fn send_message_held(ip: &str, port: u16, message: &[u8], shaping: &Shaping, hold: Duration, stop: &dyn Fn() -> bool) -> Result<Log, GenerationError> {
     if port == 0 {
         return Err(GenerationError::new("network".to_string(), "Invalid Port Number".to_string()))
     }
-    let net_address = format!("{}:{}", ip, &(port.to_string()));
-    match TcpStream::connect(net_address) {
-        Ok(mut stream) => {
-            match stream.write(&*message){
+    match connect(ip, port, shaping.connect_timeout) {
+        Ok((mut stream, peer)) => {
+            let written = if shaping.is_shaped() {
+                shaping.write(&mut stream, message, stop).map(|_| message.len())
+            } else {
+                stream.write(message).map_err(|_| GenerationError::new("network".to_string(), "Unable to open stream for writing".to_string()))
+            };
+            match written {
                 Ok(bytes_sent) => {
                     let mut local_addr = "unknown".to_string();
                     let mut local_port: u16 = 0;
@@ -39,18 +215,84 @@ pub fn send_message(ip: &String, port: u16, message: &Vec<u8>,) -> Result<Log, G
                         }
                         Err(_) => {}
                     }
+                    wait(hold, stop);
                     return Ok(adapt_log_network("Network Connection".to_string(),
                                                 local_addr, local_port,
-                                                String::from(ip), port,
+                                                peer.ip().to_string(), port,
                                                 bytes_sent, "TCP/IP".to_string()))
                 },
-                Err(_) => return Err(GenerationError::new("network".to_string(), "Unable to open stream for writing".to_string()))
+                Err(e) => return Err(e)
             }
         },
-        Err(_) => {
-            return Err(GenerationError::new("network".to_string(), "Unable to Connect".to_string()))
-        }
+        Err(e) => Err(e)
+    }
+}
+
+/// Reads a local file and sends its contents to the target, either as raw TCP data or as the body
+/// of an HTTP POST request to `/upload`. The connection is closed once the contents are sent.
+///
+/// # Parameters
+///
+/// - `path`: A string containing the path of the file to send
+/// - `ip`: A string containing the IPv4 or IPv6 address or the hostname of the target
+/// - `port`: An integer containing the port number of the target
+/// - `http`: Whether the contents are wrapped in an HTTP POST request
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data tying the file to the connection, `bytes_sent` is the size of the file
+/// - `Err`: The file could not be read, or there was an issue sending it. (Network issue)
+pub fn send_file(path: &String, ip: &str, port: u16, http: bool) -> Result<Log, GenerationError> {
+    send_file_shaped(path, ip, port, http, &Shaping::default(), || false)
+}
+
+/// Sends the contents of a local file to the target as `send_file` does, under the degraded
+/// network conditions of `shaping`
+///
+/// # Parameters
+///
+/// - `path`: A string containing the path of the file to send
+/// - `ip`: A string containing the IPv4 or IPv6 address or the hostname of the target
+/// - `port`: An integer containing the port number of the target
+/// - `http`: Whether the contents are wrapped in an HTTP POST request
+/// - `shaping`: latency, loss, and bandwidth the contents are sent with, see `Shaping::write`
+/// - `stop`: whether the instruction must stop, checked while the contents are delayed
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data tying the file to the connection, `bytes_sent` is the size of the file
+/// - `Err`: The file could not be read, there was an issue sending it, or sending was stopped
+pub fn send_file_shaped(path: &str, ip: &str, port: u16, http: bool, shaping: &Shaping, stop: impl Fn() -> bool) -> Result<Log, GenerationError> {
+    if port == 0 {
+        return Err(GenerationError::new("network".to_string(), "Invalid Port Number".to_string()))
     }
+    let contents = match fs::read(path) {
+        Ok(inner) => inner,
+        Err(e) => return Err(GenerationError::new("io".to_string(), format!("Unable to read {}: {}", path, e)))
+    };
+    let (mut stream, peer) = connect(ip, port, shaping.connect_timeout)?;
+    let local = stream.local_addr()?;
+    let mut data = Vec::new();
+    if http {
+        data.extend(format!("POST /upload HTTP/1.1\r\nHost: {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            host_port(ip, port), contents.len()).as_bytes());
+    }
+    data.extend(&contents);
+    if shaping.is_shaped() {
+        shaping.write(&mut stream, &data, &stop)?;
+    } else if stream.write_all(&data).is_err() {
+        return Err(GenerationError::new("network".to_string(), "Unable to open stream for writing".to_string()))
+    }
+    let mut log = adapt_log_network("File Exfiltration".to_string(),
+                                    local.ip().to_string(), local.port(),
+                                    peer.ip().to_string(), port,
+                                    contents.len(), if http { "HTTP" } else { "TCP/IP" }.to_string());
+    log.file_path = path.to_string();
+    Ok(log)
 }
 
 /// Opens a socket to the localhost loopback address. Will send provided message
@@ -79,8 +321,362 @@ pub fn send_loopback_message(message: &Vec<u8>) -> Result<Log, GenerationError>
     send_message(&String::from("127.0.0.1"), port, message)
 }
 
+/// Resolves the destination of a connection, which may be an IPv4 address, an IPv6 address with
+/// or without brackets, or a hostname
+///
+/// # Parameters
+///
+/// - `host`: A string containing the address or hostname of the target
+/// - `port`: An integer containing the port number of the target
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Every address the destination resolved to, in the order the resolver returned them
+/// - `Err`: The hostname could not be resolved
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, GenerationError> {
+    let host = host.trim();
+    let host = host.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')).unwrap_or(host);
+    let addresses: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+        Ok(inner) => inner.collect(),
+        Err(e) => return Err(GenerationError::new("network".to_string(), format!("Unable to Resolve {}: {}", host, e)))
+    };
+    if addresses.is_empty() {
+        return Err(GenerationError::new("network".to_string(), format!("Unable to Resolve {}: no address found", host)))
+    }
+    Ok(addresses)
+}
+
+/// Formats a destination and a port as written in a URL or a socket address, bracketing IPv6
+/// addresses (e.g. `[::1]:8080`)
+///
+/// # Parameters
+///
+/// - `host`: A string containing the address or hostname of the target
+/// - `port`: An integer containing the port number of the target
+///
+/// # Returns
+///
+/// The destination and the port separated by a colon
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = host.trim();
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(address)) => format!("[{}]:{}", address, port),
+        _ => format!("{}:{}", host, port)
+    }
+}
+
+/// Names the address family of an address, as recorded in the `address_family` field of network
+/// events
+///
+/// # Parameters
+///
+/// - `address`: A string containing an IP address
+///
+/// # Returns
+///
+/// `IPv4` or `IPv6`, or an empty string when the address is not an IP address
+pub fn address_family(address: &str) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => "IPv4".to_string(),
+        Ok(IpAddr::V6(_)) => "IPv6".to_string(),
+        Err(_) => "".to_string()
+    }
+}
+
+/// Connects to the first reachable address a destination resolves to, see `resolve`. With a
+/// timeout every address is given up on once the timeout expires.
+fn connect(host: &str, port: u16, timeout: Option<Duration>) -> Result<(TcpStream, SocketAddr), GenerationError> {
+    let addresses = resolve(host, port)?;
+    let connected = match timeout {
+        Some(timeout) => {
+            let mut connected = Err(io::Error::from(ErrorKind::NotConnected));
+            for address in &addresses {
+                connected = TcpStream::connect_timeout(address, timeout);
+                if connected.is_ok() {
+                    break;
+                }
+            }
+            connected
+        }
+        None => TcpStream::connect(&addresses[..])
+    };
+    match connected {
+        Ok(stream) => {
+            let peer = stream.peer_addr()?;
+            Ok((stream, peer))
+        }
+        Err(e) if e.kind() == ErrorKind::TimedOut => match timeout {
+            Some(timeout) => Err(GenerationError::new("network".to_string(), format!("Unable to Connect: timed out after {} ms", timeout.as_millis()))),
+            None => Err(GenerationError::new("network".to_string(), "Unable to Connect: timed out".to_string()))
+        },
+        Err(_) => Err(GenerationError::new("network".to_string(), "Unable to Connect".to_string()))
+    }
+}
+
+/// Expands the destinations of a `fan_out` instruction: addresses, hostnames, and IPv4 blocks in
+/// CIDR notation (e.g. `10.0.0.0/24`), separated by spaces. The network and broadcast addresses of
+/// blocks of more than two addresses are left out, as scanners do.
+///
+/// # Parameters
+///
+/// - `hosts`: destinations separated by spaces
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Every destination, in the order given
+/// - `Err`: No destination is given, a block is not a valid IPv4 block, or there are more than
+///   `MAX_FAN_OUT_CONNECTIONS` destinations
+pub fn expand_hosts(hosts: &str) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for host in hosts.split_whitespace() {
+        let (network, prefix) = match host.split_once('/') {
+            Some(inner) => inner,
+            None => {
+                expanded.push(host.to_string());
+                continue;
+            }
+        };
+        let network: Ipv4Addr = network.parse().map_err(|_| format!("{} is not an IPv4 block", host))?;
+        let prefix: u32 = match prefix.parse() {
+            Ok(inner) if inner <= 32 => inner,
+            _ => return Err(format!("{} is not an IPv4 block", host))
+        };
+        let size = 1u64 << (32 - prefix);
+        if size > MAX_FAN_OUT_CONNECTIONS as u64 {
+            return Err(format!("{} has more than {} addresses", host, MAX_FAN_OUT_CONNECTIONS));
+        }
+        let first = u32::from(network) & !((size - 1) as u32);
+        let (start, end) = if size > 2 { (1, size - 1) } else { (0, size) };
+        expanded.extend((start..end).map(|offset| Ipv4Addr::from(first + offset as u32).to_string()));
+    }
+    if expanded.is_empty() {
+        return Err("no destination is given".to_string());
+    }
+    if expanded.len() > MAX_FAN_OUT_CONNECTIONS {
+        return Err(format!("more than {} destinations are given", MAX_FAN_OUT_CONNECTIONS));
+    }
+    Ok(expanded)
+}
+
+/// Expands the ports of a `fan_out` instruction: ports and inclusive ranges (e.g. `8000-8010`),
+/// separated by spaces
+///
+/// # Parameters
+///
+/// - `ports`: ports separated by spaces
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Every port, in the order given
+/// - `Err`: No port is given, or a port or range is invalid
+pub fn expand_ports(ports: &str) -> Result<Vec<u16>, String> {
+    let mut expanded = Vec::new();
+    for port in ports.split_whitespace() {
+        let invalid = || format!("{} is not a port or a range of ports", port);
+        let (first, last) = match port.split_once('-') {
+            Some((first, last)) => (first.parse::<u16>().map_err(|_| invalid())?, last.parse::<u16>().map_err(|_| invalid())?),
+            None => {
+                let port = port.parse::<u16>().map_err(|_| invalid())?;
+                (port, port)
+            }
+        };
+        if first == 0 || first > last {
+            return Err(invalid());
+        }
+        expanded.extend(first..=last);
+    }
+    if expanded.is_empty() {
+        return Err("no port is given".to_string());
+    }
+    Ok(expanded)
+}
+
+/// Sends a message to many destinations at once, as a scan or an implant fanning out to several
+/// hosts would. Connections are made by a pool of `concurrency` threads, each taking the next
+/// destination once its connection closed, so up to `concurrency` connections are open at the
+/// same time. Results are reported on the calling thread as connections complete, so their order
+/// is not the order of the destinations. Destinations not attempted yet are skipped once stopped.
+///
+/// # Parameters
+///
+/// - `targets`: destination host and port of every connection
+/// - `message`: data sent once connected
+/// - `concurrency`: number of connection threads
+/// - `hold`: time each connection is held open once the message was sent
+/// - `shaping`: latency, loss, bandwidth, and connection timeout of every connection
+/// - `stop`: whether the instruction must stop, shared by the connection threads
+/// - `report`: called with the index of the destination and the result of its connection
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Every destination was attempted, or skipped once stopped
+/// - `Err`: No connection thread could be started
+pub fn fan_out(targets: &[(String, u16)], message: &[u8], concurrency: usize, hold: Duration, shaping: &Shaping,
+               stop: &(dyn Fn() -> bool + Sync), mut report: impl FnMut(usize, Result<Log, GenerationError>)) -> Result<(), GenerationError> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        let mut started = 0;
+        for index in 0..concurrency.min(targets.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            let spawned = thread::Builder::new().name(format!("fan-out-{}", index)).stack_size(CONNECTION_STACK_SIZE).spawn_scoped(scope, move || {
+                loop {
+                    let current = next.fetch_add(1, Ordering::SeqCst);
+                    if current >= targets.len() || stop() {
+                        break;
+                    }
+                    let (host, port) = &targets[current];
+                    if sender.send((current, send_message_held(host, *port, message, shaping, hold, stop))).is_err() {
+                        break;
+                    }
+                }
+            });
+            match spawned {
+                Ok(_) => started += 1,
+                // the threads started so far still attempt every destination, only fewer at once
+                Err(_) if started > 0 => break,
+                Err(e) => return Err(GenerationError::from(e))
+            }
+        }
+        drop(sender);
+        for (index, result) in receiver {
+            report(index, result);
+        }
+        Ok(())
+    })
+}
+
+/// Sleeps for a duration in short steps, waking early when stopped
+///
+/// # Parameters
+///
+/// - `duration`: time to sleep
+/// - `stop`: whether to stop waiting
+///
+/// # Returns
+///
+/// True if the full duration elapsed, false if stopped
+fn wait(duration: Duration, stop: &dyn Fn() -> bool) -> bool {
+    let end = Instant::now() + duration;
+    loop {
+        if stop() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= end {
+            return true;
+        }
+        thread::sleep((end - now).min(ACCEPT_POLL_INTERVAL));
+    }
+}
+
+/// Builds the payload of a beacon: random alphanumeric characters, so successive beacons differ
+/// in content but not in size
+///
+/// # Parameters
+///
+/// - `size`: size of the payload in bytes
+///
+/// # Returns
+///
+/// The payload
+pub fn beacon_payload(size: usize) -> Vec<u8> {
+    use rand::Rng;
+    rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(size).collect()
+}
+
+/// Opens a listening socket at the provided interface and port, so the receive side of a
+/// connection can be generated. Use 0.0.0.0 (or :: for IPv6) to listen on all interfaces and port
+/// 0 for an OS assigned port.
+///
+/// # Parameters
+///
+/// - `ip`: A string containing the local network interface to listen on
+/// - `port`: An integer containing the port number to listen on
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The listener, and Log data of the opened port
+/// - `Err`: There was an issue creating the listener. (Port in use, no permissions or other issue)
+pub fn open_listener(ip: &String, port: u16) -> Result<(TcpListener, Log), GenerationError> {
+    let listener = match spawn_server(ip, port) {
+        Ok(inner) => inner,
+        Err(e) => return Err(GenerationError::new("network".to_string(), format!("Unable to Listen on {}:{}: {}", ip, port, e.message)))
+    };
+    let local = listener.local_addr()?;
+    let mut log = adapt_log_network("Listening Port Opened".to_string(), "".to_string(), 0,
+                                    local.ip().to_string(), local.port(), 0, "TCP/IP".to_string());
+    // nothing has connected yet, so there is no source
+    log.source_port = "".to_string();
+    Ok((listener, log))
+}
+
+/// Waits for a single client to connect to a listener and receives data until the client closes
+/// the connection. The listener is closed afterwards.
+///
+/// # Parameters
+///
+/// - `listener`: A TCPListener created by `open_listener`
+/// - `timeout`: Optional time to wait for the connection and its data
+/// - `stop`: Checked while waiting, ends the wait early when it returns true (e.g. when the run is
+///   interrupted)
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the inbound connection, with the client as the source
+/// - `Err`: No client connected in time, or there was an issue receiving the data
+pub fn accept_message(listener: TcpListener, timeout: Option<Duration>, stop: impl Fn() -> bool) -> Result<Log, GenerationError> {
+    let local = listener.local_addr()?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    listener.set_nonblocking(true)?;
+    let (mut stream, peer) = loop {
+        match listener.accept() {
+            Ok(inner) => break inner,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if stop() {
+                    return Err(GenerationError::new("network".to_string(), format!("Stopped listening on {} before a connection was received", local)));
+                }
+                if deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false) {
+                    return Err(GenerationError::new("network".to_string(), format!("No connection was received on {} before the timeout", local)));
+                }
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => return Err(GenerationError::from(e))
+        }
+    };
+    drop(listener);
+    stream.set_nonblocking(false)?;
+    if let Some(deadline) = deadline {
+        // the client may keep the connection open, the data received until the timeout is kept
+        stream.set_read_timeout(Some(deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1))))?;
+    }
+    let mut data = Vec::new();
+    match stream.read_to_end(&mut data) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+        Err(_) => return Err(GenerationError::new("network".to_string(), format!("Unable to receive data from {}", peer)))
+    }
+    Ok(adapt_log_network("Inbound Network Connection".to_string(),
+                         peer.ip().to_string(), peer.port(),
+                         local.ip().to_string(), local.port(),
+                         data.len(), "TCP/IP".to_string()))
+}
+
 /// Spawns a TCPListener at the provided interface and port.
-/// Use 0.0.0.0 to listen on all interfaces.
+/// Use 0.0.0.0 (or :: for IPv6) to listen on all interfaces.
 ///
 /// # Parameters
 ///
@@ -93,9 +689,8 @@ pub fn send_loopback_message(message: &Vec<u8>) -> Result<Log, GenerationError>
 ///
 /// - `Ok`: A TCPListener was successfully created with the requested parameters
 /// - `Err`: There was an issue creating the listener. (No permissions or other issue)
-fn spawn_server(ip: &String, port: u16) -> Result<TcpListener, GenerationError> {
-    let net_address = format!("{}:{}", ip, &(port.to_string()));
-    let listener = TcpListener::bind(net_address)?;
+fn spawn_server(ip: &str, port: u16) -> Result<TcpListener, GenerationError> {
+    let listener = TcpListener::bind(&resolve(ip, port)?[..])?;
     Ok(listener)
 }
 
@@ -138,9 +733,10 @@ fn server_listen(listener: TcpListener) -> Result<Vec<u8>, GenerationError>  {
 /// # Parameters
 ///
 /// - `activity`: A string containing the type of activity that has occurred
-/// - `source_addr`: String containing the IPv4 IP address of the source
+/// - `source_addr`: String containing the IPv4 or IPv6 address of the source
 /// - `source_port`: Port number of the source connection
-/// - `dest_addr`: String containing the IPv4 IP address of the destination
+/// - `dest_addr`: String containing the IPv4 or IPv6 address of the destination, its address
+///   family is recorded in `address_family`
 /// - `dest_port`: Port number of the destination connection
 /// - `bytes_sent`: Number of bytes that were sent from source to destination
 /// - `protocol`: String containing the network protocol used for communication
@@ -151,6 +747,7 @@ fn server_listen(listener: TcpListener) -> Result<Vec<u8>, GenerationError>  {
 ///
 /// - A Log struct customized for network connection events
 pub fn adapt_log_network(activity: String, source_addr: String, source_port: u16, dest_addr: String, dest_port: u16, bytes_sent: usize, protocol: String) -> Log {
+    let family = address_family(&dest_addr);
     Log{
         t: String::from("Information"),
         timestamp: get_time(),
@@ -165,13 +762,79 @@ pub fn adapt_log_network(activity: String, source_addr: String, source_port: u16
         dest_addr,
         dest_port: dest_port.to_string(),
         bytes_sent: bytes_sent.to_string(),
-        protocol
+        protocol,
+        address_family: family,
+        stdout: String::from(""),
+        stderr: String::from(""),
+        exit_code: String::from("")
+    }
+}
+
+/// Resolves the network namespace named by a `netns=` parameter. Names are looked up where
+/// `ip netns add` creates them, paths (e.g. `/proc/<pid>/ns/net`) are used as given.
+///
+/// # Parameters
+///
+/// - `name`: name or path of the network namespace
+///
+/// # Returns
+///
+/// Path of the namespace file
+pub fn namespace_path(name: &str) -> PathBuf {
+    if name.contains('/') {
+        PathBuf::from(name)
+    } else {
+        Path::new(NETNS_DIRECTORY).join(name)
+    }
+}
+
+/// Moves the calling thread into a network namespace, so the sockets it opens from then on belong
+/// to the namespace. Other threads, including the one which started the run, are not affected.
+///
+/// # Parameters
+///
+/// - `name`: name or path of the network namespace, see `namespace_path`
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The thread is in the namespace
+/// - `Err`: The namespace does not exist, or entering it requires `CAP_SYS_ADMIN`
+#[cfg(target_os = "linux")]
+pub fn enter_namespace(name: &str) -> Result<(), GenerationError> {
+    use std::os::unix::io::AsRawFd;
+
+    const CLONE_NEWNET: i32 = 0x4000_0000;
+    extern "C" {
+        fn setns(fd: i32, nstype: i32) -> i32;
+    }
+
+    let path = namespace_path(name);
+    let namespace = match fs::File::open(&path) {
+        Ok(inner) => inner,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(GenerationError::new("network".to_string(), format!("Network namespace {} does not exist ({})", name, path.display()))),
+        Err(e) => return Err(GenerationError::from(e))
+    };
+    if unsafe { setns(namespace.as_raw_fd(), CLONE_NEWNET) } != 0 {
+        let error = std::io::Error::last_os_error();
+        return match error.kind() {
+            ErrorKind::PermissionDenied => Err(GenerationError::new("user_permissions".to_string(), format!("Entering network namespace {} requires CAP_SYS_ADMIN ({})", name, error))),
+            _ => Err(GenerationError::new("network".to_string(), format!("Unable to enter network namespace {}: {}", name, error)))
+        };
     }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enter_namespace(_name: &str) -> Result<(), GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Network namespaces are only supported on Linux".to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::modules::testing::TempPath;
 
     #[test]
     fn test_server_valid() {
@@ -189,4 +852,142 @@ mod tests {
         assert_eq!(child_result.unwrap().unwrap(), message);
     }
 
+    #[test]
+    fn test_shaped_message() {
+        let shaping = Shaping { latency: Duration::from_millis(50), loss: 0.25, bandwidth: Some(100_000), seed: 1, connect_timeout: None };
+        let message: Vec<u8> = (0..5000).map(|index| (index % 251) as u8).collect();
+        let mut retransmissions = Vec::new();
+        for _ in 0..2 {
+            let server = spawn_server(&String::from("127.0.0.1"), 0).unwrap();
+            let port = server.local_addr().unwrap().port();
+            let child = thread::spawn(move || server_listen(server));
+            let (mut stream, _) = connect("127.0.0.1", port, Some(Duration::from_secs(5))).unwrap();
+            let started = Instant::now();
+            retransmissions.push(shaping.write(&mut stream, &message, &|| false).unwrap());
+            // the latency and 5000 bytes at 100000 bytes per second
+            assert!(started.elapsed() >= Duration::from_millis(100));
+            drop(stream);
+            assert_eq!(child.join().unwrap().unwrap(), message);
+        }
+        // the same segment is lost every time
+        assert_eq!(retransmissions, vec![1, 1]);
+        assert!(!Shaping::default().is_shaped());
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let mut targets = Vec::new();
+        let mut children = Vec::new();
+        for _ in 0..4 {
+            let server = spawn_server(&String::from("127.0.0.1"), 0).unwrap();
+            targets.push(("127.0.0.1".to_string(), server.local_addr().unwrap().port()));
+            children.push(thread::spawn(move || server_listen(server)));
+        }
+        // a port nothing listens on, the listener is closed before the fan-out starts
+        let closed = spawn_server(&String::from("127.0.0.1"), 0).unwrap().local_addr().unwrap().port();
+        targets.push(("127.0.0.1".to_string(), closed));
+        let shaping = Shaping { connect_timeout: Some(Duration::from_secs(5)), ..Shaping::default() };
+        let started = Instant::now();
+        let mut results = Vec::new();
+        fan_out(&targets, b"hello", 8, Duration::from_millis(200), &shaping, &|| false, |index, result| results.push((index, result))).unwrap();
+        // every connection was held open at the same time
+        assert!(started.elapsed() < Duration::from_millis(800));
+        results.sort_by_key(|(index, _)| *index);
+        assert_eq!(results.iter().map(|(index, _)| *index).collect::<Vec<usize>>(), vec![0, 1, 2, 3, 4]);
+        assert!(results[..4].iter().all(|(_, result)| result.as_ref().is_ok_and(|log| log.bytes_sent == "5")));
+        assert!(results[4].1.is_err());
+        for child in children {
+            assert_eq!(child.join().unwrap().unwrap(), b"hello");
+        }
+
+        let mut attempted = 0;
+        fan_out(&targets, b"hello", 2, Duration::from_millis(0), &shaping, &|| true, |_, _| attempted += 1).unwrap();
+        assert_eq!(attempted, 0);
+    }
+
+    #[test]
+    fn test_fan_out_expanded() {
+        assert_eq!(expand_hosts("192.168.1.4/30 10.0.0.5/32 ::1 c2.lab.local").unwrap(), vec!["192.168.1.5", "192.168.1.6", "10.0.0.5", "::1", "c2.lab.local"]);
+        assert_eq!(expand_hosts("10.0.0.0/16").unwrap().len(), 65534);
+        assert!(expand_hosts("10.0.0.0/15").is_err());
+        assert!(expand_hosts("10.0.0.0/33").is_err());
+        assert!(expand_hosts("fe80::/64").is_err());
+        assert!(expand_hosts("").is_err());
+        assert_eq!(expand_ports("22 8000-8002").unwrap(), vec![22, 8000, 8001, 8002]);
+        assert!(expand_ports("0").is_err());
+        assert!(expand_ports("90-80").is_err());
+        assert!(expand_ports("http").is_err());
+    }
+
+    #[test]
+    fn test_listener_inbound() {
+        let (listener, opened) = open_listener(&String::from("127.0.0.1"), 0).unwrap();
+        let port: u16 = opened.dest_port.parse().unwrap();
+        assert_eq!(opened.activity, "Listening Port Opened");
+        let client = thread::spawn(move || {
+            send_message(&String::from("127.0.0.1"), port, &Vec::from("hello world".as_bytes())).unwrap()
+        });
+        let inbound = accept_message(listener, Some(Duration::from_secs(5)), || false).unwrap();
+        let outbound = client.join().unwrap();
+        assert_eq!(inbound.activity, "Inbound Network Connection");
+        assert_eq!(inbound.bytes_sent, "11");
+        assert_eq!(inbound.source_port, outbound.source_port);
+        assert_eq!(inbound.dest_port, port.to_string());
+
+        let (listener, _) = open_listener(&String::from("127.0.0.1"), 0).unwrap();
+        assert!(accept_message(listener, Some(Duration::from_millis(50)), || false).is_err());
+    }
+
+    #[test]
+    fn test_file_sent() {
+        let temp = TempPath::new("exfil.txt");
+        let path = temp.as_string();
+        fs::write(&path, "secret data").unwrap();
+        for http in [false, true].iter() {
+            let server = spawn_server(&String::from("127.0.0.1"), 0).unwrap();
+            let port = server.local_addr().unwrap().port();
+            let child = thread::spawn(move || server_listen(server));
+            let log = send_file(&path, &String::from("127.0.0.1"), port, *http).unwrap();
+            let received = String::from_utf8(child.join().unwrap().unwrap()).unwrap();
+            assert_eq!(log.activity, "File Exfiltration");
+            assert_eq!(log.file_path, path);
+            assert_eq!(log.dest_port, port.to_string());
+            assert_eq!(log.bytes_sent, "11");
+            assert!(received.ends_with("secret data"));
+            assert_eq!(received.starts_with("POST /upload HTTP/1.1\r\n"), *http);
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(send_file(&path, &String::from("127.0.0.1"), 80, false).is_err());
+    }
+
+    #[test]
+    fn test_destinations_resolved() {
+        assert_eq!(host_port("::1", 8080), "[::1]:8080");
+        assert_eq!(host_port("10.0.0.5", 443), "10.0.0.5:443");
+        assert_eq!(host_port("c2.lab.local", 443), "c2.lab.local:443");
+        assert_eq!(resolve("[::1]", 80).unwrap(), vec!["[::1]:80".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(resolve("::1", 80).unwrap(), resolve("[::1]", 80).unwrap());
+        assert!(resolve("not-a-real-host.invalid", 80).is_err());
+        assert_eq!(address_family("127.0.0.1"), "IPv4");
+        assert_eq!(address_family("fe80::1"), "IPv6");
+        assert_eq!(address_family(""), "");
+
+        let server = spawn_server(&String::from("127.0.0.1"), 0).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let child = thread::spawn(move || server_listen(server));
+        let log = send_message(&String::from("localhost"), port, &Vec::from("hello".as_bytes()));
+        child.join().unwrap().unwrap();
+        let log = log.unwrap();
+        assert_eq!((log.dest_addr.as_str(), log.address_family.as_str()), ("127.0.0.1", "IPv4"));
+
+        // IPv6 may be disabled on the host
+        if let Ok(server) = spawn_server(&String::from("::1"), 0) {
+            let port = server.local_addr().unwrap().port();
+            let child = thread::spawn(move || server_listen(server));
+            let log = send_message(&String::from("[::1]"), port, &Vec::from("hello".as_bytes())).unwrap();
+            assert_eq!(child.join().unwrap().unwrap(), b"hello");
+            assert_eq!((log.dest_addr.as_str(), log.source_addr.as_str(), log.address_family.as_str()), ("::1", "::1", "IPv6"));
+        }
+    }
+
 }
@@ -0,0 +1,555 @@
+use crate::modules::common::GenerationError;
+use crate::modules::signature::{SigningKey, TrustedKey, SIGNATURE_EXTENSION};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ureq::AgentBuilder;
+
+/// Default directory scenario packs are installed to
+pub const DEFAULT_PACK_DIRECTORY: &str = "packs";
+/// Environment variable the trusted public key is read from when `--public-key` is not given
+pub const KEY_VARIABLE: &str = "EDR_PACK_PUBLIC_KEY";
+/// Name of the file describing an installed pack, written next to its scenario files
+const MANIFEST_NAME: &str = "pack.json";
+/// Largest bundle downloaded, in bytes
+const MAX_BUNDLE_SIZE: u64 = 16 << 20;
+/// Time a bundle download may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Structure defining a scenario file distributed in a pack
+///
+/// # Parameters
+///
+/// - `name`: file name the scenario is installed as, used by `include` rows of other scenarios
+/// - `content`: contents of the scenario file
+/// - `minisig`: optional minisign signature of the contents, installed as `<name>.minisig` so the
+///   scenario can be run with `--require-signed`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackScenario {
+    pub name: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minisig: Option<String>,
+}
+
+/// Structure defining a signed bundle of scenario files, distributed as a single JSON document.
+/// The signature is a minisign signature of the bundle without its signature, made with the secret
+/// key of the team publishing the pack, so hosts installing it only hold the public key.
+///
+/// # Parameters
+///
+/// - `name`: name the pack is installed and run as
+/// - `version`: version of the pack, reported by `pack list` and `pack update`
+/// - `description`: optional description of the pack
+/// - `scenarios`: scenario files of the pack, the first is run when no scenario is named
+/// - `signature`: minisign signature of the bundle, as written to a `.minisig` file, empty until
+///   signed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub name: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub scenarios: Vec<PackScenario>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Bundle {
+    /// Reads a bundle from a file or an http or https URL
+    ///
+    /// # Parameters
+    ///
+    /// - `source`: path of the bundle file, or its URL as `http[s]://host[:port]/path`
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The bundle, which has not been verified yet
+    /// - `Err`: The bundle could not be retrieved or is not a valid bundle
+    pub fn load(source: &str) -> Result<Bundle, GenerationError> {
+        let contents = if is_url(source) {
+            fetch(source)?
+        } else {
+            fs::read_to_string(source)?
+        };
+        match serde_json::from_str(&contents) {
+            Ok(inner) => Ok(inner),
+            Err(e) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid scenario pack: {}", source, e)))
+        }
+    }
+
+    /// Signs the bundle, replacing any previous signature
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: minisign secret key of the publisher
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The bundle was signed
+    /// - `Err`: The bundle could not be serialized or signed
+    pub fn sign(&mut self, key: &SigningKey) -> Result<(), GenerationError> {
+        self.signature = key.sign(&self.signed_content()?, &format!("scenario pack {} {}", self.name, self.version))?;
+        Ok(())
+    }
+
+    /// Checks the signature of the bundle, and that its names are safe to use as file names
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: public key the bundle must be signed with
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The bundle is signed with the key and can be installed
+    /// - `Err`: The bundle is unsigned, was signed with another key, was modified, or is malformed
+    pub fn verify(&self, key: &TrustedKey) -> Result<(), GenerationError> {
+        if self.signature.is_empty() {
+            return Err(GenerationError::new("signature".to_string(), format!("Scenario pack {} is not signed", self.name)));
+        }
+        key.verify_signature(&format!("Scenario pack {}", self.name), &self.signed_content()?, &self.signature)?;
+        check_name(&self.name)?;
+        if self.scenarios.is_empty() {
+            return Err(GenerationError::new("input_format".to_string(), format!("Scenario pack {} contains no scenarios", self.name)));
+        }
+        for scenario in &self.scenarios {
+            check_name(&scenario.name)?;
+            if scenario.name == MANIFEST_NAME {
+                return Err(GenerationError::new("input_format".to_string(), format!("Scenario pack {} can not contain a scenario named {}", self.name, MANIFEST_NAME)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the bundle to a file as a JSON document
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the file to write
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The file was written
+    /// - `Err`: The bundle could not be serialized or the file could not be written
+    pub fn write(&self, path: &str) -> Result<(), GenerationError> {
+        match serde_json::to_string_pretty(self) {
+            Ok(inner) => Ok(fs::write(path, inner + "\n")?),
+            Err(_) => Err(GenerationError::new("input_format".to_string(), format!("Unable to Serialize Scenario Pack {}", self.name)))
+        }
+    }
+
+    /// Serializes the bundle without its signature, as covered by the signature
+    fn signed_content(&self) -> Result<Vec<u8>, GenerationError> {
+        let mut unsigned = self.clone();
+        unsigned.signature.clear();
+        match serde_json::to_vec(&unsigned) {
+            Ok(inner) => Ok(inner),
+            Err(_) => Err(GenerationError::new("input_format".to_string(), format!("Unable to Serialize Scenario Pack {}", self.name)))
+        }
+    }
+}
+
+/// Structure defining a pack installed in a pack directory
+///
+/// # Parameters
+///
+/// - `name`: name of the pack
+/// - `version`: installed version of the pack
+/// - `description`: optional description of the pack
+/// - `source`: file path or URL the pack was installed from, and is updated from
+/// - `scenarios`: file names of the installed scenarios, in bundle order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledPack {
+    pub name: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub source: String,
+    pub scenarios: Vec<String>,
+}
+
+/// Structure defining a local directory of installed scenario packs. Each pack is installed to a
+/// subdirectory named after it, containing its scenario files and a `pack.json` manifest.
+///
+/// # Parameters
+///
+/// - `directory`: directory packs are installed to
+pub struct PackStore {
+    directory: PathBuf,
+}
+
+impl PackStore {
+    /// Instantiates a PackStore, the directory is created when the first pack is installed
+    ///
+    /// # Parameters
+    ///
+    /// - `directory`: directory packs are installed to
+    ///
+    /// # Returns
+    ///
+    /// PackStore instance
+    pub fn new(directory: &str) -> PackStore {
+        PackStore { directory: PathBuf::from(directory) }
+    }
+
+    /// Verifies a bundle and installs it, replacing any installed version of the pack
+    ///
+    /// # Parameters
+    ///
+    /// - `source`: path or http or https URL of the bundle
+    /// - `key`: public key the bundle must be signed with
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The installed pack
+    /// - `Err`: The bundle could not be retrieved, failed verification, or could not be written
+    pub fn install(&self, source: &str, key: &TrustedKey) -> Result<InstalledPack, GenerationError> {
+        let bundle = Bundle::load(source)?;
+        bundle.verify(key)?;
+        self.write_bundle(&bundle, source)
+    }
+
+    /// Writes a verified bundle to the pack directory, replacing any installed version of the pack
+    ///
+    /// # Parameters
+    ///
+    /// - `bundle`: the verified bundle
+    /// - `source`: path or URL the bundle was read from
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The installed pack
+    /// - `Err`: The bundle could not be written
+    fn write_bundle(&self, bundle: &Bundle, source: &str) -> Result<InstalledPack, GenerationError> {
+        // record an absolute path so the pack can be updated from any working directory
+        let source = match fs::canonicalize(source) {
+            Ok(inner) if !is_url(source) => inner.to_string_lossy().to_string(),
+            _ => source.to_string()
+        };
+        let installed = InstalledPack {
+            name: bundle.name.clone(),
+            version: bundle.version.clone(),
+            description: bundle.description.clone(),
+            source,
+            scenarios: bundle.scenarios.iter().map(|scenario| scenario.name.clone()).collect(),
+        };
+        // the new version is written next to the old one, so a failed write leaves the old version
+        let staging = self.directory.join(format!(".{}.staging", bundle.name));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+        for scenario in &bundle.scenarios {
+            fs::write(staging.join(&scenario.name), &scenario.content)?;
+            if let Some(minisig) = &scenario.minisig {
+                fs::write(staging.join(format!("{}.{}", scenario.name, SIGNATURE_EXTENSION)), minisig)?;
+            }
+        }
+        match serde_json::to_string_pretty(&installed) {
+            Ok(inner) => fs::write(staging.join(MANIFEST_NAME), inner + "\n")?,
+            Err(_) => return Err(GenerationError::new("input_format".to_string(), format!("Unable to Serialize Scenario Pack {}", bundle.name)))
+        }
+        let target = self.directory.join(&bundle.name);
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(&staging, &target)?;
+        Ok(installed)
+    }
+
+    /// Lists the installed packs
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The installed packs, sorted by name. Empty when the directory does not exist
+    /// - `Err`: The directory or a manifest could not be read
+    pub fn list(&self) -> Result<Vec<InstalledPack>, GenerationError> {
+        if !self.directory.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with('.') && entry.path().join(MANIFEST_NAME).is_file() {
+                packs.push(self.get(&name)?);
+            }
+        }
+        packs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packs)
+    }
+
+    /// Reinstalls a pack from the source it was installed from. The bundle is checked before
+    /// anything is written: it must still contain the same pack, in the installed version or a
+    /// newer one.
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the installed pack
+    /// - `key`: public key the bundle must be signed with
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The version installed before the update, and the pack now installed
+    /// - `Err`: The pack is not installed, the source now contains another pack or an older
+    ///   version, or the bundle could not be installed
+    pub fn update(&self, name: &str, key: &TrustedKey) -> Result<(String, InstalledPack), GenerationError> {
+        let installed = self.get(name)?;
+        let bundle = Bundle::load(&installed.source)?;
+        bundle.verify(key)?;
+        if bundle.name != installed.name {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} now contains scenario pack {} instead of {}", installed.source, bundle.name, installed.name)));
+        }
+        if compare_versions(&bundle.version, &installed.version) == Ordering::Less {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} contains version {} of scenario pack {}, older than the installed version {}", installed.source, bundle.version, installed.name, installed.version)));
+        }
+        let updated = self.write_bundle(&bundle, &installed.source)?;
+        Ok((installed.version, updated))
+    }
+
+    /// Retrieves the path of a scenario of an installed pack
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the installed pack
+    /// - `scenario`: file name of the scenario, the first scenario of the pack when not given
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Path of the scenario file
+    /// - `Err`: The pack is not installed or does not contain the scenario
+    pub fn scenario_path(&self, name: &str, scenario: Option<&str>) -> Result<String, GenerationError> {
+        let installed = self.get(name)?;
+        let scenario = match scenario {
+            Some(scenario) if installed.scenarios.iter().any(|installed| installed == scenario) => scenario.to_string(),
+            Some(scenario) => return Err(GenerationError::new("input_format".to_string(), format!("Scenario pack {} does not contain {} (scenarios: {})", name, scenario, installed.scenarios.join(", ")))),
+            None => installed.scenarios[0].clone()
+        };
+        Ok(self.directory.join(name).join(scenario).to_string_lossy().to_string())
+    }
+
+    /// Reads the manifest of an installed pack
+    fn get(&self, name: &str) -> Result<InstalledPack, GenerationError> {
+        check_name(name)?;
+        let path = self.directory.join(name).join(MANIFEST_NAME);
+        let contents = match fs::read_to_string(&path) {
+            Ok(inner) => inner,
+            Err(_) => return Err(GenerationError::new("io".to_string(), format!("Scenario pack {} is not installed in {}", name, self.directory.display())))
+        };
+        match serde_json::from_str::<InstalledPack>(&contents) {
+            Ok(inner) if !inner.scenarios.is_empty() => Ok(inner),
+            _ => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid pack manifest", path.display())))
+        }
+    }
+}
+
+/// Checks that a pack or scenario name can be used as a single file name, so a bundle can not write
+/// outside of its pack directory
+fn check_name(name: &str) -> Result<(), GenerationError> {
+    let valid = !name.is_empty() && !name.starts_with('.') && !name.contains(&['/', '\\', ':'][..])
+        && Path::new(name).file_name().map(|file_name| file_name == name).unwrap_or(false);
+    if valid {
+        return Ok(());
+    }
+    Err(GenerationError::new("input_format".to_string(), format!("{:?} is not a valid pack or scenario name", name)))
+}
+
+/// Retrieves a bundle over http or https, the certificate of an https server is verified against
+/// the Mozilla root certificates
+///
+/// # Parameters
+///
+/// - `url`: URL of the bundle
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Contents of the bundle
+/// - `Err`: The server could not be reached, did not return the bundle, or returned more than
+///   `MAX_BUNDLE_SIZE` bytes or text which is not UTF-8
+fn fetch(url: &str) -> Result<String, GenerationError> {
+    let agent = AgentBuilder::new().timeout(DOWNLOAD_TIMEOUT).build();
+    let response = match agent.get(url).call() {
+        Ok(inner) => inner,
+        Err(ureq::Error::Status(status, _)) => return Err(GenerationError::new("network".to_string(), format!("Unable to download {} (status {})", url, status))),
+        Err(e) => return Err(GenerationError::new("network".to_string(), format!("Unable to download {}: {}", url, e)))
+    };
+    let mut contents = Vec::new();
+    response.into_reader().take(MAX_BUNDLE_SIZE + 1).read_to_end(&mut contents)?;
+    if contents.len() as u64 > MAX_BUNDLE_SIZE {
+        return Err(GenerationError::new("input_format".to_string(), format!("{} is larger than {} bytes, which is not a scenario pack", url, MAX_BUNDLE_SIZE)));
+    }
+    match String::from_utf8(contents) {
+        Ok(inner) => Ok(inner),
+        Err(_) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid scenario pack: it is not UTF-8 text", url)))
+    }
+}
+
+/// Determines if a bundle source is a URL rather than a file path
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Compares two versions of a pack component by component, numerically when both components are
+/// numbers (`1.10` is newer than `1.9`) and as text otherwise
+///
+/// # Returns
+///
+/// The ordering of `version` relative to `other`
+fn compare_versions(version: &str, other: &str) -> Ordering {
+    let mut components = version.split('.');
+    let mut others = other.split('.');
+    loop {
+        let ordering = match (components.next(), others.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::{read_request, TempPath};
+    use minisign::KeyPair;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn sample_bundle(version: &str) -> Bundle {
+        Bundle {
+            name: "basics".to_string(),
+            version: version.to_string(),
+            description: Some("Basic file activity".to_string()),
+            scenarios: vec![
+                PackScenario { name: "main.csv".to_string(), content: "include,files.csv\nconnect_self,hello\n".to_string(), minisig: None },
+                PackScenario { name: "files.csv".to_string(), content: "new_file,${TMP}/edr_pack.txt\ndelete_file,${TMP}/edr_pack.txt\n".to_string(), minisig: None },
+            ],
+            signature: "".to_string(),
+        }
+    }
+
+    fn keys(name: &str) -> (SigningKey, TrustedKey) {
+        let path = TempPath::new(name);
+        let pair = KeyPair::generate_unencrypted_keypair().unwrap();
+        fs::write(&path, pair.sk.to_box(None).unwrap().into_string()).unwrap();
+        let signing = SigningKey::load(&path.as_string()).unwrap();
+        (signing, TrustedKey::load(&pair.pk.to_base64()).unwrap())
+    }
+
+    #[test]
+    fn bundle_signatures() {
+        let (signing, trusted) = keys("signatures.key");
+        let (_, other) = keys("other.key");
+        let mut bundle = sample_bundle("1.0");
+        assert!(bundle.verify(&trusted).is_err());
+        bundle.sign(&signing).unwrap();
+        assert!(bundle.signature.starts_with("untrusted comment: "));
+        assert!(bundle.verify(&trusted).is_ok());
+        assert!(bundle.verify(&other).is_err());
+        bundle.scenarios[1].content.push_str("process,sh\n");
+        assert!(bundle.verify(&trusted).is_err());
+
+        let mut bundle = sample_bundle("1.0");
+        bundle.scenarios[0].name = "../main.csv".to_string();
+        bundle.sign(&signing).unwrap();
+        assert!(bundle.verify(&trusted).is_err());
+    }
+
+    #[test]
+    fn packs_installed() {
+        let directory = TempPath::new("store");
+        let source = TempPath::new("bundle.json");
+        let (signing, trusted) = keys("installed.key");
+        let (_, other) = keys("installed_other.key");
+        let store = PackStore::new(&directory.as_string());
+        assert!(store.list().unwrap().is_empty());
+        let mut bundle = sample_bundle("1.0");
+        bundle.sign(&signing).unwrap();
+        bundle.write(&source.as_string()).unwrap();
+        assert!(store.install(&source.as_string(), &other).is_err());
+        let installed = store.install(&source.as_string(), &trusted).unwrap();
+        assert_eq!(installed.scenarios, vec!["main.csv", "files.csv"]);
+
+        let mut bundle = sample_bundle("1.1");
+        bundle.sign(&signing).unwrap();
+        bundle.write(&source.as_string()).unwrap();
+        let (previous, updated) = store.update("basics", &trusted).unwrap();
+        assert_eq!((previous.as_str(), updated.version.as_str()), ("1.0", "1.1"));
+        assert_eq!(store.list().unwrap(), vec![updated.clone()]);
+
+        let mut bundle = sample_bundle("1.0");
+        bundle.sign(&signing).unwrap();
+        bundle.write(&source.as_string()).unwrap();
+        assert!(store.update("basics", &trusted).is_err());
+        let mut bundle = sample_bundle("1.2");
+        bundle.name = "advanced".to_string();
+        bundle.sign(&signing).unwrap();
+        bundle.write(&source.as_string()).unwrap();
+        assert!(store.update("basics", &trusted).is_err());
+        assert_eq!(store.list().unwrap(), vec![updated]);
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0", "1.0.1"), Ordering::Less);
+
+        let main = store.scenario_path("basics", None).unwrap();
+        assert!(fs::read_to_string(&main).unwrap().starts_with("include,files.csv"));
+        assert!(store.scenario_path("basics", Some("files.csv")).unwrap().ends_with("files.csv"));
+        assert!(store.scenario_path("basics", Some("missing.csv")).is_err());
+        assert!(store.scenario_path("other", None).is_err());
+    }
+
+    #[test]
+    fn packs_downloaded() {
+        let (signing, _) = keys("downloaded.key");
+        let mut bundle = sample_bundle("1.0");
+        bundle.sign(&signing).unwrap();
+        let body = serde_json::to_string(&bundle).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [format!("200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body), "404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()].iter() {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&stream));
+                stream.write_all(format!("HTTP/1.1 {}", response).as_bytes()).unwrap();
+            }
+            requests
+        });
+        let downloaded = Bundle::load(&format!("http://{}/packs/basics.json", address)).unwrap();
+        assert_eq!(downloaded, bundle);
+        assert!(Bundle::load(&format!("http://{}/packs/missing.json", address)).is_err());
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /packs/basics.json HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with("GET /packs/missing.json HTTP/1.1\r\n"));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::modules::common::GenerationError;
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+/// Largest payload written into a `connect` instruction, larger flows are replayed with `exfil` and
+/// a payload file
+pub const MAX_INLINE_PAYLOAD: u64 = 4096;
+
+/// Byte the replayed payloads are filled with, the contents of the original traffic are not kept
+const FILLER: u8 = b'A';
+
+/// Magic number of a pcapng file, which is not supported
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+
+/// Transport protocol of a flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Structure defining a TCP connection or UDP conversation read from a packet capture
+///
+/// # Parameters
+///
+/// - `protocol`: transport protocol of the flow
+/// - `source`: address and port of the endpoint which sent the first packet (for TCP, the SYN)
+/// - `destination`: address and port of the other endpoint
+/// - `start`: time of the first packet, relative to the first packet of the capture
+/// - `duration`: time between the first and last packet of the flow
+/// - `packets`: number of packets in both directions
+/// - `bytes_sent`: payload bytes sent by the source
+/// - `bytes_received`: payload bytes sent by the destination
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flow {
+    pub protocol: Protocol,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub start: Duration,
+    pub duration: Duration,
+    pub packets: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Transport header of a packet, see `parse_packet`
+struct Packet {
+    protocol: Protocol,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: u64,
+    // a SYN-ACK was sent by the destination of the connection
+    syn_ack: bool,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "TCP"),
+            Protocol::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
+/// Reads the flows of a packet capture file, see `read_flows`
+///
+/// # Parameters
+///
+/// - `path`: path of the capture file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The flows, in the order their first packet was captured
+/// - `Err`: The file could not be read or is not a valid capture file
+pub fn load(path: &str) -> Result<Vec<Flow>, GenerationError> {
+    let file = fs::File::open(path)?;
+    read_flows(file).map_err(|e| GenerationError::new(e.kind, format!("{} is not a valid capture file: {}", path, e.message)))
+}
+
+/// Reads the TCP and UDP flows of a packet capture in the classic pcap format (as written by
+/// `tcpdump -w`), with microsecond or nanosecond timestamps in either byte order. Ethernet
+/// (including VLAN tags), raw IP, BSD loopback, and Linux cooked captures are supported. Payload
+/// sizes are taken from the IP headers, so captures truncated with a small snap length still
+/// report the full size of the traffic. Packets of other protocols and IP fragments after the
+/// first are ignored.
+///
+/// # Parameters
+///
+/// - `reader`: contents of the capture file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The flows, in the order their first packet was captured
+/// - `Err`: The capture is not in the pcap format, uses an unsupported link type, or is truncated
+pub fn read_flows<R: Read>(mut reader: R) -> Result<Vec<Flow>, GenerationError> {
+    let mut header = [0u8; 24];
+    reader.read_exact(&mut header).map_err(|_| format_error("the file is too short for a pcap header"))?;
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let (big_endian, nanoseconds) = match magic {
+        0xa1b2_c3d4 => (false, false),
+        0xd4c3_b2a1 => (true, false),
+        0xa1b2_3c4d => (false, true),
+        0x4d3c_b2a1 => (true, true),
+        PCAPNG_MAGIC => return Err(format_error("pcapng files are not supported, convert the file with `editcap -F pcap`")),
+        _ => return Err(format_error("the file does not start with a pcap magic number"))
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    };
+    let link_type = read_u32(&header[20..24]);
+    if !matches!(link_type, 0 | 1 | 12 | 101 | 113 | 228 | 229 | 276) {
+        return Err(format_error(&format!("link type {} is not supported", link_type)));
+    }
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut index: HashMap<(Protocol, SocketAddr, SocketAddr), usize> = HashMap::new();
+    let mut first: Option<Duration> = None;
+    let mut record = [0u8; 16];
+    let mut data = Vec::new();
+    loop {
+        match reader.read(&mut record[..1]) {
+            Ok(0) => break,
+            Ok(_) => reader.read_exact(&mut record[1..]).map_err(|_| format_error("the last packet header is truncated"))?,
+            Err(e) => return Err(e.into())
+        }
+        let fraction = read_u32(&record[4..8]);
+        let timestamp = Duration::from_secs(read_u32(&record[0..4]) as u64) + if nanoseconds { Duration::from_nanos(fraction as u64) } else { Duration::from_micros(fraction as u64) };
+        data.resize(read_u32(&record[8..12]) as usize, 0);
+        reader.read_exact(&mut data).map_err(|_| format_error("the last packet is truncated"))?;
+        let packet = match parse_packet(link_type, &data) {
+            Some(inner) => inner,
+            None => continue
+        };
+        let offset = timestamp.saturating_sub(*first.get_or_insert(timestamp));
+        let key = if packet.source <= packet.destination { (packet.protocol, packet.source, packet.destination) } else { (packet.protocol, packet.destination, packet.source) };
+        let position = *index.entry(key).or_insert_with(|| {
+            let (source, destination) = if packet.syn_ack { (packet.destination, packet.source) } else { (packet.source, packet.destination) };
+            flows.push(Flow { protocol: packet.protocol, source, destination, start: offset, duration: Duration::default(), packets: 0, bytes_sent: 0, bytes_received: 0 });
+            flows.len() - 1
+        });
+        let flow = &mut flows[position];
+        flow.packets += 1;
+        flow.duration = offset.saturating_sub(flow.start);
+        if packet.source == flow.source {
+            flow.bytes_sent += packet.payload;
+        } else {
+            flow.bytes_received += packet.payload;
+        }
+    }
+    Ok(flows)
+}
+
+/// Converts flows into instructions generating traffic of the same shape from this endpoint.
+/// Flows are replayed one after another in the order they started, with a `pause` for the idle
+/// time between the end of a flow and the start of the next. Each flow sends as many bytes as its
+/// source sent, paced with `bandwidth=` to take as long as the original flow: a `connect` for
+/// payloads up to `MAX_INLINE_PAYLOAD` bytes, and an `exfil` of a payload file written to
+/// `payload_directory` otherwise. UDP flows are replayed over TCP, as the generator has no UDP
+/// instruction, and flows to port 0 are left out.
+///
+/// # Parameters
+///
+/// - `flows`: flows read from a capture, see `read_flows`
+/// - `payload_directory`: directory the payload files of large flows are written to, created when
+///   one is needed
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The instructions of the scenario, in execution order
+/// - `Err`: A payload file could not be written
+pub fn scenario(flows: &[Flow], payload_directory: &Path) -> Result<Vec<StringRecord>, GenerationError> {
+    let mut ordered: Vec<&Flow> = flows.iter().filter(|flow| flow.destination.port() != 0).collect();
+    ordered.sort_by_key(|flow| flow.start);
+    let mut records = Vec::new();
+    let mut finished = None;
+    for (number, flow) in ordered.into_iter().enumerate() {
+        let idle = flow.start.saturating_sub(finished.unwrap_or(flow.start));
+        if idle.as_millis() > 0 {
+            records.push(StringRecord::from(vec!["pause".to_string(), idle.as_millis().to_string()]));
+        }
+        finished = Some(finished.unwrap_or_default().max(flow.start + flow.duration));
+        let host = flow.destination.ip().to_string();
+        let port = flow.destination.port().to_string();
+        let mut record = if flow.bytes_sent <= MAX_INLINE_PAYLOAD {
+            let message = String::from_utf8(vec![FILLER; flow.bytes_sent as usize]).unwrap_or_default();
+            StringRecord::from(vec!["connect".to_string(), host, port, message])
+        } else {
+            fs::create_dir_all(payload_directory)?;
+            let path = payload_directory.join(format!("flow_{}.bin", number + 1));
+            fs::write(&path, vec![FILLER; flow.bytes_sent as usize])?;
+            StringRecord::from(vec!["exfil".to_string(), path.to_string_lossy().to_string(), host, port, "tcp".to_string()])
+        };
+        let milliseconds = flow.duration.as_millis() as u64;
+        if flow.bytes_sent > 0 && milliseconds > 0 {
+            record.push_field(&format!("bandwidth={}", (flow.bytes_sent.saturating_mul(1000) / milliseconds).max(1)));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Parses the network and transport headers of a captured packet
+///
+/// # Returns
+///
+/// The transport header, None for packets which are not TCP or UDP over IP, are fragments after
+/// the first, or are truncated before the end of the transport header
+fn parse_packet(link_type: u32, data: &[u8]) -> Option<Packet> {
+    let ip = match link_type {
+        // BSD loopback, a 4 byte address family in the byte order of the capturing host
+        0 => data.get(4..)?,
+        1 => {
+            let mut offset = 12;
+            // 802.1Q and 802.1ad VLAN tags
+            while matches!(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]), 0x8100 | 0x88a8) {
+                offset += 4;
+            }
+            ethertype(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))?;
+            data.get(offset + 2..)?
+        }
+        113 => {
+            ethertype(u16::from_be_bytes([*data.get(14)?, *data.get(15)?]))?;
+            data.get(16..)?
+        }
+        276 => {
+            ethertype(u16::from_be_bytes([*data.first()?, *data.get(1)?]))?;
+            data.get(20..)?
+        }
+        _ => data
+    };
+    let (protocol, source, destination, segment, length) = match ip.first()? >> 4 {
+        4 => {
+            let header = ((ip[0] & 0x0f) as usize) * 4;
+            let total = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x1fff;
+            if fragment != 0 || total < header {
+                return None;
+            }
+            let source = IpAddr::V4(Ipv4Addr::new(*ip.get(12)?, ip[13], ip[14], ip[15]));
+            let destination = IpAddr::V4(Ipv4Addr::new(*ip.get(16)?, ip[17], ip[18], ip[19]));
+            (*ip.get(9)?, source, destination, ip.get(header..)?, total - header)
+        }
+        6 => {
+            let address = |start: usize| -> Option<IpAddr> {
+                let bytes: [u8; 16] = ip.get(start..start + 16)?.try_into().ok()?;
+                Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+            };
+            let mut length = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            let mut next = *ip.get(6)?;
+            let mut offset = 40;
+            // hop-by-hop, routing, fragment, and destination options extension headers
+            while matches!(next, 0 | 43 | 44 | 60) {
+                let size = if next == 44 { 8 } else { (*ip.get(offset + 1)? as usize + 1) * 8 };
+                if next == 44 && u16::from_be_bytes([*ip.get(offset + 2)?, *ip.get(offset + 3)?]) >> 3 != 0 {
+                    return None;
+                }
+                next = *ip.get(offset)?;
+                offset += size;
+                length = length.checked_sub(size)?;
+            }
+            (next, address(8)?, address(24)?, ip.get(offset..)?, length)
+        }
+        _ => return None
+    };
+    let ports = (u16::from_be_bytes([*segment.first()?, *segment.get(1)?]), u16::from_be_bytes([*segment.get(2)?, *segment.get(3)?]));
+    let (protocol, header, syn_ack) = match protocol {
+        6 => (Protocol::Tcp, ((*segment.get(12)? >> 4) as usize) * 4, *segment.get(13)? & 0x12 == 0x12),
+        17 => (Protocol::Udp, 8, false),
+        _ => return None
+    };
+    Some(Packet {
+        protocol,
+        source: SocketAddr::new(source, ports.0),
+        destination: SocketAddr::new(destination, ports.1),
+        payload: length.saturating_sub(header) as u64,
+        syn_ack,
+    })
+}
+
+/// Accepts the ethertypes of IPv4 and IPv6
+fn ethertype(value: u16) -> Option<()> {
+    matches!(value, 0x0800 | 0x86dd).then_some(())
+}
+
+/// Builds the error of a file which is not a valid capture
+fn format_error(problem: &str) -> GenerationError {
+    GenerationError::new("input_format".to_string(), problem.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    /// Builds an Ethernet frame carrying an IPv4 TCP segment or UDP datagram
+    fn frame(source: [u8; 4], destination: [u8; 4], ports: (u16, u16), tcp_flags: Option<u8>, payload: usize) -> Vec<u8> {
+        let transport = if tcp_flags.is_some() { 20 } else { 8 };
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00, 0x45, 0]);
+        frame.extend_from_slice(&((20 + transport + payload) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, if tcp_flags.is_some() { 6 } else { 17 }, 0, 0]);
+        frame.extend_from_slice(&source);
+        frame.extend_from_slice(&destination);
+        frame.extend_from_slice(&ports.0.to_be_bytes());
+        frame.extend_from_slice(&ports.1.to_be_bytes());
+        match tcp_flags {
+            Some(flags) => frame.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0x50, flags, 0, 0, 0, 0, 0, 0]),
+            None => frame.extend_from_slice(&[0, (8 + payload) as u8, 0, 0])
+        }
+        frame.extend(vec![0u8; payload]);
+        frame
+    }
+
+    /// Builds a little endian microsecond pcap file of Ethernet frames
+    fn capture(packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0];
+        for (seconds, microseconds, frame) in packets {
+            file.extend_from_slice(&seconds.to_le_bytes());
+            file.extend_from_slice(&microseconds.to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(frame);
+        }
+        file
+    }
+
+    #[test]
+    fn flows_read() {
+        let (client, server) = ([10, 0, 0, 2], [10, 0, 0, 5]);
+        let file = capture(&[
+            // the SYN was not captured, the SYN-ACK identifies the client
+            (100, 0, frame(server, client, (443, 50000), Some(0x12), 0)),
+            (100, 1000, frame(client, server, (50000, 443), Some(0x18), 200)),
+            (100, 500_000, frame(server, client, (443, 50000), Some(0x18), 1000)),
+            (102, 0, frame(client, [8, 8, 8, 8], (50001, 53), None, 40)),
+        ]);
+        let flows = read_flows(&file[..]).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert_eq!((flows[0].protocol, flows[0].source, flows[0].destination), (Protocol::Tcp, "10.0.0.2:50000".parse().unwrap(), "10.0.0.5:443".parse().unwrap()));
+        assert_eq!((flows[0].packets, flows[0].bytes_sent, flows[0].bytes_received), (3, 200, 1000));
+        assert_eq!(flows[0].duration, Duration::from_millis(500));
+        assert_eq!((flows[1].protocol, flows[1].start, flows[1].bytes_sent), (Protocol::Udp, Duration::from_secs(2), 40));
+        assert!(read_flows(&[0x0a, 0x0d, 0x0d, 0x0a][..]).is_err());
+        assert!(read_flows(&file[..file.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn flows_replayed() {
+        let directory = TempPath::new("pcap");
+        let flow = |start: u64, duration: u64, bytes_sent: u64| Flow {
+            protocol: Protocol::Tcp,
+            source: "10.0.0.2:50000".parse().unwrap(),
+            destination: "10.0.0.5:443".parse().unwrap(),
+            start: Duration::from_millis(start),
+            duration: Duration::from_millis(duration),
+            packets: 4,
+            bytes_sent,
+            bytes_received: 0,
+        };
+        let records = scenario(&[flow(3000, 1000, 8000), flow(0, 500, 10), flow(600, 0, 0)], &directory).unwrap();
+        let fields: Vec<Vec<&str>> = records.iter().map(|record| record.iter().collect()).collect();
+        assert_eq!(fields[0], vec!["connect", "10.0.0.5", "443", "AAAAAAAAAA", "bandwidth=20"]);
+        assert_eq!(fields[1], vec!["pause", "100"]);
+        assert_eq!(fields[2], vec!["connect", "10.0.0.5", "443", ""]);
+        assert_eq!(fields[3], vec!["pause", "2400"]);
+        assert_eq!(fields[4][0], "exfil");
+        assert_eq!(fields[4][2..], ["10.0.0.5", "443", "tcp", "bandwidth=8000"]);
+        assert_eq!(fs::metadata(fields[4][1]).unwrap().len(), 8000);
+    }
+}
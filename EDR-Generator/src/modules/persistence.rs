@@ -0,0 +1,488 @@
+use crate::modules::common::{GenerationError, get_time};
+use crate::modules::logger::Log;
+use crate::modules::account;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Registry key (beneath `HKCU`) the values of `persist_runkey` instructions are written to
+pub const RUN_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+
+/// Comment appended to crontab entries so entries created by the generator can be recognized
+const CRON_MARKER: &str = "# edr_generator";
+
+/// Triggers of `schedule_task` instructions, along with the equivalent `schtasks /SC` value and
+/// crontab schedule
+pub const TRIGGERS: [(&str, &str, &str); 5] = [
+    ("onstart", "ONSTART", "@reboot"),
+    ("minute", "MINUTE", "* * * * *"),
+    ("hourly", "HOURLY", "@hourly"),
+    ("daily", "DAILY", "@daily"),
+    ("weekly", "WEEKLY", "@weekly"),
+];
+
+/// Persistence artifact created by an instruction, kept so it can be removed at the end of the
+/// run, see `--cleanup`
+///
+/// # Variants
+///
+/// - `RunKey`: value named `name` under the `Run` registry key
+/// - `File`: startup folder file or service unit file, and the activity logged once it is removed
+/// - `CronEntry`: line appended to the user's crontab
+/// - `Service`: Windows service registered with the service control manager
+/// - `ScheduledTask`: Windows scheduled task registered with `schtasks`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Artifact {
+    RunKey { name: String },
+    File { path: PathBuf, removed: String },
+    CronEntry { line: String },
+    Service { name: String },
+    ScheduledTask { name: String },
+    UserAccount { name: String },
+}
+
+impl Artifact {
+    /// Removes the artifact, undoing the instruction which created it
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data confirming the artifact was removed.
+    /// - `Err`: There was an issue removing the artifact. (Already removed or no permissions)
+    pub fn remove(&self) -> Result<Log, GenerationError> {
+        match self {
+            Artifact::RunKey { name } => remove_run_key(name),
+            Artifact::File { path, removed } => {
+                fs::remove_file(path)?;
+                Ok(adapt_log_persistence(removed.clone(), path.to_string_lossy().to_string(), "".to_string()))
+            }
+            Artifact::CronEntry { line } => {
+                let crontab = read_crontab()?;
+                match remove_line(&crontab, line) {
+                    Some(remaining) => write_crontab(&remaining)?,
+                    None => return Err(GenerationError::new("io".to_string(), "The crontab entry no longer exists".to_string()))
+                }
+                Ok(adapt_log_persistence("Cron Entry Removed".to_string(), "crontab".to_string(), line.clone()))
+            }
+            Artifact::Service { name } => {
+                run_tool("sc.exe", &["delete", name])?;
+                Ok(adapt_log_persistence("Service Removed".to_string(), service_key(name), "".to_string()))
+            }
+            Artifact::ScheduledTask { name } => {
+                run_tool("schtasks.exe", &["/Delete", "/TN", name, "/F"])?;
+                Ok(adapt_log_persistence("Scheduled Task Removed".to_string(), format!("\\{}", name), "".to_string()))
+            }
+            Artifact::UserAccount { name } => account::delete_user(name)
+        }
+    }
+}
+
+/// Determines if the fields of a persistence instruction can be used safely. Names are used as
+/// file, registry value, and service names so they are limited to letters, digits, `-`, `_`, and
+/// `.`, and commands may not span several lines of the files they are written to.
+///
+/// # Parameters
+///
+/// - `instruction`: name of the instruction
+/// - `target`: name of the artifact, or the schedule of a `persist_cron` instruction
+/// - `command`: command the artifact starts
+///
+/// # Returns
+///
+/// True if the fields are valid
+pub fn valid_fields(instruction: &str, target: &str, command: &str) -> bool {
+    let target_valid = if instruction == "persist_cron" {
+        (target.starts_with('@') && !target.contains(char::is_whitespace)) || target.split_whitespace().count() == 5
+    } else {
+        !target.is_empty() && target.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    target_valid && !command.trim().is_empty() && !command.contains(&['\n', '\r'][..])
+}
+
+/// Determines if a persistence instruction is supported on this platform. Run keys and services
+/// registered with `sc` are Windows only, crontabs are not available on Windows.
+///
+/// # Parameters
+///
+/// - `instruction`: name of the instruction
+///
+/// # Returns
+///
+/// True if the instruction can be executed on this platform
+pub fn supported(instruction: &str) -> bool {
+    match instruction {
+        "persist_runkey" => cfg!(windows),
+        "persist_cron" => !cfg!(windows),
+        _ => true
+    }
+}
+
+/// Determines where a persistence instruction would create its artifact, without creating it, e.g.
+/// for the artifact preview of a dry run. The location is the `file_path` the instruction logs.
+///
+/// # Parameters
+///
+/// - `instruction`: name of the instruction
+/// - `target`: name of the artifact, or the schedule of a `persist_cron` instruction
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The registry key, file path, crontab, or task name of the artifact
+/// - `Err`: The location could not be determined (e.g. `HOME` is not set) or the instruction is not
+///   a persistence instruction
+pub fn location(instruction: &str, target: &str) -> Result<String, GenerationError> {
+    match instruction {
+        "persist_runkey" => Ok(format!("HKCU\\{}\\{}", RUN_KEY, target)),
+        "persist_startup" => Ok(startup_directory()?.join(startup_file_name(target)).to_string_lossy().to_string()),
+        "persist_cron" => Ok("crontab".to_string()),
+        "persist_service" if cfg!(windows) => Ok(service_key(target)),
+        "persist_service" => Ok(config_directory()?.join("systemd").join("user").join(service_unit_name(target)).to_string_lossy().to_string()),
+        "schedule_task" if cfg!(windows) => Ok(format!("\\{}", target)),
+        "schedule_task" => Ok(format!("crontab:{}", target)),
+        _ => Err(GenerationError::new("input_format".to_string(), format!("{} is not a persistence instruction", instruction)))
+    }
+}
+
+/// Adds a value to the current user's `Run` registry key, starting a command at every logon
+///
+/// # Parameters
+///
+/// - `name`: name of the registry value
+/// - `command`: command line stored in the value
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the value was written, and the artifact to remove it with.
+/// - `Err`: There was an issue writing the value. (No permissions or the platform is not Windows)
+#[cfg(windows)]
+pub fn run_key(name: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_CURRENT_USER;
+    let (key, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(RUN_KEY)?;
+    key.set_value(name, &command.to_string())?;
+    Ok((adapt_log_persistence("Registry Run Key".to_string(), format!("HKCU\\{}\\{}", RUN_KEY, name), command.to_string()),
+        Artifact::RunKey { name: name.to_string() }))
+}
+
+#[cfg(not(windows))]
+pub fn run_key(_: &str, _: &str) -> Result<(Log, Artifact), GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Run keys are only supported on Windows".to_string()))
+}
+
+#[cfg(windows)]
+fn remove_run_key(name: &str) -> Result<Log, GenerationError> {
+    use winreg::RegKey;
+    use winreg::enums::{HKEY_CURRENT_USER, KEY_SET_VALUE};
+    RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(RUN_KEY, KEY_SET_VALUE)?.delete_value(name)?;
+    Ok(adapt_log_persistence("Registry Run Key Removed".to_string(), format!("HKCU\\{}\\{}", RUN_KEY, name), "".to_string()))
+}
+
+#[cfg(not(windows))]
+fn remove_run_key(_: &str) -> Result<Log, GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Run keys are only supported on Windows".to_string()))
+}
+
+/// Creates a file in the current user's startup folder which starts a command at every logon: a
+/// batch file in the Windows Startup folder, or an XDG autostart entry elsewhere
+///
+/// # Parameters
+///
+/// - `name`: name of the file, without its extension
+/// - `command`: command the file starts
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the file was created, and the artifact to remove it with.
+/// - `Err`: There was an issue creating the file. (Already exists or no permissions)
+pub fn startup_file(name: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    startup_file_in(&startup_directory()?, name, command)
+}
+
+fn startup_file_in(directory: &Path, name: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    let contents = if cfg!(windows) {
+        format!("@echo off\r\n{}\r\n", command)
+    } else {
+        format!("[Desktop Entry]\nType=Application\nName={}\nExec={}\n", name, command)
+    };
+    create_artifact_file(("Startup Folder File", "Startup Folder File Removed"), &directory.join(startup_file_name(name)), &contents, command)
+}
+
+/// Name of a startup folder file: a batch file on Windows, an XDG autostart entry elsewhere
+fn startup_file_name(name: &str) -> String {
+    if cfg!(windows) { format!("{}.bat", name) } else { format!("{}.desktop", name) }
+}
+
+/// Appends an entry to the current user's crontab
+///
+/// # Parameters
+///
+/// - `schedule`: five field cron schedule, or a nickname such as `@reboot`
+/// - `command`: command the entry starts
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the entry was added, and the artifact to remove it with.
+/// - `Err`: There was an issue updating the crontab. (`crontab` is not available or no permissions)
+pub fn cron_entry(schedule: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    if !supported("persist_cron") {
+        return Err(GenerationError::new("unsupported_platform".to_string(), "Crontabs are not supported on Windows".to_string()));
+    }
+    let line = format!("{} {} {}", schedule, command, CRON_MARKER);
+    write_crontab(&append_line(&read_crontab()?, &line))?;
+    Ok((adapt_log_persistence("Cron Entry".to_string(), "crontab".to_string(), line.clone()), Artifact::CronEntry { line }))
+}
+
+/// Schedules a task which starts a command: with `schtasks` on Windows, or as an entry of the
+/// current user's crontab elsewhere. An existing task of the same name is not replaced.
+///
+/// # Parameters
+///
+/// - `name`: name of the task
+/// - `trigger`: when the task runs, one of the `TRIGGERS`
+/// - `command`: command the task starts
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the task was scheduled, and the artifact to remove it with.
+/// - `Err`: There was an issue scheduling the task. (Unknown trigger, already exists or no permissions)
+pub fn scheduled_task(name: &str, trigger: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    let (_, schtasks_trigger, cron_schedule) = match TRIGGERS.iter().find(|(known, _, _)| known.eq_ignore_ascii_case(trigger)) {
+        Some(inner) => inner,
+        None => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a task trigger (onstart, minute, hourly, daily, weekly)", trigger)))
+    };
+    let activity = format!("Scheduled Task Created ({})", trigger.to_lowercase());
+    if cfg!(windows) {
+        run_tool("schtasks.exe", &["/Create", "/TN", name, "/TR", command, "/SC", schtasks_trigger])?;
+        return Ok((adapt_log_persistence(activity, format!("\\{}", name), command.to_string()), Artifact::ScheduledTask { name: name.to_string() }));
+    }
+    let line = format!("{} {} {} {}", cron_schedule, command, CRON_MARKER, name);
+    write_crontab(&append_line(&read_crontab()?, &line))?;
+    Ok((adapt_log_persistence(activity, format!("crontab:{}", name), command.to_string()), Artifact::CronEntry { line }))
+}
+
+/// Registers a service which starts a command: with the service control manager on Windows (as a
+/// demand start service), or as a systemd user unit elsewhere. The service is not started, and
+/// the unit is not enabled.
+///
+/// # Parameters
+///
+/// - `name`: name of the service
+/// - `command`: command the service starts
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data confirming the service was registered, and the artifact to remove it with.
+/// - `Err`: There was an issue registering the service. (Already exists or no permissions)
+pub fn service(name: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    if cfg!(windows) {
+        run_tool("sc.exe", &["create", name, "binPath=", command, "start=", "demand"])?;
+        return Ok((adapt_log_persistence("Service Created".to_string(), service_key(name), command.to_string()), Artifact::Service { name: name.to_string() }));
+    }
+    service_unit_in(&config_directory()?.join("systemd").join("user"), name, command)
+}
+
+fn service_unit_in(directory: &Path, name: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    let contents = format!("[Unit]\nDescription={}\n\n[Service]\nExecStart={}\n\n[Install]\nWantedBy=default.target\n", name, command);
+    create_artifact_file(("Service Created", "Service Removed"), &directory.join(service_unit_name(name)), &contents, command)
+}
+
+/// Name of the systemd user unit file of a service
+fn service_unit_name(name: &str) -> String {
+    format!("{}.service", name)
+}
+
+/// Creates a new file, and its directory if needed, for a persistence artifact. The activities are
+/// logged when the file is created and when it is removed.
+fn create_artifact_file((activity, removed): (&str, &str), path: &Path, contents: &str, command: &str) -> Result<(Log, Artifact), GenerationError> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok((adapt_log_persistence(activity.to_string(), path.to_string_lossy().to_string(), command.to_string()),
+        Artifact::File { path: path.to_path_buf(), removed: removed.to_string() }))
+}
+
+/// Determines the current user's startup folder
+fn startup_directory() -> Result<PathBuf, GenerationError> {
+    if cfg!(windows) {
+        return match env::var_os("APPDATA") {
+            Some(appdata) => Ok(PathBuf::from(appdata).join("Microsoft\\Windows\\Start Menu\\Programs\\Startup")),
+            None => Err(GenerationError::new("io".to_string(), "Unable to locate the startup folder, APPDATA is not set".to_string()))
+        };
+    }
+    Ok(config_directory()?.join("autostart"))
+}
+
+/// Determines the current user's configuration directory (`$XDG_CONFIG_HOME` or `~/.config`)
+fn config_directory() -> Result<PathBuf, GenerationError> {
+    match (env::var_os("XDG_CONFIG_HOME").filter(|directory| !directory.is_empty()), env::var_os("HOME")) {
+        (Some(directory), _) => Ok(PathBuf::from(directory)),
+        (None, Some(home)) => Ok(PathBuf::from(home).join(".config")),
+        (None, None) => Err(GenerationError::new("io".to_string(), "Unable to locate the configuration directory, HOME is not set".to_string()))
+    }
+}
+
+/// Registry key the service control manager stores a service under, used as the path of service events
+fn service_key(name: &str) -> String {
+    format!("HKLM\\SYSTEM\\CurrentControlSet\\Services\\{}", name)
+}
+
+/// Runs an administration tool (`sc.exe`, `schtasks.exe`, `systemctl`) with the given arguments
+pub(crate) fn run_tool(program: &str, arguments: &[&str]) -> Result<(), GenerationError> {
+    let output = Command::new(program).args(arguments).output()?;
+    if !output.status.success() {
+        let message = [output.stdout, output.stderr].concat();
+        return Err(GenerationError::new("io".to_string(), format!("{} {} failed: {}", program, arguments[0], String::from_utf8_lossy(&message).trim())));
+    }
+    Ok(())
+}
+
+/// Reads the current user's crontab, a user without a crontab has an empty one
+fn read_crontab() -> Result<String, GenerationError> {
+    let output = Command::new("crontab").arg("-l").output()?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+    }
+    let error = String::from_utf8_lossy(&output.stderr).to_string();
+    if error.contains("no crontab") {
+        return Ok("".to_string());
+    }
+    Err(GenerationError::new("io".to_string(), format!("crontab -l failed: {}", error.trim())))
+}
+
+/// Replaces the current user's crontab
+fn write_crontab(contents: &str) -> Result<(), GenerationError> {
+    let mut child = Command::new("crontab").arg("-").stdin(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(contents.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(GenerationError::new("io".to_string(), format!("crontab - failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// Appends a line to a crontab, which must end with a newline to be installed
+fn append_line(crontab: &str, line: &str) -> String {
+    let mut contents = crontab.to_string();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(line);
+    contents.push('\n');
+    contents
+}
+
+/// Removes the last occurrence of a line from a crontab, leaving entries added by others untouched
+///
+/// # Returns
+///
+/// The crontab without the line, None when the line is not in the crontab
+fn remove_line(crontab: &str, line: &str) -> Option<String> {
+    let mut lines: Vec<&str> = crontab.lines().collect();
+    let index = lines.iter().rposition(|existing| *existing == line)?;
+    lines.remove(index);
+    Some(lines.iter().map(|existing| format!("{}\n", existing)).collect())
+}
+
+/// Creates a log structure for persistence events
+///
+/// # Parameters
+///
+/// - `activity`: short text describing the type of event
+/// - `file_path`: path of the file or registry value holding the artifact
+/// - `proc_cmd`: command started by the artifact
+///
+/// # Returns
+///
+/// A Log struct customized for persistence events
+fn adapt_log_persistence(activity: String, file_path: String, proc_cmd: String) -> Log {
+    Log{
+        t: String::from("Information"),
+        timestamp: get_time(),
+        username: String::from(""),
+        proc_name: String::from(""),
+        proc_cmd,
+        proc_id: String::from(""),
+        activity,
+        file_path,
+        source_addr: String::from(""),
+        source_port: String::from(""),
+        dest_addr: String::from(""),
+        dest_port: String::from(""),
+        bytes_sent: String::from(""),
+        protocol: String::from(""),
+        address_family: String::from(""),
+        stdout: String::from(""),
+        stderr: String::from(""),
+        exit_code: String::from("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn fields_checked() {
+        assert!(valid_fields("persist_startup", "updater", "payload.exe --quiet"));
+        assert!(!valid_fields("persist_startup", "../updater", "payload.exe"));
+        assert!(!valid_fields("persist_service", "", "payload.exe"));
+        assert!(!valid_fields("persist_runkey", "updater", "payload.exe\nrm -rf ~"));
+        assert!(valid_fields("persist_cron", "*/5 * * * *", "/tmp/payload"));
+        assert!(valid_fields("persist_cron", "@reboot", "/tmp/payload"));
+        assert!(!valid_fields("persist_cron", "*/5 * *", "/tmp/payload"));
+        assert!(!valid_fields("persist_cron", "@reboot", " "));
+        assert!(valid_fields("schedule_task", "EdrUpdate", "payload"));
+        assert!(matches!(scheduled_task("EdrUpdate", "fortnightly", "payload"), Err(e) if e.kind == "input_format"));
+    }
+
+    #[test]
+    fn files_created_and_removed() {
+        let directory = TempPath::new("persistence");
+        let (log, artifact) = startup_file_in(&directory.join("autostart"), "edr_updater", "payload --quiet").unwrap();
+        assert_eq!(log.activity, "Startup Folder File");
+        assert_eq!(log.proc_cmd, "payload --quiet");
+        assert!(fs::read_to_string(&log.file_path).unwrap().contains("payload --quiet"));
+        assert!(startup_file_in(&directory.join("autostart"), "edr_updater", "payload").is_err());
+        let (service_log, service_artifact) = service_unit_in(&directory, "edr_updater", "payload").unwrap();
+        assert_eq!(service_log.activity, "Service Created");
+        assert!(location("persist_startup", "edr_updater").unwrap().ends_with(&startup_file_name("edr_updater")));
+        assert!(Path::new(&service_log.file_path).ends_with(service_unit_name("edr_updater")));
+        assert!(location("process", "edr_updater").is_err());
+
+        let removed = artifact.remove().unwrap();
+        assert_eq!(removed.activity, "Startup Folder File Removed");
+        assert!(!Path::new(&log.file_path).exists());
+        assert!(artifact.remove().is_err());
+        assert_eq!(service_artifact.remove().unwrap().activity, "Service Removed");
+    }
+
+    #[test]
+    fn crontab_lines() {
+        let line = format!("@reboot /tmp/payload {}", CRON_MARKER);
+        let crontab = append_line("MAILTO=\"\"\n0 * * * * backup", &line);
+        assert_eq!(crontab, format!("MAILTO=\"\"\n0 * * * * backup\n{}\n", line));
+        assert_eq!(append_line("", &line), format!("{}\n", line));
+        assert_eq!(remove_line(&crontab, &line).unwrap(), "MAILTO=\"\"\n0 * * * * backup\n");
+        assert!(remove_line("0 * * * * backup\n", &line).is_none());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::modules::common::GenerationError;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::IpAddr;
+
+/// Instruction categories which may be named in a policy, along with the instructions they contain.
+/// `pause` only delays the run and is always permitted.
+const CATEGORIES: [(&str, &[&str]); 6] = [
+    ("process", &["process", "process_storm", "process_with_parent", "process_in_session", "wait_process"]),
+    ("file", &["new_file", "mod_file", "delete_file", "encrypt_burst", "ads_write", "symlink", "hardlink", "chmod_file"]),
+    ("network", &["connect", "connect_self", "listen", "exfil", "beacon", "fan_out"]),
+    ("persistence", &["persist_runkey", "persist_startup", "persist_cron", "persist_service", "schedule_task"]),
+    ("tamper", &["tamper_service", "tamper_files", "tamper_rename"]),
+    ("account", &["create_user", "delete_user", "add_to_group"]),
+];
+
+/// Structure defining which instructions a role may trigger
+///
+/// # Parameters
+///
+/// - `allow`: instructions or categories permitted, every instruction when not given
+/// - `deny`: instructions or categories refused, even if they are allowed
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// Structure defining a role assigned to a set of controllers
+///
+/// # Parameters
+///
+/// - `controllers`: IP addresses of the controllers the role applies to
+/// - `allow`: instructions or categories permitted, every instruction when not given
+/// - `deny`: instructions or categories refused, even if they are allowed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Role {
+    pub controllers: Vec<IpAddr>,
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// Structure defining the instructions remote controllers may trigger on this host. Controllers
+/// are identified by their IP address, a controller which is not listed by any role is given the
+/// default rule. Without a default rule, such a controller may not trigger any instruction.
+///
+/// ```yaml
+/// default:
+///   allow: [file, network]
+/// roles:
+///   operators:
+///     controllers: [10.0.0.5]
+///     deny: [process_with_parent]
+/// ```
+///
+/// # Parameters
+///
+/// - `default`: rule of controllers which are not listed by any role, denying every instruction
+///   when not given
+/// - `roles`: roles by name
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default = "Rule::deny_all")]
+    pub default: Rule,
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+}
+
+/// Structure defining the instructions a single controller may trigger
+///
+/// # Parameters
+///
+/// - `role`: name of the role the permissions were resolved from
+/// - `allowed`: names of the permitted instructions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permissions {
+    pub role: String,
+    allowed: BTreeSet<String>,
+}
+
+impl Rule {
+    /// Builds the rule of a policy without a `default` section, which permits no instruction
+    fn deny_all() -> Rule {
+        Rule { allow: Some(Vec::new()), deny: Vec::new() }
+    }
+}
+
+impl Policy {
+    /// Reads a policy file. Files with a `.json` extension are read as JSON, all others as YAML.
+    /// Every instruction and category named by the policy must exist, so a misspelled `deny` entry
+    /// can not silently permit an instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the policy file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Policy instance
+    /// - `Err`: The file could not be read, is not a valid policy, or names an unknown instruction
+    pub fn load(path: &String) -> Result<Policy, GenerationError> {
+        let contents = fs::read_to_string(path)?;
+        let parsed = if path.to_lowercase().ends_with(".json") {
+            serde_json::from_str::<Policy>(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_str::<Policy>(&contents).map_err(|e| e.to_string())
+        };
+        let policy = match parsed {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid policy: {}", path, e)))
+        };
+        let rules = std::iter::once((&policy.default.allow, &policy.default.deny))
+            .chain(policy.roles.values().map(|role| (&role.allow, &role.deny)));
+        for (allow, deny) in rules {
+            if let Some(name) = allow.iter().flatten().chain(deny.iter()).find(|name| expand(name).is_empty()) {
+                return Err(GenerationError::new("input_format".to_string(), format!("{} names {} which is not an instruction or category (process, file, network, persistence, tamper, account)", path, name)));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Resolves the permissions of a controller. When several roles list the controller, the
+    /// first role by name is used. Addresses are compared in their canonical form, so an IPv4
+    /// controller connecting to a dual-stack listener as `::ffff:10.0.0.5` matches `10.0.0.5`.
+    ///
+    /// # Parameters
+    ///
+    /// - `controller`: IP address of the controller
+    ///
+    /// # Returns
+    ///
+    /// The permissions of the controller's role, or of the default rule
+    pub fn permissions(&self, controller: &IpAddr) -> Permissions {
+        let controller = controller.to_canonical();
+        match self.roles.iter().find(|(_, role)| role.controllers.iter().any(|address| address.to_canonical() == controller)) {
+            Some((name, role)) => Permissions::new(name.clone(), role.allow.as_ref(), &role.deny),
+            None => Permissions::new("default".to_string(), self.default.allow.as_ref(), &self.default.deny)
+        }
+    }
+}
+
+impl Permissions {
+    fn new(role: String, allow: Option<&Vec<String>>, deny: &[String]) -> Permissions {
+        let mut allowed: BTreeSet<String> = match allow {
+            Some(names) => names.iter().flat_map(|name| expand(name)).collect(),
+            None => CATEGORIES.iter().flat_map(|(_, instructions)| instructions.iter().map(|instruction| instruction.to_string())).collect()
+        };
+        for name in deny.iter().flat_map(|name| expand(name)) {
+            allowed.remove(&name);
+        }
+        Permissions { role, allowed }
+    }
+
+    /// Determines if an instruction may be executed. Instructions which are not covered by any
+    /// category (`pause`, and names which are not instructions) are permitted so they are handled
+    /// as usual.
+    ///
+    /// # Parameters
+    ///
+    /// - `instruction`: name of the instruction
+    ///
+    /// # Returns
+    ///
+    /// True if the instruction may be executed
+    pub fn permits(&self, instruction: &str) -> bool {
+        self.allowed.contains(instruction) || expand(instruction).is_empty()
+    }
+}
+
+/// Determines if a name is a built-in instruction covered by a category, every instruction
+/// except `pause`
+///
+/// # Parameters
+///
+/// - `name`: name of the instruction
+///
+/// # Returns
+///
+/// True if the name is an instruction
+pub(crate) fn is_instruction(name: &str) -> bool {
+    CATEGORIES.iter().any(|(_, instructions)| instructions.contains(&name))
+}
+
+/// Expands a category into its instructions, or an instruction into itself
+///
+/// # Returns
+///
+/// The instruction names, empty when the name is neither a category nor an instruction
+fn expand(name: &str) -> Vec<String> {
+    for (category, instructions) in CATEGORIES.iter() {
+        if *category == name {
+            return instructions.iter().map(|instruction| instruction.to_string()).collect();
+        }
+        if instructions.contains(&name) {
+            return vec![name.to_string()];
+        }
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    fn sample_policy() -> Policy {
+        serde_yaml::from_str("
+default:
+  allow: [file, network]
+roles:
+  operators:
+    controllers: [10.0.0.5, '::1']
+    deny: [process_with_parent, listen]
+").unwrap()
+    }
+
+    #[test]
+    fn controllers_resolved() {
+        let policy = sample_policy();
+        let default = policy.permissions(&"10.0.0.9".parse().unwrap());
+        assert_eq!(default.role, "default");
+        assert!(default.permits("new_file"));
+        assert!(default.permits("listen"));
+        assert!(!default.permits("process"));
+        assert!(default.permits("pause"));
+        assert!(default.permits("not_an_instruction"));
+
+        let operators = policy.permissions(&"::1".parse().unwrap());
+        assert_eq!(operators.role, "operators");
+        assert!(operators.permits("process"));
+        assert!(!operators.permits("process_with_parent"));
+        assert!(!operators.permits("listen"));
+    }
+
+    #[test]
+    fn mapped_controllers_resolved() {
+        let policy: Policy = serde_yaml::from_str("roles:\n  restricted:\n    controllers: [10.0.0.5, '::ffff:10.0.0.6']\n    allow: [file]\n").unwrap();
+        for controller in ["::ffff:10.0.0.5", "10.0.0.6"].iter() {
+            let permissions = policy.permissions(&controller.parse().unwrap());
+            assert_eq!(permissions.role, "restricted");
+            assert!(!permissions.permits("process"));
+        }
+        // without a default rule, controllers outside of every role may not trigger anything
+        let unknown = policy.permissions(&"::ffff:10.0.0.9".parse().unwrap());
+        assert_eq!(unknown.role, "default");
+        assert!(!unknown.permits("new_file"));
+        assert!(unknown.permits("pause"));
+    }
+
+    #[test]
+    fn unknown_names_rejected() {
+        let temp = TempPath::new("policy.yaml");
+        let path = temp.as_string();
+        fs::write(&path, "default:\n  deny: [proccess]\n").unwrap();
+        assert!(Policy::load(&path).is_err());
+        fs::write(&path, "roles:\n  ops:\n    controllers: [10.0.0.5]\n    allow: [user_create]\n").unwrap();
+        assert!(Policy::load(&path).is_err());
+        fs::write(&path, "default:\n  deny: [process]\n").unwrap();
+        assert!(Policy::load(&path).is_ok());
+    }
+}
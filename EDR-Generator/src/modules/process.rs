@@ -1,11 +1,18 @@
-use std::process::Command;
+use std::process::{Child, Command, ExitStatus, Stdio};
 use crate::modules::common::GenerationError;
-use std::thread;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use shlex::Shlex;
 use sysinfo::{SystemExt, ProcessExt};
-use crate::modules::logger::Log;
+use crate::modules::logger::{Log, LogUsage};
+use chrono::Utc;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
+/// Largest number of bytes which may be captured from each output stream of a process
+pub const MAX_CAPTURE_SIZE: usize = 1048576;
 
 /// Structure defining the a process
 ///
@@ -15,22 +22,30 @@ use crate::modules::logger::Log;
 /// - `name`: Process Name
 /// - `cmd`: Process Command Line
 /// - `stime`: Start Time
+/// - `alias`: optional name the process can be waited for by, see `ProcessOptions::alias`
+/// - `child`: handle of a process spawned directly by the generator, which collects its exit status
 pub struct Process {
     pub id: usize,
     pub name: String,
     pub cmd: String,
     pub stime: u64,
+    pub alias: Option<String>,
+    child: Option<Child>,
 }
 
 /// Structure defining the Process Manager Class
 ///
 /// # Parameters
 ///
-/// - `processes`: Process Vector of all running processes
+/// - `processes`: Process Vector of all running processes, including tracked descendants
 /// - `system`: System instance that tracks system processes
+/// - `track_descendants`: whether processes started by child processes are tracked
+/// - `working_directory`: optional directory new processes are started in
 pub struct ProcessManager{
     processes: Vec<Process>,
     system: sysinfo::System,
+    track_descendants: bool,
+    working_directory: Option<PathBuf>,
 }
 
 /// Structure defining the process status
@@ -46,6 +61,111 @@ pub struct KillCount {
     pub failures: Vec<Log>,
 }
 
+/// Structure defining how the standard streams of a new process are connected
+///
+/// # Parameters
+///
+/// - `stdin`: optional text written to the standard input of the process, which is closed
+///   afterwards. The standard input is inherited when not given.
+/// - `capture`: optional number of bytes captured from each of the standard output and standard
+///   error of the process. The streams are inherited when not given.
+/// - `alias`: optional name the process can be waited for by instead of its process ID, see
+///   `ProcessManager::exited`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessOptions {
+    pub stdin: Option<String>,
+    pub capture: Option<usize>,
+    pub alias: Option<String>,
+}
+
+/// Structure defining the output captured from a process, see `ProcessOptions::capture`. The
+/// streams are read on threads of their own until the process closes them, usually when it exits.
+///
+/// # Parameters
+///
+/// - `log`: Log data of the process the output is captured from
+/// - `streams`: captured standard output and standard error, and the threads reading them
+pub struct OutputCapture {
+    log: Log,
+    streams: Vec<(Arc<Mutex<Captured>>, JoinHandle<()>)>,
+}
+
+/// Structure defining the data captured from a single stream
+///
+/// # Parameters
+///
+/// - `data`: bytes captured, up to the size limit
+/// - `truncated`: whether the stream produced more bytes than the size limit
+#[derive(Default)]
+struct Captured {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+impl OutputCapture {
+    /// Starts capturing a stream of a process
+    fn read(&mut self, stream: impl Read + Send + 'static, limit: usize) {
+        let captured = Arc::new(Mutex::new(Captured::default()));
+        let shared = captured.clone();
+        let reader = thread::spawn(move || {
+            let mut stream = stream;
+            let mut buffer = [0; 4096];
+            // the stream is read to its end even once the limit is reached, so the process is not
+            // blocked writing to a full pipe
+            while let Ok(size) = stream.read(&mut buffer) {
+                if size == 0 {
+                    break;
+                }
+                let mut captured = shared.lock().unwrap();
+                let kept = size.min(limit - captured.data.len());
+                captured.data.extend_from_slice(&buffer[..kept]);
+                captured.truncated |= kept < size;
+            }
+        });
+        self.streams.push((captured, reader));
+    }
+
+    /// Determines if the process closed every captured stream
+    ///
+    /// # Returns
+    ///
+    /// True once the output is complete, false otherwise
+    pub fn finished(&self) -> bool {
+        self.streams.iter().all(|(_, reader)| reader.is_finished())
+    }
+
+    /// Ends the capture. When the output is not complete, the output captured so far is kept.
+    ///
+    /// # Returns
+    ///
+    /// Log data of the process, as a `Process Output` event with its standard output and standard
+    /// error. The activity notes when a stream was truncated or the process was still running.
+    pub fn into_log(self) -> Log {
+        let finished = self.finished();
+        let mut log = self.log;
+        let mut truncated = false;
+        let mut outputs = Vec::new();
+        for (captured, reader) in self.streams {
+            if finished {
+                let _ = reader.join();
+            }
+            let captured = captured.lock().unwrap();
+            truncated |= captured.truncated;
+            outputs.push(String::from_utf8_lossy(&captured.data).to_string());
+        }
+        let mut outputs = outputs.into_iter();
+        log.stdout = outputs.next().unwrap_or_default();
+        log.stderr = outputs.next().unwrap_or_default();
+        log.activity = match (finished, truncated) {
+            (true, false) => "Process Output".to_string(),
+            (true, true) => "Process Output (truncated)".to_string(),
+            (false, false) => "Process Output (still running)".to_string(),
+            (false, true) => "Process Output (truncated, still running)".to_string(),
+        };
+        log
+    }
+}
+
 impl Drop for ProcessManager {
     fn drop(&mut self) {
         match self.stop_all() {
@@ -68,9 +188,111 @@ impl ProcessManager{
         Ok(ProcessManager {
             processes: Vec::new(),
             system: sysinfo::System::new(),
+            track_descendants: false,
+            working_directory: None,
         })
 
     }
+    /// Enables or disables tracking of descendant processes, see `poll_descendants`
+    /// # Parameters
+    ///
+    /// - `enabled`: whether processes started by child processes are tracked
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_track_descendants(&mut self, enabled: bool) {
+        self.track_descendants = enabled;
+    }
+
+    /// Sets the directory new processes are started in, see `--workdir`
+    /// # Parameters
+    ///
+    /// - `directory`: directory to start processes in, the current directory when not given
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_working_directory(&mut self, directory: Option<PathBuf>) {
+        self.working_directory = directory;
+    }
+
+    /// Finds processes whose parent chain leads to a tracked process and tracks them as well, so
+    /// they are stopped with the child processes. A descendant whose parent exits before it is
+    /// found is re-parented by the operating system and can no longer be found.
+    ///
+    /// # Returns
+    ///
+    /// Log data for every newly found descendant, empty when tracking is disabled
+    pub fn poll_descendants(&mut self) -> Vec<Log> {
+        let mut found = vec![];
+        if !self.track_descendants || self.processes.is_empty() {
+            return found;
+        }
+        self.system.refresh_processes();
+        let mut tracked: HashSet<usize> = self.processes.iter().map(|process| process.id).collect();
+        // a process is only found once its parent is tracked, so repeat until a pass finds nothing
+        loop {
+            let descendants: Vec<&sysinfo::Process> = self.system.get_processes().values()
+                .filter(|process| !tracked.contains(&process.pid()) && matches!(process.parent(), Some(parent) if tracked.contains(&parent)))
+                .collect();
+            if descendants.is_empty() {
+                break;
+            }
+            for process in descendants {
+                let pid = process.pid();
+                let cmd = process.cmd().join(" ");
+                tracked.insert(pid);
+                self.processes.push(Process {
+                    id: pid,
+                    name: String::from(process.name()),
+                    cmd: cmd.clone(),
+                    stime: process.start_time(),
+                    alias: None,
+                    child: None,
+                });
+                found.push(adapt_log_process("New Descendant Process".to_string(),
+                                             process.start_time(),
+                                             String::from(process.name()),
+                                             cmd,
+                                             pid.to_string()));
+            }
+        }
+        found
+    }
+
+    /// Samples the CPU, memory, and disk usage of every tracked process which is still running.
+    /// CPU usage is measured since the process list was previously refreshed, so the first sample
+    /// of a process may report 0.
+    ///
+    /// # Returns
+    ///
+    /// Log data for every running tracked process
+    pub fn sample_usage(&mut self) -> Vec<LogUsage> {
+        let mut samples = vec![];
+        if self.processes.is_empty() {
+            return samples;
+        }
+        self.system.refresh_processes();
+        let timestamp = Utc::now().timestamp().to_string();
+        for tracked in &self.processes {
+            if let Some(process) = self.system.get_process(tracked.id) {
+                let disk = process.disk_usage();
+                samples.push(LogUsage {
+                    t: "Resource Usage".to_string(),
+                    timestamp: timestamp.clone(),
+                    proc_name: tracked.name.clone(),
+                    proc_id: tracked.id.to_string(),
+                    cpu_percent: format!("{:.2}", process.cpu_usage()),
+                    memory_kb: process.memory().to_string(),
+                    disk_read_bytes: disk.total_read_bytes.to_string(),
+                    disk_written_bytes: disk.total_written_bytes.to_string(),
+                });
+            }
+        }
+        samples
+    }
+
     /// Spawns a new process from the shell
     /// # Parameters
     ///
@@ -84,33 +306,182 @@ impl ProcessManager{
     /// - `Ok`: Log data confirming the process was created
     /// - `Err`: Error when executing command
     pub fn new_process(&mut self, path: String, arguments: Option<String>) -> Result<Log, GenerationError>{
+        self.new_process_with(path, arguments, &ProcessOptions::default()).map(|(log, _)| log)
+    }
+
+    /// Spawns a new process from the shell, feeding its standard input and capturing its output
+    /// as requested
+    /// # Parameters
+    ///
+    /// - `path`: Path to the executable to execute
+    /// - `arguments`: additional arguments to pass to the process
+    /// - `options`: how the standard streams of the process are connected
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data confirming the process was created, and the capture of its output when
+    ///   requested
+    /// - `Err`: Error when executing command
+    pub fn new_process_with(&mut self, path: String, arguments: Option<String>, options: &ProcessOptions) -> Result<(Log, Option<OutputCapture>), GenerationError>{
         let args = String::from(arguments.unwrap_or(String::from(" ")));
-        match Command::new(&path).args(Shlex::new(&args)).spawn() {
-            Ok(child) =>{
+        let mut command = Command::new(&path);
+        if let Some(directory) = &self.working_directory {
+            command.current_dir(directory);
+        }
+        if options.stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        if options.capture.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        match command.args(Shlex::new(&args)).spawn() {
+            Ok(mut child) =>{
                 self.system.refresh_processes();
                 let process = match self.system.get_process(child.id() as usize){
                    Some(inner) => inner,
                    None => return Err(GenerationError::new("processes".to_string(), "Process Died Unexpectedly".to_string())),
                 };
                 let full_cmd = format!("{} {}", path, args);
-
-                self.processes.push(Process{
+                let mut tracked = Process{
                     id: child.id() as usize,
                     name: String::from(process.name()),
                     cmd: String::from(full_cmd.clone()),
                     stime: process.start_time(),
-                });
+                    alias: options.alias.clone(),
+                    child: None,
+                };
 
-                Ok(adapt_log_process("New Process".to_string(),
-                                     process.start_time(),
-                                     String::from(process.name()),
-                                     String::from(full_cmd),
-                                     process.pid().to_string()))
+                let log = adapt_log_process("New Process".to_string(),
+                                            process.start_time(),
+                                            String::from(process.name()),
+                                            String::from(full_cmd),
+                                            process.pid().to_string());
+                let capture = options.capture.map(|limit| {
+                    let mut capture = OutputCapture { log: log.clone(), streams: Vec::new() };
+                    if let Some(stdout) = child.stdout.take() {
+                        capture.read(stdout, limit);
+                    }
+                    if let Some(stderr) = child.stderr.take() {
+                        capture.read(stderr, limit);
+                    }
+                    capture
+                });
+                if let (Some(input), Some(mut stdin)) = (options.stdin.clone(), child.stdin.take()) {
+                    // written once the process is found, so it can not exit before, and on a thread of
+                    // its own as the process may not read its input at once. The input is closed once
+                    // written.
+                    thread::spawn(move || {
+                        let _ = stdin.write_all(input.as_bytes());
+                    });
+                }
+                tracked.child = Some(child);
+                self.processes.push(tracked);
+                Ok((log, capture))
             },
             Err(err) => return Err(GenerationError::from(err))
         }
     }
 
+    /// Spawns a new process as a child of an already running process, so the process tree shows the
+    /// running process as its parent (e.g. a shell spawned from winword.exe). Only supported on
+    /// Windows, where the parent is assigned with `PROC_THREAD_ATTRIBUTE_PARENT_PROCESS`.
+    /// # Parameters
+    ///
+    /// - `parent`: name of the running process to use as the parent (case insensitive)
+    /// - `path`: Path to the executable to execute
+    /// - `arguments`: additional arguments to pass to the process
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data confirming the process was created
+    /// - `Err`: The parent is not running, the platform is not supported, or the process could not
+    ///   be created (e.g. insufficient permissions to open the parent)
+    pub fn new_process_with_parent(&mut self, parent: &str, path: String, arguments: Option<String>) -> Result<Log, GenerationError> {
+        if !cfg!(windows) {
+            return Err(GenerationError::new("unsupported_platform".to_string(), "Assigning the parent of a new process is only supported on Windows".to_string()));
+        }
+        self.system.refresh_processes();
+        let parent_pid = match self.system.get_processes().values().find(|process| process.name().eq_ignore_ascii_case(parent)) {
+            Some(inner) => inner.pid(),
+            None => return Err(GenerationError::new("process".to_string(), format!("No running process named {}", parent))),
+        };
+        let args = arguments.unwrap_or_default();
+        let full_cmd = format!("{} {}", path, args);
+        let pid = spawn_with_parent(parent_pid as u32, &windows_command_line(&path, &args), self.working_directory.as_deref())? as usize;
+        self.system.refresh_processes();
+        let process = match self.system.get_process(pid) {
+            Some(inner) => inner,
+            None => return Err(GenerationError::new("processes".to_string(), "Process Died Unexpectedly".to_string())),
+        };
+        self.processes.push(Process {
+            id: pid,
+            name: String::from(process.name()),
+            cmd: full_cmd.clone(),
+            stime: process.start_time(),
+            alias: None,
+            child: None,
+        });
+        Ok(adapt_log_process("New Process".to_string(),
+                             process.start_time(),
+                             String::from(process.name()),
+                             full_cmd,
+                             pid.to_string()))
+    }
+
+    /// Spawns a new process in another active user session, with the token of the user logged on to
+    /// it, so the process is attributed to that user and session (e.g. on a multi-user Remote Desktop
+    /// Services host). Only supported on Windows, where the generator must run as `SYSTEM` to obtain
+    /// the token of the session with `WTSQueryUserToken`.
+    /// # Parameters
+    ///
+    /// - `session`: ID of the session, name of the user logged on to it (case insensitive), or
+    ///   `other` for the first active session other than the session of the generator
+    /// - `path`: Path to the executable to execute
+    /// - `arguments`: additional arguments to pass to the process
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data confirming the process was created, with the user of the session as the
+    ///   username and the session ID in the activity
+    /// - `Err`: No active session matches, the platform is not supported, or the process could not be
+    ///   created (e.g. the generator is not running as `SYSTEM`)
+    pub fn new_process_in_session(&mut self, session: &str, path: String, arguments: Option<String>) -> Result<Log, GenerationError> {
+        if !cfg!(windows) {
+            return Err(GenerationError::new("unsupported_platform".to_string(), "Starting processes in another user session is only supported on Windows".to_string()));
+        }
+        let args = arguments.unwrap_or_default();
+        let full_cmd = format!("{} {}", path, args);
+        let (own, sessions) = active_sessions()?;
+        let (session_id, user) = select_session(session, &sessions, own)?;
+        let pid = spawn_in_session(session_id, &windows_command_line(&path, &args), self.working_directory.as_deref())? as usize;
+        self.system.refresh_processes();
+        let process = match self.system.get_process(pid) {
+            Some(inner) => inner,
+            None => return Err(GenerationError::new("processes".to_string(), "Process Died Unexpectedly".to_string())),
+        };
+        self.processes.push(Process {
+            id: pid,
+            name: String::from(process.name()),
+            cmd: full_cmd.clone(),
+            stime: process.start_time(),
+            alias: None,
+            child: None,
+        });
+        let mut log = adapt_log_process(format!("New Process (session {})", session_id),
+                                        process.start_time(),
+                                        String::from(process.name()),
+                                        full_cmd,
+                                        pid.to_string());
+        log.username = user;
+        Ok(log)
+    }
+
     /// Stops a process with a given Process ID
     /// # Parameters
     ///
@@ -131,7 +502,102 @@ impl ProcessManager{
         Ok((process).clone())
     }
 
-    /// Stops all child processes spawned by the Process Manager instance
+    /// Stops a single child process spawned by the Process Manager instance
+    /// # Parameters
+    ///
+    /// - `pid`: Process ID of the child process to stop
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data confirming the process was stopped, or had already terminated along with
+    ///   its exit code when known
+    /// - `Err`: The process is not a child of this instance or could not be stopped
+    pub fn stop(&mut self, pid: usize) -> Result<Log, GenerationError> {
+        let index = match self.processes.iter().position(|process| process.id == pid) {
+            Some(inner) => inner,
+            None => return Err(GenerationError::new("process".to_string(), format!("Process {} was not started by the generator", pid))),
+        };
+        let exited = self.exit_status(index);
+        let mut process = self.processes.remove(index);
+        let stop_time = Utc::now().timestamp() as u64;
+        let activity = match exited {
+            Some(_) => "Process had prematurely terminated",
+            None => {
+                self.system.refresh_processes();
+                match self.system.get_process(pid) {
+                    Some(inner) => {
+                        if !inner.kill(sysinfo::Signal::Kill) {
+                            return Err(GenerationError::new("process".to_string(), format!("Process {} Failed to Stop", pid)));
+                        }
+                        if let Some(child) = process.child.as_mut() {
+                            // collects the killed process so it does not linger as a zombie
+                            let _ = child.wait();
+                        }
+                        "Process Stopped"
+                    }
+                    None => "Process had prematurely terminated"
+                }
+            }
+        };
+        let mut log = adapt_log_process(activity.to_string(), stop_time, process.name, process.cmd, process.id.to_string());
+        log.exit_code = exited.unwrap_or_default();
+        Ok(log)
+    }
+
+    /// Determines if a child process spawned by the Process Manager instance has exited, without
+    /// waiting for it. The process stays tracked, so stopping it later reports it as terminated.
+    /// # Parameters
+    ///
+    /// - `target`: alias or Process ID of the process. When several processes were given the same
+    ///   alias, the most recent one is used.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Log data of a `Process Exited` event with the exit code of the process once it has
+    ///   exited, nothing while it is running. The exit code is only known for processes spawned
+    ///   directly by this instance, not for descendants or processes with an assigned parent.
+    /// - `Err`: No process with the alias or Process ID was started by this instance
+    pub fn exited(&mut self, target: &str) -> Result<Option<Log>, GenerationError> {
+        let index = match self.processes.iter().rposition(|process| process.alias.as_deref() == Some(target) || process.id.to_string() == target) {
+            Some(inner) => inner,
+            None => return Err(GenerationError::new("process".to_string(), format!("No process with alias or PID {} was started by the generator", target))),
+        };
+        Ok(self.exit_status(index).map(|code| {
+            let process = &self.processes[index];
+            let mut log = adapt_log_process("Process Exited".to_string(), Utc::now().timestamp() as u64, process.name.clone(), process.cmd.clone(), process.id.to_string());
+            log.exit_code = code;
+            log
+        }))
+    }
+
+    /// Determines if a tracked process has exited, collecting the exit status of processes spawned
+    /// directly
+    /// # Parameters
+    ///
+    /// - `index`: position of the process in the tracked processes
+    ///
+    /// # Returns
+    ///
+    /// The exit code of the process once it has exited (empty when it is not known), nothing while
+    /// it is running
+    fn exit_status(&mut self, index: usize) -> Option<String> {
+        let pid = self.processes[index].id;
+        if let Some(child) = self.processes[index].child.as_mut() {
+            return child.try_wait().ok().flatten().map(exit_code);
+        }
+        self.system.refresh_processes();
+        match self.system.get_process(pid) {
+            Some(_) => None,
+            None => Some(String::new())
+        }
+    }
+
+    /// Stops all child processes spawned by the Process Manager instance. When descendant tracking is
+    /// enabled, descendants started since the last poll are found and stopped as well.
     ///
     /// # Returns
     ///
@@ -145,14 +611,18 @@ impl ProcessManager{
             premature: vec![],
             failures: vec![]
         };
+        self.poll_descendants();
         self.system.refresh_processes();
-        for process in &self.processes{
-
-
-            match self.stop_process(process.id) {
+        for index in 0..self.processes.len() {
+            match self.stop_process(self.processes[index].id) {
                 Ok(_) => {
                     thread::sleep(Duration::from_millis(100));
+                    if let Some(child) = self.processes[index].child.as_mut() {
+                        // collects the killed process so it is not mistaken for a running zombie
+                        let _ = child.try_wait();
+                    }
                     self.system.refresh_processes();
+                    let process = &self.processes[index];
                     match self.stop_process(process.id){
                         Ok(_) => {result.failures.push(adapt_log_process("Process Failed to Stop".to_string(),
                                                                          process.stime.clone(),
@@ -166,11 +636,14 @@ impl ProcessManager{
                                                                        process.id.to_string()))
                     };
                 },
-                Err(_) => result.premature.push(adapt_log_process("Process had prematurely terminated".to_string(),
-                                                                  process.stime.clone(),
-                                                                  process.name.clone(),
-                                                                  process.cmd.clone(),
-                                                                  process.id.to_string()))
+                Err(_) => {
+                    let process = &self.processes[index];
+                    result.premature.push(adapt_log_process("Process had prematurely terminated".to_string(),
+                                                            process.stime.clone(),
+                                                            process.name.clone(),
+                                                            process.cmd.clone(),
+                                                            process.id.to_string()))
+                }
             }
 
         };
@@ -181,6 +654,323 @@ impl ProcessManager{
     }
 }
 
+/// Creates a process with the given parent using `PROC_THREAD_ATTRIBUTE_PARENT_PROCESS`
+///
+/// # Parameters
+///
+/// - `parent_pid`: Process ID of the parent process
+/// - `command_line`: full command line of the new process, including the quoted executable path
+/// - `directory`: optional directory to start the process in, the current directory when not given
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Process ID of the new process
+/// - `Err`: The parent could not be opened or the process could not be created
+#[cfg(windows)]
+fn spawn_with_parent(parent_pid: u32, command_line: &str, directory: Option<&Path>) -> Result<u32, GenerationError> {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::iter::once;
+    use std::mem;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{CreateProcessW, DeleteProcThreadAttributeList, InitializeProcThreadAttributeList,
+                                        OpenProcess, UpdateProcThreadAttribute, LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION};
+    use winapi::um::winbase::{EXTENDED_STARTUPINFO_PRESENT, STARTUPINFOEXW};
+    use winapi::um::winnt::{HANDLE, PROCESS_CREATE_PROCESS};
+    const PROC_THREAD_ATTRIBUTE_PARENT_PROCESS: usize = 0x0002_0000;
+
+    unsafe {
+        let mut parent: HANDLE = OpenProcess(PROCESS_CREATE_PROCESS, FALSE, parent_pid);
+        if parent.is_null() {
+            return Err(GenerationError::from(io::Error::last_os_error()));
+        }
+        // the first call only reports the size of the attribute list
+        let mut size = 0;
+        InitializeProcThreadAttributeList(ptr::null_mut(), 1, 0, &mut size);
+        let mut buffer = vec![0usize; (size + mem::size_of::<usize>() - 1) / mem::size_of::<usize>()];
+        let list = buffer.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+        if InitializeProcThreadAttributeList(list, 1, 0, &mut size) == 0 {
+            let error = io::Error::last_os_error();
+            CloseHandle(parent);
+            return Err(GenerationError::from(error));
+        }
+        let mut result = Err(GenerationError::new("process".to_string(), "Unable to assign the parent process".to_string()));
+        if UpdateProcThreadAttribute(list, 0, PROC_THREAD_ATTRIBUTE_PARENT_PROCESS, &mut parent as *mut HANDLE as *mut _,
+                                     mem::size_of::<HANDLE>(), ptr::null_mut(), ptr::null_mut()) != 0 {
+            let mut startup: STARTUPINFOEXW = mem::zeroed();
+            startup.StartupInfo.cb = mem::size_of::<STARTUPINFOEXW>() as u32;
+            startup.lpAttributeList = list;
+            let mut information: PROCESS_INFORMATION = mem::zeroed();
+            let mut command: Vec<u16> = OsStr::new(command_line).encode_wide().chain(once(0)).collect();
+            let directory: Option<Vec<u16>> = directory.map(|directory| directory.as_os_str().encode_wide().chain(once(0)).collect());
+            if CreateProcessW(ptr::null(), command.as_mut_ptr(), ptr::null_mut(), ptr::null_mut(), FALSE,
+                              EXTENDED_STARTUPINFO_PRESENT, ptr::null_mut(), directory.as_ref().map_or(ptr::null(), |directory| directory.as_ptr()),
+                              &mut startup.StartupInfo, &mut information) != 0 {
+                CloseHandle(information.hProcess);
+                CloseHandle(information.hThread);
+                result = Ok(information.dwProcessId);
+            } else {
+                result = Err(GenerationError::from(io::Error::last_os_error()));
+            }
+        }
+        DeleteProcThreadAttributeList(list);
+        CloseHandle(parent);
+        result
+    }
+}
+
+#[cfg(not(windows))]
+fn spawn_with_parent(_parent_pid: u32, _command_line: &str, _directory: Option<&Path>) -> Result<u32, GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Assigning the parent of a new process is only supported on Windows".to_string()))
+}
+
+/// Creates a process in an active user session with the token of the user logged on to it, using
+/// `CreateProcessAsUserW` on the interactive desktop of the session
+///
+/// # Parameters
+///
+/// - `session`: ID of the session
+/// - `command_line`: full command line of the new process, including the quoted executable path
+/// - `directory`: optional directory to start the process in, the current directory when not given
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Process ID of the new process
+/// - `Err`: The token of the session could not be obtained (the generator is not running as
+///   `SYSTEM`), or the process could not be created
+#[cfg(windows)]
+fn spawn_in_session(session: u32, command_line: &str, directory: Option<&Path>) -> Result<u32, GenerationError> {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::iter::once;
+    use std::mem;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::shared::minwindef::{FALSE, LPVOID};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{CreateProcessAsUserW, PROCESS_INFORMATION, STARTUPINFOW};
+    use winapi::um::securitybaseapi::DuplicateTokenEx;
+    use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
+    use winapi::um::winbase::CREATE_UNICODE_ENVIRONMENT;
+    use winapi::um::winnt::{SecurityImpersonation, TokenPrimary, HANDLE, MAXIMUM_ALLOWED};
+    use winapi::um::wtsapi32::WTSQueryUserToken;
+    const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+        if WTSQueryUserToken(session, &mut token) == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) {
+                return Err(GenerationError::new("user_permissions".to_string(), format!("The token of session {} can only be obtained when running as SYSTEM ({})", session, error)));
+            }
+            return Err(GenerationError::from(error));
+        }
+        let mut primary: HANDLE = ptr::null_mut();
+        let duplicated = DuplicateTokenEx(token, MAXIMUM_ALLOWED, ptr::null_mut(), SecurityImpersonation, TokenPrimary, &mut primary);
+        CloseHandle(token);
+        if duplicated == 0 {
+            return Err(GenerationError::from(io::Error::last_os_error()));
+        }
+        // without the environment of the user the process would inherit the environment of SYSTEM
+        let mut environment: LPVOID = ptr::null_mut();
+        if CreateEnvironmentBlock(&mut environment, primary, FALSE) == 0 {
+            environment = ptr::null_mut();
+        }
+        let mut desktop: Vec<u16> = OsStr::new("winsta0\\default").encode_wide().chain(once(0)).collect();
+        let mut startup: STARTUPINFOW = mem::zeroed();
+        startup.cb = mem::size_of::<STARTUPINFOW>() as u32;
+        startup.lpDesktop = desktop.as_mut_ptr();
+        let mut information: PROCESS_INFORMATION = mem::zeroed();
+        let mut command: Vec<u16> = OsStr::new(command_line).encode_wide().chain(once(0)).collect();
+        let directory: Option<Vec<u16>> = directory.map(|directory| directory.as_os_str().encode_wide().chain(once(0)).collect());
+        let result = if CreateProcessAsUserW(primary, ptr::null(), command.as_mut_ptr(), ptr::null_mut(), ptr::null_mut(), FALSE,
+                                             CREATE_UNICODE_ENVIRONMENT, environment, directory.as_ref().map_or(ptr::null(), |directory| directory.as_ptr()),
+                                             &mut startup, &mut information) != 0 {
+            CloseHandle(information.hProcess);
+            CloseHandle(information.hThread);
+            Ok(information.dwProcessId)
+        } else {
+            Err(GenerationError::from(io::Error::last_os_error()))
+        };
+        if !environment.is_null() {
+            DestroyEnvironmentBlock(environment);
+        }
+        CloseHandle(primary);
+        result
+    }
+}
+
+#[cfg(not(windows))]
+fn spawn_in_session(_session: u32, _command_line: &str, _directory: Option<&Path>) -> Result<u32, GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Starting processes in another user session is only supported on Windows".to_string()))
+}
+
+/// Lists the active sessions of the host which have a user logged on, with `WTSEnumerateSessionsW`
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: ID of the session of the generator, and the ID of each active session with the name of
+///   its user
+/// - `Err`: The sessions could not be listed
+#[cfg(windows)]
+fn active_sessions() -> Result<(u32, Vec<(u32, String)>), GenerationError> {
+    use std::io;
+    use std::ptr;
+    use std::slice;
+    use winapi::shared::minwindef::{BOOL, DWORD, LPVOID};
+    use winapi::um::processthreadsapi::{GetCurrentProcessId, ProcessIdToSessionId};
+    use winapi::um::winnt::{HANDLE, LPWSTR};
+    const WTS_ACTIVE: DWORD = 0;
+    const WTS_USER_NAME: DWORD = 5;
+
+    #[repr(C)]
+    struct SessionInfo {
+        session_id: DWORD,
+        station_name: LPWSTR,
+        state: DWORD,
+    }
+    #[link(name = "wtsapi32")]
+    extern "system" {
+        fn WTSEnumerateSessionsW(server: HANDLE, reserved: DWORD, version: DWORD, sessions: *mut *mut SessionInfo, count: *mut DWORD) -> BOOL;
+        fn WTSQuerySessionInformationW(server: HANDLE, session: DWORD, class: DWORD, buffer: *mut LPWSTR, size: *mut DWORD) -> BOOL;
+        fn WTSFreeMemory(memory: LPVOID);
+    }
+
+    unsafe {
+        let mut own = 0;
+        ProcessIdToSessionId(GetCurrentProcessId(), &mut own);
+        let mut sessions: *mut SessionInfo = ptr::null_mut();
+        let mut count = 0;
+        if WTSEnumerateSessionsW(ptr::null_mut(), 0, 1, &mut sessions, &mut count) == 0 {
+            return Err(GenerationError::from(io::Error::last_os_error()));
+        }
+        let mut active = Vec::new();
+        for session in slice::from_raw_parts(sessions, count as usize).iter().filter(|session| session.state == WTS_ACTIVE) {
+            let mut buffer: LPWSTR = ptr::null_mut();
+            let mut size = 0;
+            if WTSQuerySessionInformationW(ptr::null_mut(), session.session_id, WTS_USER_NAME, &mut buffer, &mut size) == 0 {
+                continue;
+            }
+            // the size is in bytes and includes the terminating null character
+            let name = String::from_utf16_lossy(slice::from_raw_parts(buffer, (size as usize / 2).saturating_sub(1)));
+            WTSFreeMemory(buffer as LPVOID);
+            if !name.is_empty() {
+                active.push((session.session_id, name));
+            }
+        }
+        WTSFreeMemory(sessions as LPVOID);
+        Ok((own, active))
+    }
+}
+
+#[cfg(not(windows))]
+fn active_sessions() -> Result<(u32, Vec<(u32, String)>), GenerationError> {
+    Err(GenerationError::new("unsupported_platform".to_string(), "Starting processes in another user session is only supported on Windows".to_string()))
+}
+
+/// Selects the session a process is started in
+///
+/// # Parameters
+///
+/// - `target`: ID of the session, name of the user logged on to it (case insensitive, optionally
+///   prefixed with the domain), or `other` for the first session which is not `own`
+/// - `sessions`: ID of each active session and the name of its user
+/// - `own`: ID of the session of the generator
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: ID of the selected session and the name of its user
+/// - `Err`: No active session matches
+fn select_session(target: &str, sessions: &[(u32, String)], own: u32) -> Result<(u32, String), GenerationError> {
+    let user = target.rsplit('\\').next().unwrap_or(target);
+    let found = match target.parse::<u32>() {
+        Ok(id) => sessions.iter().find(|(session, _)| *session == id),
+        Err(_) if target.eq_ignore_ascii_case("other") => sessions.iter().find(|(session, _)| *session != own),
+        Err(_) => sessions.iter().find(|(_, name)| name.eq_ignore_ascii_case(user)),
+    };
+    match found {
+        Some(inner) => Ok(inner.clone()),
+        None => Err(GenerationError::new("process".to_string(), format!("No active user session matches {}", target))),
+    }
+}
+
+/// Builds the command line of a process created through the Windows API. The arguments are split
+/// like the arguments of any other process, then each is quoted so `CommandLineToArgvW` gives the
+/// process back the same arguments.
+///
+/// # Parameters
+///
+/// - `path`: path of the executable, quoted as the first argument
+/// - `arguments`: arguments as written in the scenario, quoted as in a POSIX shell
+///
+/// # Returns
+///
+/// The full command line
+fn windows_command_line(path: &str, arguments: &str) -> String {
+    let mut command_line = format!("\"{}\"", path);
+    for argument in Shlex::new(arguments) {
+        command_line.push(' ');
+        command_line.push_str(&windows_argument(&argument));
+    }
+    command_line
+}
+
+/// Quotes one argument of a Windows command line so `CommandLineToArgvW` gives it back unchanged
+///
+/// # Parameters
+///
+/// - `argument`: the argument
+///
+/// # Returns
+///
+/// The argument, quoted and escaped when it is empty or contains whitespace or quotes
+pub(crate) fn windows_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains(&[' ', '\t', '\n', '\x0b', '"'][..]) {
+        return argument.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // backslashes are only special before a quote, where they are escaped along with it
+        let escaped = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+        quoted.push_str(&"\\".repeat(escaped));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    // backslashes before the closing quote are escaped so it still ends the argument
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Formats the exit status of a process as its exit code, or the signal which terminated it
+/// (e.g. `signal 9`) on Unix
+fn exit_code(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("signal {}", signal);
+        }
+    }
+    status.code().map(|code| code.to_string()).unwrap_or_default()
+}
+
 /// Adapts a process event into a log struct used for logging
 ///
 /// # Parameters
@@ -212,7 +1002,11 @@ fn adapt_log_process(activity: String, timestamp: u64, proc_name: String, proc_c
         dest_addr: String::from(""),
         dest_port: String::from(""),
         bytes_sent: String::from(""),
-        protocol: String::from("")
+        protocol: String::from(""),
+        address_family: String::from(""),
+        stdout: String::from(""),
+        stderr: String::from(""),
+        exit_code: String::from("")
     }
 }
 
@@ -243,6 +1037,120 @@ mod tests {
         assert!(manager.new_process(String::from("garbasgwe"), None).is_err())
     }
 
+    #[test]
+    fn single_process_stopped() {
+        let mut manager = ProcessManager::new().unwrap();
+        let started = manager.new_process(get_os_shell(), None).unwrap();
+        let pid = started.proc_id.parse::<usize>().unwrap();
+        let stopped = manager.stop(pid).unwrap();
+        assert_eq!(stopped.proc_id, started.proc_id);
+        assert!(manager.stop(pid).is_err());
+    }
+
+    #[test]
+    fn output_captured() {
+        let mut manager = ProcessManager::new().unwrap();
+        let input = if cfg!(windows) { "echo hello\r\necho oops 1>&2\r\nexit\r\n" } else { "echo hello; echo oops >&2; exit\n" };
+        for (size, activity) in [(4096, "Process Output"), (3, "Process Output (truncated)")].iter() {
+            let options = ProcessOptions { stdin: Some(input.to_string()), capture: Some(*size), ..ProcessOptions::default() };
+            let (started, capture) = manager.new_process_with(get_os_shell(), None, &options).unwrap();
+            let capture = capture.unwrap();
+            for _ in 0..250 {
+                if capture.finished() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            let output = capture.into_log();
+            assert_eq!(output.activity, *activity);
+            assert_eq!(output.proc_id, started.proc_id);
+            assert!(started.stdout.is_empty());
+            if *size == 3 {
+                assert_eq!(output.stdout, "hel");
+            } else {
+                assert!(output.stdout.contains("hello"));
+                assert!(output.stderr.contains("oops"));
+            }
+        }
+        assert!(manager.new_process_with(get_os_shell(), None, &ProcessOptions::default()).unwrap().1.is_none());
+    }
+
+    #[test]
+    fn process_exit_detected() {
+        let mut manager = ProcessManager::new().unwrap();
+        let arguments = if cfg!(windows) { "/c \"ping -n 2 127.0.0.1 >NUL & exit 3\"" } else { "-c \"sleep 1; exit 3\"" };
+        let options = ProcessOptions { alias: Some("slow".to_string()), ..ProcessOptions::default() };
+        let (started, _) = manager.new_process_with(get_os_shell(), Some(arguments.to_string()), &options).unwrap();
+        assert!(manager.exited("slow").unwrap().is_none());
+        assert!(manager.exited("fast").is_err());
+        let mut exited = None;
+        for _ in 0..250 {
+            exited = manager.exited(&started.proc_id).unwrap();
+            if exited.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let exited = exited.unwrap();
+        assert_eq!(exited.activity, "Process Exited");
+        assert_eq!(exited.exit_code, "3");
+        let stopped = manager.stop(started.proc_id.parse::<usize>().unwrap()).unwrap();
+        assert_eq!((stopped.activity.as_str(), stopped.exit_code.as_str()), ("Process had prematurely terminated", "3"));
+    }
+
+    #[test]
+    fn session_selected() {
+        let sessions = vec![(1, String::from("Administrator")), (2, String::from("alice")), (3, String::from("bob"))];
+        assert_eq!(select_session("3", &sessions, 1).unwrap(), (3, String::from("bob")));
+        assert_eq!(select_session("ALICE", &sessions, 1).unwrap(), (2, String::from("alice")));
+        assert_eq!(select_session("LAB\\bob", &sessions, 1).unwrap().0, 3);
+        assert_eq!(select_session("other", &sessions, 1).unwrap().0, 2);
+        assert!(select_session("4", &sessions, 1).is_err());
+        assert!(select_session("other", &sessions[..1], 1).is_err());
+        if !cfg!(windows) {
+            let mut manager = ProcessManager::new().unwrap();
+            assert!(matches!(manager.new_process_in_session("other", get_os_shell(), None), Err(e) if e.kind == "unsupported_platform"));
+        }
+    }
+
+    #[test]
+    fn windows_command_line_quoted() {
+        assert_eq!(windows_command_line(r"C:\Windows\cmd.exe", ""), r#""C:\Windows\cmd.exe""#);
+        assert_eq!(windows_command_line("cmd", "/c whoami"), r#""cmd" /c whoami"#);
+        assert_eq!(windows_command_line("cmd", "/c 'echo a b'"), r#""cmd" /c "echo a b""#);
+        assert_eq!(windows_command_line("x", r#"'say "hi"' 'C:\dir with space\' ''"#), r#""x" "say \"hi\"" "C:\dir with space\\" """#);
+        assert_eq!(windows_command_line("x", r#"'a\"b'"#), r#""x" "a\\\"b""#);
+    }
+
+    #[test]
+    fn descendant_processes_tracked() {
+        let mut manager = ProcessManager::new().unwrap();
+        let arguments = if cfg!(windows) { "/c ping -n 3 127.0.0.1" } else { "-c \"sleep 2; exit\"" };
+        let started = manager.new_process(get_os_shell(), Some(arguments.to_string())).unwrap();
+        assert!(manager.poll_descendants().is_empty());
+        manager.set_track_descendants(true);
+        thread::sleep(Duration::from_millis(500));
+        let descendants = manager.poll_descendants();
+        assert_eq!(descendants.len(), 1);
+        assert_eq!(descendants[0].activity, "New Descendant Process");
+        assert_ne!(descendants[0].proc_id, started.proc_id);
+        // already tracked descendants are not reported again
+        assert!(manager.poll_descendants().is_empty());
+        assert_eq!(manager.processes.len(), 2);
+    }
+
+    #[test]
+    fn resource_usage_sampled() {
+        let mut manager = ProcessManager::new().unwrap();
+        assert!(manager.sample_usage().is_empty());
+        let arguments = if cfg!(windows) { "/c ping -n 2 127.0.0.1" } else { "-c \"sleep 1; exit\"" };
+        let started = manager.new_process(get_os_shell(), Some(arguments.to_string())).unwrap();
+        let samples = manager.sample_usage();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].proc_id, started.proc_id);
+        assert!(samples[0].memory_kb.parse::<u64>().unwrap() > 0);
+    }
+
     #[test]
     fn all_processes_killed(){
         let mut pids:Vec<Log> =  vec![];
@@ -0,0 +1,131 @@
+use crate::modules::common::GenerationError;
+use rand::Rng;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Structure defining a token bucket which limits the rate instructions are executed at. Tokens are
+/// refilled continuously at `rate` per second up to `burst`, and each instruction consumes one, so
+/// the long run rate stays exact even when individual waits are lengthened by jitter.
+///
+/// # Parameters
+///
+/// - `rate`: sustained number of instructions per second
+/// - `burst`: maximum number of instructions which may be executed back to back
+/// - `jitter`: fraction (0-1) by which each wait is randomly shortened or lengthened
+/// - `tokens`: number of instructions which may currently be executed without waiting
+/// - `updated`: time the tokens were last refilled
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    jitter: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Instantiates a rate limiter with a full bucket
+    ///
+    /// # Parameters
+    ///
+    /// - `rate`: sustained number of instructions per second
+    /// - `burst`: maximum number of instructions which may be executed back to back
+    /// - `jitter`: percentage (0-100) by which each wait is randomly shortened or lengthened
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: RateLimiter instance
+    /// - `Err`: The rate or burst is not positive, or the jitter is outside of 0-100
+    pub fn new(rate: f64, burst: u32, jitter: f64) -> Result<RateLimiter, GenerationError> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid rate (must be above 0)", rate)));
+        }
+        if burst == 0 {
+            return Err(GenerationError::new("input_format".to_string(), "Burst must be at least 1".to_string()));
+        }
+        if !(0.0..=100.0).contains(&jitter) {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid jitter (0-100)", jitter)));
+        }
+        Ok(RateLimiter {
+            rate,
+            burst: burst as f64,
+            jitter: jitter / 100.0,
+            tokens: burst as f64,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Blocks until the next instruction may be executed
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn acquire(&mut self) {
+        let wait = self.reserve(Instant::now());
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Consumes a token and calculates how long to wait before using it
+    ///
+    /// # Parameters
+    ///
+    /// - `now`: current time
+    ///
+    /// # Returns
+    ///
+    /// Time to wait before executing the instruction, zero if a token was available
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            return Duration::from_secs(0);
+        }
+        let mut wait = -self.tokens / self.rate;
+        if self.jitter > 0.0 {
+            wait *= 1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        }
+        Duration::from_secs_f64(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_rate() {
+        let mut limiter = RateLimiter::new(10.0, 3, 0.0).unwrap();
+        let start = limiter.updated;
+        for _ in 0..3 {
+            assert_eq!(limiter.reserve(start), Duration::from_secs(0));
+        }
+        assert_eq!(limiter.reserve(start).as_millis(), 100);
+        // waits accumulate while the bucket is empty
+        assert_eq!(limiter.reserve(start).as_millis(), 200);
+        // tokens refilled while the previous waits elapsed are used before waiting again
+        let later = start + Duration::from_millis(300);
+        assert_eq!(limiter.reserve(later), Duration::from_secs(0));
+        assert_eq!(limiter.reserve(later).as_millis(), 100);
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let mut limiter = RateLimiter::new(1.0, 1, 50.0).unwrap();
+        let start = limiter.updated;
+        limiter.reserve(start);
+        let wait = limiter.reserve(start).as_millis();
+        assert!((500..=1500).contains(&wait));
+    }
+
+    #[test]
+    fn invalid_limits() {
+        assert!(RateLimiter::new(0.0, 1, 0.0).is_err());
+        assert!(RateLimiter::new(5.0, 0, 0.0).is_err());
+        assert!(RateLimiter::new(5.0, 1, 150.0).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+use crate::modules::common::{GenerationError, get_time};
+use crate::modules::inventory::HostInventory;
+use crate::modules::verify::Verification;
+use crate::modules::ingest::IngestLag;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::time::Duration;
+use ureq::{Agent, AgentBuilder};
+
+/// Default Elasticsearch port used when the URL does not contain one
+const ELASTICSEARCH_DEFAULT_PORT: u16 = 9200;
+
+/// Time a request to Elasticsearch may take
+const ELASTICSEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variable an Elasticsearch API key is read from
+pub const API_KEY_VARIABLE: &str = "EDR_ELASTICSEARCH_API_KEY";
+
+/// Environment variable the Elasticsearch password is read from when the URL names a user
+/// without a password
+pub const PASSWORD_VARIABLE: &str = "EDR_ELASTICSEARCH_PASSWORD";
+
+/// Structure defining the outcome of a run, logged as the `Run Summary` record and optionally
+/// pushed to a reporting backend
+///
+/// # Parameters
+///
+/// - `timestamp`: time the run finished
+/// - `host`: hostname of the machine the run was executed on, `unknown` if it could not be read
+/// - `scenario`: path of the input scenario file, or a description of a generated scenario
+/// - `scenario_sha256`: SHA-256 digest of the input scenario file
+/// - `instructions`: number of instructions processed
+/// - `errors`: number of errors encountered
+/// - `skipped`: number of instructions which were not executed, see `skip_reasons`
+/// - `duration_seconds`: wall clock duration of the run
+/// - `interrupted`: whether the run was interrupted before every instruction was processed
+/// - `instruction_counts`: number of instructions processed by instruction name
+/// - `error_kinds`: number of errors encountered by error kind (e.g. `io`, `input_format`)
+/// - `skip_reasons`: number of instructions skipped by reason (e.g. `unsupported_platform`, `policy`)
+/// - `inventory`: operating system, domain, and security products of the host when the run started
+/// - `boot_uptime_seconds`: uptime of the host when a boot-time run (`--boot`) started
+/// - `verification`: expected events which were and were not generated, see `--verify`
+/// - `ingest_lag`: time events took to become searchable in the SIEM by event type, see `--ingest-lag`
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    #[serde(rename = "@timestamp")]
+    pub timestamp: String,
+    pub host: String,
+    pub scenario: String,
+    pub scenario_sha256: String,
+    pub instructions: usize,
+    pub errors: usize,
+    pub skipped: usize,
+    pub duration_seconds: f64,
+    pub interrupted: bool,
+    pub instruction_counts: BTreeMap<String, usize>,
+    pub error_kinds: BTreeMap<String, usize>,
+    pub skip_reasons: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<HostInventory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_uptime_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_lag: Option<BTreeMap<String, IngestLag>>,
+}
+
+impl RunSummary {
+    /// Instantiates a summary of a run which has just finished on this host, without skipped
+    /// instructions, per instruction, per error kind, or per skip reason counts, host inventory, boot uptime,
+    /// verification, or ingest lag
+    ///
+    /// # Parameters
+    ///
+    /// - `scenario`: path of the input scenario file, or a description of a generated scenario
+    /// - `scenario_sha256`: SHA-256 digest of the input scenario file
+    /// - `instructions`: number of instructions processed
+    /// - `errors`: number of errors encountered
+    /// - `duration`: wall clock duration of the run
+    /// - `interrupted`: whether the run was interrupted
+    ///
+    /// # Returns
+    ///
+    /// RunSummary instance
+    pub fn new(scenario: String, scenario_sha256: String, instructions: usize, errors: usize, duration: Duration, interrupted: bool) -> RunSummary {
+        RunSummary {
+            timestamp: get_time(),
+            host: whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_string()),
+            scenario,
+            scenario_sha256,
+            instructions,
+            errors,
+            skipped: 0,
+            duration_seconds: duration.as_secs_f64(),
+            interrupted,
+            instruction_counts: BTreeMap::new(),
+            error_kinds: BTreeMap::new(),
+            skip_reasons: BTreeMap::new(),
+            inventory: None,
+            boot_uptime_seconds: None,
+            verification: None,
+            ingest_lag: None,
+        }
+    }
+
+    /// Writes the summary to a file as a JSON document
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the file to write
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The file was written
+    /// - `Err`: The summary could not be serialized or the file could not be written
+    pub fn write(&self, path: &String) -> Result<(), GenerationError> {
+        match serde_json::to_string_pretty(self) {
+            Ok(inner) => Ok(fs::write(path, inner + "\n")?),
+            Err(_) => Err(GenerationError::new("logging".to_string(), "Unable to Serialize Run Summary".to_string()))
+        }
+    }
+
+    /// Formats the summary as the message of the `Run Summary` record
+    ///
+    /// # Returns
+    ///
+    /// Human readable description of the outcome
+    pub fn message(&self) -> String {
+        let mut message = format!("{} instruction(s) processed, {} error(s) encountered", self.instructions, self.errors);
+        if self.skipped > 0 {
+            message.push_str(&format!(", {} skipped", self.skipped));
+        }
+        message.push_str(&format!(", {:.3}s elapsed", self.duration_seconds));
+        if self.interrupted {
+            message.push_str(", interrupted");
+        }
+        if let Some(uptime) = self.boot_uptime_seconds {
+            message.push_str(&format!(", boot-time run started {}s after boot", uptime));
+        }
+        message
+    }
+}
+
+/// Structure defining an Elasticsearch (or OpenSearch) index run summaries are pushed to, so
+/// detection coverage over time can be charted in Kibana directly from generator output. Each
+/// summary is indexed as a new document over HTTP or HTTPS, authenticated with a user and password
+/// or an API key when the cluster has security enabled. The index (or index pattern) can also be
+/// searched, e.g. for the events of an EDR agent, see `--ingest-lag`.
+///
+/// # Parameters
+///
+/// - `base`: scheme, host, and port of the server, e.g. `https://es.lab.local:9200`
+/// - `index`: name of the index documents are added to
+/// - `authorization`: optional value of the `Authorization` header sent with every request
+/// - `agent`: HTTP client the requests are sent with
+pub struct ElasticsearchReporter {
+    base: String,
+    index: String,
+    authorization: Option<String>,
+    agent: Agent,
+}
+
+impl ElasticsearchReporter {
+    /// Parses the URL of an index. A user given in the URL is authenticated with the password
+    /// given in the URL, or read from `EDR_ELASTICSEARCH_PASSWORD`. Without a user, the API key
+    /// read from `EDR_ELASTICSEARCH_API_KEY` is sent when it is set.
+    ///
+    /// # Parameters
+    ///
+    /// - `url`: URL of the index as `[http[s]://][user[:password]@]host[:port]/<index>`
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: ElasticsearchReporter instance
+    /// - `Err`: The URL does not name an index
+    pub fn new(url: &str) -> Result<ElasticsearchReporter, GenerationError> {
+        let (scheme, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme == "http" || scheme == "https" => (scheme, rest),
+            Some(_) => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid index URL (http[s]://[user[:password]@]<host>[:port]/<index>)", url))),
+            None => ("http", url)
+        };
+        let mut parts = rest.splitn(2, '/');
+        let authority = parts.next().unwrap_or("");
+        let index = parts.next().unwrap_or("").trim_matches('/').to_string();
+        let (user, mut address) = match authority.rsplit_once('@') {
+            Some((user, address)) => (Some(user), address.to_string()),
+            None => (None, authority.to_string())
+        };
+        if address.is_empty() || index.is_empty() || index.contains('/') {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid index URL (http[s]://[user[:password]@]<host>[:port]/<index>)", url)));
+        }
+        // a bracketed IPv6 address contains colons without naming a port
+        if !address.rsplit(']').next().unwrap_or("").contains(':') {
+            address = format!("{}:{}", address, ELASTICSEARCH_DEFAULT_PORT);
+        }
+        let authorization = match user {
+            Some(user) => {
+                let credentials = match user.split_once(':') {
+                    Some(_) => user.to_string(),
+                    None => format!("{}:{}", user, env::var(PASSWORD_VARIABLE).unwrap_or_default())
+                };
+                Some(format!("Basic {}", BASE64.encode(credentials)))
+            }
+            None => env::var(API_KEY_VARIABLE).ok().filter(|key| !key.is_empty()).map(|key| format!("ApiKey {}", key))
+        };
+        // connections are not kept open, a connection closed by the server while idle would fail
+        // the next request
+        let agent = AgentBuilder::new().timeout(ELASTICSEARCH_TIMEOUT).max_idle_connections(0).build();
+        Ok(ElasticsearchReporter { base: format!("{}://{}", scheme, address), index, authorization, agent })
+    }
+
+    /// Sets the API key sent with every request, replacing any other credentials
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: API key, base64 encoded as returned by the `_security/api_key` API
+    pub fn set_api_key(&mut self, key: &str) {
+        self.authorization = Some(format!("ApiKey {}", key));
+    }
+
+    /// Indexes a run summary as a new document
+    ///
+    /// # Parameters
+    ///
+    /// - `summary`: summary of the run
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The document was indexed
+    /// - `Err`: The server could not be reached or rejected the document
+    pub fn push(&self, summary: &RunSummary) -> Result<(), GenerationError> {
+        let body = match serde_json::to_string(summary) {
+            Ok(inner) => inner,
+            Err(_) => return Err(GenerationError::new("logging".to_string(), "Unable to Serialize Run Summary".to_string()))
+        };
+        let (status, reason) = self.post("_doc", "application/json", &body)?;
+        if (200..300).contains(&status) {
+            return Ok(());
+        }
+        Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} rejected the run summary ({}): {}", self.base, status, reason)))
+    }
+
+    /// Counts the documents of the index matching a search
+    ///
+    /// # Parameters
+    ///
+    /// - `query`: body of a `_search` request, e.g. `{"query": {"match_phrase": {"file.path": "C:\\x.txt"}}}`
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Number of matching documents, as reported by `hits.total`
+    /// - `Err`: The server could not be reached or rejected the search
+    pub fn search(&self, query: &str) -> Result<u64, GenerationError> {
+        let (status, body) = self.post("_search", "application/json", query)?;
+        if !(200..300).contains(&status) {
+            return Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} rejected the search ({}): {}", self.base, status, body)));
+        }
+        let response: serde_json::Value = match serde_json::from_str(&body) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} returned an invalid search response: {}", self.base, e)))
+        };
+        Ok(hit_count(&response))
+    }
+
+    /// Counts the documents of the index matching each of several searches, sent as a single
+    /// `_msearch` request
+    ///
+    /// # Parameters
+    ///
+    /// - `queries`: bodies of `_search` requests
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Number of matching documents of each search, in the order of the queries, or the
+    ///   error of a search which is not valid JSON or was rejected
+    /// - `Err`: The server could not be reached or rejected the request
+    pub fn multi_search(&self, queries: &[&str]) -> Result<Vec<Result<u64, GenerationError>>, GenerationError> {
+        let mut results: Vec<Option<Result<u64, GenerationError>>> = Vec::new();
+        let mut body = String::new();
+        for query in queries {
+            // every search is a header line and a body line, so the body is compacted to one line
+            match serde_json::from_str::<serde_json::Value>(query) {
+                Ok(inner) => {
+                    body.push_str(&format!("{{}}\n{}\n", inner));
+                    results.push(None);
+                }
+                Err(e) => results.push(Some(Err(GenerationError::new("input_format".to_string(), format!("The search is not valid JSON: {}", e)))))
+            }
+        }
+        if body.is_empty() {
+            return Ok(results.into_iter().flatten().collect());
+        }
+        let (status, response) = self.post("_msearch", "application/x-ndjson", &body)?;
+        if !(200..300).contains(&status) {
+            return Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} rejected the search ({}): {}", self.base, status, response)));
+        }
+        let response: serde_json::Value = match serde_json::from_str(&response) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} returned an invalid search response: {}", self.base, e)))
+        };
+        let mut responses = response["responses"].as_array().cloned().unwrap_or_default().into_iter();
+        Ok(results.into_iter().map(|result| match result {
+            Some(inner) => inner,
+            None => match responses.next() {
+                Some(response) if response.get("error").is_none() => Ok(hit_count(&response)),
+                Some(response) => Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} rejected the search: {}", self.base, response["error"]))),
+                None => Err(GenerationError::new("network".to_string(), format!("Elasticsearch server {} returned fewer responses than searches", self.base)))
+            }
+        }).collect())
+    }
+
+    /// Sends a document to an endpoint of the index
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Status code and body of the response
+    /// - `Err`: The server could not be reached
+    fn post(&self, endpoint: &str, content_type: &str, body: &str) -> Result<(u16, String), GenerationError> {
+        let mut request = self.agent.post(&format!("{}/{}/{}", self.base, self.index, endpoint)).set("Content-Type", content_type);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        let response = match request.send_string(body) {
+            Ok(inner) | Err(ureq::Error::Status(_, inner)) => inner,
+            Err(e) => return Err(GenerationError::new("network".to_string(), format!("Unable to Connect to Elasticsearch server {}: {}", self.base, e)))
+        };
+        let status = response.status();
+        match response.into_string() {
+            Ok(body) => Ok((status, body)),
+            Err(e) => Err(GenerationError::new("network".to_string(), format!("Unable to read the response of Elasticsearch server {}: {}", self.base, e)))
+        }
+    }
+}
+
+/// Reads the number of matching documents from a search response
+fn hit_count(response: &serde_json::Value) -> u64 {
+    // Elasticsearch 7 and OpenSearch report {"value": n}, Elasticsearch 6 reports n
+    let total = &response["hits"]["total"];
+    let hits = response["hits"]["hits"].as_array().map_or(0, |hits| hits.len() as u64);
+    total["value"].as_u64().or_else(|| total.as_u64()).unwrap_or(hits).max(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+    use crate::modules::testing::read_request;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn sample_summary() -> RunSummary {
+        let mut summary = RunSummary::new(String::from("examples/windows_input.csv"), String::from("3f1e9c4b"), 13, 1, Duration::from_millis(4), false);
+        summary.instruction_counts = vec![(String::from("new_file"), 9), (String::from("process"), 4)].into_iter().collect();
+        summary.error_kinds = vec![(String::from("io"), 1)].into_iter().collect();
+        summary.skip_reasons = vec![(String::from("dependency"), 2)].into_iter().collect();
+        summary
+    }
+
+    /// Answers a single request with the given status line and returns the request
+    fn serve_once(status: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_request(&stream);
+            stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 2\r\n\r\n{{}}", status).as_bytes()).unwrap();
+            request
+        });
+        (address, server)
+    }
+
+    #[test]
+    fn summary_message() {
+        let mut summary = sample_summary();
+        assert_eq!(summary.message(), "13 instruction(s) processed, 1 error(s) encountered, 0.004s elapsed");
+        summary.interrupted = true;
+        assert!(summary.message().ends_with(", interrupted"));
+        summary.skipped = summary.skip_reasons.values().sum();
+        assert!(summary.message().starts_with("13 instruction(s) processed, 1 error(s) encountered, 2 skipped, 0.004s"));
+    }
+
+    #[test]
+    fn index_urls() {
+        let reporter = ElasticsearchReporter::new("http://es.lab.local/edr-runs/").unwrap();
+        assert_eq!(reporter.base, "http://es.lab.local:9200");
+        assert_eq!(reporter.index, "edr-runs");
+        let reporter = ElasticsearchReporter::new("https://elastic:changeme@[::1]/edr-runs").unwrap();
+        assert_eq!(reporter.base, "https://[::1]:9200");
+        assert_eq!(reporter.authorization.as_deref(), Some("Basic ZWxhc3RpYzpjaGFuZ2VtZQ=="));
+        assert_eq!(ElasticsearchReporter::new("es.lab.local:9243/edr-runs").unwrap().base, "http://es.lab.local:9243");
+        assert!(ElasticsearchReporter::new("es.lab.local:9200").is_err());
+        assert!(ElasticsearchReporter::new("ftp://es.lab.local/edr-runs").is_err());
+    }
+
+    #[test]
+    fn summary_indexed() {
+        let (address, server) = serve_once("201 Created");
+        let mut reporter = ElasticsearchReporter::new(&format!("http://{}/edr-runs", address)).unwrap();
+        reporter.set_api_key("VnVhQ2ZHY0JDZGJrUW0tZTVhT3g6dWkybHAyYXhUTm1zeWFrdzl0dk5udw==");
+        reporter.push(&sample_summary()).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /edr-runs/_doc HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: ApiKey VnVhQ2ZHY0JDZGJrUW0tZTVhT3g6dWkybHAyYXhUTm1zeWFrdzl0dk5udw==\r\n"));
+        assert!(request.contains("\"scenario\":\"examples/windows_input.csv\""));
+        assert!(request.contains("\"@timestamp\":"));
+
+        let (address, server) = serve_once("400 Bad Request");
+        let reporter = ElasticsearchReporter::new(&format!("http://{}/edr-runs", address)).unwrap();
+        assert!(reporter.push(&sample_summary()).is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn index_searched() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reporter = ElasticsearchReporter::new(&format!("http://{}/logs-*", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let responses = ["200 OK", "{\"hits\":{\"total\":{\"value\":2},\"hits\":[]}}", "200 OK", "{\"hits\":{\"total\":0,\"hits\":[]}}", "404 Not Found", "{}"];
+            let mut requests = vec![];
+            for response in responses.chunks(2) {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&stream));
+                stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", response[0], response[1].len(), response[1]).as_bytes()).unwrap();
+            }
+            requests
+        });
+        assert_eq!(reporter.search("{\"query\":{\"match_all\":{}}}").unwrap(), 2);
+        assert_eq!(reporter.search("{}").unwrap(), 0);
+        assert!(reporter.search("{}").is_err());
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /logs-*/_search HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with("{\"query\":{\"match_all\":{}}}"));
+    }
+
+    #[test]
+    fn index_multi_searched() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reporter = ElasticsearchReporter::new(&format!("http://{}/logs-*", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_request(&stream);
+            let body = "{\"responses\":[{\"hits\":{\"total\":{\"value\":3},\"hits\":[]},\"status\":200},{\"error\":{\"type\":\"parsing_exception\"},\"status\":400}]}";
+            stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap();
+            request
+        });
+        let results = reporter.multi_search(&["{\"query\":\n  {\"match_all\":{}}}", "not json", "{\"query\":{\"bad\":{}}}"]).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /logs-*/_msearch HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/x-ndjson\r\n"));
+        assert!(request.ends_with("\r\n\r\n{}\n{\"query\":{\"match_all\":{}}}\n{}\n{\"query\":{\"bad\":{}}}\n"));
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &3);
+        assert_eq!(results[1].as_ref().err().unwrap().kind, "input_format");
+        assert!(results[2].as_ref().err().unwrap().message.contains("parsing_exception"));
+        assert!(reporter.multi_search(&["not json"]).unwrap()[0].is_err());
+    }
+
+    #[test]
+    fn summary_written() {
+        let path = TempPath::new("summary.json");
+        sample_summary().write(&path.as_string()).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["instructions"], 13);
+        assert_eq!(written["instruction_counts"]["process"], 4);
+        assert_eq!(written["error_kinds"]["io"], 1);
+        assert_eq!(written["skip_reasons"]["dependency"], 2);
+        assert_eq!(written["duration_seconds"], 0.004);
+        assert!(written.get("inventory").is_none());
+        assert!(written.get("ingest_lag").is_none());
+    }
+}
@@ -0,0 +1,530 @@
+use crate::modules::common::GenerationError;
+use csv::StringRecord;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Structure defining a structured (JSON or YAML) scenario file
+///
+/// # Parameters
+///
+/// - `description`: optional free text describing the scenario, recorded in the run header
+/// - `steps`: ordered list of steps to execute
+#[derive(Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+/// Structure defining a single named step of a structured scenario. Unrecognized keys such as
+/// `comment` are ignored so steps may be annotated freely.
+///
+/// # Parameters
+///
+/// - `name`: optional name of the step, used to identify the step in error messages
+/// - `delay`: optional number of milliseconds to wait before executing the step
+/// - `instruction_timeout`: optional number of milliseconds the step may take before it is abandoned
+/// - `netns`: optional Linux network namespace a network step is executed in
+/// - `os`: optional platforms the step is restricted to (e.g. `windows` or `linux|macos`), it is
+///   skipped on all others
+/// - `save`: optional name the events logged by the step are saved as, so later steps can
+///   reference their fields as `${name.field}`
+/// - `shaping`: optional degraded network conditions a `connect`, `beacon`, or `exfil` step is
+///   executed under
+/// - `overrides`: log field values replacing those of the event logged by the step
+/// - `instruction`: instruction to execute along with its typed fields
+#[derive(Deserialize)]
+pub struct Step {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub delay: Option<u64>,
+    #[serde(default)]
+    pub instruction_timeout: Option<u64>,
+    #[serde(default)]
+    pub netns: Option<String>,
+    #[serde(default)]
+    pub os: Option<String>,
+    #[serde(default)]
+    pub save: Option<String>,
+    #[serde(default)]
+    pub shaping: Option<StepShaping>,
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub instruction: StepInstruction,
+}
+
+/// Structure defining the network conditions of a step, written as the `latency=`, `loss=`,
+/// `bandwidth=`, `loss_seed=`, and `connect_timeout=` parameters of the instruction
+///
+/// # Parameters
+///
+/// - `latency`: optional delay in milliseconds before the data is sent
+/// - `loss`: optional percentage of segments which are lost and retransmitted
+/// - `bandwidth`: optional rate in bytes per second the data is paced to
+/// - `seed`: optional seed deciding which segments are lost
+/// - `connect_timeout`: optional milliseconds after which each connection attempt is given up on
+#[derive(Deserialize)]
+pub struct StepShaping {
+    #[serde(default)]
+    pub latency: Option<u64>,
+    #[serde(default)]
+    pub loss: Option<f64>,
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+}
+
+/// Instructions supported by structured scenarios, selected by the `instruction` field of a step
+#[derive(Deserialize)]
+#[serde(tag = "instruction", rename_all = "snake_case")]
+pub enum StepInstruction {
+    Process {
+        path: String,
+        #[serde(default)]
+        arguments: Vec<String>,
+        #[serde(default)]
+        run_for: Option<u64>,
+        #[serde(default)]
+        stdin: Option<String>,
+        #[serde(default)]
+        capture: Option<usize>,
+        #[serde(default)]
+        alias: Option<String>,
+    },
+    ProcessWithParent {
+        parent: String,
+        path: String,
+        #[serde(default)]
+        arguments: Vec<String>,
+    },
+    ProcessInSession {
+        session: String,
+        path: String,
+        #[serde(default)]
+        arguments: Vec<String>,
+    },
+    ProcessStorm {
+        path: String,
+        count: u64,
+        interval: u64,
+        #[serde(default)]
+        arguments: Vec<String>,
+    },
+    NewFile { path: String },
+    ModFile { path: String },
+    DeleteFile { path: String },
+    Connect {
+        host: String,
+        port: u16,
+        message: String,
+        #[serde(default)]
+        retries: Option<u64>,
+        #[serde(default)]
+        backoff: Option<u64>,
+    },
+    ConnectSelf { message: String },
+    FanOut {
+        hosts: String,
+        ports: String,
+        message: String,
+        #[serde(default)]
+        concurrency: Option<usize>,
+        #[serde(default)]
+        hold: Option<u64>,
+    },
+    Beacon {
+        host: String,
+        port: u16,
+        interval: u64,
+        count: u64,
+        #[serde(default)]
+        size: Option<usize>,
+        #[serde(default)]
+        jitter: Option<f64>,
+    },
+    Listen {
+        interface: String,
+        port: u16,
+        #[serde(default)]
+        timeout: Option<u64>,
+    },
+    Exfil {
+        path: String,
+        host: String,
+        port: u16,
+        #[serde(default)]
+        protocol: Option<String>,
+    },
+    EncryptBurst {
+        directory: String,
+        count: u64,
+        #[serde(default)]
+        extension: Option<String>,
+    },
+    AdsWrite {
+        path: String,
+        stream: String,
+        #[serde(default)]
+        payload: Option<String>,
+    },
+    Symlink { target: String, link: String },
+    Hardlink { target: String, link: String },
+    ChmodFile { path: String, permissions: String },
+    PersistRunkey { name: String, command: String },
+    PersistStartup { name: String, command: String },
+    PersistCron { schedule: String, command: String },
+    PersistService { name: String, command: String },
+    ScheduleTask { name: String, trigger: String, command: String },
+    TamperService { service: String },
+    TamperFiles { directory: String },
+    TamperRename { path: String },
+    CreateUser { name: String },
+    DeleteUser { name: String },
+    AddToGroup { name: String, group: String },
+    WaitProcess {
+        target: String,
+        #[serde(default)]
+        timeout: Option<u64>,
+    },
+    Pause { duration: u64 },
+}
+
+impl Step {
+    /// Converts the step into the equivalent input record so it is processed exactly like a row of
+    /// a csv scenario. Process arguments are quoted so they are passed to the process unchanged.
+    ///
+    /// # Returns
+    ///
+    /// A StringRecord containing the instruction name followed by its parameters, the shaping,
+    /// `netns=<name>`, `timeout=<msec>`, `os=<platform>`, and `save=<name>` parameters, and any
+    /// `@field=value` overrides
+    pub fn to_record(&self) -> StringRecord {
+        let mut record = self.instruction_record();
+        if let Some(shaping) = &self.shaping {
+            if let Some(latency) = shaping.latency {
+                record.push_field(&format!("latency={}", latency));
+            }
+            if let Some(loss) = shaping.loss {
+                record.push_field(&format!("loss={}", loss));
+            }
+            if let Some(bandwidth) = shaping.bandwidth {
+                record.push_field(&format!("bandwidth={}", bandwidth));
+            }
+            if let Some(seed) = shaping.seed {
+                record.push_field(&format!("loss_seed={}", seed));
+            }
+            if let Some(connect_timeout) = shaping.connect_timeout {
+                record.push_field(&format!("connect_timeout={}", connect_timeout));
+            }
+        }
+        if let Some(name) = &self.netns {
+            record.push_field(&format!("netns={}", name));
+        }
+        if let Some(timeout) = self.instruction_timeout {
+            record.push_field(&format!("timeout={}", timeout));
+        }
+        if let Some(platforms) = &self.os {
+            record.push_field(&format!("os={}", platforms));
+        }
+        if let Some(name) = &self.save {
+            record.push_field(&format!("save={}", name));
+        }
+        for (name, value) in &self.overrides {
+            record.push_field(&format!("@{}={}", name, value));
+        }
+        record
+    }
+
+    fn instruction_record(&self) -> StringRecord {
+        match &self.instruction {
+            StepInstruction::Process { path, arguments, run_for, stdin, capture, alias } => {
+                let mut record = StringRecord::from(vec!["process", path]);
+                if !arguments.is_empty() {
+                    record.push_field(&join_arguments(arguments.iter().map(|argument| argument.as_str())));
+                }
+                if let Some(duration) = run_for {
+                    record.push_field(&format!("run_for={}", duration));
+                }
+                if let Some(input) = stdin {
+                    record.push_field(&format!("stdin={}", input));
+                }
+                if let Some(size) = capture {
+                    record.push_field(&format!("capture={}", size));
+                }
+                if let Some(name) = alias {
+                    record.push_field(&format!("alias={}", name));
+                }
+                record
+            }
+            StepInstruction::ProcessWithParent { parent, path, arguments } => {
+                let mut record = StringRecord::from(vec!["process_with_parent", parent, path]);
+                if !arguments.is_empty() {
+                    record.push_field(&join_arguments(arguments.iter().map(|argument| argument.as_str())));
+                }
+                record
+            }
+            StepInstruction::ProcessInSession { session, path, arguments } => {
+                let mut record = StringRecord::from(vec!["process_in_session", session, path]);
+                if !arguments.is_empty() {
+                    record.push_field(&join_arguments(arguments.iter().map(|argument| argument.as_str())));
+                }
+                record
+            }
+            StepInstruction::ProcessStorm { path, count, interval, arguments } => {
+                let mut record = StringRecord::from(vec!["process_storm".to_string(), path.clone(), count.to_string(), interval.to_string()]);
+                if !arguments.is_empty() {
+                    record.push_field(&join_arguments(arguments.iter().map(|argument| argument.as_str())));
+                }
+                record
+            }
+            StepInstruction::NewFile { path } => StringRecord::from(vec!["new_file", path]),
+            StepInstruction::ModFile { path } => StringRecord::from(vec!["mod_file", path]),
+            StepInstruction::DeleteFile { path } => StringRecord::from(vec!["delete_file", path]),
+            StepInstruction::Connect { host, port, message, retries, backoff } => {
+                let mut record = StringRecord::from(vec!["connect".to_string(), host.clone(), port.to_string(), message.clone()]);
+                if let Some(retries) = retries {
+                    record.push_field(&format!("retries={}", retries));
+                }
+                if let Some(backoff) = backoff {
+                    record.push_field(&format!("backoff={}", backoff));
+                }
+                record
+            }
+            StepInstruction::ConnectSelf { message } => StringRecord::from(vec!["connect_self", message]),
+            StepInstruction::FanOut { hosts, ports, message, concurrency, hold } => {
+                let mut record = StringRecord::from(vec!["fan_out", hosts, ports, message]);
+                if let Some(concurrency) = concurrency {
+                    record.push_field(&format!("concurrency={}", concurrency));
+                }
+                if let Some(hold) = hold {
+                    record.push_field(&format!("hold={}", hold));
+                }
+                record
+            }
+            StepInstruction::Beacon { host, port, interval, count, size, jitter } => {
+                let mut record = StringRecord::from(vec!["beacon".to_string(), host.clone(), port.to_string(), interval.to_string(), count.to_string()]);
+                if size.is_some() || jitter.is_some() {
+                    record.push_field(&size.map(|size| size.to_string()).unwrap_or_default());
+                }
+                if let Some(jitter) = jitter {
+                    record.push_field(&jitter.to_string());
+                }
+                record
+            }
+            StepInstruction::Listen { interface, port, timeout } => {
+                let mut record = StringRecord::from(vec!["listen".to_string(), interface.clone(), port.to_string()]);
+                if let Some(timeout) = timeout {
+                    record.push_field(&timeout.to_string());
+                }
+                record
+            }
+            StepInstruction::Exfil { path, host, port, protocol } => {
+                let mut record = StringRecord::from(vec!["exfil".to_string(), path.clone(), host.clone(), port.to_string()]);
+                if let Some(protocol) = protocol {
+                    record.push_field(protocol);
+                }
+                record
+            }
+            StepInstruction::EncryptBurst { directory, count, extension } => {
+                let mut record = StringRecord::from(vec!["encrypt_burst".to_string(), directory.clone(), count.to_string()]);
+                if let Some(extension) = extension {
+                    record.push_field(extension);
+                }
+                record
+            }
+            StepInstruction::AdsWrite { path, stream, payload } => {
+                let mut record = StringRecord::from(vec!["ads_write", path, stream]);
+                if let Some(payload) = payload {
+                    record.push_field(payload);
+                }
+                record
+            }
+            StepInstruction::Symlink { target, link } => StringRecord::from(vec!["symlink", target, link]),
+            StepInstruction::Hardlink { target, link } => StringRecord::from(vec!["hardlink", target, link]),
+            StepInstruction::ChmodFile { path, permissions } => StringRecord::from(vec!["chmod_file", path, permissions]),
+            StepInstruction::PersistRunkey { name, command } => StringRecord::from(vec!["persist_runkey", name, command]),
+            StepInstruction::PersistStartup { name, command } => StringRecord::from(vec!["persist_startup", name, command]),
+            StepInstruction::PersistCron { schedule, command } => StringRecord::from(vec!["persist_cron", schedule, command]),
+            StepInstruction::PersistService { name, command } => StringRecord::from(vec!["persist_service", name, command]),
+            StepInstruction::ScheduleTask { name, trigger, command } => StringRecord::from(vec!["schedule_task", name, trigger, command]),
+            StepInstruction::TamperService { service } => StringRecord::from(vec!["tamper_service", service]),
+            StepInstruction::TamperFiles { directory } => StringRecord::from(vec!["tamper_files", directory]),
+            StepInstruction::TamperRename { path } => StringRecord::from(vec!["tamper_rename", path]),
+            StepInstruction::CreateUser { name } => StringRecord::from(vec!["create_user", name]),
+            StepInstruction::DeleteUser { name } => StringRecord::from(vec!["delete_user", name]),
+            StepInstruction::AddToGroup { name, group } => StringRecord::from(vec!["add_to_group", name, group]),
+            StepInstruction::WaitProcess { target, timeout } => {
+                let mut record = StringRecord::from(vec!["wait_process", target]);
+                if let Some(duration) = timeout {
+                    record.push_field(&duration.to_string());
+                }
+                record
+            }
+            StepInstruction::Pause { duration } => StringRecord::from(vec!["pause".to_string(), duration.to_string()]),
+        }
+    }
+}
+
+/// Quotes the arguments of a process into a single field which splits back into the same
+/// arguments when the process is started
+///
+/// # Parameters
+///
+/// - `arguments`: arguments of the process
+///
+/// # Returns
+///
+/// The arguments as a single field
+pub(crate) fn join_arguments<'a>(arguments: impl IntoIterator<Item = &'a str>) -> String {
+    let arguments: Vec<&str> = arguments.into_iter().collect();
+    // an argument containing a nul byte can not be quoted, nor passed to a process, it is kept as
+    // written so starting the process reports it
+    shlex::try_join(arguments.iter().copied()).unwrap_or_else(|_| arguments.join(" "))
+}
+
+/// Determines if a scenario file uses the structured format based on its file extension
+///
+/// # Parameters
+///
+/// - `path`: path of the scenario file
+///
+/// # Returns
+///
+/// True for `.json`, `.yaml`, and `.yml` files, false otherwise
+pub fn is_structured(path: &String) -> bool {
+    match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => matches!(extension.to_lowercase().as_str(), "json" | "yaml" | "yml"),
+        None => false
+    }
+}
+
+/// Reads and parses a structured scenario file. The parser is selected by the file extension.
+///
+/// # Parameters
+///
+/// - `path`: path of the scenario file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The parsed scenario
+/// - `Err`: The file could not be read or is not a valid scenario
+pub fn load(path: &String) -> Result<Scenario, GenerationError> {
+    parse(path, &fs::read_to_string(path)?)
+}
+
+/// Parses the contents of a structured scenario file. The parser is selected by the file extension.
+///
+/// # Parameters
+///
+/// - `path`: path of the scenario file
+/// - `contents`: contents of the scenario file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The parsed scenario
+/// - `Err`: The contents are not a valid scenario
+pub fn parse(path: &String, contents: &str) -> Result<Scenario, GenerationError> {
+    let is_json = path.to_lowercase().ends_with(".json");
+    let parsed = if is_json {
+        serde_json::from_str::<Scenario>(contents).map_err(|e| e.to_string())
+    } else {
+        serde_yaml::from_str::<Scenario>(contents).map_err(|e| e.to_string())
+    };
+    match parsed {
+        Ok(scenario) => Ok(scenario),
+        Err(e) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid scenario: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shlex::Shlex;
+
+    #[test]
+    fn structured_extensions() {
+        assert!(is_structured(&String::from("tests/good_test.json")));
+        assert!(is_structured(&String::from("scenario.YML")));
+        assert!(!is_structured(&String::from("tests/good_test.csv")));
+        assert!(!is_structured(&String::from("scenario")));
+    }
+
+    #[test]
+    fn yaml_steps() {
+        let scenario: Scenario = serde_yaml::from_str("
+steps:
+  # arguments containing the csv deliminator are kept intact
+  - name: list
+    comment: quoted by the generator
+    instruction: process
+    path: cmd
+    arguments: ['/c', 'echo a,b c']
+    run_for: 500
+    stdin: |
+      whoami
+    capture: 4096
+    save: listing
+    delay: 20
+  - instruction: connect
+    host: 127.0.0.1
+    port: 8080
+    message: hello, world
+    retries: 3
+    shaping: {latency: 200, loss: 2.5}
+    netns: dmz
+    instruction_timeout: 2000
+    os: linux|macos
+    overrides:
+      protocol: HTTPS
+      activity: Beacon
+").unwrap();
+        assert_eq!(scenario.steps.len(), 2);
+        assert_eq!(scenario.steps[0].name.as_deref(), Some("list"));
+        assert_eq!(scenario.steps[0].delay, Some(20));
+        let record = scenario.steps[0].to_record();
+        assert_eq!(Shlex::new(&record[2]).collect::<Vec<String>>(), vec!["/c", "echo a,b c"]);
+        assert_eq!(record.iter().skip(3).collect::<Vec<&str>>(), vec!["run_for=500", "stdin=whoami\n", "capture=4096", "save=listing"]);
+        assert_eq!(scenario.steps[1].to_record(), vec!["connect", "127.0.0.1", "8080", "hello, world", "retries=3", "latency=200", "loss=2.5", "netns=dmz", "timeout=2000", "os=linux|macos", "@activity=Beacon", "@protocol=HTTPS"]);
+    }
+
+    #[test]
+    fn process_storm_step() {
+        let scenario: Scenario = serde_json::from_str(r#"{"steps": [{"instruction": "process_storm", "path": "sh", "count": 50, "interval": 2, "arguments": ["-c", "exit"]}]}"#).unwrap();
+        assert_eq!(scenario.steps[0].to_record(), vec!["process_storm", "sh", "50", "2", "-c exit"]);
+        let scenario: Scenario = serde_json::from_str(r#"{"steps": [{"instruction": "process_in_session", "session": "alice", "path": "cmd", "arguments": ["/c", "whoami"]}]}"#).unwrap();
+        assert_eq!(scenario.steps[0].to_record(), vec!["process_in_session", "alice", "cmd", "/c whoami"]);
+    }
+
+    #[test]
+    fn wait_process_step() {
+        let scenario: Scenario = serde_json::from_str(r#"{"steps": [{"instruction": "process", "path": "sh", "arguments": ["-c", "sleep 1"], "alias": "slow"}, {"instruction": "wait_process", "target": "slow", "timeout": 5000}]}"#).unwrap();
+        let record = scenario.steps[0].to_record();
+        assert_eq!(Shlex::new(&record[2]).collect::<Vec<String>>(), vec!["-c", "sleep 1"]);
+        assert_eq!(record[3].to_string(), "alias=slow");
+        assert_eq!(scenario.steps[1].to_record(), vec!["wait_process", "slow", "5000"]);
+    }
+
+    #[test]
+    fn arguments_joined() {
+        let arguments = vec!["/c", "echo \"a b\"", "it's", "", "$HOME"];
+        assert_eq!(Shlex::new(&join_arguments(arguments.clone())).collect::<Vec<String>>(), arguments);
+        assert_eq!(join_arguments(vec!["-c", "exit"]), "-c exit");
+    }
+
+    #[test]
+    fn invalid_step() {
+        assert!(serde_json::from_str::<Scenario>(r#"{"steps": [{"instruction": "pause", "duration": "soon"}]}"#).is_err());
+        assert!(serde_json::from_str::<Scenario>(r#"{"steps": [{"instruction": "teleport"}]}"#).is_err());
+    }
+}
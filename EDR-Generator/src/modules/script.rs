@@ -0,0 +1,427 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use csv::{ReaderBuilder, StringRecord};
+use rand::Rng;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Maximum depth of nested `include` rows, guards against files including each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A parsed element of a csv scenario
+///
+/// # Variants
+///
+/// - `Row`: a single instruction row
+/// - `Repeat`: a block of elements executed `count` times
+pub enum Block {
+    Row(StringRecord),
+    Repeat { count: u64, body: Rc<Vec<Block>> },
+}
+
+/// Structure defining a position within a block list while iterating a script
+///
+/// # Parameters
+///
+/// - `blocks`: elements of the block being executed
+/// - `position`: index of the next element to execute
+/// - `remaining`: number of iterations of the block left, including the current one
+/// - `index`: zero based iteration of the block currently executing
+struct Frame {
+    blocks: Rc<Vec<Block>>,
+    position: usize,
+    remaining: u64,
+    index: u64,
+}
+
+/// Structure defining a csv scenario with scripting constructs. Rows are produced one at a time so
+/// large `repeat` blocks are never expanded in memory.
+///
+/// # Parameters
+///
+/// - `stack`: frames of the blocks currently being executed, innermost last
+/// - `files`: path and contents of the scenario file and every included file, as they were parsed
+pub struct Script {
+    stack: Vec<Frame>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Script {
+    /// Parses a csv scenario, resolving `include` rows and `repeat`/`end` blocks
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the csv scenario file
+    /// - `deliminator`: deliminator used by the scenario file and every included file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Script instance positioned at the first row
+    /// - `Err`: A file could not be read, or a scripting row is malformed
+    pub fn new(path: &String, deliminator: u8) -> Result<Script, GenerationError> {
+        let mut files = Vec::new();
+        let blocks = parse_file(path, deliminator, 0, &mut files)?;
+        Ok(Script {
+            stack: vec![Frame { blocks: Rc::new(blocks), position: 0, remaining: 1, index: 0 }],
+            files,
+        })
+    }
+
+    /// Retrieves the files the script was parsed from, e.g. for verifying their signatures
+    ///
+    /// # Returns
+    ///
+    /// The path and contents of the scenario file followed by every included file, in the order
+    /// they were read
+    pub fn files(&self) -> &[(String, Vec<u8>)] {
+        &self.files
+    }
+
+    /// Retrieves the next instruction row of the script
+    ///
+    /// # Returns
+    ///
+    /// The next row along with the iteration index of its innermost `repeat` block (0 outside of a
+    /// block), or None when the script is complete
+    pub fn next_row(&mut self) -> Option<(StringRecord, u64)> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            if frame.position >= frame.blocks.len() {
+                if frame.remaining > 1 {
+                    frame.remaining -= 1;
+                    frame.index += 1;
+                    frame.position = 0;
+                } else {
+                    self.stack.pop();
+                }
+                continue;
+            }
+            let blocks = frame.blocks.clone();
+            frame.position += 1;
+            match &blocks[frame.position - 1] {
+                Block::Row(record) => return Some((record.clone(), frame.index)),
+                Block::Repeat { count, body } => {
+                    if *count > 0 {
+                        self.stack.push(Frame { blocks: body.clone(), position: 0, remaining: *count, index: 0 });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads a csv scenario file into a list of blocks
+///
+/// # Parameters
+///
+/// - `path`: path of the csv scenario file
+/// - `deliminator`: deliminator used by the file
+/// - `depth`: number of `include` rows that led to this file
+/// - `files`: path and contents of every file read so far, this file is added to it
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The top level blocks of the file
+/// - `Err`: The file could not be read, or a scripting row is malformed
+fn parse_file(path: &String, deliminator: u8, depth: usize, files: &mut Vec<(String, Vec<u8>)>) -> Result<Vec<Block>, GenerationError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(GenerationError::new("input_format".to_string(), format!("Include depth exceeded at {} (are files including each other?)", path)));
+    }
+    // the file is read once, so the rows parsed are exactly the contents recorded for verification
+    let contents = match fs::read(path) {
+        Ok(inner) => inner,
+        Err(e) => return Err(GenerationError::new("io".to_string(), format!("The following error was encountered when attempting to open {} for processing: {}", path, e)))
+    };
+    files.push((path.clone(), contents.clone()));
+    let mut reader = ReaderBuilder::new().delimiter(deliminator).has_headers(false).flexible(true).from_reader(&contents[..]);
+    // stack of open blocks, the bottom entry holds the top level rows of the file
+    let mut open: Vec<(u64, Vec<Block>)> = vec![(1, Vec::new())];
+    for result in reader.records() {
+        let record = match result {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("Unable to read {}: {}", path, e)))
+        };
+        match record.get(0).unwrap_or("").trim() {
+            "repeat" => {
+                let count = match record.get(1).map(|count| count.trim().parse::<u64>()) {
+                    Some(Ok(inner)) => inner,
+                    _ => return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} in {} is not formatted correctly for a repeat (repeat,<count>)", record, path)))
+                };
+                open.push((count, Vec::new()));
+            }
+            "end" => {
+                if open.len() < 2 {
+                    return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} in {} has no matching repeat", record, path)));
+                }
+                let (count, body) = open.pop().unwrap();
+                open.last_mut().unwrap().1.push(Block::Repeat { count, body: Rc::new(body) });
+            }
+            "include" => {
+                let target = match record.get(1) {
+                    Some(inner) if !inner.trim().is_empty() => resolve_include(path, inner.trim()),
+                    _ => return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} in {} is not formatted correctly for an include (include,<path>)", record, path)))
+                };
+                let included = parse_file(&target, deliminator, depth + 1, files)?;
+                open.last_mut().unwrap().1.extend(included);
+            }
+            _ => open.last_mut().unwrap().1.push(Block::Row(record)),
+        }
+    }
+    if open.len() > 1 {
+        return Err(GenerationError::new("input_format".to_string(), format!("{} has {} repeat block(s) without a matching end", path, open.len() - 1)));
+    }
+    Ok(open.pop().unwrap().1)
+}
+
+/// Resolves the path of an included file relative to the directory of the including file
+fn resolve_include(parent: &String, target: &str) -> String {
+    let target_path = Path::new(target);
+    if target_path.is_absolute() {
+        return target.to_string();
+    }
+    match Path::new(parent).parent() {
+        Some(directory) => directory.join(target_path).to_string_lossy().to_string(),
+        None => target.to_string()
+    }
+}
+
+/// Replaces `${NAME}` variables in every field of a record. The built in variables are `TMP`
+/// (system temporary directory), `RANDOM` (new random number for every occurrence), `HOSTNAME`,
+/// and `INDEX` (iteration of the innermost repeat block). Any other name is looked up in the
+/// environment and left untouched if it is not set.
+///
+/// `TEMP`, `TMP`, and `TMPDIR` are the system temporary directory, `HOME` and `USERPROFILE` the
+/// home directory of the user, and `USER` and `USERNAME` the user name, whether or not the
+/// platform sets them. The temporary directory may also be written Windows style (`%TEMP%`,
+/// `%TMP%`) or Unix style (`$TMPDIR`, `$TMP`), so one scenario can name paths on every platform.
+/// Any other `%NAME%` or `$NAME` is left for the shell of a process to expand.
+///
+/// # Parameters
+///
+/// - `record`: record containing variables
+/// - `index`: iteration of the innermost repeat block
+///
+/// # Returns
+///
+/// A copy of the record with all known variables replaced
+pub fn substitute(record: &StringRecord, index: u64) -> StringRecord {
+    record.iter().map(|field| substitute_field(field, index)).collect()
+}
+
+/// Replaces `${name.field}` references to the saved result of an earlier instruction (see
+/// `save=<name>`) with the value of a field of the events it logged, e.g. `${shell.proc_id}`.
+/// References to results which were not saved are left untouched.
+///
+/// # Parameters
+///
+/// - `record`: record containing references
+/// - `results`: events logged by the instructions which saved their result, by name
+///
+/// # Returns
+///
+/// A copy of the record with all saved results replaced
+pub fn substitute_results(record: &StringRecord, results: &HashMap<String, Log>) -> StringRecord {
+    record.iter().map(|field| {
+        let mut result = String::new();
+        let mut rest = field;
+        while let Some(start) = rest.find("${") {
+            let end = match rest[start..].find('}') {
+                Some(inner) => start + inner,
+                None => break
+            };
+            result.push_str(&rest[..start]);
+            let value = rest[start + 2..end].split_once('.')
+                .and_then(|(name, field)| results.get(name).and_then(|log| log.field(field)));
+            match value {
+                Some(value) => result.push_str(value),
+                None => result.push_str(&rest[start..=end])
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        result
+    }).collect()
+}
+
+/// Finds the names of the saved results a record references, see `substitute_results`
+///
+/// # Parameters
+///
+/// - `record`: record containing references
+///
+/// # Returns
+///
+/// The names of the results referenced with a valid field, in the order they appear
+pub fn referenced_results(record: &StringRecord) -> Vec<String> {
+    let mut names = Vec::new();
+    for field in record.iter() {
+        let mut rest = field;
+        while let Some(start) = rest.find("${") {
+            let end = match rest[start..].find('}') {
+                Some(inner) => start + inner,
+                None => break
+            };
+            if let Some((name, field)) = rest[start + 2..end].split_once('.') {
+                if is_result_name(name) && Log::is_field(field) {
+                    names.push(name.to_string());
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    names
+}
+
+/// Determines if a name may be used to save the result of an instruction: letters, digits, and
+/// underscores only
+pub fn is_result_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Variables naming the temporary directory, the only variables expanded when written as `%NAME%`
+/// or `$NAME`
+const TEMP_VARIABLES: [&str; 3] = ["TEMP", "TMP", "TMPDIR"];
+
+fn substitute_field(field: &str, index: u64) -> String {
+    let mut result = String::new();
+    let mut rest = field;
+    while let Some(start) = rest.find(['$', '%']) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match expand(rest, index) {
+            Some((value, length)) => {
+                result.push_str(&value);
+                rest = &rest[length..];
+            }
+            None => {
+                result.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Expands the variable at the start of a field, written as `${NAME}`, `$NAME`, or `%NAME%`
+///
+/// # Returns
+///
+/// The value of the variable and the length of the reference, None if it is not a known variable
+fn expand(reference: &str, index: u64) -> Option<(String, usize)> {
+    if let Some(rest) = reference.strip_prefix("${") {
+        let end = rest.find('}')?;
+        return variable(&rest[..end], index).map(|value| (value, end + 3));
+    }
+    let rest = &reference[1..];
+    let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    let name = &rest[..length];
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if !TEMP_VARIABLES.contains(&name) {
+        return None;
+    }
+    match reference.starts_with('%') {
+        true if rest[length..].starts_with('%') => Some((temp_directory(), length + 2)),
+        true => None,
+        false => Some((temp_directory(), length + 1))
+    }
+}
+
+fn variable(name: &str, index: u64) -> Option<String> {
+    match name {
+        "TMP" => Some(temp_directory()),
+        "RANDOM" => Some(rand::thread_rng().gen::<u32>().to_string()),
+        // the HOSTNAME environment variable, if any, is used when the host name can not be read
+        "HOSTNAME" => whoami::fallible::hostname().ok().or_else(|| environment(name)),
+        "INDEX" => Some(index.to_string()),
+        _ => environment(name)
+    }
+}
+
+/// Looks up an environment variable, giving the variables naming the temporary directory, home
+/// directory, and user name their value on every platform
+fn environment(name: &str) -> Option<String> {
+    match name {
+        "TEMP" | "TMP" | "TMPDIR" => Some(temp_directory()),
+        "HOME" | "USERPROFILE" => env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok(),
+        "USER" | "USERNAME" => Some(whoami::username()),
+        _ => env::var(name).ok()
+    }
+}
+
+fn temp_directory() -> String {
+    env::temp_dir().to_string_lossy().trim_end_matches(['/', '\\']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    fn rows(script: &mut Script) -> Vec<String> {
+        let mut rows = vec![];
+        while let Some((record, index)) = script.next_row() {
+            rows.push(format!("{}:{}", &record[0], index));
+        }
+        rows
+    }
+
+    #[test]
+    fn nested_repeat() {
+        let inner = Block::Repeat { count: 2, body: Rc::new(vec![Block::Row(StringRecord::from(vec!["b"]))]) };
+        let outer = Block::Repeat { count: 2, body: Rc::new(vec![Block::Row(StringRecord::from(vec!["a"])), inner]) };
+        let empty = Block::Repeat { count: 0, body: Rc::new(vec![Block::Row(StringRecord::from(vec!["never"]))]) };
+        let mut script = Script { stack: vec![Frame { blocks: Rc::new(vec![outer, empty, Block::Row(StringRecord::from(vec!["c"]))]), position: 0, remaining: 1, index: 0 }], files: vec![] };
+        assert_eq!(rows(&mut script), vec!["a:0", "b:0", "b:1", "a:1", "b:0", "b:1", "c:0"]);
+    }
+
+    #[test]
+    fn include_and_repeat_file() {
+        let mut script = Script::new(&String::from("tests/script_test.csv"), b',').unwrap();
+        assert_eq!(script.files().len(), 2);
+        assert_eq!(rows(&mut script).len(), 11);
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        let path = TempPath::new("script.csv");
+        std::fs::write(&path, "repeat,2\npause,1\n").unwrap();
+        assert!(Script::new(&path.as_string(), b',').is_err());
+        std::fs::write(&path, "pause,1\nend\n").unwrap();
+        assert!(Script::new(&path.as_string(), b',').is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let record = substitute(&StringRecord::from(vec!["new_file", "${TMP}/file_${INDEX}.txt", "${NOT_A_VARIABLE_X24}", "${RANDOM"]), 7);
+        assert_eq!(&record[1], format!("{}/file_7.txt", env::temp_dir().to_string_lossy().trim_end_matches(['/', '\\'])));
+        assert_eq!(&record[2], "${NOT_A_VARIABLE_X24}");
+        assert_eq!(&record[3], "${RANDOM");
+        assert!(substitute(&StringRecord::from(vec!["${RANDOM}"]), 0)[0].parse::<u32>().is_ok());
+        let temp = temp_directory();
+        let record = substitute(&StringRecord::from(vec!["%TEMP%\\a.txt", "$TMPDIR/b.txt", "100% of %NOT_A_VARIABLE_X24%", "$5 or $NOT_A_VARIABLE_X24", "${USERNAME}-${USER}"]), 0);
+        assert_eq!(&record[0], format!("{}\\a.txt", temp));
+        assert_eq!(&record[1], format!("{}/b.txt", temp));
+        assert_eq!(&record[2], "100% of %NOT_A_VARIABLE_X24%");
+        assert_eq!(&record[3], "$5 or $NOT_A_VARIABLE_X24");
+        assert_eq!(&record[4], format!("{}-{}", whoami::username(), whoami::username()));
+        // variables in arguments are expanded by the shell of the process, not by the generator
+        let record = substitute(&StringRecord::from(vec!["process", "sh", "-c \"echo $HOME $PATH\"", "stdin=echo %PATH% %USERNAME%"]), 0);
+        assert_eq!(&record[2], "-c \"echo $HOME $PATH\"");
+        assert_eq!(&record[3], "stdin=echo %PATH% %USERNAME%");
+        let mut results = HashMap::new();
+        results.insert("shell".to_string(), Log { proc_id: "4242".to_string(), ..Log::default() });
+        let record = StringRecord::from(vec!["wait_process", "${shell.proc_id}", "${other.proc_id}${shell.colour}"]);
+        assert_eq!(referenced_results(&record), vec!["shell", "other"]);
+        assert_eq!(substitute_results(&record, &results), vec!["wait_process", "4242", "${other.proc_id}${shell.colour}"]);
+    }
+}
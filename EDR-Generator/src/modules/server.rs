@@ -0,0 +1,538 @@
+use crate::modules::alias::Aliases;
+use crate::modules::commander::TaskCommander;
+use crate::modules::common::GenerationError;
+use crate::modules::events::{EventBus, RunEvent};
+use crate::modules::formatter::LogFormat;
+use crate::modules::logger::{Logger, OutputOptions};
+use crate::modules::policy::Policy;
+use crate::modules::sink::{Sink, StreamSink};
+use crate::modules::workdir::WorkDir;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// Default address the remote control server listens on
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:7878";
+
+/// Environment variable holding the token controllers must send when `--token` is not given
+pub const TOKEN_VARIABLE: &str = "EDR_SERVE_TOKEN";
+
+/// Default time a controller may stay silent before its connection is closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Time a controller has to send its token once connected
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest token line read from a controller
+const MAX_TOKEN_LINE: usize = 1024;
+
+/// Structure defining the remote control server. Each TCP connection is a run: csv instructions
+/// sent over the connection are executed as they arrive, and every record logged by the run is
+/// written back over the same connection. The run ends, and its summary is sent, once the client
+/// closes its side of the connection, or once it has sent nothing for the idle timeout, so a silent
+/// controller can not hold the server. Connections are served one at a time. With a token, the
+/// first line of every connection must be `token,<token>` or the connection is closed unserved.
+///
+/// # Parameters
+///
+/// - `listener`: socket accepting controller connections
+/// - `out_file`: output file path, each run is logged to a numbered copy of it
+/// - `format`: format of the records logged and sent back
+/// - `deliminator`: deliminator used by the instructions
+/// - `track_descendants`: whether processes started by child processes are tracked
+/// - `policy`: optional policy restricting the instructions each controller may trigger
+/// - `aliases`: alternative names of instructions controllers may send
+/// - `token`: optional secret controllers must send before their instructions
+/// - `workdir`: optional directory a working directory is created in for every run
+/// - `cleanup`: whether persistence artifacts are removed at the end of every run
+/// - `write_ahead`: whether destructive instructions of every run are logged before and after they
+///   are executed
+/// - `instruction_timeout`: optional time any instruction of a run may take
+/// - `output_options`: flushing and rotation of the output file of every run
+/// - `idle_timeout`: time a controller may send nothing before its run ends, or leave the records
+///   sent back unread before they are no longer sent
+/// - `events`: channel the events of every run are reported over
+/// - `runs`: number of connections served so far
+pub struct Server {
+    listener: TcpListener,
+    out_file: String,
+    format: LogFormat,
+    deliminator: u8,
+    track_descendants: bool,
+    policy: Option<Policy>,
+    aliases: Aliases,
+    token: Option<String>,
+    workdir: Option<String>,
+    cleanup: bool,
+    write_ahead: bool,
+    instruction_timeout: Option<Duration>,
+    output_options: OutputOptions,
+    idle_timeout: Duration,
+    events: EventBus,
+    runs: usize,
+}
+
+impl Server {
+    /// Starts listening for controller connections
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: address to listen on as `host:port`
+    /// - `out_file`: output file path, run `n` is logged to `<name>_<n>.<extension>`
+    /// - `format`: format of the records logged and sent back
+    /// - `deliminator`: deliminator used by the instructions
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Server instance
+    /// - `Err`: The address is invalid or already in use
+    pub fn bind(address: &str, out_file: &str, format: LogFormat, deliminator: u8) -> Result<Server, GenerationError> {
+        let listener = match TcpListener::bind(address) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("network".to_string(), format!("Unable to listen on {}: {}", address, e)))
+        };
+        Ok(Server {
+            listener,
+            out_file: out_file.to_string(),
+            format,
+            deliminator,
+            track_descendants: false,
+            policy: None,
+            aliases: Aliases::default(),
+            token: None,
+            workdir: None,
+            cleanup: false,
+            write_ahead: false,
+            instruction_timeout: None,
+            output_options: OutputOptions::default(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            events: EventBus::new(),
+            runs: 0,
+        })
+    }
+
+    /// Tracks processes started by child processes in every run, see `--track-descendants`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_track_descendants(&mut self) {
+        self.track_descendants = true;
+    }
+
+    /// Removes the persistence artifacts created by every run once it ends, see `--cleanup`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_cleanup(&mut self) {
+        self.cleanup = true;
+    }
+
+    /// Logs destructive instructions of every run before and after they are executed, see `--write-ahead`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_write_ahead(&mut self) {
+        self.write_ahead = true;
+    }
+
+    /// Abandons the instructions of every run which do not complete in time, see `--instruction-timeout`
+    ///
+    /// # Parameters
+    ///
+    /// - `timeout`: time any instruction may take
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_instruction_timeout(&mut self, timeout: Duration) {
+        self.instruction_timeout = Some(timeout);
+    }
+
+    /// Flushes and rotates the output file of every run, see `--flush-interval` and `--max-log-size`
+    ///
+    /// # Parameters
+    ///
+    /// - `options`: flush interval, rotation size, and compression of rotated files
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_output_options(&mut self, options: OutputOptions) {
+        self.output_options = options;
+    }
+
+    /// Ends the run of a controller which sends nothing for longer than a timeout, and stops sending
+    /// records back to one which does not read them for as long, see `serve --idle-timeout`
+    ///
+    /// # Parameters
+    ///
+    /// - `timeout`: time a controller may stay silent
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Runs every connection in a working directory of its own, see `--workdir`
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: directory the working directories are created in
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_workdir(&mut self, base: &str) {
+        self.workdir = Some(base.to_string());
+    }
+
+    /// Restricts the instructions controllers may trigger, see `serve --policy`
+    ///
+    /// # Parameters
+    ///
+    /// - `policy`: policy resolving the role of each controller from its address
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = Some(policy);
+    }
+
+    /// Resolves instructions named by an alias in every run, see `--aliases`
+    ///
+    /// # Parameters
+    ///
+    /// - `aliases`: alternative names of instructions
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_aliases(&mut self, aliases: Aliases) {
+        self.aliases = aliases;
+    }
+
+    /// Requires controllers to send a shared secret before their instructions, see `serve --token`
+    ///
+    /// # Parameters
+    ///
+    /// - `token`: secret controllers must send as the first line, `token,<token>`
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    /// Determines if controllers on other hosts can connect, the server does not listen on a
+    /// loopback address
+    pub fn is_exposed(&self) -> bool {
+        self.listener.local_addr().map(|address| !address.ip().is_loopback()).unwrap_or(true)
+    }
+
+    /// Subscribes to the events of every run served from now on, preceded by a `Connected` event
+    /// naming the controller of the run
+    ///
+    /// # Returns
+    ///
+    /// Receiver of the events
+    pub fn subscribe(&mut self) -> Receiver<RunEvent> {
+        self.events.subscribe()
+    }
+
+    /// Retrieves the address the server is listening on
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The local address of the listener
+    /// - `Err`: The address could not be determined
+    pub fn local_addr(&self) -> Result<SocketAddr, GenerationError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves connections until the process is stopped. A connection which could not be served is
+    /// reported as an `Error` event and does not stop the server.
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn run(&mut self) {
+        loop {
+            if let Err(e) = self.serve_one() {
+                self.events.emit(RunEvent::Error(e));
+            }
+        }
+    }
+
+    /// Waits for a connection and executes the instructions it sends until it is closed
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: Address of the controller, number of instructions processed, and number of errors
+    /// - `Err`: The connection could not be accepted or set up (e.g. its working directory could not
+    ///   be created), or the controller did not send the token
+    pub fn serve_one(&mut self) -> Result<(SocketAddr, usize, usize), GenerationError> {
+        let (mut stream, peer) = self.listener.accept()?;
+        stream.set_read_timeout(Some(self.idle_timeout))?;
+        stream.set_write_timeout(Some(self.idle_timeout))?;
+        if let Some(token) = &self.token {
+            if let Err(e) = authenticate(&mut stream, token, self.deliminator, self.idle_timeout) {
+                let _ = stream.write_all(format!("{}\n", e.message).as_bytes());
+                return Err(GenerationError::new(e.kind, format!("Connection from {} refused: {}", peer, e.message)));
+            }
+        }
+        self.runs += 1;
+        self.events.emit(RunEvent::Connected(peer));
+        let mut logger = Logger::new(&run_output(&self.out_file, self.runs), self.format.clone());
+        logger.set_output_options(self.output_options);
+        logger.add_sink(Sink::Stream(StreamSink::from_writer(peer.to_string(), Box::new(stream.try_clone()?))));
+        let reader = IdleStream { stream, timeout: self.idle_timeout };
+        let mut commander = TaskCommander::from_stream(format!("tcp://{}", peer), Box::new(reader), self.deliminator, logger);
+        commander.set_events(self.events.clone());
+        commander.set_aliases(self.aliases.clone());
+        if self.track_descendants {
+            commander.set_track_descendants();
+        }
+        if self.cleanup {
+            commander.set_cleanup();
+        }
+        if self.write_ahead {
+            commander.set_write_ahead();
+        }
+        if let Some(timeout) = self.instruction_timeout {
+            commander.set_instruction_timeout(timeout);
+        }
+        if let Some(policy) = &self.policy {
+            commander.set_permissions(policy.permissions(&peer.ip()));
+        }
+        if let Some(base) = &self.workdir {
+            commander.set_workdir(WorkDir::create(base)?);
+        }
+        let mut instructions = 0;
+        while commander.read_next() {
+            instructions += 1;
+        }
+        commander.log_summary(instructions);
+        Ok((peer, instructions, commander.get_num_errors()))
+    }
+}
+
+/// Connection of a controller whose reads fail with a message naming the idle timeout once the
+/// controller has sent nothing for that long
+///
+/// # Parameters
+///
+/// - `stream`: connection of the controller, with its read timeout set
+/// - `timeout`: the read timeout of the connection
+struct IdleStream {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl Read for IdleStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf).map_err(|e| match e.kind() {
+            // the kind reported for an expired read timeout depends on the platform
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => io::Error::new(ErrorKind::TimedOut, format!("The controller sent nothing for {} ms", self.timeout.as_millis())),
+            _ => e
+        })
+    }
+}
+
+/// Reads the token line a controller must send before its instructions. Bytes are read one at a
+/// time so no instruction following the token is consumed. The idle timeout of the connection is
+/// restored once the line is read.
+///
+/// # Parameters
+///
+/// - `stream`: connection of the controller
+/// - `token`: secret the controller must send
+/// - `deliminator`: deliminator separating `token` from the secret
+/// - `idle_timeout`: read timeout of the connection once the token is read
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The controller sent the token
+/// - `Err`: The controller sent another token, or none in time
+fn authenticate(stream: &mut TcpStream, token: &str, deliminator: u8, idle_timeout: Duration) -> Result<(), GenerationError> {
+    stream.set_read_timeout(Some(TOKEN_TIMEOUT.min(idle_timeout)))?;
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while line.len() < MAX_TOKEN_LINE {
+        match stream.read(&mut byte) {
+            Ok(1) if byte[0] == b'\n' => break,
+            Ok(1) => line.push(byte[0]),
+            _ => break
+        }
+    }
+    stream.set_read_timeout(Some(idle_timeout))?;
+    let mut expected = b"token".to_vec();
+    expected.push(deliminator);
+    expected.extend_from_slice(token.as_bytes());
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    // every byte is compared so the time taken does not reveal how much of the token matched
+    let matched = line.len() == expected.len() && line.iter().zip(expected.iter()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0;
+    if !matched {
+        return Err(GenerationError::new("authentication".to_string(), "The controller did not send a valid token".to_string()));
+    }
+    Ok(())
+}
+
+/// Determines the output file of a run by appending the run number to the file name
+fn run_output(out_file: &str, run: usize) -> String {
+    let path = Path::new(out_file);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, run, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, run)
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn output_numbered() {
+        assert_eq!(run_output("log.csv", 3), "log_3.csv");
+        assert_eq!(run_output("logs", 1), "logs_1");
+    }
+
+    #[test]
+    fn instructions_executed_remotely() {
+        let directory = TempPath::new("server");
+        fs::create_dir(&directory).unwrap();
+        let out_file = directory.join("log.csv").to_string_lossy().to_string();
+        let mut server = Server::bind("127.0.0.1:0", &out_file, LogFormat::Csv, b',').unwrap();
+        let address = server.local_addr().unwrap();
+        let events = server.subscribe();
+        let controller = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"connect_self,hello world\nnot_an_instruction\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (_, instructions, errors) = server.serve_one().unwrap();
+        let response = controller.join().unwrap();
+        assert_eq!((instructions, errors), (2, 1));
+        assert!(response.contains("Run Start"));
+        assert!(response.contains("Network Connection"));
+        assert!(response.contains("not a valid instruction"));
+        assert!(response.contains("Run Summary"));
+
+        let events: Vec<RunEvent> = events.try_iter().collect();
+        assert!(matches!(events.first(), Some(RunEvent::Connected(_))));
+        assert!(matches!(events.last(), Some(RunEvent::RunFinished(summary)) if summary.scenario.starts_with("tcp://") && summary.errors == 1));
+        assert!(events.iter().any(|event| matches!(event, RunEvent::InstructionFinished { index: 1, instruction, .. } if instruction == "connect_self,hello world")));
+        assert!(events.iter().any(|event| matches!(event, RunEvent::Error(e) if e.message.contains("not_an_instruction"))));
+        assert!(events.iter().any(|event| matches!(event, RunEvent::Progress { processed: 2, errors: 1, .. })));
+    }
+
+    #[test]
+    fn token_required() {
+        let directory = TempPath::new("server");
+        fs::create_dir(&directory).unwrap();
+        let out_file = directory.join("log.csv").to_string_lossy().to_string();
+        let mut server = Server::bind("127.0.0.1:0", &out_file, LogFormat::Csv, b',').unwrap();
+        server.set_token("lab-secret".to_string());
+        assert!(!server.is_exposed());
+        let address = server.local_addr().unwrap();
+        let controller = thread::spawn(move || {
+            let mut responses = Vec::new();
+            // the refused controller sends nothing past its token, unread data would reset the
+            // connection before the refusal is received
+            for input in [&b"token,wrong\n"[..], &b"token,lab-secret\r\nconnect_self,hello\n"[..]] {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream.write_all(input).unwrap();
+                stream.shutdown(Shutdown::Write).unwrap();
+                let mut response = String::new();
+                let _ = stream.read_to_string(&mut response);
+                responses.push(response);
+            }
+            responses
+        });
+        let refused = server.serve_one().err().unwrap();
+        let (_, instructions, errors) = server.serve_one().unwrap();
+        let responses = controller.join().unwrap();
+        assert_eq!(refused.kind, "authentication");
+        assert!(!responses[0].contains("Run Start"));
+        assert!(responses[0].contains("did not send a valid token"));
+        assert_eq!((instructions, errors), (1, 0));
+        assert!(responses[1].contains("Network Connection"));
+    }
+
+    #[test]
+    fn policy_enforced() {
+        let directory = TempPath::new("server");
+        fs::create_dir(&directory).unwrap();
+        let out_file = directory.join("log.csv").to_string_lossy().to_string();
+        let mut server = Server::bind("127.0.0.1:0", &out_file, LogFormat::Csv, b',').unwrap();
+        server.set_policy(serde_yaml::from_str("roles:\n  local:\n    controllers: [127.0.0.1]\n    allow: [network]\n").unwrap());
+        let address = server.local_addr().unwrap();
+        let controller = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"connect_self,hello world\nprocess,whoami\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (_, instructions, errors) = server.serve_one().unwrap();
+        let response = controller.join().unwrap();
+        assert_eq!((instructions, errors), (2, 0));
+        assert!(response.contains("Network Connection"));
+        assert!(response.contains("process is not permitted for controller role local"));
+        assert!(!response.contains("New Process"));
+    }
+
+    #[test]
+    fn idle_controller_dropped() {
+        let directory = TempPath::new("server");
+        fs::create_dir(&directory).unwrap();
+        let out_file = directory.join("log.csv").to_string_lossy().to_string();
+        let mut server = Server::bind("127.0.0.1:0", &out_file, LogFormat::Csv, b',').unwrap();
+        server.set_token("lab-secret".to_string());
+        server.set_idle_timeout(Duration::from_millis(200));
+        let address = server.local_addr().unwrap();
+        let controller = thread::spawn(move || {
+            // the controller goes quiet after its first instruction without closing the connection
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"token,lab-secret\nconnect_self,hello\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (_, instructions, errors) = server.serve_one().unwrap();
+        let response = controller.join().unwrap();
+        assert_eq!((instructions, errors), (1, 1));
+        assert!(response.contains("Network Connection"));
+        assert!(response.contains("The controller sent nothing for 200 ms"));
+        assert!(response.contains("Run Summary"));
+    }
+}
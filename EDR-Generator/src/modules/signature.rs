@@ -0,0 +1,180 @@
+use crate::modules::common::GenerationError;
+use minisign::{SecretKey, SecretKeyBox};
+use minisign_verify::{PublicKey, Signature};
+use std::fs;
+use std::path::Path;
+
+/// Extension appended to a scenario file name to find its signature, as written by `minisign -S`
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Structure defining the minisign (Ed25519) public key scenario files must be signed with. Each
+/// scenario file is verified against a detached `<file>.minisig` signature next to it.
+///
+/// # Parameters
+///
+/// - `public_key`: trusted public key
+pub struct TrustedKey {
+    public_key: PublicKey,
+}
+
+impl TrustedKey {
+    /// Loads a minisign public key
+    ///
+    /// # Parameters
+    ///
+    /// - `source`: path of a minisign public key file (e.g. `minisign.pub`), or the base64 encoded
+    ///   public key itself
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: TrustedKey instance
+    /// - `Err`: The key file could not be read or does not contain a minisign public key
+    pub fn load(source: &str) -> Result<TrustedKey, GenerationError> {
+        let public_key = if Path::new(source).is_file() {
+            PublicKey::decode(&fs::read_to_string(source)?)
+        } else {
+            PublicKey::from_base64(source.trim())
+        };
+        match public_key {
+            Ok(public_key) => Ok(TrustedKey { public_key }),
+            Err(e) => Err(GenerationError::new("signature".to_string(), format!("{} is not a minisign public key: {}", source, e)))
+        }
+    }
+
+    /// Verifies the contents of a scenario file against the signature next to it
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the scenario file, the signature is read from `<path>.minisig`
+    /// - `contents`: contents of the scenario file as it was read for execution
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The contents are signed with the trusted key
+    /// - `Err`: The file is unsigned, or the signature was made with another key or for other contents
+    pub fn verify(&self, path: &str, contents: &[u8]) -> Result<(), GenerationError> {
+        let signature_path = format!("{}.{}", path, SIGNATURE_EXTENSION);
+        let signature = match fs::read_to_string(&signature_path) {
+            Ok(inner) => inner,
+            Err(_) => return Err(GenerationError::new("signature".to_string(), format!("{} is not signed ({} could not be read)", path, signature_path)))
+        };
+        self.verify_signature(path, contents, &signature)
+    }
+
+    /// Verifies contents against a minisign signature
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: name of the signed contents, used in the error message
+    /// - `contents`: signed contents
+    /// - `signature`: minisign signature of the contents, as written to a `.minisig` file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The contents are signed with the trusted key
+    /// - `Err`: The signature is malformed, or was made with another key or for other contents
+    pub fn verify_signature(&self, name: &str, contents: &[u8], signature: &str) -> Result<(), GenerationError> {
+        let result = Signature::decode(signature).and_then(|signature| self.public_key.verify(contents, &signature, false));
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(GenerationError::new("signature".to_string(), format!("{} does not have a valid signature: {}", name, e)))
+        }
+    }
+}
+
+/// Structure defining a minisign secret key, used to sign scenario packs so hosts only need the
+/// public key to verify them
+///
+/// # Parameters
+///
+/// - `secret_key`: decrypted secret key
+pub struct SigningKey {
+    secret_key: SecretKey,
+}
+
+impl SigningKey {
+    /// Loads a minisign secret key, prompting for its password when it is encrypted
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of a minisign secret key file (e.g. `minisign.key`)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: SigningKey instance
+    /// - `Err`: The key file could not be read, is not a minisign secret key, or could not be decrypted
+    pub fn load(path: &str) -> Result<SigningKey, GenerationError> {
+        let contents = fs::read_to_string(path)?;
+        // the password is only prompted for when the key is not stored unencrypted
+        let secret_key = SecretKeyBox::from_string(&contents).and_then(SecretKey::from_unencrypted_box)
+            .or_else(|_| SecretKeyBox::from_string(&contents).and_then(|secret_box| SecretKey::from_box(secret_box, None)));
+        match secret_key {
+            Ok(secret_key) => Ok(SigningKey { secret_key }),
+            Err(e) => Err(GenerationError::new("signature".to_string(), format!("Unable to load the minisign secret key {}: {}", path, e)))
+        }
+    }
+
+    /// Signs contents
+    ///
+    /// # Parameters
+    ///
+    /// - `contents`: contents to sign
+    /// - `comment`: trusted comment of the signature, covered by the signature
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The minisign signature, as written to a `.minisig` file
+    /// - `Err`: The contents could not be signed
+    pub fn sign(&self, contents: &[u8], comment: &str) -> Result<String, GenerationError> {
+        match minisign::sign(None, &self.secret_key, contents, Some(comment), None) {
+            Ok(signature) => Ok(signature.into_string()),
+            Err(e) => Err(GenerationError::new("signature".to_string(), format!("Unable to sign {}: {}", comment, e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn signatures_verified() {
+        let key = TrustedKey::load("tests/minisign.pub").unwrap();
+        let contents = fs::read("tests/signed_test.csv").unwrap();
+        assert!(key.verify("tests/signed_test.csv", &contents).is_ok());
+        assert!(key.verify("tests/signed_test.csv", b"process,cmd,/c whoami\n").is_err());
+        assert!(key.verify("tests/good_test.csv", &fs::read("tests/good_test.csv").unwrap()).is_err());
+    }
+
+    #[test]
+    fn keys_loaded() {
+        assert!(TrustedKey::load("RWQR4DTJ2+m459LlVwAlDWOh1BoCTWvm7sw29kfvxuM18lR8n9kNlR6y").is_ok());
+        assert!(TrustedKey::load("tests/good_test.csv").is_err());
+        assert!(TrustedKey::load("not a key").is_err());
+    }
+
+    #[test]
+    fn contents_signed() {
+        let path = TempPath::new("signing.key");
+        let pair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        fs::write(&path, pair.sk.to_box(None).unwrap().into_string()).unwrap();
+        let signing = SigningKey::load(&path.as_string()).unwrap();
+        let key = TrustedKey::load(&pair.pk.to_base64()).unwrap();
+        let signature = signing.sign(b"process,cmd,/c whoami\n", "whoami.csv").unwrap();
+        assert!(key.verify_signature("whoami.csv", b"process,cmd,/c whoami\n", &signature).is_ok());
+        assert!(key.verify_signature("whoami.csv", b"process,cmd,/c hostname\n", &signature).is_err());
+        assert!(TrustedKey::load("tests/minisign.pub").unwrap().verify_signature("whoami.csv", b"process,cmd,/c whoami\n", &signature).is_err());
+        assert!(SigningKey::load("tests/minisign.pub").is_err());
+    }
+}
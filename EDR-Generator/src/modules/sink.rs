@@ -0,0 +1,389 @@
+use crate::modules::common::GenerationError;
+use chrono::Utc;
+use std::process;
+use std::io::{Write, Read, BufRead, BufReader};
+use std::fs::OpenOptions;
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Default NATS server port used when the address does not contain one
+const NATS_DEFAULT_PORT: u16 = 4222;
+
+/// Additional outputs which receive a copy of every record written by the Logger
+///
+/// # Variants
+///
+/// - `Registry`: writes each record as a value under a per-run registry key (Windows)
+/// - `Stream`: streams each record as a line over a named pipe or Unix domain socket
+/// - `Nats`: publishes each record as a message to a NATS subject
+pub enum Sink {
+    Registry(RegistrySink),
+    Stream(StreamSink),
+    Nats(NatsSink),
+}
+
+impl Sink {
+    /// Forwards a formatted record to the sink
+    ///
+    /// # Parameters
+    ///
+    /// - `line`: record formatted in the Logger's output format
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The record was delivered
+    /// - `Err`: The record could not be delivered
+    pub fn send(&mut self, line: &str) -> Result<(), GenerationError> {
+        match self {
+            Sink::Registry(inner) => inner.send(line),
+            Sink::Stream(inner) => inner.send(line),
+            Sink::Nats(inner) => inner.send(line),
+        }
+    }
+
+    /// Retrieves a short description of the sink used when reporting sink failures
+    ///
+    /// # Returns
+    ///
+    /// The description of the sink
+    pub fn name(&self) -> String {
+        match self {
+            Sink::Registry(inner) => format!("registry ({})", inner.path),
+            Sink::Stream(inner) => format!("stream ({})", inner.path),
+            Sink::Nats(inner) => format!("nats ({} {})", inner.address, inner.subject),
+        }
+    }
+}
+
+/// Structure defining the registry output sink. Each run creates a subkey named after the start
+/// time and process id of the run, and every record is stored as a sequentially numbered string
+/// value beneath it.
+///
+/// # Parameters
+///
+/// - `key`: handle to the per-run registry key
+/// - `path`: full path of the per-run registry key
+/// - `sequence`: number of records written so far
+pub struct RegistrySink {
+    #[cfg(windows)]
+    key: winreg::RegKey,
+    path: String,
+    sequence: usize,
+}
+
+impl RegistrySink {
+    /// Creates the per-run subkey beneath the configured registry key
+    ///
+    /// # Parameters
+    ///
+    /// - `base_key`: registry key prefixed with its hive (e.g. `HKCU\Software\EDRGenerator`)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: A RegistrySink instance writing under the new per-run key
+    /// - `Err`: The key is malformed, could not be created, or the platform is not Windows
+    #[cfg(windows)]
+    pub fn new(base_key: &String) -> Result<RegistrySink, GenerationError> {
+        use winreg::RegKey;
+        use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+        let (hive, subkey) = split_key(base_key)?;
+        let root = match hive.as_str() {
+            "HKCU" => RegKey::predef(HKEY_CURRENT_USER),
+            _ => RegKey::predef(HKEY_LOCAL_MACHINE),
+        };
+        let run_key = format!("{}\\{}", subkey, run_id());
+        let (key, _) = root.create_subkey(&run_key)?;
+        Ok(RegistrySink {
+            key,
+            path: format!("{}\\{}", hive, run_key),
+            sequence: 0,
+        })
+    }
+
+    #[cfg(not(windows))]
+    pub fn new(base_key: &String) -> Result<RegistrySink, GenerationError> {
+        split_key(base_key)?;
+        Err(GenerationError::new("unsupported_platform".to_string(), "Registry output is only supported on Windows".to_string()))
+    }
+
+    /// Stores a record as the next sequentially numbered string value
+    #[cfg(windows)]
+    fn send(&mut self, line: &str) -> Result<(), GenerationError> {
+        self.sequence += 1;
+        self.key.set_value(format!("{:06}", self.sequence), &line.to_string())?;
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    fn send(&mut self, _: &str) -> Result<(), GenerationError> {
+        self.sequence += 1;
+        Err(GenerationError::new("unsupported_platform".to_string(), "Registry output is only supported on Windows".to_string()))
+    }
+}
+
+/// Structure defining the stream output sink. Records are written one per line to a connected
+/// Unix domain socket, a FIFO, or a Windows named pipe (`\\.\pipe\<name>`) so a co-resident
+/// collector can consume them as they are generated.
+///
+/// # Parameters
+///
+/// - `writer`: connected socket or opened pipe
+/// - `path`: path of the socket or pipe
+pub struct StreamSink {
+    writer: Box<dyn Write + Send>,
+    path: String,
+}
+
+impl StreamSink {
+    /// Connects to a listening Unix domain socket, or opens a named pipe / FIFO for writing.
+    /// Opening a FIFO blocks until the collector opens it for reading.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the socket or pipe
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: A StreamSink instance connected to the collector
+    /// - `Err`: No collector is listening at the path or it could not be opened
+    pub fn new(path: &String) -> Result<StreamSink, GenerationError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            use std::os::unix::net::UnixStream;
+            if std::fs::metadata(path)?.file_type().is_socket() {
+                return Ok(StreamSink {
+                    writer: Box::new(UnixStream::connect(path)?),
+                    path: path.clone(),
+                });
+            }
+        }
+        Ok(StreamSink {
+            writer: Box::new(OpenOptions::new().write(true).open(path)?),
+            path: path.clone(),
+        })
+    }
+
+    /// Streams records to an already connected writer, e.g. a remote control connection
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: description of the writer used when reporting failures
+    /// - `writer`: connected writer
+    ///
+    /// # Returns
+    ///
+    /// A StreamSink instance writing to the writer
+    pub fn from_writer(name: String, writer: Box<dyn Write + Send>) -> StreamSink {
+        StreamSink { writer, path: name }
+    }
+
+    /// Writes a record followed by a line terminator and flushes it to the collector
+    fn send(&mut self, line: &str) -> Result<(), GenerationError> {
+        self.writer.write_all(format!("{}\n", line).as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Structure defining the NATS message bus sink. Each record is published as a single message so
+/// many generator instances can publish to a central subscriber.
+///
+/// # Parameters
+///
+/// - `stream`: connection to the NATS server
+/// - `address`: address of the NATS server
+/// - `subject`: subject each record is published to
+pub struct NatsSink {
+    stream: TcpStream,
+    address: String,
+    subject: String,
+}
+
+impl NatsSink {
+    /// Connects to a NATS server and completes the protocol handshake
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: address of the server as `host[:port]`, optionally prefixed with `nats://`
+    /// - `subject`: subject each record is published to
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: A NatsSink instance connected to the server
+    /// - `Err`: The server could not be reached or is not a NATS server
+    pub fn new(address: &String, subject: &String) -> Result<NatsSink, GenerationError> {
+        if subject.is_empty() || subject.contains(char::is_whitespace) {
+            return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid NATS subject", subject)));
+        }
+        let mut address = address.trim_start_matches("nats://").to_string();
+        if !address.contains(':') {
+            address = format!("{}:{}", address, NATS_DEFAULT_PORT);
+        }
+        let stream = match TcpStream::connect(&address) {
+            Ok(inner) => inner,
+            Err(_) => return Err(GenerationError::new("network".to_string(), format!("Unable to Connect to NATS server {}", address)))
+        };
+        // the server greets every client with an INFO line before accepting commands
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut greeting = String::new();
+        BufReader::new(&stream).read_line(&mut greeting)?;
+        if !greeting.starts_with("INFO") {
+            return Err(GenerationError::new("network".to_string(), format!("{} is not a NATS server", address)));
+        }
+        let mut sink = NatsSink {
+            stream,
+            address,
+            subject: subject.clone(),
+        };
+        sink.stream.write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"edr_generator\"}\r\n")?;
+        Ok(sink)
+    }
+
+    /// Publishes a record to the configured subject. Pending server messages are read first so
+    /// keep-alive pings are answered and server errors are reported.
+    fn send(&mut self, line: &str) -> Result<(), GenerationError> {
+        self.handle_server_messages()?;
+        let message = format!("PUB {} {}\r\n{}\r\n", self.subject, line.len(), line);
+        self.stream.write_all(message.as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads any messages the server has sent without blocking, replying to PING with PONG
+    fn handle_server_messages(&mut self) -> Result<(), GenerationError> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let mut received = String::new();
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.stream.set_nonblocking(false)?;
+                    return Err(GenerationError::new("network".to_string(), "NATS server closed the connection".to_string()));
+                }
+                Ok(size) => received.push_str(&String::from_utf8_lossy(&buffer[..size])),
+                Err(_) => break
+            }
+        }
+        self.stream.set_nonblocking(false)?;
+        for message in received.lines() {
+            if message.starts_with("PING") {
+                self.stream.write_all(b"PONG\r\n")?;
+            } else if message.starts_with("-ERR") {
+                return Err(GenerationError::new("network".to_string(), format!("NATS server error {}", message)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a registry key into its normalized hive abbreviation and the remaining subkey path
+///
+/// # Parameters
+///
+/// - `key`: registry key prefixed with its hive
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Tuple of hive (`HKCU` or `HKLM`) and subkey path
+/// - `Err`: The hive is not supported or the subkey path is empty
+fn split_key(key: &String) -> Result<(String, String), GenerationError> {
+    let mut parts = key.splitn(2, '\\');
+    let hive = match parts.next().unwrap_or("").to_uppercase().as_str() {
+        "HKCU" | "HKEY_CURRENT_USER" => "HKCU",
+        "HKLM" | "HKEY_LOCAL_MACHINE" => "HKLM",
+        _ => return Err(GenerationError::new("input_format".to_string(), format!("{} does not start with a supported hive (HKCU, HKLM)", key)))
+    };
+    let subkey = parts.next().unwrap_or("").trim_matches('\\');
+    if subkey.is_empty() {
+        return Err(GenerationError::new("input_format".to_string(), format!("{} does not contain a subkey", key)));
+    }
+    Ok((hive.to_string(), subkey.to_string()))
+}
+
+/// Builds a unique identifier for the current run from the start time and process id
+#[cfg_attr(not(windows), allow(dead_code))]
+fn run_id() -> String {
+    format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%SZ"), process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn registry_key_hives() {
+        let (hive, subkey) = split_key(&String::from("HKEY_CURRENT_USER\\Software\\EDRGenerator\\")).unwrap();
+        assert_eq!(hive, "HKCU");
+        assert_eq!(subkey, "Software\\EDRGenerator");
+        assert!(split_key(&String::from("HKCR\\Software")).is_err());
+        assert!(split_key(&String::from("HKLM")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_stream() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+        let path = TempPath::new("stream.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut sink = Sink::Stream(StreamSink::new(&path.as_string()).unwrap());
+        sink.send("first").unwrap();
+        sink.send("second").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<String> = BufReader::new(stream).lines().take(2).map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn nats_publish() {
+        use std::net::TcpListener;
+        use std::thread;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {\"server_id\":\"test\"}\r\n").unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = vec![];
+            for _ in 0..3 {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                lines.push(line.trim_end().to_string());
+            }
+            lines
+        });
+        let mut sink = Sink::Nats(NatsSink::new(&format!("nats://{}", address), &String::from("edr.events")).unwrap());
+        sink.send("hello world").unwrap();
+        let lines = server.join().unwrap();
+        assert!(lines[0].starts_with("CONNECT {"));
+        assert_eq!(lines[1], "PUB edr.events 11");
+        assert_eq!(lines[2], "hello world");
+    }
+
+    #[test]
+    fn nats_invalid_subject() {
+        assert!(NatsSink::new(&String::from("127.0.0.1"), &String::from("edr events")).is_err());
+    }
+
+    #[test]
+    fn missing_stream() {
+        assert!(StreamSink::new(&String::from("no_such_collector.sock")).is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn registry_unsupported() {
+        assert!(RegistrySink::new(&String::from("HKCU\\Software\\EDRGenerator")).is_err());
+    }
+}
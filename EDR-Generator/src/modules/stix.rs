@@ -0,0 +1,365 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use crate::modules::report::RunSummary;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Namespace of the deterministic identifiers of STIX Cyber-observable Objects (00abedb4-aa42-466c-9c01-fed23315a9b7)
+const SCO_NAMESPACE: Uuid = Uuid::from_bytes([0x00, 0xab, 0xed, 0xb4, 0xaa, 0x42, 0x46, 0x6c, 0x9c, 0x01, 0xfe, 0xd2, 0x33, 0x15, 0xa9, 0xb7]);
+
+/// MITRE ATT&CK technique exercised by each event activity, along with its tactic
+const TECHNIQUES: [(&str, &str, &str, &str); 25] = [
+    ("New Process", "T1059", "Command and Scripting Interpreter", "Execution"),
+    ("New Descendant Process", "T1059", "Command and Scripting Interpreter", "Execution"),
+    ("Delete File", "T1070.004", "Indicator Removal: File Deletion", "Defense Evasion"),
+    ("Network Connection", "T1071", "Application Layer Protocol", "Command and Control"),
+    ("Inbound Network Connection", "T1021", "Remote Services", "Lateral Movement"),
+    ("File Exfiltration", "T1041", "Exfiltration Over C2 Channel", "Exfiltration"),
+    ("Overwrite File", "T1486", "Data Encrypted for Impact", "Impact"),
+    ("Rename File", "T1486", "Data Encrypted for Impact", "Impact"),
+    ("Alternate Data Stream Written", "T1564.004", "Hide Artifacts: NTFS File Attributes", "Defense Evasion"),
+    ("Permissions Changed", "T1222", "File and Directory Permissions Modification", "Defense Evasion"),
+    ("Registry Run Key", "T1547.001", "Boot or Logon Autostart Execution: Registry Run Keys / Startup Folder", "Persistence"),
+    ("Startup Folder File", "T1547.001", "Boot or Logon Autostart Execution: Registry Run Keys / Startup Folder", "Persistence"),
+    ("Cron Entry", "T1053.003", "Scheduled Task/Job: Cron", "Persistence"),
+    ("Service Created", "T1543", "Create or Modify System Process", "Persistence"),
+    ("Scheduled Task Created", "T1053", "Scheduled Task/Job", "Persistence"),
+    ("Agent Service Queried", "T1518.001", "Software Discovery: Security Software Discovery", "Discovery"),
+    ("Agent File Opened", "T1518.001", "Software Discovery: Security Software Discovery", "Discovery"),
+    ("Agent File Open Denied", "T1518.001", "Software Discovery: Security Software Discovery", "Discovery"),
+    ("Agent Service Stopped", "T1562.001", "Impair Defenses: Disable or Modify Tools", "Defense Evasion"),
+    ("Agent Service Stop Denied", "T1562.001", "Impair Defenses: Disable or Modify Tools", "Defense Evasion"),
+    ("Agent Binary Renamed", "T1562.001", "Impair Defenses: Disable or Modify Tools", "Defense Evasion"),
+    ("Agent Binary Rename Denied", "T1562.001", "Impair Defenses: Disable or Modify Tools", "Defense Evasion"),
+    ("User Account Created", "T1136.001", "Create Account: Local Account", "Persistence"),
+    ("User Account Deleted", "T1531", "Account Access Removal", "Impact"),
+    ("Group Membership Added", "T1098", "Account Manipulation", "Persistence"),
+];
+
+/// MITRE ATT&CK technique ID, technique name, and tactic
+pub type Technique = (&'static str, &'static str, &'static str);
+
+/// Looks up the MITRE ATT&CK technique exercised by an event. Variants of an activity with a
+/// parenthesized suffix, such as `Network Connection (attempt 2 of 3)`, map to the same technique.
+///
+/// # Parameters
+///
+/// - `activity`: activity of the event
+///
+/// # Returns
+///
+/// The technique ID, technique name, and tactic, None when the activity is not mapped
+pub fn technique(activity: &str) -> Option<Technique> {
+    let base = activity.split(" (").next().unwrap_or(activity);
+    TECHNIQUES.iter().find(|(mapped, _, _, _)| *mapped == base).map(|(_, id, name, tactic)| (*id, *name, *tactic))
+}
+
+/// Builds a STIX 2.1 bundle of a run, so the exercise can be imported into threat intelligence
+/// platforms (e.g. OpenCTI) and mapped against detections there. The bundle contains:
+///
+/// - an `identity` of the generator, referenced as the creator of every other object
+/// - the artifacts of every event as cyber-observable objects (processes, files, directories,
+///   addresses, network traffic, and user accounts), shared between the events they appear in
+/// - an `observed-data` object per event, recording its time and activity
+/// - an `attack-pattern` per ATT&CK technique exercised, with a `sighting` referencing the
+///   observed data of every event of the technique
+/// - a `report` of the run referencing the objects above
+///
+/// # Parameters
+///
+/// - `summary`: summary of the run
+/// - `events`: events logged by the run
+///
+/// # Returns
+///
+/// The bundle as a JSON document
+pub fn bundle(summary: &RunSummary, events: &[Log]) -> Value {
+    let now = stix_time(&summary.timestamp);
+    let identity = format!("identity--{}", Uuid::new_v4());
+    let mut objects = vec![json!({
+        "type": "identity", "spec_version": "2.1", "id": identity, "created": now, "modified": now,
+        "name": "EDR Generator", "identity_class": "system",
+    })];
+    let mut observables = Observables::default();
+    let mut observed = vec![];
+    let mut sightings: BTreeMap<Technique, Vec<(String, String)>> = BTreeMap::new();
+    for event in events {
+        let time = stix_time(&event.timestamp);
+        let id = format!("observed-data--{}", Uuid::new_v4());
+        objects.push(json!({
+            "type": "observed-data", "spec_version": "2.1", "id": id, "created_by_ref": identity,
+            "created": now, "modified": now, "first_observed": time, "last_observed": time,
+            "number_observed": 1, "object_refs": observables.add_event(event), "x_edr_activity": event.activity,
+        }));
+        if let Some(mapped) = technique(&event.activity) {
+            sightings.entry(mapped).or_default().push((id.clone(), time));
+        }
+        observed.push(id);
+    }
+    let mut references = observed;
+    for ((technique, name, tactic), sighted) in sightings {
+        let pattern = format!("attack-pattern--{}", Uuid::new_v4());
+        objects.push(json!({
+            "type": "attack-pattern", "spec_version": "2.1", "id": pattern, "created_by_ref": identity,
+            "created": now, "modified": now, "name": name,
+            "kill_chain_phases": [{"kill_chain_name": "mitre-attack", "phase_name": tactic.to_lowercase().replace(' ', "-")}],
+            "external_references": [{
+                "source_name": "mitre-attack", "external_id": technique,
+                "url": format!("https://attack.mitre.org/techniques/{}/", technique.replace('.', "/")),
+            }],
+        }));
+        let sighting = format!("sighting--{}", Uuid::new_v4());
+        objects.push(json!({
+            "type": "sighting", "spec_version": "2.1", "id": sighting, "created_by_ref": identity,
+            "created": now, "modified": now, "sighting_of_ref": pattern, "count": sighted.len(),
+            "first_seen": sighted.iter().map(|(_, time)| time).min(), "last_seen": sighted.iter().map(|(_, time)| time).max(),
+            "observed_data_refs": sighted.iter().map(|(id, _)| id).collect::<Vec<&String>>(),
+        }));
+        references.push(pattern);
+        references.push(sighting);
+    }
+    if references.is_empty() {
+        references.push(identity.clone());
+    }
+    objects.push(json!({
+        "type": "report", "spec_version": "2.1", "id": format!("report--{}", Uuid::new_v4()), "created_by_ref": identity,
+        "created": now, "modified": now, "published": now, "name": format!("EDR Generator run of {} on {}", summary.scenario, summary.host),
+        "description": summary.message(), "report_types": ["observed-data"], "object_refs": references,
+        "x_edr_scenario": summary.scenario, "x_edr_scenario_sha256": summary.scenario_sha256,
+    }));
+    objects.extend(observables.objects);
+    json!({"type": "bundle", "id": format!("bundle--{}", Uuid::new_v4()), "objects": objects})
+}
+
+/// Writes the STIX 2.1 bundle of a run to a file, see `bundle`
+///
+/// # Parameters
+///
+/// - `path`: path of the file to write
+/// - `summary`: summary of the run
+/// - `events`: events logged by the run
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The file was written
+/// - `Err`: The bundle could not be serialized or the file could not be written
+pub fn write_bundle(path: &String, summary: &RunSummary, events: &[Log]) -> Result<(), GenerationError> {
+    match serde_json::to_string_pretty(&bundle(summary, events)) {
+        Ok(inner) => Ok(fs::write(path, inner + "\n")?),
+        Err(_) => Err(GenerationError::new("logging".to_string(), "Unable to Serialize STIX Bundle".to_string()))
+    }
+}
+
+/// Structure defining the cyber-observable objects of a bundle. Objects with the same properties
+/// are only added once, so events share the artifacts they have in common.
+///
+/// # Parameters
+///
+/// - `objects`: cyber-observable objects in the order they were first seen
+/// - `ids`: identifiers of the objects
+/// - `processes`: identifiers of the processes by process ID and command line
+#[derive(Default)]
+struct Observables {
+    objects: Vec<Value>,
+    ids: HashSet<String>,
+    processes: BTreeMap<(String, String), String>,
+}
+
+impl Observables {
+    /// Adds the artifacts of an event
+    ///
+    /// # Returns
+    ///
+    /// Identifiers of the objects the event refers to
+    fn add_event(&mut self, event: &Log) -> Vec<String> {
+        let mut refs = vec![];
+        let user = if event.username.is_empty() {
+            None
+        } else {
+            Some(self.add("user-account", json!({"account_login": event.username}), json!({})))
+        };
+        if !event.proc_id.is_empty() || !event.proc_cmd.is_empty() {
+            refs.push(self.add_process(event, user.clone()));
+        }
+        refs.extend(user);
+        if !event.file_path.is_empty() {
+            let path = Path::new(&event.file_path);
+            let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| event.file_path.clone());
+            let mut properties = json!({"name": name});
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                properties["parent_directory_ref"] = json!(self.add("directory", json!({"path": parent.to_string_lossy()}), json!({})));
+            }
+            refs.push(self.add("file", properties, json!({})));
+        }
+        if !event.dest_addr.is_empty() {
+            let destination = self.add_address(&event.dest_addr);
+            let mut properties = json!({"dst_ref": destination, "protocols": protocols(&event.protocol)});
+            if !event.source_addr.is_empty() {
+                properties["src_ref"] = json!(self.add_address(&event.source_addr));
+            }
+            if let Ok(port) = event.source_port.parse::<u16>() {
+                properties["src_port"] = json!(port);
+            }
+            if let Ok(port) = event.dest_port.parse::<u16>() {
+                properties["dst_port"] = json!(port);
+            }
+            let bytes = event.bytes_sent.parse::<u64>().map(|bytes| json!({"src_byte_count": bytes})).unwrap_or_else(|_| json!({}));
+            refs.push(self.add("network-traffic", properties, bytes));
+        }
+        refs
+    }
+
+    /// Adds the process of an event, along with a file object of its executable
+    fn add_process(&mut self, event: &Log, user: Option<String>) -> String {
+        let key = (event.proc_id.clone(), event.proc_cmd.clone());
+        if let Some(id) = self.processes.get(&key) {
+            return id.clone();
+        }
+        // processes have no identifying properties, each one gets a random identifier
+        let id = format!("process--{}", Uuid::new_v4());
+        let mut process = json!({"type": "process", "spec_version": "2.1", "id": id});
+        if let Ok(pid) = event.proc_id.parse::<u64>() {
+            process["pid"] = json!(pid);
+        }
+        if !event.proc_cmd.trim().is_empty() {
+            process["command_line"] = json!(event.proc_cmd.trim());
+        }
+        if !event.proc_name.is_empty() {
+            process["image_ref"] = json!(self.add("file", json!({"name": event.proc_name}), json!({})));
+        }
+        if let Some(user) = user {
+            process["creator_user_ref"] = json!(user);
+        }
+        self.objects.push(process);
+        self.processes.insert(key, id.clone());
+        id
+    }
+
+    /// Adds an IPv4 or IPv6 address, or a domain name when the address is not an IP address
+    fn add_address(&mut self, address: &str) -> String {
+        let kind = match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => "ipv4-addr",
+            Ok(IpAddr::V6(_)) => "ipv6-addr",
+            Err(_) => "domain-name"
+        };
+        self.add(kind, json!({"value": address}), json!({}))
+    }
+
+    /// Adds an object with a deterministic identifier derived from its identifying properties
+    ///
+    /// # Parameters
+    ///
+    /// - `kind`: type of the object
+    /// - `identifying`: properties the identifier is derived from
+    /// - `other`: additional properties which do not contribute to the identifier
+    ///
+    /// # Returns
+    ///
+    /// Identifier of the object
+    fn add(&mut self, kind: &str, identifying: Value, other: Value) -> String {
+        // object keys are sorted, so equal properties always serialize to the same string
+        let id = format!("{}--{}", kind, Uuid::new_v5(&SCO_NAMESPACE, identifying.to_string().as_bytes()));
+        if self.ids.insert(id.clone()) {
+            let mut object = json!({"type": kind, "spec_version": "2.1", "id": id});
+            for (name, value) in identifying.as_object().into_iter().chain(other.as_object()).flatten() {
+                object[name] = value.clone();
+            }
+            self.objects.push(object);
+        }
+        id
+    }
+}
+
+/// Converts the protocol of an event into the protocols of a network traffic object, every
+/// connection of the generator uses TCP
+fn protocols(protocol: &str) -> Vec<String> {
+    match protocol.to_lowercase().as_str() {
+        "" | "tcp/ip" | "tcp" => vec!["tcp".to_string()],
+        other => vec!["tcp".to_string(), other.to_string()],
+    }
+}
+
+/// Converts a timestamp into the STIX timestamp format (UTC with millisecond precision), using
+/// the current time when it can not be parsed (e.g. a `@timestamp` override)
+pub(crate) fn stix_time(timestamp: &str) -> String {
+    let time = match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(inner) => inner.with_timezone(&Utc),
+        Err(_) => Utc::now()
+    };
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::network::adapt_log_network;
+    use std::time::Duration;
+
+    fn sample_events() -> Vec<Log> {
+        let mut exfil = adapt_log_network("File Exfiltration".to_string(), "10.0.0.2".to_string(), 50123,
+                                          "10.0.0.5".to_string(), 8080, 11, "HTTP".to_string());
+        exfil.file_path = "/home/x24/secrets.txt".to_string();
+        exfil.username = "x24".to_string();
+        exfil.proc_name = "edr_generator".to_string();
+        exfil.proc_id = "4242".to_string();
+        let mut connect = adapt_log_network("Network Connection (attempt 2 of 3)".to_string(), "10.0.0.2".to_string(), 50124,
+                                            "10.0.0.5".to_string(), 4444, 5, "TCP/IP".to_string());
+        connect.username = "x24".to_string();
+        connect.proc_name = "edr_generator".to_string();
+        connect.proc_id = "4242".to_string();
+        vec![exfil, connect]
+    }
+
+    fn objects_of<'a>(bundle: &'a Value, kind: &str) -> Vec<&'a Value> {
+        bundle["objects"].as_array().unwrap().iter().filter(|object| object["type"] == kind).collect()
+    }
+
+    #[test]
+    fn bundle_contents() {
+        let summary = RunSummary::new(String::from("exfil.csv"), String::from("3f1e9c4b"), 2, 0, Duration::from_millis(4), false);
+        let bundle = bundle(&summary, &sample_events());
+        assert_eq!(bundle["type"], "bundle");
+        assert_eq!(objects_of(&bundle, "observed-data").len(), 2);
+        // both events share the process, user, and addresses they have in common
+        assert_eq!(objects_of(&bundle, "process").len(), 1);
+        assert_eq!(objects_of(&bundle, "user-account").len(), 1);
+        assert_eq!(objects_of(&bundle, "ipv4-addr").len(), 2);
+        assert_eq!(objects_of(&bundle, "network-traffic").len(), 2);
+        let file = objects_of(&bundle, "file").into_iter().find(|file| file["name"] == "secrets.txt").unwrap();
+        assert_eq!(objects_of(&bundle, "directory")[0]["id"], file["parent_directory_ref"]);
+        let techniques: Vec<&Value> = objects_of(&bundle, "attack-pattern").into_iter().map(|pattern| &pattern["external_references"][0]["external_id"]).collect();
+        assert_eq!(techniques, vec!["T1041", "T1071"]);
+        assert_eq!(objects_of(&bundle, "sighting").len(), 2);
+        let report = objects_of(&bundle, "report")[0];
+        assert_eq!(report["object_refs"].as_array().unwrap().len(), 6);
+        assert_eq!(report["x_edr_scenario_sha256"], "3f1e9c4b");
+        assert!(bundle["objects"].as_array().unwrap().iter().all(|object| object["spec_version"] == "2.1"));
+    }
+
+    #[test]
+    fn observable_ids_deterministic() {
+        let mut first = Observables::default();
+        let mut second = Observables::default();
+        assert_eq!(first.add_address("10.0.0.5"), second.add_address("10.0.0.5"));
+        assert_ne!(first.add_address("10.0.0.5"), first.add_address("10.0.0.6"));
+        assert!(first.add_address("::1").starts_with("ipv6-addr--"));
+        assert!(first.add_address("testvm01").starts_with("domain-name--"));
+        assert_eq!(stix_time("2021-04-01T12:00:00.123456789+02:00"), "2021-04-01T10:00:00.123Z");
+    }
+
+    #[test]
+    fn techniques_mapped() {
+        assert_eq!(technique("Network Connection (attempt 2 of 3)"), Some(("T1071", "Application Layer Protocol", "Command and Control")));
+        assert_eq!(technique("Scheduled Task Created (daily)").map(|(id, _, _)| id), Some("T1053"));
+        assert_eq!(technique("Agent Service Stop Denied (exit code 5)").map(|(id, _, _)| id), Some("T1562.001"));
+        assert_eq!(technique("Registry Run Key Removed"), None);
+        assert_eq!(technique("New File"), None);
+    }
+}
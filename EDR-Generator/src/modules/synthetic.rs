@@ -0,0 +1,199 @@
+use crate::modules::common::GenerationError;
+use csv::StringRecord;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::distributions::{Alphanumeric, WeightedIndex, Distribution};
+use std::env;
+
+/// Structure defining the relative weights of each event category in randomized generation
+///
+/// # Parameters
+///
+/// - `process`: weight of process creation events
+/// - `file`: weight of file creation, modification, and deletion events
+/// - `network`: weight of network connection events
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    pub process: u32,
+    pub file: u32,
+    pub network: u32,
+}
+
+impl Weights {
+    /// Parses weights in the form `process=1,file=3,network=1`. Omitted categories keep a weight of 1.
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: comma separated list of category=weight pairs
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The parsed weights
+    /// - `Err`: A category or weight is invalid, or every weight is zero
+    pub fn parse(value: &str) -> Result<Weights, GenerationError> {
+        let mut weights = Weights::default();
+        for pair in value.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let category = parts.next().unwrap_or("").trim();
+            let weight = match parts.next().map(|weight| weight.trim().parse::<u32>()) {
+                Some(Ok(inner)) => inner,
+                _ => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid weight (<category>=<weight>)", pair)))
+            };
+            match category {
+                "process" => weights.process = weight,
+                "file" => weights.file = weight,
+                "network" => weights.network = weight,
+                _ => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid event category (process, file, network)", category)))
+            }
+        }
+        if weights.process == 0 && weights.file == 0 && weights.network == 0 {
+            return Err(GenerationError::new("input_format".to_string(), "At least one event category must have a weight above 0".to_string()));
+        }
+        Ok(weights)
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights { process: 1, file: 1, network: 1 }
+    }
+}
+
+/// Structure defining a randomized scenario which produces instruction rows instead of reading
+/// them from an input file. File events operate on a pool of generated temporary files so every
+/// modification and deletion targets a file created earlier in the run. Files remaining in the pool
+/// once all events are generated are deleted by additional rows.
+///
+/// # Parameters
+///
+/// - `rng`: seeded random number generator, the same seed produces the same rows
+/// - `categories`: distribution used to pick the category of each event
+/// - `remaining`: number of events left to generate
+/// - `files`: paths of generated files which currently exist
+pub struct RandomScenario {
+    rng: StdRng,
+    categories: WeightedIndex<u32>,
+    remaining: u64,
+    files: Vec<String>,
+}
+
+impl RandomScenario {
+    /// Instantiates a randomized scenario
+    ///
+    /// # Parameters
+    ///
+    /// - `count`: number of events to generate
+    /// - `weights`: relative weights of each event category
+    /// - `seed`: seed of the random number generator
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: RandomScenario instance
+    /// - `Err`: The weights can not be used for sampling
+    pub fn new(count: u64, weights: &Weights, seed: u64) -> Result<RandomScenario, GenerationError> {
+        let categories = match WeightedIndex::new(vec![weights.process, weights.file, weights.network]) {
+            Ok(inner) => inner,
+            Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("Invalid event weights: {}", e)))
+        };
+        Ok(RandomScenario {
+            rng: StdRng::seed_from_u64(seed),
+            categories,
+            remaining: count,
+            files: Vec::new(),
+        })
+    }
+
+    /// Generates the next instruction row
+    ///
+    /// # Returns
+    ///
+    /// The next row, or None once every event and clean up row has been generated
+    pub fn next_row(&mut self) -> Option<StringRecord> {
+        if self.remaining == 0 {
+            return self.files.pop().map(|path| StringRecord::from(vec!["delete_file".to_string(), path]));
+        }
+        self.remaining -= 1;
+        Some(match self.categories.sample(&mut self.rng) {
+            0 => process_row(),
+            1 => self.file_row(),
+            _ => {
+                let size = self.rng.gen_range(1..=512);
+                let message: String = (&mut self.rng).sample_iter(&Alphanumeric).take(size).map(char::from).collect();
+                StringRecord::from(vec!["connect_self".to_string(), message])
+            }
+        })
+    }
+
+    /// Generates a file event, creating a new file when the pool is empty
+    fn file_row(&mut self) -> StringRecord {
+        let operation = if self.files.is_empty() { 0 } else { self.rng.gen_range(0..3) };
+        match operation {
+            0 => {
+                let name: String = (&mut self.rng).sample_iter(&Alphanumeric).take(12).map(char::from).collect();
+                let path = env::temp_dir().join(format!("edr_random_{}.txt", name)).to_string_lossy().to_string();
+                self.files.push(path.clone());
+                StringRecord::from(vec!["new_file".to_string(), path])
+            }
+            1 => {
+                let path = self.files[self.rng.gen_range(0..self.files.len())].clone();
+                StringRecord::from(vec!["mod_file".to_string(), path])
+            }
+            _ => {
+                let path = self.files.swap_remove(self.rng.gen_range(0..self.files.len()));
+                StringRecord::from(vec!["delete_file".to_string(), path])
+            }
+        }
+    }
+}
+
+/// Generates a process row which starts the platform shell and exits immediately
+fn process_row() -> StringRecord {
+    if cfg!(windows) {
+        StringRecord::from(vec!["process", "cmd", "/c exit"])
+    } else {
+        StringRecord::from(vec!["process", "sh", "-c exit"])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(scenario: &mut RandomScenario) -> Vec<StringRecord> {
+        let mut rows = vec![];
+        while let Some(row) = scenario.next_row() {
+            rows.push(row);
+        }
+        rows
+    }
+
+    #[test]
+    fn weights_parsing() {
+        assert_eq!(Weights::parse("file=3, network=0").unwrap(), Weights { process: 1, file: 3, network: 0 });
+        assert!(Weights::parse("registry=1").is_err());
+        assert!(Weights::parse("file=lots").is_err());
+        assert!(Weights::parse("process=0,file=0,network=0").is_err());
+    }
+
+    #[test]
+    fn seed_reproducible() {
+        let weights = Weights::default();
+        let first = rows(&mut RandomScenario::new(50, &weights, 42).unwrap());
+        let second = rows(&mut RandomScenario::new(50, &weights, 42).unwrap());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn file_pool_cleaned_up() {
+        let weights = Weights { process: 0, file: 1, network: 0 };
+        let rows = rows(&mut RandomScenario::new(100, &weights, 7).unwrap());
+        let created = rows.iter().filter(|row| &row[0] == "new_file").count();
+        let deleted = rows.iter().filter(|row| &row[0] == "delete_file").count();
+        assert!(rows.len() >= 100);
+        assert_eq!(created, deleted);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::modules::common::{GenerationError, get_time};
+use crate::modules::logger::Log;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Largest number of files opened by a single `tamper_files` instruction
+pub const MAX_TAMPER_FILES: usize = 100;
+
+/// Number of bytes read from each file opened by a `tamper_files` instruction
+const READ_SIZE: usize = 4096;
+
+/// Suffix appended to the name of an agent binary by a `tamper_rename` instruction
+pub const RENAMED_SUFFIX: &str = ".edr_generator";
+
+/// Determines if agent services can be controlled on this platform, which needs `sc.exe` on
+/// Windows or `systemctl` on Linux
+///
+/// # Returns
+///
+/// True if `tamper_service` instructions can be executed on this platform
+pub fn services_supported() -> bool {
+    cfg!(any(windows, target_os = "linux"))
+}
+
+/// Queries the status of the service of a security agent with the service control tool of the
+/// platform (`sc.exe query` on Windows, `systemctl status` on Linux)
+///
+/// # Parameters
+///
+/// - `name`: name of the service (e.g. `WinDefend`, `falcon-sensor`)
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the query, with the status reported by the tool as its standard output
+/// - `Err`: The service does not exist, or the platform has no supported service control tool
+pub fn query_service(name: &str) -> Result<Log, GenerationError> {
+    let (program, arguments) = service_command("query", name)?;
+    let (mut log, code) = run_tool(program, &arguments)?;
+    // systemctl status exits with 3 for an inactive unit and 4 for a unit which does not exist
+    let found = match code {
+        Some(0) => true,
+        Some(3) => !cfg!(windows),
+        _ => false
+    };
+    if !found {
+        return Err(GenerationError::new("io".to_string(), format!("Service {} could not be queried: {}", name, log.stdout.trim())));
+    }
+    log.activity = "Agent Service Queried".to_string();
+    Ok(log)
+}
+
+/// Attempts to stop the service of a security agent, which its tamper protection is expected to
+/// deny (`sc.exe stop` on Windows, `systemctl stop` on Linux)
+///
+/// # Parameters
+///
+/// - `name`: name of the service
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the attempt, as an `Agent Service Stopped` event when the service was
+///   stopped or an `Agent Service Stop Denied` event with the exit code of the tool otherwise, and
+///   whether the service was stopped
+/// - `Err`: The tool could not be started, or the platform has no supported service control tool
+pub fn stop_service(name: &str) -> Result<(Log, bool), GenerationError> {
+    let (program, arguments) = service_command("stop", name)?;
+    let (mut log, code) = run_tool(program, &arguments)?;
+    let stopped = code == Some(0);
+    log.activity = match code {
+        Some(0) => "Agent Service Stopped".to_string(),
+        Some(code) => format!("Agent Service Stop Denied (exit code {})", code),
+        None => "Agent Service Stop Denied".to_string()
+    };
+    Ok((log, stopped))
+}
+
+/// Starts the service of a security agent again after it was stopped by `stop_service`
+///
+/// # Parameters
+///
+/// - `name`: name of the service
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the restart
+/// - `Err`: The service could not be started, the agent is left stopped
+pub fn start_service(name: &str) -> Result<Log, GenerationError> {
+    let (program, arguments) = service_command("start", name)?;
+    let (mut log, code) = run_tool(program, &arguments)?;
+    if code != Some(0) {
+        return Err(GenerationError::new("io".to_string(), format!("Service {} was stopped and could not be started again: {}", name, log.stdout.trim())));
+    }
+    log.activity = "Agent Service Restarted".to_string();
+    Ok(log)
+}
+
+/// Lists the files of the install directory of a security agent, including its subdirectories,
+/// sorted by path. Subdirectories which can not be listed and links to directories are skipped,
+/// and the walk stops once `MAX_TAMPER_FILES` files are found.
+///
+/// # Parameters
+///
+/// - `directory`: install directory of the agent
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Paths of up to `MAX_TAMPER_FILES` files
+/// - `Err`: The directory does not exist or can not be listed
+pub fn agent_files(directory: &Path) -> Result<Vec<PathBuf>, GenerationError> {
+    let mut files = Vec::new();
+    let mut pending = vec![directory.to_path_buf()];
+    let mut first = true;
+    'walk: while let Some(current) = pending.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(inner) => inner,
+            Err(e) if first => return Err(GenerationError::new("io".to_string(), format!("Unable to list {}: {}", current.display(), e))),
+            Err(_) => continue
+        };
+        first = false;
+        // file_type does not follow links, so a link to a directory can not lead the walk in a loop
+        let mut paths: Vec<(PathBuf, Option<fs::FileType>)> = entries.filter_map(|entry| entry.ok())
+            .map(|entry| (entry.path(), entry.file_type().ok()))
+            .collect();
+        paths.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut directories = Vec::new();
+        for (path, kind) in paths {
+            if kind.map(|kind| kind.is_dir()).unwrap_or(false) {
+                directories.push(path);
+            } else if kind.map(|kind| kind.is_symlink()).unwrap_or(false) && path.is_dir() {
+                continue;
+            } else {
+                files.push(path);
+                if files.len() == MAX_TAMPER_FILES {
+                    break 'walk;
+                }
+            }
+        }
+        pending.extend(directories.into_iter().rev());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Opens a file of a security agent read-only and reads its first block
+///
+/// # Parameters
+///
+/// - `path`: path of the file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the attempt, as an `Agent File Opened` event with the number of bytes read
+///   or an `Agent File Open Denied` event when access was denied
+/// - `Err`: The file could not be opened for another reason (e.g. it no longer exists)
+pub fn open_file(path: &Path) -> Result<Log, GenerationError> {
+    let mut buffer = [0; READ_SIZE];
+    let result = File::open(path).and_then(|mut file| file.read(&mut buffer));
+    let activity = match result {
+        Ok(size) => format!("Agent File Opened ({} bytes read)", size),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => "Agent File Open Denied".to_string(),
+        Err(e) => return Err(GenerationError::new("io".to_string(), format!("Unable to open {}: {}", path.display(), e)))
+    };
+    Ok(adapt_log_tamper(activity, path.to_string_lossy().to_string()))
+}
+
+/// Attempts to rename the binary of a security agent by appending `RENAMED_SUFFIX` to its name,
+/// which its tamper protection is expected to deny
+///
+/// # Parameters
+///
+/// - `path`: path of the binary
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the attempt, as an `Agent Binary Renamed` event or an
+///   `Agent Binary Rename Denied` event with the reason, and whether the binary was renamed
+/// - `Err`: The binary does not exist
+pub fn rename_binary(path: &Path) -> Result<(Log, bool), GenerationError> {
+    if !path.is_file() {
+        return Err(GenerationError::new("io".to_string(), format!("{} is not a file", path.display())));
+    }
+    let renamed = renamed_path(path);
+    let (activity, done) = match fs::rename(path, &renamed) {
+        Ok(_) => (format!("Agent Binary Renamed (to {})", renamed.display()), true),
+        Err(e) => (format!("Agent Binary Rename Denied ({})", e), false)
+    };
+    Ok((adapt_log_tamper(activity, path.to_string_lossy().to_string()), done))
+}
+
+/// Restores the name of an agent binary renamed by `rename_binary`
+///
+/// # Parameters
+///
+/// - `path`: original path of the binary
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the restore
+/// - `Err`: The binary could not be renamed back, it is left under its new name
+pub fn restore_binary(path: &Path) -> Result<Log, GenerationError> {
+    let renamed = renamed_path(path);
+    if let Err(e) = fs::rename(&renamed, path) {
+        return Err(GenerationError::new("io".to_string(), format!("{} was renamed to {} and could not be renamed back: {}", path.display(), renamed.display(), e)));
+    }
+    Ok(adapt_log_tamper("Agent Binary Restored".to_string(), path.to_string_lossy().to_string()))
+}
+
+/// Builds the path an agent binary is renamed to
+fn renamed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(RENAMED_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Selects the service control tool and its arguments for an action on a service
+///
+/// # Parameters
+///
+/// - `action`: `query`, `stop`, or `start`
+/// - `name`: name of the service
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The program and its arguments
+/// - `Err`: The platform has no supported service control tool
+fn service_command<'a>(action: &'a str, name: &'a str) -> Result<(&'static str, Vec<&'a str>), GenerationError> {
+    if !services_supported() {
+        Err(GenerationError::new("unsupported_platform".to_string(), "Agent services can only be controlled on Windows and Linux".to_string()))
+    } else if cfg!(windows) {
+        Ok(("sc.exe", vec![action, name]))
+    } else {
+        Ok(("systemctl", vec![if action == "query" { "status" } else { action }, "--no-pager", name]))
+    }
+}
+
+/// Runs an administration tool (a service control tool, or an account management tool), logging it
+/// as the process which acted on the service or account
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: Log data of the tool, with its standard output and standard error as the standard
+///   output, and its exit code
+/// - `Err`: The tool could not be started
+pub(crate) fn run_tool(program: &str, arguments: &[&str]) -> Result<(Log, Option<i32>), GenerationError> {
+    let child = Command::new(program).args(arguments).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let pid = child.id();
+    let output = child.wait_with_output()?;
+    let mut log = adapt_log_tamper("".to_string(), "".to_string());
+    log.proc_name = program.to_string();
+    log.proc_cmd = format!("{} {}", program, arguments.join(" "));
+    log.proc_id = pid.to_string();
+    log.stdout = String::from_utf8_lossy(&[output.stdout, output.stderr].concat()).trim().to_string();
+    Ok((log, output.status.code()))
+}
+
+/// Adapts a tampering event into a log struct used for logging
+///
+/// # Parameters
+///
+/// - `activity`: A string containing the type of activity that has occurred
+/// - `file_path`: A string containing the path of the file of the agent, if any
+///
+/// # Returns
+///
+/// A Log struct customized for tampering events
+fn adapt_log_tamper(activity: String, file_path: String) -> Log {
+    Log{
+        t: String::from("Information"),
+        timestamp: get_time(),
+        username: String::from(""),
+        proc_name: String::from(""),
+        proc_cmd: String::from(""),
+        proc_id: String::from(""),
+        activity,
+        file_path,
+        source_addr: String::from(""),
+        source_port: String::from(""),
+        dest_addr: String::from(""),
+        dest_port: String::from(""),
+        bytes_sent: String::from(""),
+        protocol: String::from(""),
+        address_family: String::from(""),
+        stdout: String::from(""),
+        stderr: String::from(""),
+        exit_code: String::from("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::file_system;
+    use crate::modules::testing::TempPath;
+
+    #[test]
+    fn agent_files_tampered() {
+        let directory = TempPath::new("agent");
+        fs::create_dir_all(directory.join("drivers")).unwrap();
+        fs::write(directory.join("agent.exe"), "MZ agent").unwrap();
+        fs::write(directory.join("drivers").join("filter.sys"), "driver").unwrap();
+
+        let files = agent_files(&directory).unwrap();
+        assert_eq!(files, vec![directory.join("agent.exe"), directory.join("drivers").join("filter.sys")]);
+        let opened = open_file(&files[0]).unwrap();
+        assert_eq!(opened.activity, "Agent File Opened (8 bytes read)");
+        assert_eq!(opened.file_path, files[0].to_string_lossy());
+
+        let (renamed, done) = rename_binary(&files[0]).unwrap();
+        assert!(done);
+        assert!(renamed.activity.starts_with("Agent Binary Renamed (to "));
+        assert!(!files[0].exists());
+        assert_eq!(restore_binary(&files[0]).unwrap().activity, "Agent Binary Restored");
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "MZ agent");
+
+        file_system::symlink(&directory.as_string(), &directory.join("drivers").join("agent").to_string_lossy().to_string()).unwrap();
+        assert_eq!(agent_files(&directory).unwrap(), files);
+        for index in 0..MAX_TAMPER_FILES {
+            fs::write(directory.join("drivers").join(format!("{:03}.dll", index)), "library").unwrap();
+        }
+        let files = agent_files(&directory).unwrap();
+        assert_eq!(files.len(), MAX_TAMPER_FILES);
+        assert_eq!(files[0], directory.join("agent.exe"));
+
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(agent_files(&directory).is_err());
+        assert!(rename_binary(&files[0]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::modules::common::GenerationError;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+
+/// Transformations which may be applied to a template field with `{{ field | filter }}`
+///
+/// # Variants
+///
+/// - `Json`: quoted and escaped JSON string, for building JSON documents
+/// - `Upper`: uppercase value
+/// - `Lower`: lowercase value
+/// - `Basename`: final component of a file path
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Json,
+    Upper,
+    Lower,
+    Basename,
+}
+
+/// A parsed element of a template
+///
+/// # Variants
+///
+/// - `Text`: literal text copied to the output
+/// - `Field`: name of a record field followed by the filters applied to its value, in order
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(String, Vec<Filter>),
+}
+
+/// Structure defining a user supplied output template. Placeholders of the form `{{ field }}` are
+/// replaced with the value of the matching record field, fields a record does not have (such as
+/// `file_path` on an error record) are replaced with an empty string.
+///
+/// # Parameters
+///
+/// - `parts`: literal text and placeholders making up the template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Reads and parses a template file. A single trailing line terminator is ignored so every
+    /// record is written on its own line.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path of the template file
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The parsed template
+    /// - `Err`: The file could not be read or is not a valid template
+    pub fn load(path: &String) -> Result<Template, GenerationError> {
+        let contents = fs::read_to_string(path)?;
+        let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+        let contents = contents.strip_suffix('\r').unwrap_or(contents);
+        match Template::parse(contents) {
+            Ok(template) => Ok(template),
+            Err(e) => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid template: {}", path, e.message)))
+        }
+    }
+
+    /// Parses template text
+    ///
+    /// # Parameters
+    ///
+    /// - `text`: template containing `{{ field }}` or `{{ field | filter | ... }}` placeholders
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The parsed template
+    /// - `Err`: A placeholder is unterminated, empty, or uses an unknown filter
+    pub fn parse(text: &str) -> Result<Template, GenerationError> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = match rest[start..].find("}}") {
+                Some(inner) => start + inner,
+                None => return Err(GenerationError::new("input_format".to_string(), format!("Placeholder {} is missing a closing }}}}", &rest[start..])))
+            };
+            let mut segments = rest[start + 2..end].split('|').map(|segment| segment.trim());
+            let name = segments.next().unwrap_or("");
+            if name.is_empty() {
+                return Err(GenerationError::new("input_format".to_string(), format!("Placeholder {} does not name a field", &rest[start..end + 2])));
+            }
+            let mut filters = Vec::new();
+            for filter in segments {
+                filters.push(match filter {
+                    "json" => Filter::Json,
+                    "upper" => Filter::Upper,
+                    "lower" => Filter::Lower,
+                    "basename" => Filter::Basename,
+                    _ => return Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid filter (json, upper, lower, basename)", filter)))
+                });
+            }
+            parts.push(Part::Field(name.to_string(), filters));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Renders a record with the template
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: record whose fields are substituted into the template
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The rendered record
+    /// - `Err`: The record could not be serialized
+    pub fn render<S: Serialize>(&self, record: &S) -> Result<String, GenerationError> {
+        let fields = match serde_json::to_value(record) {
+            Ok(Value::Object(inner)) => inner,
+            _ => return Err(GenerationError::new("logging".to_string(), "Unable to Serialize Log Message".to_string()))
+        };
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field(name, filters) => {
+                    let mut value = match fields.get(name) {
+                        Some(Value::String(inner)) => inner.clone(),
+                        Some(Value::Null) | None => "".to_string(),
+                        Some(other) => other.to_string()
+                    };
+                    for filter in filters {
+                        value = apply(filter, value);
+                    }
+                    line.push_str(&value);
+                }
+            }
+        }
+        Ok(line)
+    }
+}
+
+fn apply(filter: &Filter, value: String) -> String {
+    match filter {
+        Filter::Json => Value::String(value).to_string(),
+        Filter::Upper => value.to_uppercase(),
+        Filter::Lower => value.to_lowercase(),
+        Filter::Basename => value.rsplit(['/', '\\']).next().unwrap_or("").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::logger::LogError;
+
+    #[test]
+    fn render_fields() {
+        let template = Template::parse(r#"{"type": {{t|json}}, "msg": {{ message | upper | json }}, "file": "{{ file_path | basename }}"}"#).unwrap();
+        let record = LogError {
+            t: String::from("Error"),
+            timestamp: String::from("2021-04-15T05:49:10+00:00"),
+            message: String::from("io: \"C:\\temp\" missing"),
+        };
+        assert_eq!(template.render(&record).unwrap(), r#"{"type": "Error", "msg": "IO: \"C:\\TEMP\" MISSING", "file": ""}"#);
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(Template::parse("{{ t").is_err());
+        assert!(Template::parse("{{ }}").is_err());
+        assert!(Template::parse("{{ t | reverse }}").is_err());
+        assert_eq!(Template::parse("plain text").unwrap().parts, vec![Part::Text(String::from("plain text"))]);
+    }
+}
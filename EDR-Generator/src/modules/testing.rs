@@ -0,0 +1,98 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of temporary paths reserved so far by the tests of this process
+static RESERVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Path in the temporary directory which no other test, nor another run of the tests, uses.
+/// Nothing is created; whatever the test creates at the path is removed when it is dropped, even
+/// when the test fails.
+///
+/// # Parameters
+///
+/// - `path`: the reserved path
+pub(crate) struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// Reserves a path in the temporary directory ending with a name, e.g. `log.csv`
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: file or directory name, keeping its extension
+    ///
+    /// # Returns
+    ///
+    /// TempPath instance
+    pub(crate) fn new(name: &str) -> TempPath {
+        let index = RESERVED.fetch_add(1, Ordering::SeqCst);
+        TempPath { path: env::temp_dir().join(format!("edr_{}_{}_{}", process::id(), index, name)) }
+    }
+
+    /// Converts the path to a string, as taken by most instructions and outputs
+    pub(crate) fn as_string(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = match self.path.is_dir() {
+            true => fs::remove_dir_all(&self.path),
+            false => fs::remove_file(&self.path)
+        };
+    }
+}
+
+/// Reads an HTTP request sent to a test server: its request line and headers, then as many bytes
+/// of body as its `Content-Length`
+///
+/// # Parameters
+///
+/// - `stream`: connection the request is read from
+///
+/// # Returns
+///
+/// The request as received
+pub(crate) fn read_request(stream: &TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+            request.push_str(&line);
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        request.push_str(&line);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    request + &String::from_utf8_lossy(&body)
+}
@@ -0,0 +1,656 @@
+use crate::modules::instruction::{FileCmd, NetworkCmd, PauseCmd, ProcessCmd};
+use crate::modules::commander::{parse_beacon, parse_wait, split_overrides, split_netns, split_os, split_save, split_shaping, split_timeout, runs_on, DEFAULT_ENCRYPTED_EXTENSION};
+use crate::modules::common::GenerationError;
+use crate::modules::file_system;
+use crate::modules::network;
+use crate::modules::persistence;
+use crate::modules::scenario;
+use crate::modules::tamper;
+use crate::modules::account;
+use crate::modules::alias::Aliases;
+use crate::modules::script::{self, Script};
+use csv::StringRecord;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Structure defining the outcome of validating a single instruction
+///
+/// # Parameters
+///
+/// - `location`: position of the instruction in the scenario (line or step)
+/// - `record`: instruction after variables were substituted
+/// - `result`: whether the instruction is valid, and the problem found if not
+/// - `artifacts`: artifacts the instruction would create or touch, see `preview`, empty when the
+///   instruction is not valid
+pub struct Finding {
+    pub location: String,
+    pub record: StringRecord,
+    pub result: Result<(), GenerationError>,
+    pub artifacts: Vec<PlannedArtifact>,
+}
+
+/// Structure defining an artifact an instruction would create or touch when executed
+///
+/// # Parameters
+///
+/// - `kind`: kind of the artifact (`process`, `file`, `directory`, `connection`, `listener`,
+///   `registry`, `crontab`, `service`, or `task`)
+/// - `target`: resolved path, command line, address, or key of the artifact, followed by what the
+///   instruction does to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedArtifact {
+    pub kind: String,
+    pub target: String,
+}
+
+impl fmt::Display for PlannedArtifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.target)
+    }
+}
+
+/// Structure defining the state of a dry run. Files created and deleted by earlier instructions are
+/// remembered so file instructions are checked against the state the scenario would leave the file
+/// system in, rather than its current state, and process aliases are remembered so processes are
+/// only waited for once started. Saved results are remembered so they are only referenced once
+/// saved.
+///
+/// # Parameters
+///
+/// - `files`: paths touched by earlier instructions, and whether the file exists afterwards
+/// - `aliases`: aliases given to processes by earlier instructions
+/// - `results`: names earlier instructions saved their result as
+pub struct Validator {
+    files: HashMap<String, bool>,
+    aliases: HashSet<String>,
+    results: HashSet<String>,
+}
+
+impl Validator {
+    /// Instantiates a Validator for a new scenario
+    ///
+    /// # Returns
+    ///
+    /// Validator instance
+    pub fn new() -> Validator {
+        Validator { files: HashMap::new(), aliases: HashSet::new(), results: HashSet::new() }
+    }
+
+    /// Checks a single instruction without executing it: the instruction name, the number of
+    /// parameters, numeric parameters, log field overrides, and whether executables and files
+    /// plausibly exist
+    ///
+    /// # Parameters
+    ///
+    /// - `record`: instruction to check, after variables were substituted
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The instruction is valid
+    /// - `Err`: The problem which would cause the instruction to fail
+    pub fn check(&mut self, record: &StringRecord) -> Result<(), GenerationError> {
+        let (record, _) = split_overrides(record.clone())?;
+        let (record, save) = split_save(record)?;
+        let (record, platforms) = split_os(record)?;
+        if platforms.is_some_and(|platforms| !runs_on(&platforms)) {
+            // skipped on this platform, its paths and executables are those of another one
+            return Ok(());
+        }
+        if let Some(name) = script::referenced_results(&record).into_iter().find(|name| !self.results.contains(name)) {
+            return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} would fail: no earlier instruction saves its result as {}", record, name)));
+        }
+        if let Some(name) = save {
+            self.results.insert(name);
+        }
+        if !script::referenced_results(&record).is_empty() {
+            // the values of saved results are only known once the scenario runs
+            return Ok(());
+        }
+        let (record, _) = split_timeout(record)?;
+        let (record, netns) = split_netns(record)?;
+        if let Some(name) = netns {
+            if !cfg!(target_os = "linux") {
+                return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: network namespaces are only supported on Linux", record)));
+            }
+            if !network::namespace_path(&name).exists() {
+                return Err(GenerationError::new("network".to_string(), format!("Record {:?} would fail: network namespace {} does not exist", record, name)));
+            }
+        }
+        let (record, _) = split_shaping(record)?;
+        match record.get(0).unwrap_or("") {
+            "process" => {
+                let command = ProcessCmd::parse(&record)?;
+                check_executable(&record, &command.path)?;
+                if let Some(alias) = command.options.alias {
+                    self.aliases.insert(alias);
+                }
+                Ok(())
+            }
+            "wait_process" => {
+                let (target, _) = parse_wait(&record)?;
+                if target.parse::<usize>().is_err() && !self.aliases.contains(&target) {
+                    return Err(GenerationError::new("process".to_string(), format!("Record {:?} would fail: no earlier process has the alias {}", record, target)));
+                }
+                Ok(())
+            }
+            "process_with_parent" => {
+                require(&record, record.len() >= 3 && !record[1].trim().is_empty(), "a process with a parent (process_with_parent,<parent_name>,<path>,[arguments...])")?;
+                check_executable(&record, &record[2])
+            }
+            "process_in_session" => {
+                require(&record, record.len() >= 3 && !record[1].trim().is_empty(), "a process in a session (process_in_session,<session|user|other>,<path>,[arguments...])")?;
+                if !cfg!(windows) {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: processes can only be started in another user session on Windows", record)));
+                }
+                check_executable(&record, &record[2])
+            }
+            "process_storm" => {
+                let format = "a process storm (process_storm,<path>,<count>,<interval_ms>,[arguments...])";
+                require(&record, record.len() >= 4, format)?;
+                parse_number(&record, &record[2], format)?;
+                parse_number(&record, &record[3], format)?;
+                check_executable(&record, &record[1])
+            }
+            "new_file" | "mod_file" | "delete_file" => {
+                FileCmd::parse(&record)?;
+                self.check_file(&record)
+            }
+            "connect" | "connect_self" | "fan_out" => NetworkCmd::parse(&record).map(|_| ()),
+            "beacon" => parse_beacon(&record).map(|_| ()),
+            "listen" => {
+                let format = "a listener (listen,<interface>,<port>,[timeout_ms])";
+                require(&record, record.len() >= 3 && !record[1].trim().is_empty(), format)?;
+                if record[2].parse::<u16>().is_err() {
+                    return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}: {} is not a valid port", record, format, &record[2])));
+                }
+                match record.get(3).filter(|timeout| !timeout.is_empty()) {
+                    Some(timeout) => parse_number(&record, timeout, format).map(|_| ()),
+                    None => Ok(())
+                }
+            }
+            "exfil" => {
+                let format = "an exfiltration (exfil,<file_path>,<destination_host>,<destination_port>,[tcp|http])";
+                require(&record, record.len() >= 4 && !record[1].is_empty() && !record[2].trim().is_empty(), format)?;
+                if record[3].parse::<u16>().is_err() {
+                    return Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}: {} is not a valid port", record, format, &record[3])));
+                }
+                require(&record, matches!(record.get(4).unwrap_or("tcp").to_lowercase().as_str(), "tcp" | "http" | ""), format)?;
+                self.check_file(&record)
+            }
+            "encrypt_burst" => {
+                let format = "an encryption burst (encrypt_burst,<directory>,<count>,[extension])";
+                require(&record, record.len() >= 3 && !record[1].is_empty(), format)?;
+                parse_number(&record, &record[2], format)?;
+                let extension = record.get(3).filter(|extension| !extension.is_empty()).unwrap_or(DEFAULT_ENCRYPTED_EXTENSION).trim_start_matches('.');
+                require(&record, !extension.is_empty() && !extension.contains(&['/', '\\'][..]), format)?;
+                let exists = self.files.get(&record[1]).copied().unwrap_or_else(|| Path::new(&record[1]).exists());
+                if exists {
+                    return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: the directory already exists", record)));
+                }
+                self.files.insert(record[1].to_string(), true);
+                Ok(())
+            }
+            "symlink" | "hardlink" => {
+                let format = format!("a link ({},<target>,<link>)", &record[0]);
+                require(&record, record.len() >= 3 && !record[1].is_empty() && !record[2].is_empty(), &format)?;
+                let exists = |validator: &Validator, path: &str| validator.files.get(path).copied().unwrap_or_else(|| Path::new(path).exists());
+                if &record[0] == "hardlink" && !exists(self, &record[1]) {
+                    return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: the target does not exist", record)));
+                }
+                if exists(self, &record[2]) {
+                    return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: the link already exists", record)));
+                }
+                self.files.insert(record[2].to_string(), true);
+                Ok(())
+            }
+            "chmod_file" => {
+                let format = "a permission change (chmod_file,<path>,<mode|attributes>)";
+                let permissions = record.get(2).and_then(file_system::FilePermissions::parse);
+                require(&record, record.len() >= 3 && !record[1].is_empty() && permissions.is_some(), format)?;
+                if !permissions.is_some_and(|permissions| permissions.supported()) {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: {} is not supported on this platform, use octal mode bits on Unix and attributes on Windows", record, &record[2])));
+                }
+                self.check_file(&record)
+            }
+            "ads_write" => {
+                let format = "an alternate data stream write (ads_write,<path>,<stream>,[payload])";
+                require(&record, record.len() >= 3 && !record[1].is_empty() && file_system::valid_stream_name(&record[2]), format)?;
+                if !cfg!(windows) {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: alternate data streams are only supported on Windows (NTFS)", record)));
+                }
+                self.files.insert(record[1].to_string(), true);
+                Ok(())
+            }
+            "persist_runkey" | "persist_startup" | "persist_cron" | "persist_service" => {
+                let target = if &record[0] == "persist_cron" { "<schedule>" } else { "<name>" };
+                let format = format!("a persistence mechanism ({},{},<command>)", &record[0], target);
+                require(&record, record.len() >= 3 && persistence::valid_fields(&record[0], &record[1], &record[2]), &format)?;
+                if !persistence::supported(&record[0]) {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: {} is not supported on this platform", record, &record[0])));
+                }
+                Ok(())
+            }
+            "schedule_task" => {
+                let format = "a scheduled task (schedule_task,<name>,<onstart|minute|hourly|daily|weekly>,<command>)";
+                require(&record, record.len() >= 4 && persistence::valid_fields(&record[0], &record[1], &record[3]), format)?;
+                require(&record, persistence::TRIGGERS.iter().any(|(trigger, _, _)| trigger.eq_ignore_ascii_case(&record[2])), format)
+            }
+            "tamper_service" => {
+                require(&record, record.len() >= 2 && !record[1].is_empty(), "agent tampering (tamper_service,<service>)")?;
+                if !tamper::services_supported() {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: agent services can only be controlled on Windows and Linux", record)));
+                }
+                Ok(())
+            }
+            "tamper_files" => {
+                require(&record, record.len() >= 2 && !record[1].is_empty(), "agent tampering (tamper_files,<directory>)")?;
+                if !Path::new(&record[1]).is_dir() {
+                    return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: the directory does not exist", record)));
+                }
+                Ok(())
+            }
+            "tamper_rename" => {
+                require(&record, record.len() >= 2 && !record[1].is_empty(), "agent tampering (tamper_rename,<path>)")?;
+                if !self.files.get(&record[1]).copied().unwrap_or_else(|| Path::new(&record[1]).is_file()) {
+                    return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: the file does not exist", record)));
+                }
+                Ok(())
+            }
+            "create_user" | "delete_user" | "add_to_group" => {
+                let group = &record[0] == "add_to_group";
+                let format = if group { "an account instruction (add_to_group,<name>,<group>)".to_string() } else { format!("an account instruction ({},<name>)", &record[0]) };
+                require(&record, record.len() >= if group { 3 } else { 2 } && account::valid_name(&record[1]) && (!group || account::valid_group(&record[2])), &format)?;
+                if !account::supported() {
+                    return Err(GenerationError::new("unsupported_platform".to_string(), format!("Record {:?} would fail: local accounts can only be managed on Windows, Linux, and macOS", record)));
+                }
+                if !account::elevated() {
+                    return Err(GenerationError::new("user_permissions".to_string(), format!("Record {:?} would fail: managing local accounts requires Administrator (root)", record)));
+                }
+                Ok(())
+            }
+            "pause" => PauseCmd::parse(&record).map(|_| ()),
+            name => Err(GenerationError::new("input_format".to_string(), format!("{} is not a valid instruction", name)))
+        }
+    }
+
+    /// Checks a file instruction against the files created and deleted by earlier instructions,
+    /// falling back to the current file system for paths the scenario has not touched yet
+    fn check_file(&mut self, record: &StringRecord) -> Result<(), GenerationError> {
+        let path = &record[1];
+        let exists = match self.files.get(path) {
+            Some(exists) => *exists,
+            None => Path::new(path).exists()
+        };
+        let problem = match &record[0] {
+            "new_file" if exists => Some("the file already exists".to_string()),
+            "new_file" => match Path::new(path).parent() {
+                Some(directory) if !directory.as_os_str().is_empty() && !directory.is_dir() => Some(format!("the directory {} does not exist", directory.display())),
+                _ => None
+            },
+            _ if !exists => Some("the file does not exist".to_string()),
+            _ => None
+        };
+        if let Some(problem) = problem {
+            return Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: {}", record, problem)));
+        }
+        self.files.insert(path.to_string(), &record[0] != "delete_file");
+        Ok(())
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new()
+    }
+}
+
+/// Checks every instruction of a csv or structured scenario file without executing anything
+///
+/// # Parameters
+///
+/// - `path`: path of the scenario file
+/// - `deliminator`: deliminator used by a csv scenario file
+/// - `aliases`: alternative names of instructions used by a csv scenario file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A finding for every instruction, in execution order
+/// - `Err`: The scenario could not be read or parsed (e.g. a `repeat` without an `end`)
+pub fn validate_scenario(path: &String, deliminator: u8, aliases: &Aliases) -> Result<Vec<Finding>, GenerationError> {
+    let mut validator = Validator::new();
+    let mut findings = Vec::new();
+    if scenario::is_structured(path) {
+        for (index, step) in scenario::load(path)?.steps.into_iter().enumerate() {
+            let record = script::substitute(&step.to_record(), 0);
+            let location = match &step.name {
+                Some(name) => format!("step {} ({})", index + 1, name),
+                None => format!("step {}", index + 1)
+            };
+            let result = validator.check(&record);
+            let artifacts = if result.is_ok() { preview(&record) } else { Vec::new() };
+            findings.push(Finding { location, record, result, artifacts });
+        }
+    } else {
+        let mut script = Script::new(path, deliminator)?;
+        while let Some((record, index)) = script.next_row() {
+            let line = record.position().map(|position| position.line()).unwrap_or(0);
+            let location = if index > 0 { format!("line {} (iteration {})", line, index + 1) } else { format!("line {}", line) };
+            let record = aliases.resolve(script::substitute(&record, index));
+            let result = validator.check(&record);
+            let artifacts = if result.is_ok() { preview(&record) } else { Vec::new() };
+            findings.push(Finding { location, record, result, artifacts });
+        }
+    }
+    Ok(findings)
+}
+
+/// Determines the artifacts an instruction would create or touch, so the blast radius of a
+/// scenario can be reviewed before it is executed: executables resolved on the `PATH`, files as
+/// absolute paths, destination hosts and ports, and the locations of persistence artifacts. The
+/// instruction is expected to be valid, see `Validator::check`. Paths of a run with `--workdir`
+/// resolve inside its working directory instead.
+///
+/// # Parameters
+///
+/// - `record`: instruction, after variables were substituted
+///
+/// # Returns
+///
+/// The artifacts, in the order the instruction creates them
+pub fn preview(record: &StringRecord) -> Vec<PlannedArtifact> {
+    let (record, platforms) = match split_overrides(record.clone()).and_then(|(record, _)| split_save(record)).and_then(|(record, _)| split_os(record)) {
+        Ok(inner) => inner,
+        Err(_) => return Vec::new()
+    };
+    if platforms.is_some_and(|platforms| !runs_on(&platforms)) {
+        return Vec::new();
+    }
+    let (record, netns) = match split_timeout(record).and_then(|(record, _)| split_netns(record)) {
+        Ok(inner) => inner,
+        Err(_) => return Vec::new()
+    };
+    let record = match split_shaping(record) {
+        Ok((record, _)) => record,
+        Err(_) => return Vec::new()
+    };
+    // connections and listeners of an instruction run in a network namespace belong to the namespace
+    let artifact = |kind: &str, target: String| match &netns {
+        Some(name) if kind == "connection" || kind == "listener" => PlannedArtifact { kind: kind.to_string(), target: format!("{} in netns {}", target, name) },
+        _ => PlannedArtifact { kind: kind.to_string(), target }
+    };
+    let field = |index: usize| record.get(index).unwrap_or("");
+    match field(0) {
+        "process" => {
+            match ProcessCmd::parse(&record) {
+                Ok(command) => vec![artifact("process", command_line(&command.path, command.arguments.iter().map(String::as_str)))],
+                Err(_) => vec![artifact("process", command_line(field(1), record.iter().skip(2)))]
+            }
+        }
+        "process_with_parent" => vec![artifact("process", format!("{} (parent {})", command_line(field(2), record.iter().skip(3)), field(1).trim()))],
+        "process_in_session" => vec![artifact("process", format!("{} (session {})", command_line(field(2), record.iter().skip(3)), field(1).trim()))],
+        "process_storm" => vec![artifact("process", format!("{} ({} times)", command_line(field(1), record.iter().skip(4)), field(2)))],
+        "new_file" => vec![artifact("file", format!("{} (create)", absolute(field(1))))],
+        "mod_file" => vec![artifact("file", format!("{} (modify)", absolute(field(1))))],
+        "delete_file" => vec![artifact("file", format!("{} (delete)", absolute(field(1))))],
+        "connect" => vec![artifact("connection", destination(field(1), field(2)))],
+        "fan_out" => match NetworkCmd::parse(&record) {
+            Ok(NetworkCmd::FanOut { hosts, ports, concurrency, .. }) => {
+                let count = hosts.len() * ports.len();
+                let described = |values: &[String]| if values.len() > 1 { format!("{} .. {}", values[0], values[values.len() - 1]) } else { values[0].clone() };
+                let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+                vec![artifact("connection", format!("{} port {} ({} connections, {} at once)", described(&hosts), described(&ports), count, concurrency.min(count)))]
+            }
+            _ => vec![artifact("connection", format!("{} port {}", field(1), field(2)))]
+        },
+        "connect_self" => vec![artifact("connection", "127.0.0.1 (loopback)".to_string())],
+        "beacon" => vec![artifact("connection", format!("{} ({} beacons every {} ms)", destination(field(1), field(2)), field(4), field(3)))],
+        "listen" => vec![artifact("listener", destination(field(1), field(2)))],
+        "exfil" => {
+            let protocol = record.get(4).filter(|protocol| !protocol.is_empty()).unwrap_or("tcp").to_lowercase();
+            vec![artifact("file", format!("{} (read)", absolute(field(1)))), artifact("connection", format!("{} ({})", destination(field(2), field(3)), protocol))]
+        }
+        "encrypt_burst" => {
+            let extension = record.get(3).filter(|extension| !extension.is_empty()).unwrap_or(DEFAULT_ENCRYPTED_EXTENSION).trim_start_matches('.');
+            vec![artifact("directory", format!("{} (create, {} files renamed to .{})", absolute(field(1)), field(2), extension))]
+        }
+        "ads_write" => vec![artifact("file", format!("{}:{} (write)", absolute(field(1)), field(2)))],
+        "symlink" | "hardlink" => vec![artifact("file", format!("{} (link to {})", absolute(field(2)), field(1)))],
+        "chmod_file" => vec![artifact("file", format!("{} (permissions {})", absolute(field(1)), field(2)))],
+        instruction @ ("persist_runkey" | "persist_startup" | "persist_cron" | "persist_service" | "schedule_task") => {
+            let kind = match instruction {
+                "persist_runkey" => "registry",
+                "persist_startup" => "file",
+                "persist_cron" => "crontab",
+                "persist_service" => "service",
+                _ => "task"
+            };
+            let command = if instruction == "schedule_task" { field(3) } else { field(2) };
+            match persistence::location(instruction, field(1)) {
+                Ok(location) => vec![artifact(kind, format!("{} (starts {})", location, command))],
+                Err(e) => vec![artifact(kind, format!("unknown location: {}", e.message))]
+            }
+        }
+        "tamper_service" => vec![artifact("service", format!("{} (query and stop attempt)", field(1)))],
+        "tamper_files" => vec![artifact("directory", format!("{} (read, up to {} files)", absolute(field(1)), tamper::MAX_TAMPER_FILES))],
+        "tamper_rename" => vec![artifact("file", format!("{} (rename attempt to {}{})", absolute(field(1)), field(1), tamper::RENAMED_SUFFIX))],
+        "create_user" => vec![artifact("account", format!("{} (create)", field(1)))],
+        "delete_user" => vec![artifact("account", format!("{} (delete)", field(1)))],
+        "add_to_group" => vec![artifact("account", format!("{} (add to group {})", field(1), field(2)))],
+        _ => Vec::new()
+    }
+}
+
+/// Builds the command line of a process instruction, with the executable resolved on the `PATH`
+fn command_line<'a>(executable: &str, arguments: impl Iterator<Item = &'a str>) -> String {
+    let executable = find_executable(executable).map(|path| path.to_string_lossy().to_string()).unwrap_or_else(|| executable.to_string());
+    let arguments: Vec<&str> = arguments.collect();
+    if arguments.is_empty() { executable } else { format!("{} {}", executable, arguments.join(" ")) }
+}
+
+/// Formats the destination of a network instruction, bracketing IPv6 addresses. Hostnames are not
+/// resolved.
+fn destination(host: &str, port: &str) -> String {
+    match port.trim().parse() {
+        Ok(port) => network::host_port(host, port),
+        Err(_) => format!("{}:{}", host.trim(), port)
+    }
+}
+
+/// Resolves a relative path against the current directory, without requiring the file to exist
+fn absolute(path: &str) -> String {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.to_string_lossy().to_string();
+    }
+    env::current_dir().map(|directory| directory.join(path)).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+/// Reports an instruction with the wrong number of parameters
+fn require(record: &StringRecord, condition: bool, format: &str) -> Result<(), GenerationError> {
+    if condition {
+        return Ok(());
+    }
+    Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}", record, format)))
+}
+
+/// Parses a numeric parameter, reporting the instruction format when it is not a number
+fn parse_number(record: &StringRecord, value: &str, format: &str) -> Result<u64, GenerationError> {
+    match value.parse::<u64>() {
+        Ok(inner) => Ok(inner),
+        Err(_) => Err(GenerationError::new("input_format".to_string(), format!("Record {:?} is not formatted correctly for {}: {} is not a number", record, format, value)))
+    }
+}
+
+/// Checks that an executable exists, either at the given path or in a directory on the `PATH`
+fn check_executable(record: &StringRecord, executable: &str) -> Result<(), GenerationError> {
+    if find_executable(executable).is_some() {
+        return Ok(());
+    }
+    Err(GenerationError::new("io".to_string(), format!("Record {:?} would fail: {} was not found", record, executable)))
+}
+
+/// Locates an executable, either at the given path or in a directory on the `PATH`
+fn find_executable(executable: &str) -> Option<PathBuf> {
+    let candidate = Path::new(executable);
+    if executable.is_empty() {
+        None
+    } else if candidate.components().count() > 1 {
+        Some(candidate.to_path_buf()).filter(|path| path.is_file())
+    } else {
+        let extensions: Vec<String> = if cfg!(windows) && candidate.extension().is_none() {
+            env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".to_string()).split(';').map(|extension| extension.to_string()).collect()
+        } else {
+            vec!["".to_string()]
+        };
+        let paths = env::var_os("PATH")?;
+        env::split_paths(&paths).find_map(|directory| {
+            extensions.iter().map(|extension| directory.join(format!("{}{}", executable, extension))).find(|path| path.is_file())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+
+    fn get_os_shell() -> &'static str {
+        if cfg!(windows) { "cmd" } else { "sh" }
+    }
+
+    #[test]
+    fn instruction_formats() {
+        let mut validator = Validator::new();
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "run_for=100"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "run_for=soon"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process", "not_a_real_binary_x24"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process_storm", get_os_shell(), "10"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "stdin=whoami", "capture=4096", "run_for=100"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "capture=0"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "capture=2000000"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "99999", "hello"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "retries=3", "backoff=200"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "retries=many"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "retries=3", "latency=200", "loss=5%", "bandwidth=64K"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "loss=100"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "latency=1", "latency=2"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "connect_timeout=500"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "connect_timeout=0"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["fan_out", "10.0.0.0/24", "22 445 3389", "hello", "concurrency=256", "connect_timeout=500"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["fan_out", "10.0.0.0/24", "22", "hello", "concurrency=5000"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["fan_out", "10.0.0.0/24", "22", "hello", "save=scan"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["exfil", "Cargo.toml", "10.0.0.5", "8080", "bandwidth=0"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100", "", "20", "loss=10", "loss_seed=7"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect", "127.0.0.1", "80", "hello", "retries=3", "timeout=2000"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["listen", "0.0.0.0", "4444", "timeout=0"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "@username=bob"])).is_ok());
+        assert_eq!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "netns=/proc/self/ns/net"])).is_ok(), cfg!(target_os = "linux"));
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "netns=edr_missing_x24"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "netns="])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100", "", "20"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "0", "100"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100", "1000000"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000", "100", "64", "150"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["beacon", "10.0.0.5", "443", "60000"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "@colour=blue"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["pause", "1s"])).is_err());
+        let other = if cfg!(windows) { "linux" } else { "windows" };
+        assert!(validator.check(&StringRecord::from(vec!["process", "not_an_executable_x24", &format!("os={}", other)])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["process", "not_an_executable_x24", &format!("os={}|{}", other, env::consts::OS)])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["pause", "100", "os=beos"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "${shell.proc_id}"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "save=shell"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "${shell.proc_id}"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["connect_self", "hello", "save=a-b"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["listen", "0.0.0.0", "4444", "5000"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["listen", "0.0.0.0", "4444", "soon"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["exfil", "Cargo.toml", "10.0.0.5", "8080", "http"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["exfil", "Cargo.toml", "10.0.0.5", "8080", "ftp"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["exfil", "not_a_real_file_x24", "10.0.0.5", "8080"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["encrypt_burst", "edr_validate_burst_x24", "50", ".crypt"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["encrypt_burst", "edr_validate_burst_x24", "50"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["encrypt_burst", "src", "50"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["encrypt_burst", "edr_validate_other_x24", "many"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["ads_write", "edr_validate_host_x24.txt", "payload.exe"])).is_ok(), cfg!(windows));
+        assert!(validator.check(&StringRecord::from(vec!["ads_write", "edr_validate_host_x24.txt", "payload.exe:$DATA"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["symlink", "edr_validate_missing_x24.txt", "edr_validate_link_x24"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["symlink", "Cargo.toml", "edr_validate_link_x24"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["hardlink", "edr_validate_missing_x24.txt", "edr_validate_hard_x24"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["hardlink", "Cargo.toml"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["chmod_file", "Cargo.toml", "0644"])).is_ok(), cfg!(unix));
+        assert_eq!(validator.check(&StringRecord::from(vec!["chmod_file", "Cargo.toml", "readonly+hidden"])).is_ok(), cfg!(windows));
+        assert!(validator.check(&StringRecord::from(vec!["chmod_file", "Cargo.toml", "rwx"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["chmod_file", "edr_validate_missing_x24.txt", if cfg!(windows) { "readonly" } else { "0400" }])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["persist_startup", "edr_updater", "payload --quiet"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["persist_service", "edr updater", "payload"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["persist_cron", "*/5 * * * *"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["persist_runkey", "edr_updater", "payload"])).is_ok(), cfg!(windows));
+        assert_eq!(validator.check(&StringRecord::from(vec!["persist_cron", "@reboot", "payload"])).is_ok(), !cfg!(windows));
+        assert!(validator.check(&StringRecord::from(vec!["schedule_task", "EdrUpdate", "Hourly", "payload --quiet"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["schedule_task", "EdrUpdate", "fortnightly", "payload"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["tamper_service", "WinDefend"])).is_ok(), cfg!(any(windows, target_os = "linux")));
+        assert!(validator.check(&StringRecord::from(vec!["tamper_service", ""])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["tamper_files", "src"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["tamper_files", "Cargo.toml"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["tamper_rename", "Cargo.toml"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["tamper_rename", "edr_validate_missing_x24.exe"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["create_user", "edr_test"])).is_ok(), account::supported() && account::elevated());
+        assert_eq!(validator.check(&StringRecord::from(vec!["create_user", "edr test"])).unwrap_err().kind, "input_format");
+        assert_eq!(validator.check(&StringRecord::from(vec!["add_to_group", "edr_test"])).unwrap_err().kind, "input_format");
+        assert_eq!(validator.check(&StringRecord::from(vec!["add_to_group", "edr_test", "Remote Desktop Users"])).is_ok(), account::supported() && account::elevated());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "slow"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "alias=123"])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["process", get_os_shell(), "alias=slow", "run_for=500"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "slow", "5000"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "4242"])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["wait_process", "slow", "soon"])).is_err());
+        assert_eq!(validator.check(&StringRecord::from(vec!["process_in_session", "other", get_os_shell()])).is_ok(), cfg!(windows));
+        assert_eq!(validator.check(&StringRecord::from(vec!["process_in_session", " ", get_os_shell()])).unwrap_err().kind, "input_format");
+        assert!(validator.check(&StringRecord::from(vec!["launch", "rocket"])).is_err());
+    }
+
+    #[test]
+    fn file_state_tracked() {
+        let temp = TempPath::new("validate.txt");
+        let path = temp.as_string();
+        let mut validator = Validator::new();
+        assert!(validator.check(&StringRecord::from(vec!["mod_file", path.as_str()])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["new_file", path.as_str()])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["new_file", path.as_str()])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["mod_file", path.as_str()])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["delete_file", path.as_str()])).is_ok());
+        assert!(validator.check(&StringRecord::from(vec!["delete_file", path.as_str()])).is_err());
+        assert!(validator.check(&StringRecord::from(vec!["new_file", "/not_a_real_directory_x24/file.txt"])).is_err());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn artifacts_previewed() {
+        let artifacts = preview(&StringRecord::from(vec!["exfil", "secrets.txt", "10.0.0.5", "8080", "HTTP", "@username=bob"]));
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(artifacts[0].kind, "file");
+        assert!(Path::new(artifacts[0].target.trim_end_matches(" (read)")).is_absolute());
+        assert_eq!(artifacts[1].to_string(), "connection 10.0.0.5:8080 (http)");
+        assert_eq!(preview(&StringRecord::from(vec!["connect", "10.0.0.5", "4444", "hello", "retries=3"]))[0].target, "10.0.0.5:4444");
+        assert_eq!(preview(&StringRecord::from(vec!["fan_out", "10.0.0.0/24", "22 80-89", "hello", "hold=500"]))[0].target, "10.0.0.1 .. 10.0.0.254 port 22 .. 89 (2794 connections, 64 at once)");
+        assert_eq!(preview(&StringRecord::from(vec!["listen", "::", "4444"]))[0].target, "[::]:4444");
+        assert_eq!(preview(&StringRecord::from(vec!["connect", "10.0.0.5", "4444", "hello", "retries=3", "netns=dmz", "timeout=500"]))[0].target, "10.0.0.5:4444 in netns dmz");
+        assert_eq!(preview(&StringRecord::from(vec!["process", "not_a_real_binary_x24", "-v", "run_for=100"]))[0].target, "not_a_real_binary_x24 -v");
+        assert_eq!(preview(&StringRecord::from(vec!["process", "not_a_real_binary_x24", "-v", "stdin=whoami", "capture=64"]))[0].target, "not_a_real_binary_x24 -v");
+        assert_eq!(preview(&StringRecord::from(vec!["encrypt_burst", "ransom", "20"]))[0].target, format!("{} (create, 20 files renamed to .locked)", absolute("ransom")));
+        let startup = preview(&StringRecord::from(vec!["persist_startup", "edr_updater", "payload --quiet"]));
+        assert_eq!(startup[0].kind, "file");
+        assert!(startup[0].target.contains("edr_updater") && startup[0].target.ends_with("(starts payload --quiet)"));
+        assert!(preview(&StringRecord::from(vec!["pause", "100"])).is_empty());
+        let other = if cfg!(windows) { "unix" } else { "windows" };
+        assert!(preview(&StringRecord::from(vec!["connect", "10.0.0.5", "4444", "hello", "timeout=500", &format!("os={}", other)])).is_empty());
+    }
+}
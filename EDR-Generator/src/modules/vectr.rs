@@ -0,0 +1,148 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use crate::modules::network::host_port;
+use crate::modules::report::RunSummary;
+use crate::modules::stix::{self, stix_time};
+use csv::Writer;
+
+/// Columns of the VECTR test case import template written by `write_test_cases`
+pub const VECTR_HEADER: [&str; 15] = ["Campaign", "Variant", "Objective", "Phase", "MitreID", "Technique", "Status", "Outcome",
+    "Outcome Notes", "Description", "Targets", "Sources", "Tags", "Start Time", "Stop Time"];
+
+/// Outcome of every test case, the detection outcome is recorded by the blue team in VECTR
+const PENDING_OUTCOME: &str = "TBD";
+
+/// Structure defining the events of a run grouped into a single VECTR test case
+///
+/// # Parameters
+///
+/// - `activity`: activity of the events, without any parenthesized suffix
+/// - `count`: number of events
+/// - `example`: artifact of the first event (command line, file path, or destination)
+/// - `first`: time of the first event
+/// - `last`: time of the last event
+struct TestCase {
+    activity: String,
+    count: usize,
+    example: String,
+    first: String,
+    last: String,
+}
+
+/// Groups the events of a run into VECTR test cases, one per activity in the order the activities
+/// first occurred. Variants of an activity (e.g. `Rename File (from a.txt)`) are grouped together.
+///
+/// # Parameters
+///
+/// - `summary`: summary of the run
+/// - `events`: events logged by the run
+///
+/// # Returns
+///
+/// The rows of the test cases, in the order of `VECTR_HEADER`
+pub fn test_cases(summary: &RunSummary, events: &[Log]) -> Vec<Vec<String>> {
+    let mut cases: Vec<TestCase> = vec![];
+    for event in events {
+        let activity = event.activity.split(" (").next().unwrap_or("").to_string();
+        let time = stix_time(&event.timestamp);
+        match cases.iter_mut().find(|case| case.activity == activity) {
+            Some(case) => {
+                case.count += 1;
+                case.first = case.first.clone().min(time.clone());
+                case.last = case.last.clone().max(time);
+            }
+            None => cases.push(TestCase { activity, count: 1, example: artifact(event), first: time.clone(), last: time })
+        }
+    }
+    let campaign = format!("EDR Generator: {}", summary.scenario);
+    cases.into_iter().map(|case| {
+        let (id, name, tactic) = stix::technique(&case.activity).unwrap_or(("", "", ""));
+        let mut description = format!("{} event(s) generated by {} (SHA-256 {})", case.count, summary.scenario, summary.scenario_sha256);
+        if !case.example.is_empty() {
+            description.push_str(&format!(", e.g. {}", case.example));
+        }
+        vec![campaign.clone(), case.activity.clone(), if name.is_empty() { case.activity } else { name.to_string() },
+             tactic.to_string(), id.to_string(), name.to_string(), "Completed".to_string(), PENDING_OUTCOME.to_string(), "".to_string(),
+             description, summary.host.clone(), "edr_generator".to_string(), "edr_generator".to_string(), case.first, case.last]
+    }).collect()
+}
+
+/// Writes the test cases of a run as a CSV file matching the VECTR import template, see `test_cases`
+///
+/// # Parameters
+///
+/// - `path`: path of the file to write
+/// - `summary`: summary of the run
+/// - `events`: events logged by the run
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The file was written
+/// - `Err`: The file could not be written
+pub fn write_test_cases(path: &String, summary: &RunSummary, events: &[Log]) -> Result<(), GenerationError> {
+    let result = Writer::from_path(path).and_then(|mut writer| {
+        writer.write_record(VECTR_HEADER)?;
+        for row in test_cases(summary, events) {
+            writer.write_record(&row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    });
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(GenerationError::new("io".to_string(), format!("Unable to write {}: {}", path, e)))
+    }
+}
+
+/// Describes the artifact of an event: the command line of process events, the path of file
+/// events, or the destination of network events
+fn artifact(event: &Log) -> String {
+    if !event.proc_cmd.trim().is_empty() {
+        event.proc_cmd.trim().to_string()
+    } else if !event.file_path.is_empty() {
+        event.file_path.clone()
+    } else if !event.dest_addr.is_empty() {
+        match event.dest_port.parse() {
+            Ok(port) => host_port(&event.dest_addr, port),
+            Err(_) => format!("{}:{}", event.dest_addr, event.dest_port)
+        }
+    } else {
+        "".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::testing::TempPath;
+    use crate::modules::network::adapt_log_network;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn events_grouped() {
+        let summary = RunSummary::new(String::from("beacon.csv"), String::from("3f1e9c4b"), 3, 0, Duration::from_millis(4), false);
+        let mut first = adapt_log_network("Network Connection (attempt 2 of 3)".to_string(), "10.0.0.2".to_string(), 50123,
+                                          "10.0.0.5".to_string(), 4444, 5, "TCP/IP".to_string());
+        first.timestamp = "2021-04-15T05:49:10.2+00:00".to_string();
+        let mut second = first.clone();
+        second.activity = "Network Connection".to_string();
+        second.timestamp = "2021-04-15T05:49:10.1+00:00".to_string();
+        let mut listen = first.clone();
+        listen.activity = "Listening Port Opened".to_string();
+        let rows = test_cases(&summary, &[first, second, listen]);
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.len() == VECTR_HEADER.len()));
+        assert_eq!(rows[0][1..7], ["Network Connection", "Application Layer Protocol", "Command and Control", "T1071", "Application Layer Protocol", "Completed"]);
+        assert!(rows[0][9].starts_with("2 event(s) generated by beacon.csv"));
+        assert!(rows[0][9].ends_with("e.g. 10.0.0.5:4444"));
+        assert_eq!((rows[0][13].as_str(), rows[0][14].as_str()), ("2021-04-15T05:49:10.100Z", "2021-04-15T05:49:10.200Z"));
+        assert_eq!(rows[1][1..5], ["Listening Port Opened", "Listening Port Opened", "", ""]);
+
+        let path = TempPath::new("vectr.csv");
+        write_test_cases(&path.as_string(), &summary, &[]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", VECTR_HEADER.join(",")));
+    }
+}
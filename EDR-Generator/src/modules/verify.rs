@@ -0,0 +1,242 @@
+use crate::modules::common::GenerationError;
+use crate::modules::logger::Log;
+use csv::ReaderBuilder;
+use regex::Regex;
+use serde::Serialize;
+use std::io::Read;
+
+/// Column of an expectations file naming the expected event in the report
+pub const NAME_COLUMN: &str = "name";
+
+/// Prefix of a value which is a regular expression rather than an exact value
+const REGEX_PREFIX: char = '~';
+
+/// Condition on a single field of an event
+///
+/// # Parameters
+///
+/// - `Equals`: the field has exactly this value. For the `activity` field, variants of the activity
+///   with a parenthesized suffix (e.g. `Network Connection (attempt 2 of 3)`) also match
+/// - `Matches`: the field contains a match of the regular expression
+#[derive(Debug, Clone)]
+enum Predicate {
+    Equals(String),
+    Matches(Regex),
+}
+
+/// Structure defining an event the run is expected to generate, read from a row of an
+/// expectations file
+///
+/// # Parameters
+///
+/// - `name`: name of the expected event in the report, its conditions when the row has no name
+/// - `conditions`: name of each field with a condition and the condition it must satisfy
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    pub name: String,
+    conditions: Vec<(String, Predicate)>,
+}
+
+/// Structure defining the outcome of verifying the events of a run against its expectations
+///
+/// # Parameters
+///
+/// - `expected`: number of expected events
+/// - `generated`: names of the expected events which were generated
+/// - `missing`: names of the expected events which were not generated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Verification {
+    pub expected: usize,
+    pub generated: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl Expectation {
+    /// Determines if an event satisfies every condition of the expectation
+    ///
+    /// # Parameters
+    ///
+    /// - `event`: event logged by the run
+    ///
+    /// # Returns
+    ///
+    /// True if the event matches, false otherwise
+    pub fn matches(&self, event: &Log) -> bool {
+        self.conditions.iter().all(|(field, predicate)| {
+            let value = event.field(field).unwrap_or("");
+            match predicate {
+                Predicate::Equals(expected) if field == "activity" => value == expected || value.split(" (").next() == Some(expected.as_str()),
+                Predicate::Equals(expected) => value == expected,
+                Predicate::Matches(pattern) => pattern.is_match(value)
+            }
+        })
+    }
+}
+
+impl Verification {
+    /// Determines if every expected event was generated
+    ///
+    /// # Returns
+    ///
+    /// True if no expected event is missing
+    pub fn passed(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Formats the outcome for the console
+    ///
+    /// # Returns
+    ///
+    /// The number of expected events generated, followed by a line per missing event
+    pub fn message(&self) -> String {
+        let mut message = format!("{} of {} expected event(s) generated", self.generated.len(), self.expected);
+        for name in &self.missing {
+            message.push_str(&format!("\n  missing: {}", name));
+        }
+        message
+    }
+}
+
+/// Reads an expectations file, see `parse`
+///
+/// # Parameters
+///
+/// - `path`: path of the expectations file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The expected events, in the order of the file
+/// - `Err`: The file could not be read or is not a valid expectations file
+pub fn load(path: &String) -> Result<Vec<Expectation>, GenerationError> {
+    let file = std::fs::File::open(path)?;
+    parse(file).map_err(|e| GenerationError::new(e.kind, format!("{} is not a valid expectations file: {}", path, e.message)))
+}
+
+/// Parses expectations written as a csv file. The header row names the fields of the output file
+/// the conditions apply to (`activity`, `file_path`, `dest_port`, ...) and optionally a `name`
+/// column. Each following row is an expected event: an empty value matches any value, a value
+/// starting with `~` is a regular expression the field must contain a match of, and any other
+/// value must be equal to the field.
+///
+/// ```csv
+/// name,activity,file_path,dest_port
+/// report dropped,New File,~report\.txt$,
+/// beacon,Network Connection,,443
+/// ```
+///
+/// # Parameters
+///
+/// - `reader`: contents of the expectations file
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The expected events, in the order of the file
+/// - `Err`: A column is not a field of the output file, a regular expression is not valid, or a
+///   row has no condition
+pub fn parse<R: Read>(reader: R) -> Result<Vec<Expectation>, GenerationError> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(reader);
+    let header: Vec<String> = reader.headers().map_err(csv_error)?.iter().map(|column| column.trim().to_string()).collect();
+    if let Some(column) = header.iter().find(|column| column.as_str() != NAME_COLUMN && !Log::is_field(column)) {
+        return Err(GenerationError::new("input_format".to_string(), format!("column {} is not a field of the output file", column)));
+    }
+    let mut expectations = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(csv_error)?;
+        let row = index + 2;
+        let mut name = String::new();
+        let mut conditions = Vec::new();
+        for (column, value) in header.iter().zip(record.iter()) {
+            if value.is_empty() {
+                continue;
+            }
+            if column == NAME_COLUMN {
+                name = value.to_string();
+                continue;
+            }
+            let predicate = match value.strip_prefix(REGEX_PREFIX) {
+                Some(pattern) => match Regex::new(pattern) {
+                    Ok(inner) => Predicate::Matches(inner),
+                    Err(e) => return Err(GenerationError::new("input_format".to_string(), format!("row {} has an invalid regular expression for {}: {}", row, column, e)))
+                },
+                None => Predicate::Equals(value.to_string())
+            };
+            conditions.push((column.clone(), predicate));
+        }
+        if conditions.is_empty() {
+            return Err(GenerationError::new("input_format".to_string(), format!("row {} has no condition and would match every event", row)));
+        }
+        if name.is_empty() {
+            name = describe(&conditions);
+        }
+        expectations.push(Expectation { name, conditions });
+    }
+    Ok(expectations)
+}
+
+/// Determines which expected events were generated by a run. An event may satisfy several
+/// expectations.
+///
+/// # Parameters
+///
+/// - `expectations`: the expected events
+/// - `events`: events logged by the run
+///
+/// # Returns
+///
+/// The expected events which were and were not generated
+pub fn verify(expectations: &[Expectation], events: &[Log]) -> Verification {
+    let (generated, missing): (Vec<&Expectation>, Vec<&Expectation>) = expectations.iter()
+        .partition(|expectation| events.iter().any(|event| expectation.matches(event)));
+    Verification {
+        expected: expectations.len(),
+        generated: generated.into_iter().map(|expectation| expectation.name.clone()).collect(),
+        missing: missing.into_iter().map(|expectation| expectation.name.clone()).collect(),
+    }
+}
+
+/// Describes the conditions of an expectation without a name, e.g. `activity=New File, file_path~\.txt$`
+fn describe(conditions: &[(String, Predicate)]) -> String {
+    let described: Vec<String> = conditions.iter().map(|(field, predicate)| match predicate {
+        Predicate::Equals(value) => format!("{}={}", field, value),
+        Predicate::Matches(pattern) => format!("{}{}{}", field, REGEX_PREFIX, pattern.as_str())
+    }).collect();
+    described.join(", ")
+}
+
+/// Converts an error reading the expectations file
+fn csv_error(error: csv::Error) -> GenerationError {
+    GenerationError::new("input_format".to_string(), error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(activity: &str, file_path: &str, dest_port: &str) -> Log {
+        Log { activity: activity.to_string(), file_path: file_path.to_string(), dest_port: dest_port.to_string(), ..Log::default() }
+    }
+
+    #[test]
+    fn expectations_verified() {
+        let expectations = parse("name,activity,file_path,dest_port\nreport dropped,New File,~report\\.txt$,\n,Network Connection,,443\nbeacon,Network Connection,,8443\n".as_bytes()).unwrap();
+        assert_eq!(expectations[1].name, "activity=Network Connection, dest_port=443");
+        let events = vec![event("New File", "/tmp/report.txt", ""), event("Network Connection (attempt 2 of 3)", "", "443")];
+        let verification = verify(&expectations, &events);
+        assert_eq!(verification.generated, vec!["report dropped", "activity=Network Connection, dest_port=443"]);
+        assert_eq!(verification.missing, vec!["beacon"]);
+        assert!(!verification.passed());
+        assert!(verification.message().starts_with("2 of 3 expected event(s) generated\n  missing: beacon"));
+        assert!(!expectations[0].matches(&event("New File", "/tmp/report.txt.bak", "")));
+    }
+
+    #[test]
+    fn invalid_expectations() {
+        assert!(parse("activity,color\nNew File,blue\n".as_bytes()).is_err());
+        assert!(parse("activity,file_path\nNew File,~(\n".as_bytes()).is_err());
+        assert!(parse("name,activity\nanything,\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::modules::common::GenerationError;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of names tried before giving up on creating a working directory
+const CREATE_ATTEMPTS: u32 = 100;
+
+/// Structure defining a directory created for a single run. Relative paths of the run's
+/// instructions resolve inside it, and it is removed with everything in it when dropped, so
+/// concurrent runs on the same host do not collide on artifact paths.
+///
+/// # Parameters
+///
+/// - `path`: absolute path of the directory
+pub struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    /// Creates a new, uniquely named directory (`edr_run_<pid>_<timestamp>`) inside a base directory
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: directory the working directory is created in, relative to the current directory
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: WorkDir instance
+    /// - `Err`: The base directory does not exist or the directory could not be created
+    pub fn create(base: &str) -> Result<WorkDir, GenerationError> {
+        let base = env::current_dir()?.join(base);
+        if !base.is_dir() {
+            return Err(GenerationError::new("io".to_string(), format!("{} is not a directory", base.display())));
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis()).unwrap_or(0);
+        for attempt in 0..CREATE_ATTEMPTS {
+            let path = base.join(format!("edr_run_{}_{}_{}", process::id(), timestamp, attempt));
+            match fs::create_dir(&path) {
+                Ok(_) => return Ok(WorkDir { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(GenerationError::new("io".to_string(), format!("Unable to create a working directory in {}: {}", base.display(), e)))
+            }
+        }
+        Err(GenerationError::new("io".to_string(), format!("Unable to create a working directory in {}: every name is in use", base.display())))
+    }
+
+    /// Retrieves the path of the directory
+    ///
+    /// # Returns
+    ///
+    /// Absolute path of the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Resolves a file path of an instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: path as written in the scenario
+    ///
+    /// # Returns
+    ///
+    /// The path inside the directory if it is relative, otherwise the path unchanged
+    pub fn resolve(&self, path: &str) -> String {
+        if path.is_empty() || Path::new(path).is_absolute() {
+            return path.to_string();
+        }
+        self.path.join(path).to_string_lossy().to_string()
+    }
+
+    /// Resolves the executable path of a process instruction. Bare names (e.g. `cmd`) are still
+    /// looked up on the `PATH`, only relative paths with a directory (e.g. `./payload`) resolve
+    /// inside the directory.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: executable path as written in the scenario
+    ///
+    /// # Returns
+    ///
+    /// The path inside the directory if it is relative and names a directory, otherwise the path
+    /// unchanged
+    pub fn resolve_executable(&self, path: &str) -> String {
+        if Path::new(path).components().count() > 1 {
+            self.resolve(path)
+        } else {
+            path.to_string()
+        }
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            eprintln!("Unable to remove the working directory {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_unique() {
+        let base = env::temp_dir().to_string_lossy().to_string();
+        let first = WorkDir::create(&base).unwrap();
+        let second = WorkDir::create(&base).unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().is_absolute());
+        let path = first.path().to_path_buf();
+        fs::write(first.resolve("artifact.txt"), "data").unwrap();
+        assert!(path.join("artifact.txt").is_file());
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.path().is_dir());
+        assert!(WorkDir::create("not_a_real_directory_x24").is_err());
+    }
+
+    #[test]
+    fn paths_resolved() {
+        let workdir = WorkDir::create(&env::temp_dir().to_string_lossy()).unwrap();
+        let absolute = env::temp_dir().join("edr.txt").to_string_lossy().to_string();
+        assert_eq!(workdir.resolve(&absolute), absolute);
+        assert_eq!(Path::new(&workdir.resolve("logs/edr.txt")), workdir.path().join("logs/edr.txt"));
+        assert_eq!(workdir.resolve_executable("cmd"), "cmd");
+        assert_eq!(Path::new(&workdir.resolve_executable("./payload")), workdir.path().join("./payload"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::modules::common::GenerationError;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Number of jobs which may wait in the queue of each worker
+const QUEUE_DEPTH: usize = 2;
+
+/// Structure defining a fixed size pool of threads which process submitted jobs concurrently. Every
+/// worker has its own bounded queue, so jobs are only submitted as fast as the workers can process
+/// them and jobs submitted to the same worker are processed in order.
+///
+/// # Parameters
+///
+/// - `senders`: queue of jobs waiting for each worker, empty once the pool has been joined
+/// - `handles`: threads processing jobs
+/// - `next`: worker receiving the next job without an affinity
+pub struct WorkerPool<T: Send + 'static> {
+    senders: Vec<SyncSender<T>>,
+    handles: Vec<JoinHandle<()>>,
+    next: usize,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Starts the worker threads
+    ///
+    /// # Parameters
+    ///
+    /// - `workers`: number of threads to start
+    /// - `handler`: function processing a single job, every thread receives its own copy
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: WorkerPool instance
+    /// - `Err`: The number of workers is zero or a thread could not be started
+    pub fn new<F>(workers: usize, handler: F) -> Result<WorkerPool<T>, GenerationError>
+        where F: FnMut(T) + Send + Clone + 'static {
+        if workers == 0 {
+            return Err(GenerationError::new("input_format".to_string(), "Workers must be at least 1".to_string()));
+        }
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for index in 0..workers {
+            let (sender, receiver) = mpsc::sync_channel::<T>(QUEUE_DEPTH);
+            let handler = handler.clone();
+            let handle = thread::Builder::new()
+                .name(format!("worker-{}", index))
+                .spawn(move || work(receiver, handler))?;
+            senders.push(sender);
+            handles.push(handle);
+        }
+        Ok(WorkerPool { senders, handles, next: 0 })
+    }
+
+    /// Queues a job, blocking while the queue of the selected worker is full
+    ///
+    /// # Parameters
+    ///
+    /// - `job`: job to process
+    /// - `affinity`: jobs with the same affinity are processed by the same worker, in the order they
+    ///   were submitted. Jobs without an affinity are distributed evenly
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn submit(&mut self, job: T, affinity: Option<u64>) {
+        if self.senders.is_empty() {
+            return;
+        }
+        let index = match affinity {
+            Some(key) => (key % self.senders.len() as u64) as usize,
+            None => {
+                self.next = (self.next + 1) % self.senders.len();
+                self.next
+            }
+        };
+        // the workers only exit once the sender is dropped, so the job is always received
+        let _ = self.senders[index].send(job);
+    }
+
+    /// Number of worker threads, 0 once the pool has been joined
+    ///
+    /// # Returns
+    ///
+    /// Number of threads processing jobs
+    pub fn workers(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Waits for every queued job to be processed and stops the worker threads
+    ///
+    /// # Returns
+    ///
+    /// Nothing
+    pub fn join(&mut self) {
+        self.senders.clear();
+        for handle in self.handles.drain(..) {
+            if handle.join().is_err() {
+                eprintln!("A worker thread panicked, events it was processing may be missing");
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// Processes jobs until the queue is closed
+fn work<T, F: FnMut(T)>(receiver: Receiver<T>, mut handler: F) {
+    for job in receiver {
+        handler(job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn jobs_processed_concurrently() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let counter = total.clone();
+        let mut pool = WorkerPool::new(4, move |value: usize| {
+            thread::sleep(Duration::from_millis(50));
+            counter.fetch_add(value, Ordering::SeqCst);
+        }).unwrap();
+        let start = Instant::now();
+        for value in 1..=8 {
+            pool.submit(value, None);
+        }
+        pool.join();
+        assert_eq!(total.load(Ordering::SeqCst), 36);
+        assert!(start.elapsed() < Duration::from_millis(350));
+    }
+
+    #[test]
+    fn affinity_preserves_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let mut pool = WorkerPool::new(4, move |value: u64| {
+            thread::sleep(Duration::from_millis(20 - value));
+            recorder.lock().unwrap().push(value);
+        }).unwrap();
+        for value in 0..10 {
+            pool.submit(value, Some(3));
+        }
+        pool.join();
+        assert_eq!(*seen.lock().unwrap(), (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn zero_workers() {
+        assert!(WorkerPool::new(0, |_: usize| {}).is_err());
+    }
+}
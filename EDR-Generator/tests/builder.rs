@@ -0,0 +1,24 @@
+use edr_generator::modules::builder::{RunReport, ScenarioBuilder};
+use std::env;
+use std::fs;
+
+#[test]
+fn arguments_passed_unchanged() {
+    let output = env::temp_dir().join(format!("edr_builder_arguments_{}.csv", std::process::id()));
+    let created = env::temp_dir().join(format!("edr builder {}.txt", std::process::id()));
+    let path = created.to_string_lossy().to_string();
+    let builder = ScenarioBuilder::new().output(&output.to_string_lossy());
+    let builder = if cfg!(windows) {
+        builder.process("cmd", &["/c", "copy", "NUL", &path])
+    } else {
+        builder.process("touch", &[&path])
+    };
+    let report = builder.pause(500).run().unwrap();
+    let log = fs::read_to_string(&output).unwrap();
+    let _ = fs::remove_file(&output);
+    assert_eq!(report, RunReport { instructions: 2, errors: 0, skipped: 0 });
+    assert!(log.contains("New Process"));
+    // the path reaches the process as a single argument, so exactly that file is created
+    assert!(created.is_file());
+    fs::remove_file(&created).unwrap();
+}